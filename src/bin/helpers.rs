@@ -15,7 +15,7 @@
 // You should have received a copy of the GNU Lesser General Public License
 // along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
 
-use crowbook::Book;
+use crowbook::{Book, import_document};
 use clap::{App, Arg,  ArgMatches, AppSettings};
 use console::style;
 
@@ -23,6 +23,7 @@ use std::io::{self, Write};
 use std::process::exit;
 use std::fs;
 use std::env;
+use std::path::{Path, PathBuf};
 
 
 static BIRD: &str = "🐦 ";
@@ -173,17 +174,47 @@ lang: en
     }
 }
 
+/// Import a document in a new book with the command line arguments
+/// and exit the process at the end
+pub fn import_book(matches: &ArgMatches) -> ! {
+    // ok to unwrap since clap checks it's there
+    let document = matches.value_of("import").unwrap();
+    let book = match matches.value_of("BOOK") {
+        Some(book) => PathBuf::from(book),
+        None => {
+            let stem = Path::new(document)
+                .file_stem()
+                .map(|s| s.to_string_lossy().into_owned())
+                .unwrap_or_else(|| String::from("book"));
+            PathBuf::from(format!("{}.book", stem))
+        }
+    };
+    match import_document(document, &book) {
+        Ok(n) => {
+            println!("{}",
+                     lformat!("Imported {document} in {book} ({n} chapters), now you'll have to check it!",
+                              document = document,
+                              book = book.display(),
+                              n = n));
+            exit(0);
+        }
+        Err(err) => print_error_and_exit(&format!("{}", err), false),
+    }
+}
+
 pub fn create_matches<'a>() -> (ArgMatches<'a>, String, String) {
     lazy_static! {
         static ref HELP: String = lformat!("Print help information");
         static ref VERSION: String = lformat!("Print version information");
         static ref ABOUT: String = lformat!("Render a Markdown book in EPUB, PDF or HTML.");
         static ref SINGLE: String = lformat!("Use a single Markdown file instead of a book configuration file");
+        static ref JSON: String = lformat!("Use the JSON representation of a book (as generated with --to json) instead of a book configuration file");
         static ref EMOJI: String = lformat!("Force emoji usage even if it might not work on your system");
         static ref VERBOSE: String = lformat!("Print warnings in parsing/rendering");
         static ref QUIET: String = lformat!("Don't print info/error messages");
         static ref PROOFREAD: String = lformat!("Enable proofreading");
         static ref CREATE: String = lformat!("Create a new book with existing Markdown files");
+        static ref IMPORT: String = lformat!("Create a new book from an EPUB, ODT or DOCX document");
         static ref AUTOGRAPH: String = lformat!("Prompts for an autograph for this book");
         static ref OUTPUT: String = lformat!("Specify output file");
         static ref LANG: String = lformat!("Set the runtime language used by Crowbook");
@@ -222,6 +253,9 @@ ARGS:
         .about(ABOUT.as_str())
         .arg(Arg::from_usage("-☺️, --force-emoji").help(EMOJI.as_str()))
         .arg(Arg::from_usage("-s, --single").help(SINGLE.as_str()))
+        .arg(Arg::from_usage("-j, --json")
+            .help(JSON.as_str())
+            .conflicts_with("single"))
         .arg(Arg::from_usage("-n, --no-fancy").help(NO_FANCY.as_str()))
         .arg(Arg::from_usage("-v, --verbose").help(VERBOSE.as_str()))
         .arg(Arg::from_usage("-a, --autograph").help(AUTOGRAPH.as_str()))
@@ -232,6 +266,9 @@ ARGS:
         .arg(Arg::from_usage("-V, --version").help(VERSION.as_str()))
         .arg(Arg::from_usage("-p, --proofread").help(PROOFREAD.as_str()))
         .arg(Arg::from_usage("-c, --create [FILES]...").help(CREATE.as_str()))
+        .arg(Arg::from_usage("-i, --import [FILE]")
+            .help(IMPORT.as_str())
+            .conflicts_with("create"))
         .arg(Arg::from_usage("-o, --output [FILE]")
             .help(OUTPUT.as_str())
             .requires("to"))
@@ -246,7 +283,8 @@ ARGS:
                                "proofread.html",
                                "proofread.html.dir",
                                "proofread.pdf",
-                               "proofread.tex"]))
+                               "proofread.tex",
+                               "json"]))
         .arg(Arg::from_usage("--set [KEY_VALUES]")
             .help(SET.as_str())
             .min_values(2))
@@ -140,6 +140,10 @@ pub fn try_main() -> Result<()> {
         create_book(&matches);
     }
 
+    if matches.is_present("import") {
+        import_book(&matches);
+    }
+
     if !matches.is_present("BOOK") {
         print_error_and_exit(&lformat!("You must pass the file of a book configuration \
                                file.\n\n{}\n\nFor more information try --help.",
@@ -209,6 +213,12 @@ pub fn try_main() -> Result<()> {
                 } else {
                     book.read_markdown_config(io::stdin())
                 }
+            } else if matches.is_present("json") {
+                if s != "-" {
+                    book.load_json_file(s)
+                } else {
+                    book.read_json(io::stdin())
+                }
             } else if s != "-" {
                 book.load_file(s)
             } else {
@@ -17,6 +17,7 @@
 
 use error::{Error, Result, Source};
 use cleaner::{Cleaner, CleanerParams, French, Off, Default};
+use dialogue::{Dialogue, Convention};
 use bookoptions::BookOptions;
 use parser::Parser;
 use parser::Features;
@@ -26,14 +27,19 @@ use html_dir::{HtmlDir, ProofHtmlDir};
 use html_if::{HtmlIf};
 use latex::{Latex, ProofLatex, Pdf, ProofPdf};
 use odt::{Odt};
-use templates::{epub, html, epub3, latex, html_dir, highlight, html_single, html_if};
+use cover::GeneratedCover;
+use json::{self, Json};
+use templates::{epub, html, epub3, latex, html_dir, highlight, html_single, html_if, cover};
 use number::Number;
 use resource_handler::ResourceHandler;
 use lang;
 use misc;
+use include;
+use variables;
+use filter;
 use book_renderer::BookRenderer;
 use chapter::Chapter;
-use token::Token;
+use token::{Token, Attributes};
 use text_view::view_as_text;
 use book_bars::Bars;
 
@@ -77,15 +83,38 @@ use std::io::{Write, Read};
 use std::path::{Path, PathBuf};
 use std::borrow::Cow;
 use std::iter::IntoIterator;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::cmp::Ordering;
 use std::fmt;
+use std::env;
 
 use rayon::prelude::*;
 use mustache;
 use mustache::{MapBuilder, Template};
 use yaml_rust::{YamlLoader, Yaml};
+use chrono::NaiveDateTime;
 use numerals::roman::Roman;
+use uuid::Uuid;
+
+lazy_static! {
+    /// Value of the `SOURCE_DATE_EPOCH` environment variable, read (and checked) once
+    static ref SOURCE_DATE_EPOCH: Option<i64> = {
+        match env::var("SOURCE_DATE_EPOCH") {
+            Ok(epoch) => {
+                match epoch.trim().parse::<i64>() {
+                    Ok(n) if NaiveDateTime::from_timestamp_opt(n, 0).is_some() => Some(n),
+                    _ => {
+                        warn!("{}", lformat!("SOURCE_DATE_EPOCH is set to '{value}', which is \
+                                              not a valid timestamp; ignoring it",
+                                             value = epoch));
+                        None
+                    }
+                }
+            }
+            Err(_) => None,
+        }
+    };
+}
 
 /// Type of header (part or chapter)
 #[derive(Copy, Clone, Debug)]
@@ -96,6 +125,20 @@ pub enum Header {
     Part,
 }
 
+/// Where footnotes are placed
+#[doc(hidden)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum NotesPlacement {
+    /// At the bottom of the page (or the format's usual place if it has no pages)
+    Page,
+    /// At the end of each chapter
+    Chapter,
+    /// In a "Notes" chapter at the end of the book
+    Book,
+    /// In the margin
+    Side,
+}
+
 
 /// Header data (for chapter or part)
 #[derive(Debug, Clone)]
@@ -186,6 +229,10 @@ pub struct Book {
     grammalecte: Option<GrammalecteChecker>,
     detector: Option<RepetitionDetector>,
     formats: HashMap<&'static str, (String, Box<BookRenderer>)>,
+    /// Identifiers already used in the book
+    ids: HashSet<String>,
+    /// Cover generated from the book's metadata, shared by the renderers
+    generated_cover: Option<GeneratedCover>,
 
     #[doc(hidden)]
     pub bars: Bars,
@@ -197,7 +244,7 @@ impl Book {
         let mut book = Book {
             source: Source::empty(),
             chapters: vec![],
-            cleaner: Box::new(Off),
+            cleaner: Box::new(Off::new(None)),
             root: PathBuf::new(),
             options: BookOptions::new(),
             chapter_template: None,
@@ -206,6 +253,8 @@ impl Book {
             grammalecte: None,
             detector: None,
             formats: HashMap::new(),
+            generated_cover: None,
+            ids: HashSet::new(),
             features: Features::new(),
             bars: Bars::new(),
         };
@@ -219,7 +268,8 @@ impl Book {
             .add_format("proofread.pdf", lformat!("PDF (proofreading)"), Box::new(ProofPdf{}))
             .add_format("epub", lformat!("EPUB"), Box::new(Epub{}))
             .add_format("odt", lformat!("ODT"), Box::new(Odt{}))
-            .add_format("html.if", lformat!("HTML (interactive fiction)"), Box::new(HtmlIf{}));
+            .add_format("html.if", lformat!("HTML (interactive fiction)"), Box::new(HtmlIf{}))
+            .add_format("json", lformat!("JSON (AST)"), Box::new(Json{}));
         book
     }
 
@@ -637,7 +687,8 @@ impl Book {
                     let subline = subline.trim();
                     let ast = Parser::from(&self)
                         .parse_inline(subline)?;
-                    let ast = vec!(Token::Header(1, ast));
+                    let mut ast = vec!(Token::Header(1, ast));
+                    self.add_header_ids(&mut ast);
                     self.chapters.push(Chapter::new(Number::DefaultPart, String::new(), ast));
                 } else if subline.starts_with('-') {
                     /* Unnumbered part */
@@ -681,6 +732,126 @@ impl Book {
         Ok(self)
     }
 
+    /// Loads the JSON representation of a book (as rendered by the `json` format)
+    ///
+    /// Like with `load_file`, the directory of this file is used as the "root"
+    /// directory for the paths referenced in the book.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use crowbook::Book;
+    /// let mut book = Book::new();
+    /// let result = book.load_json_file("some.json");
+    /// ```
+    pub fn load_json_file<P: AsRef<Path>>(&mut self, path: P) -> Result<&mut Book> {
+        let filename = format!("{}", path.as_ref().display());
+        self.source = Source::new(filename.as_str());
+        self.options.source = Source::new(filename.as_str());
+
+        let f = File::open(path.as_ref())
+            .map_err(|_| {
+                Error::file_not_found(Source::empty(), lformat!("book"), filename.clone())
+            })?;
+        // Set book path to JSON file's directory
+        if let Some(parent) = path.as_ref().parent() {
+            self.root = parent.to_owned();
+            self.options.root = self.root.clone();
+        }
+        self.read_json(f)
+    }
+
+    /// Reads the JSON representation of a book (as rendered by the `json` format)
+    /// from a `Read`able object.
+    ///
+    /// The chapters are not parsed again, so their content can have been modified
+    /// by external tools before it is read.
+    pub fn read_json<R: Read>(&mut self, source: R) -> Result<&mut Book> {
+        self.bar_set_message(Crowbar::Main, &lformat!("setting options"));
+        let ast = json::read_ast(source)?;
+        for (key, value) in &ast.options {
+            self.options.set_yaml(Yaml::String(key.clone()), json::json_to_yaml(value))?;
+        }
+
+        // Update cleaner according to options (autoclean/lang)
+        self.update_cleaner();
+
+        // Update grammar checker according to options (proofread.*)
+        self.init_checker();
+
+        self.features = self.features | ast.features;
+        for chapter in ast.chapters {
+            self.register_ids(&chapter.content);
+            self.chapters.push(chapter);
+        }
+        self.set_chapter_template()?;
+        Ok(self)
+    }
+
+    /// Sets an identifier, derived from their text, to the headers that don't
+    /// have an explicit one, making sure it is unique in the book.
+    fn add_header_ids(&mut self, tokens: &mut [Token]) {
+        self.register_ids(tokens);
+        self.set_header_ids(tokens);
+    }
+
+    /// Registers the explicit identifiers set in `tokens`
+    fn register_ids(&mut self, tokens: &[Token]) {
+        for token in tokens {
+            if let Some(id) = token.attributes().and_then(|a| a.id.as_ref()) {
+                if !self.ids.insert(id.clone()) {
+                    warn!("{}", lformat!("in {source}, identifier '{id}' is used more than once",
+                                         source = self.source,
+                                         id = id));
+                }
+            }
+            if let Some(inner) = token.inner() {
+                self.register_ids(inner);
+            }
+        }
+    }
+
+    /// Sets a unique identifier to headers that don't have one
+    fn set_header_ids(&mut self, tokens: &mut [Token]) {
+        for token in tokens {
+            if let Token::Header(_, ref mut inner) = *token {
+                let has_id = match inner.last() {
+                    Some(&Token::Attributes(ref attributes)) => attributes.id.is_some(),
+                    _ => false,
+                };
+                if !has_id {
+                    let id = self.unique_id(&misc::slugify(&view_as_text(inner)));
+                    if let Some(&mut Token::Attributes(ref mut attributes)) = inner.last_mut() {
+                        attributes.id = Some(id);
+                        continue;
+                    }
+                    inner.push(Token::Attributes(Attributes {
+                        id: Some(id),
+                        .. Attributes::default()
+                    }));
+                }
+                continue;
+            }
+            if let Some(inner) = token.inner_mut() {
+                self.set_header_ids(inner);
+            }
+        }
+    }
+
+    /// Returns `slug`, or `slug-1`, `slug-2`, and so on, so it is not an identifier
+    /// that is already used
+    fn unique_id(&mut self, slug: &str) -> String {
+        let slug = if slug.is_empty() { "section" } else { slug };
+        let mut id = slug.to_owned();
+        let mut n = 0;
+        while self.ids.contains(&id) || misc::is_generated_id(&id) {
+            n += 1;
+            id = format!("{}-{}", slug, n);
+        }
+        self.ids.insert(id.clone());
+        id
+    }
+
     /// Determine whether proofreading is activated or not
     fn is_proofread(&self) -> bool {
         self.options.get_bool("proofread").unwrap() &&
@@ -689,6 +860,80 @@ impl Book {
          self.options.get("output.proofread.pdf").is_ok())
     }
 
+    /// Returns the timestamp that must be used in generated files if the build
+    /// must be reproducible, or `None` if it doesn't matter.
+    ///
+    /// Uses the `SOURCE_DATE_EPOCH` environment variable if it is set (which
+    /// also enables reproducible builds), else 1980-01-01 if `crowbook.reproducible`
+    /// is set (zip files can't store earlier dates).
+    #[doc(hidden)]
+    pub fn reproducible_timestamp(&self) -> Option<i64> {
+        if let Some(epoch) = *SOURCE_DATE_EPOCH {
+            return Some(epoch);
+        }
+        if self.options.get_bool("crowbook.reproducible").unwrap() {
+            Some(315532800)
+        } else {
+            None
+        }
+    }
+
+    /// Returns where footnotes must be placed, according to `rendering.notes.placement`.
+    ///
+    /// `default` is the renderer's usual placement, used if this option is set to "auto".
+    /// Returns an error if the option is set to an unknown value.
+    #[doc(hidden)]
+    pub fn notes_placement(&self, default: NotesPlacement) -> Result<NotesPlacement> {
+        match self.options.get_str("rendering.notes.placement").unwrap() {
+            "auto" => Ok(default),
+            "page" => Ok(NotesPlacement::Page),
+            "chapter" => Ok(NotesPlacement::Chapter),
+            "book" => Ok(NotesPlacement::Book),
+            "side" => Ok(NotesPlacement::Side),
+            value => {
+                Err(Error::book_option(&self.source,
+                                       lformat!("rendering.notes.placement: unknown value \
+                                                 '{value}', expected 'auto', 'page', \
+                                                 'chapter', 'book' or 'side'",
+                                                value = value)))
+            }
+        }
+    }
+
+    /// Generates the cover from the book's metadata, if `cover.generate` is set and
+    /// there is no `cover`, so it is only generated once for all formats
+    fn update_generated_cover(&mut self) -> Result<()> {
+        let cover = GeneratedCover::new(self)?;
+        self.generated_cover = cover;
+        Ok(())
+    }
+
+    /// Returns the cover generated from the book's metadata, if there is one
+    #[doc(hidden)]
+    pub fn generated_cover(&self) -> Option<&GeneratedCover> {
+        self.generated_cover.as_ref()
+    }
+
+    /// Returns an identifier (an URN) for the book.
+    ///
+    /// It is random, unless the build must be reproducible, in which case it is
+    /// derived from the book's metadata so it stays the same across builds.
+    #[doc(hidden)]
+    pub fn identifier(&self) -> String {
+        if self.reproducible_timestamp().is_some() {
+            let mut name = String::new();
+            for key in &["title", "author", "lang", "subtitle", "version"] {
+                if let Ok(value) = self.options.get_str(key) {
+                    name.push_str(value);
+                }
+                name.push('\n');
+            }
+            misc::stable_uuid(&name)
+        } else {
+            format!("{}", Uuid::new_v4().urn())
+        }
+    }
+
     /// Initialize the grammar checker and repetetion detector if they needs to be
     #[cfg(feature = "proofread")]
     fn init_checker(&mut self) {
@@ -762,6 +1007,9 @@ impl Book {
     ///       .render_all(); // renders foo.tex in /tmp
     /// ```
     pub fn render_all(&mut self) -> () {
+        if let Err(err) = self.update_generated_cover() {
+            error!("{}", lformat!("Error generating cover: {error}", error = err));
+        }
         let mut keys: Vec<_> = self.formats
             .keys()
             .filter(|fmt| {
@@ -883,6 +1131,7 @@ impl Book {
     pub fn render_format_to<T: Write>(&mut self, format: &str, f: &mut T) -> Result<()> {
         debug!("{}", lformat!("Attempting to generate {format}...",
                               format = format));
+        self.update_generated_cover()?;
         let bar = self.add_spinner_to_multibar(format);
         match self.formats.get(format) {
             Some(&(ref description, ref renderer)) => {
@@ -927,6 +1176,7 @@ impl Book {
     pub fn render_format_to_file<P:Into<PathBuf>>(&mut self,
                                                   format: &str,
                                                   path: P) -> Result<()> {
+        self.update_generated_cover()?;
         let bar = self.add_spinner_to_multibar(format);
         let path = path.into();
         let normalized = misc::normalize(&path);
@@ -961,13 +1211,22 @@ impl Book {
         // Ignore YAML blocks (or not)
         self.parse_yaml(&mut content);
 
+        // Replace variables, and include directives with the content of included files
+        let content = variables::substitute(&self.options, file, content)?;
+        let (content, lines) = include::resolve_includes(&self.root,
+                                                         &self.options,
+                                                         file,
+                                                         content)?;
+
         // parse the file
         self.bar_set_message(Crowbar::Second, &lformat!("Parsing..."));
 
         let mut parser = Parser::from(self);
         parser.set_source_file(file);
         let mut tokens = parser.parse(&content)?;
-        self.features = self.features | parser.features();
+
+        // Apply typographic rules that depend on the paragraphs, e.g. for dialogues
+        self.cleaner.clean_tokens(&mut tokens);
 
         // transform the AST to make local links and images relative to `book` directory
         let offset = if let Some(f) = Path::new(file).parent() {
@@ -1004,9 +1263,18 @@ impl Book {
         // add offset
         ResourceHandler::add_offset(link_offset.as_ref(), image_offset.as_ref(), &mut tokens);
 
+        // Run external filters on the AST
+        let mut tokens = filter::apply(&self.options, &self.root, file, tokens)?;
+
+        // Filters can add or remove elements, so features are those of the filtered tokens
+        self.features = self.features | Features::of(&tokens);
+
         // Add a title if there is none in the chapter
         misc::insert_title(&mut tokens);
 
+        // Give an identifier to headers, so links to them don't depend on the renderer
+        self.add_header_ids(&mut tokens);
+
         // If one of the renderers requires it, perform grammarcheck
         if cfg!(feature = "proofread") && self.is_proofread() {
             let normalized = misc::normalize(file);
@@ -1049,7 +1317,9 @@ impl Book {
         }
         self.bar_set_message(Crowbar::Second, "");
 
-        self.chapters.push(Chapter::new(number, file, tokens));
+        let mut chapter = Chapter::new(number, file, tokens);
+        chapter.source = Some((content, lines));
+        self.chapters.push(chapter);
 
         Ok(self)
     }
@@ -1173,6 +1443,7 @@ impl Book {
             "html.if.js" => html_if::JS,
             "html.if.new_game" => html_if::NEW_GAME,
             "tex.template" => latex::TEMPLATE,
+            "cover.generate.template" => cover::SVG,
             _ => {
                 return Err(Error::config_parser(&self.source,
                                                 lformat!("invalid template '{template}'",
@@ -1232,7 +1503,22 @@ impl Book {
 
     /// Returns the string corresponding to a number, title, and the numbering template for chapter
     #[doc(hidden)]
-    pub fn get_header<F>(&self, header: Header, n: i32, title: String, mut f: F) -> Result<HeaderData>
+    pub fn get_header<F>(&self, header: Header, n: i32, title: String, f: F) -> Result<HeaderData>
+        where F: FnMut(&str) -> Result<String>
+    {
+        let number = self.get_header_number(header, n)?;
+        self.get_header_with_number(header, number, title, f)
+    }
+
+    /// Same as `get_header`, but with an already formatted number (e.g. a LaTeX
+    /// command displaying a counter)
+    #[doc(hidden)]
+    pub fn get_header_with_number<F>(&self,
+                                     header: Header,
+                                     number: String,
+                                     title: String,
+                                     mut f: F)
+                                     -> Result<HeaderData>
         where F: FnMut(&str) -> Result<String>
     {
         let header_type = match header {
@@ -1244,7 +1530,6 @@ impl Book {
             data = data.insert_bool(&format!("has_{}_title", header_type),
                                     true);
         }
-        let number = self.get_header_number(header, n)?;
         let header_name = self
             .options
             .get_str(&format!("rendering.{}", header_type))
@@ -1302,6 +1587,41 @@ impl Book {
         self.get_header(Header::Part, n, title, f)
     }
 
+    /// Returns the left, center and right parts of a running header or footer,
+    /// given the option describing it (e.g. `pdf.header.odd`).
+    ///
+    /// The option contains up to three mustache templates separated by `|` (a
+    /// single template is centered), rendered with `data`. Since `data` is already
+    /// in the output format, variables are never escaped, even with double braces.
+    #[doc(hidden)]
+    pub fn get_running_layout(&self, key: &str, data: &mustache::Data) -> Result<[String; 3]> {
+        let value = self.options.get_str(key).unwrap();
+        let parts: Vec<&str> = value.split('|').collect();
+        let parts = match parts.len() {
+            1 => ["", parts[0], ""],
+            2 => [parts[0], "", parts[1]],
+            3 => [parts[0], parts[1], parts[2]],
+            _ => {
+                return Err(Error::book_option(&self.source,
+                                              lformat!("{key}: expected at most three parts \
+                                                        separated by '|', found {n}",
+                                                       key = key,
+                                                       n = parts.len())));
+            }
+        };
+        let mut res = [String::new(), String::new(), String::new()];
+        for (i, part) in parts.iter().enumerate() {
+            let template = compile_str(&unescaped(part.trim()), &self.source, key)?;
+            let mut output: Vec<u8> = vec![];
+            template.render_data(&mut output, data)?;
+            res[i] = String::from_utf8(output)
+                .map_err(|_| Error::render(&self.source,
+                                           lformat!("{key}: generated header was not valid utf-8",
+                                                    key = key)))?;
+        }
+        Ok(res)
+    }
+
     /// Returns a `MapBuilder` (used by `Mustache` for templating), to be used (and completed)
     /// by renderers. It fills it with the metadata options.
     ///
@@ -1432,6 +1752,11 @@ impl Book {
                                                                    block = &yaml_block));
                                     }
                                     valid_block = true;
+                                    // Keep blank lines, so lines of the content match
+                                    // the ones of the source file
+                                    for _ in 0..yaml_block.lines().count() + 2 {
+                                        new_content.push_str("\n");
+                                    }
                                 }
                                 Err(err) => {
                                     error!("{}", lformat!("Found something that looked like a \
@@ -1451,6 +1776,7 @@ impl Book {
                     }
                     if !valid_block {
                         // Block was invalid, so add it to markdown content
+                        new_content.push_str("\n");
                         new_content.push_str(&yaml_block);
                         new_content.push_str("\n");
                     }
@@ -1470,12 +1796,39 @@ impl Book {
     }
 
 
+    // Returns the dialogue conventions to apply, if any
+    fn dialogue(&self) -> Option<Dialogue> {
+        let convention = match self.options.get_str("input.clean.dialogue").unwrap() {
+            "off" => None,
+            "auto" => Convention::from_lang(self.options.get_str("lang").unwrap()),
+            "french" => Some(Convention::French),
+            "spanish" => Some(Convention::Spanish),
+            "english" => Some(Convention::English),
+            value => {
+                error!("{}", lformat!("input.clean.dialogue set to '{value}', not a valid \
+                                       value (expected 'off', 'auto', 'french', 'spanish' or \
+                                       'english')",
+                                      value = value));
+                None
+            }
+        };
+        convention.map(|convention| {
+            let reopening = match self.options.get_str("input.clean.dialogue.reopening").unwrap() {
+                "auto" => Some(convention.reopening().to_owned()),
+                "none" => None,
+                value => Some(value.to_owned()),
+            };
+            Dialogue::new(convention, reopening)
+        })
+    }
+
     // Update the cleaner according to autoclean and lang options
     fn update_cleaner(&mut self) {
         let params = CleanerParams {
             smart_quotes: self.options.get_bool("input.clean.smart_quotes").unwrap(),
             ligature_dashes: self.options.get_bool("input.clean.ligature.dashes").unwrap(),
             ligature_guillemets: self.options.get_bool("input.clean.ligature.guillemets").unwrap(),
+            dialogue: self.dialogue(),
         };
         if self.options.get_bool("input.clean").unwrap() {
             let lang = self.options.get_str("lang").unwrap().to_lowercase();
@@ -1486,7 +1839,7 @@ impl Book {
             };
             self.cleaner = cleaner;
         } else {
-            self.cleaner = Box::new(Off);
+            self.cleaner = Box::new(Off::new(params.dialogue));
         }
     }
 }
@@ -1505,3 +1858,27 @@ pub fn compile_str<O>(template: &str, source: O, template_name: &str) -> Result<
                                                          error = err))),
     }
 }
+
+/// Turns the escaped variables (`{{x}}`) of a mustache template into unescaped ones
+/// (`{{&x}}`), leaving sections, comments and partials untouched
+fn unescaped(template: &str) -> String {
+    let mut res = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(i) = rest.find("{{") {
+        res.push_str(&rest[..i + 2]);
+        rest = &rest[i + 2..];
+        match rest.chars().next() {
+            Some('{') | Some('&') | Some('#') | Some('^') | Some('/') | Some('!') |
+            Some('>') | Some('=') | None => (),
+            _ => res.push('&'),
+        }
+    }
+    res.push_str(rest);
+    res
+}
+
+#[test]
+fn book_unescaped() {
+    assert_eq!(unescaped("{{page}} | {{{title}}} | {{#a}}{{ b }}{{/a}}"),
+               "{{&page}} | {{{title}}} | {{#a}}{{& b }}{{/a}}");
+}
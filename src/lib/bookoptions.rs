@@ -35,6 +35,7 @@ output.tex:path                     # {output_tex}
 output.pdf:path                     # {output_pdf}
 output.odt:path                     # {output_odt}
 output.html.if:path                 # {output_if}
+output.json:path                    # {output_json}
 output.base_path:path:\"\"            # {output_base_path}
 
 # {render_opt}
@@ -52,6 +53,11 @@ rendering.part.reset_counter:bool:true                                      # {r
 rendering.chapter.template:str:\"{{{{{{number}}}}}}. {{{{{{chapter_title}}}}}}\" # {chapter_template}
 
 rendering.part.template:str:\"{{{{{{number}}}}}}. {{{{{{part_title}}}}}}\" # {part_template}
+rendering.notes.placement:str:auto                                   # {notes_placement}
+rendering.verse.line_numbers:int:0                                   # {verse_line_numbers}
+rendering.scene_break:str:***                                        # {scene_break}
+rendering.scene_break.image:path                                     # {scene_break_image}
+rendering.scene_break.command:str:rsvg-convert                       # {scene_break_command}
 
 
 
@@ -59,6 +65,14 @@ rendering.part.template:str:\"{{{{{{number}}}}}}. {{{{{{part_title}}}}}}\" # {pa
 # {special_ops}
 import:path                  # {import_config}
 
+# {cover_opt}
+cover.generate:bool:false                   # {cover_generate}
+cover.generate.background:str:\"#2c3e50\"   # {cover_background}
+cover.generate.foreground:str:\"#ffffff\"   # {cover_foreground}
+cover.generate.pattern:bool:false           # {cover_pattern}
+cover.generate.template:tpl                 # {cover_template}
+cover.generate.command:str:rsvg-convert     # {cover_command}
+
 # {html_opt}
 html.icon:path                      # {html_icon}
 html.highlight.theme:str            # {html_theme}
@@ -73,8 +87,14 @@ html.highlight.js:tpl               # {highlight_js}
 html.highlight.css:tpl              # {highlight_css}
 html.side_notes:bool:false          # {side_notes}
 html.escape_nb_spaces:bool:true     # {nb_spaces}
-html.chapter.template:str:\"<h1 id = 'link-{{{{{{link}}}}}}'>{{{{#has_number}}}}<span class = 'chapter-header'>{{{{{{header}}}}}} {{{{{{number}}}}}}</span>{{{{#has_title}}}}<br />{{{{/has_title}}}}{{{{/has_number}}}}{{{{{{title}}}}}}</h1>\" # {html_chapter_template}
-html.part.template:str:\"<h2 class = 'part'>{{{{{{header}}}}}} {{{{{{number}}}}}}</h2> <h1 id = 'link-{{{{{{link}}}}}}' class = 'part'>{{{{{{title}}}}}}</h1>\" # {html_part_template}
+html.hyphenate:bool:false           # {hyphenate}
+html.hyphenate.min_length:int:6     # {hyphenate_min_length}
+html.hyphenate.left_min:int:2       # {hyphenate_left_min}
+html.hyphenate.right_min:int:3      # {hyphenate_right_min}
+html.split.level:int:0              # {html_split_level}
+html.split.size:int:0               # {html_split_size}
+html.chapter.template:str:\"<h1 id = '{{{{{{id}}}}}}'>{{{{#has_number}}}}<span class = 'chapter-header'>{{{{{{header}}}}}} {{{{{{number}}}}}}</span>{{{{#has_title}}}}<br />{{{{/has_title}}}}{{{{/has_number}}}}{{{{{{title}}}}}}</h1>\" # {html_chapter_template}
+html.part.template:str:\"<h2 class = 'part'>{{{{{{header}}}}}} {{{{{{number}}}}}}</h2> <h1 id = '{{{{{{id}}}}}}' class = 'part'>{{{{{{title}}}}}}</h1>\" # {html_part_template}
 
 # {html_single_opt}
 html.standalone.template:tpl                # {single_html}
@@ -99,11 +119,19 @@ epub.css.add:str                    # {epub_css_add}
 epub.chapter.xhtml:tpl              # {chapter_xhtml}
 epub.toc.extras:bool:true           # {epub_toc}
 epub.escape_nb_spaces:bool:true     # {nb_spaces}
+epub.hyphenate:bool:false           # {hyphenate}
+epub.hyphenate.min_length:int       # {epub_hyphenate_min_length}
+epub.hyphenate.left_min:int         # {epub_hyphenate_left_min}
+epub.hyphenate.right_min:int        # {epub_hyphenate_right_min}
 
 # {tex_opt}
 tex.highlight.theme:str             # {tex_theme}
 tex.links_as_footnotes:bool:true    # {tex_links}
 tex.command:str:xelatex             # {tex_command}
+tex.latexmk:bool:false              # {tex_latexmk}
+tex.bibliography.command:str:auto   # {tex_bib_command}
+tex.index.command:str:makeindex     # {tex_index_command}
+tex.max_passes:int:5                # {tex_max_passes}
 tex.template:tpl                    # {tex_tmpl}
 tex.template.add:str                # {tex_tmpl_add}
 tex.class:str:book                  # {tex_class}
@@ -116,6 +144,24 @@ tex.title:bool:true                 # {tex_title}
 tex.font.size:int                   # {tex_font_size}
 tex.hyperref:bool:true              # {tex_hyperref}
 tex.stdpage:bool:false              # {tex_stdpage}
+tex.environments:strvec:[center, right=flushright, left=flushleft] # {tex_environments}
+tex.keep_build_dir:bool:false       # {tex_keep_build_dir}
+tex.print.preset:str                # {tex_print_preset}
+tex.print.bleed:str                 # {tex_print_bleed}
+tex.print.crop_marks:bool:false     # {tex_print_crop_marks}
+tex.print.page_count:int            # {tex_print_page_count}
+tex.print.pdfx:str                  # {tex_print_pdfx}
+
+# {pdf_opt}
+pdf.engine:str:latex                # {pdf_engine}
+pdf.paper.size:str:a5               # {pdf_paper_size}
+pdf.font.size:float:11.0            # {pdf_font_size}
+pdf.margin:float:15.0               # {pdf_margin}
+pdf.header.odd:str:\"{{{{{{page}}}}}}\"  # {pdf_header_odd}
+pdf.header.even:str:\"{{{{{{page}}}}}}\" # {pdf_header_even}
+pdf.footer.odd:str:\"\"               # {pdf_footer_odd}
+pdf.footer.even:str:\"\"              # {pdf_footer_even}
+pdf.header.chapter_pages:bool:false # {pdf_header_chapter_pages}
 
 
 # {rs_opt}
@@ -132,7 +178,15 @@ input.clean:bool:true               # {autoclean}
 input.clean.smart_quotes:bool:true  # {smart_quotes}
 input.clean.ligature.dashes:bool:false # {ligature_dashes}
 input.clean.ligature.guillemets:bool:false # {ligature_guillemets}
+input.clean.dialogue:str:off        # {dialogue}
+input.clean.dialogue.reopening:str:auto # {dialogue_reopening}
 input.yaml_blocks:bool:false        # {yaml}
+input.variables:bool:false          # {variables}
+filters:strvec                      # {filters}
+filters.html:strvec                 # {filters_html}
+filters.epub:strvec                 # {filters_epub}
+filters.tex:strvec                  # {filters_tex}
+filters.odt:strvec                  # {filters_odt}
 
 
 # {crowbook_opt}
@@ -140,6 +194,7 @@ crowbook.html_as_text:bool:true     # {html_as_text}
 crowbook.markdown.superscript:bool:false  # {superscript}
 crowbook.temp_dir:path:             # {tmp_dir}
 crowbook.zip.command:str:zip        # {zip}
+crowbook.reproducible:bool:false    # {reproducible}
 
 # {prf_opt}
 output.proofread.html:path          # {prf_html}
@@ -208,12 +263,16 @@ crowbook.verbose:alias                              # {removed}
                                          output = lformat!("Specify a list of output formats to render"),
                                          render_opt = lformat!("Rendering options"),
                                          special_ops = lformat!("Special option"),
+                                         cover_opt = lformat!("Cover generation options"),
                                          html_opt = lformat!("HTML options"),
+                                         html_split_level = lformat!("If set to a value greater than 0, split chapters into multiple files at each header of this level or less (EPUB and multifile HTML only)"),
+                                         html_split_size = lformat!("If set to a value greater than 0, split chapters into multiple files when a file would contain more than this number of characters (EPUB and multifile HTML only)"),
                                          html_single_opt = lformat!("Standalone HTML options"),
                                          html_dir_opt = lformat!("Multifile HTML options"),
                                          html_if_opt = lformat!("Interactive fiction HTML options"),
                                          epub_opt = lformat!("EPUB options"),
                                          tex_opt = lformat!("LaTeX options"),
+                                         pdf_opt = lformat!("PDF options"),
                                          rs_opt = lformat!("Resources option"),
                                          input_opt = lformat!("Input options"),
                                          crowbook_opt = lformat!("Crowbook options"),
@@ -227,6 +286,12 @@ crowbook.verbose:alias                              # {removed}
                                          subject = lformat!("Subject of the book (used for EPUB metadata)"),
                                          description = lformat!("Description of the book (used for EPUB metadata)"),
                                          cover = lformat!("Path to the cover of the book"),
+                                         cover_generate = lformat!("If set to true and no cover is specified, generate a cover from the title, subtitle and author of the book"),
+                                         cover_background = lformat!("Background colour of the generated cover"),
+                                         cover_foreground = lformat!("Text colour of the generated cover"),
+                                         cover_pattern = lformat!("If set to true, draw a striped pattern on the background of the generated cover"),
+                                         cover_template = lformat!("SVG template of the generated cover"),
+                                         cover_command = lformat!("Command used to convert the generated SVG cover to PNG (called with '-o cover.png cover.svg'). If it fails, only the SVG version is used"),
 
                                          subtitle = lformat!("Subtitle of the book"),
                                          license = lformat!("License of the book. This information will be displayed on PDF documents"),
@@ -240,6 +305,7 @@ crowbook.verbose:alias                              # {removed}
                                          output_pdf = lformat!("Output file name for PDF rendering"),
                                          output_odt = lformat!("Output file name for ODT rendering"),
                                          output_if = lformat!("Output file name for HTML (interactive fiction) rendering"),
+                                         output_json = lformat!("Output file name for JSON rendering of the book's AST"),
                                          output_html_dir = lformat!("Output directory name for HTML rendering"),
                                          output_base_path = lformat!("Directory where those output files will we written"),
 
@@ -269,8 +335,20 @@ crowbook.verbose:alias                              # {removed}
                                          css_print = lformat!("Path of a media print stylesheet for HTML rendering"),
                                          highlight_js = lformat!("Set another highlight.js version than the bundled one"),
                                          highlight_css = lformat!("Set another highlight.js CSS theme than the default one"),
+                                         notes_placement = lformat!("Where to place footnotes: \"auto\" (each format's usual placement), \"page\" (at the bottom of the page; at the end of the chapter in HTML and EPUB), \"chapter\" (at the end of each chapter), \"book\" (in a Notes chapter at the end of the book, grouped by chapter) or \"side\" (in the margin)"),
+                                         scene_break = lformat!("Text displayed for scene breaks (horizontal rules), e.g. '* * *' for a dinkus, '⁂' or '❦'"),
+                                         scene_break_image = lformat!("Image (e.g. an SVG ornament) displayed for scene breaks instead of the text of rendering.scene_break"),
+                                         scene_break_command = lformat!("Command used to convert an SVG scene break image to PDF for LaTeX (called with '-f pdf -o images/scene_break.pdf images/scene_break.svg')"),
+                                         verse_line_numbers = lformat!("Number the lines of verse blocks every N lines (0 to disable it); a block can override it with ```verse N"),
                                          side_notes = lformat!("Display footnotes as side notes in HTML/Epub (experimental)"),
                                          nb_spaces = lformat!("Replace unicode non breaking spaces with HTML entities and CSS"),
+                                         hyphenate = lformat!("Insert soft hyphens in words, according to the book's language, so reading software can hyphenate them"),
+                                         hyphenate_min_length = lformat!("Minimal length of a word for it to be hyphenated (HTML, and EPUB unless epub.hyphenate.min_length is set)"),
+                                         hyphenate_left_min = lformat!("Minimal number of characters before a soft hyphen (HTML, and EPUB unless epub.hyphenate.left_min is set)"),
+                                         hyphenate_right_min = lformat!("Minimal number of characters after a soft hyphen (HTML, and EPUB unless epub.hyphenate.right_min is set)"),
+                                         epub_hyphenate_min_length = lformat!("Minimal length of a word for it to be hyphenated in EPUB (default: html.hyphenate.min_length)"),
+                                         epub_hyphenate_left_min = lformat!("Minimal number of characters before a soft hyphen in EPUB (default: html.hyphenate.left_min)"),
+                                         epub_hyphenate_right_min = lformat!("Minimal number of characters after a soft hyphen in EPUB (default: html.hyphenate.right_min)"),
                                          
                                          one_chapter = lformat!("Display only one chapter at a time (with a button to display all)"),
                                          single_html = lformat!("Path of an HTML template for standalone HTML"),
@@ -292,6 +370,10 @@ crowbook.verbose:alias                              # {removed}
                                          
                                          tex_links = lformat!("Add foontotes to URL of links so they are readable when printed"),
                                          tex_command = lformat!("LaTeX command to use for generating PDF"),
+                                         tex_latexmk = lformat!("If set to true, use latexmk to run the LaTeX command and auxiliary tools instead of Crowbook's own passes"),
+                                         tex_bib_command = lformat!("Command used to process the bibliography if the document has one: 'biber', 'bibtex', or 'auto' to detect it"),
+                                         tex_index_command = lformat!("Command used to process the index if the document has one, e.g. 'makeindex' or 'texindy'"),
+                                         tex_max_passes = lformat!("Maximum number of times the LaTeX command is run until cross-references are stable"),
                                          tex_tmpl = lformat!("Path of a LaTeX template file"),
                                          tex_tmpl_add = lformat!("Inline code added in the LaTeX template"),
                                          tex_class = lformat!("LaTeX class to use"),
@@ -304,6 +386,23 @@ crowbook.verbose:alias                              # {removed}
                                          tex_font_size = lformat!("Specify latex font size (in pt, 10 (default), 11, or 12 are accepted)"),
                                          tex_hyperref = lformat!("If disabled, don't try to find references inside the document"),
                                          tex_stdpage = lformat!("If set to true, use 'stdpage' package to format a manuscript according to standards"),
+                                         tex_environments = lformat!("LaTeX environments used for paragraphs and images with a class attribute, as a list of 'class=environment' (or just 'class' if the environment has the same name)"),
+                                         tex_keep_build_dir = lformat!("If set to true, don't delete the temporary directory where LaTeX is run, so generated files (result.tex, result.log, ...) can be inspected"),
+                                         tex_print_preset = lformat!("Print-on-demand preset setting the trim size and mirrored margins: 'pocket', '5x8', '5.25x8', '5.5x8.5', '6x9', 'a5', 'royal' or 'a4', optionally followed by '-bleed' (e.g. 'a5-bleed'). Overrides tex.paper.size and left/right margins"),
+                                         tex_print_bleed = lformat!("Bleed added to the outer edge, top and bottom of pages with a print preset (e.g. '0.125in' or '3mm'). Defaults to 0.125in for '-bleed' presets, none else"),
+                                         tex_print_crop_marks = lformat!("If set to true, add crop marks around pages with a print preset"),
+                                         tex_print_page_count = lformat!("Number of pages of the printed book, used to compute the gutter (inner margin) with a print preset. If not set, it is estimated from the number of words"),
+                                         tex_print_pdfx = lformat!("If set, generate a PDF conforming to this standard (e.g. 'x-1a' or 'x-3') using the pdfx package, as required by some printers"),
+
+                                         pdf_engine = lformat!("Engine used to generate PDF files: 'latex' (default, requires a LaTeX installation) or 'native' (simpler layout, no external dependency, only supports Latin-1 characters and languages using them)"),
+                                         pdf_paper_size = lformat!("Paper size for the native PDF renderer: 'a4', 'a5', 'a6', 'letter' or 'legal'"),
+                                         pdf_font_size = lformat!("Base font size (in pt) for the native PDF renderer"),
+                                         pdf_margin = lformat!("Margins (in mm) for the native PDF renderer"),
+                                         pdf_header_odd = lformat!("Running header of odd (right) pages: up to three templates (left|center|right) that can use the mustache variables title, author, part, chapter and page"),
+                                         pdf_header_even = lformat!("Running header of even (left) pages, see pdf.header.odd"),
+                                         pdf_footer_odd = lformat!("Running footer of odd (right) pages, see pdf.header.odd"),
+                                         pdf_footer_even = lformat!("Running footer of even (left) pages, see pdf.header.odd"),
+                                         pdf_header_chapter_pages = lformat!("If set to true, also display running headers and footers on pages where a chapter begins (by default, these pages only display the page number)"),
                                          
                                          rs_files = lformat!("Whitespace-separated list of files to embed in e.g. EPUB file; useful for including e.g. fonts"),
                                          rs_out = lformat!("Paths where additional resources should be copied in the EPUB file or HTML directory"),
@@ -317,11 +416,20 @@ crowbook.verbose:alias                              # {removed}
                                          smart_quotes = lformat!("If enabled, tries to replace vertical quotations marks to curly ones"),
                                          ligature_dashes = lformat!("If enabled, replaces '--' to en dash ('–') and '---' to em dash ('—')"),
                                          ligature_guillemets = lformat!("If enabled, replaces '<<' and '>>' to french \"guillemets\" ('«' and '»')"),
+                                         dialogue = lformat!("Typographic conventions for dialogues: \"off\", \"auto\" (according to lang), \"french\" (dashes followed by a non-breaking space, « » quotes), \"spanish\" (dashes attached to the words, « » quotes) or \"english\" (“ ” quotes with ‘ ’ inside)"),
+                                         dialogue_reopening = lformat!("Mark starting paragraphs where a quote continues from the previous one (e.g. '«' or '»'): \"auto\" (according to the dialogue conventions) or \"none\" to remove it"),
                                          superscript = lformat!("If enabled, allow support for superscript and subscript using respectively foo^up^  and bar~down~ syntax."),
                                          yaml = lformat!("Enable inline YAML blocks to override options set in config file"),
+                                         variables = lformat!("If set to true, replace variables used in chapters by their value (see the vars section)"),
+                                         filters = lformat!("List of commands run on the AST of each chapter, receiving it as JSON on their standard input and writing the modified one on their standard output"),
+                                         filters_html = lformat!("List of filters only used for HTML output, run after the ones of 'filters'"),
+                                         filters_epub = lformat!("List of filters only used for EPUB output, run after the ones of 'filters'"),
+                                         filters_tex = lformat!("List of filters only used for LaTeX and PDF output, run after the ones of 'filters'"),
+                                         filters_odt = lformat!("List of filters only used for ODT output, run after the ones of 'filters'"),
                                          html_as_text = lformat!("Consider HTML blocks as text. This avoids having <foo> being considered as HTML and thus ignored."),
                                          tmp_dir = lformat!("Path where to create a temporary directory (default: uses result from Rust's std::env::temp_dir())"),
                                          zip = lformat!("Command to use to zip files (for EPUB/ODT)"),
+                                         reproducible = lformat!("If set to true, generate byte-identical files across builds (timestamps are read from SOURCE_DATE_EPOCH if it is set, which also enables this option)"),
                                          
                                          prf_html = lformat!("Output file name for HTML rendering with proofread features"),
                                          prf_html_dir = lformat!("Output directory name for HTML rendering with proofread features"),
@@ -640,6 +748,42 @@ impl BookOptions {
                                                 &key,
                                                 &value)))
             }
+        } else if key == "vars" {
+            // a section of variables, equivalent to setting `vars.name` for each of them
+            if let Yaml::Hash(hash) = value {
+                for (name, value) in hash {
+                    let name = if let Yaml::String(name) = name {
+                        name
+                    } else {
+                        return Err(Error::book_option(&self.source,
+                                                      lformat!("Expected a String as a key, \
+                                                                found {:?}",
+                                                               name)));
+                    };
+                    self.set_yaml(Yaml::String(format!("vars.{}", name)), value)?;
+                }
+                Ok(None)
+            } else {
+                Err(Error::book_option(&self.source,
+                                       lformat!("expected a list of variables as value for key \
+                                                 'vars', found {:?}",
+                                                &value)))
+            }
+        } else if key.starts_with("vars.") {
+            // key is a variable that can be used in chapters
+            let value = match value {
+                Yaml::String(value) | Yaml::Real(value) => value,
+                Yaml::Integer(value) => format!("{}", value),
+                Yaml::Boolean(value) => format!("{}", value),
+                value => {
+                    return Err(Error::book_option(&self.source,
+                                                  lformat!("expected a string as value for key \
+                                                            '{}', found {:?}",
+                                                           &key,
+                                                           &value)));
+                }
+            };
+            Ok(self.options.insert(key, BookOption::String(value)))
         } else {
             // key not recognized
             Err(Error::book_option(self.source.clone(),
@@ -772,7 +916,8 @@ impl BookOptions {
             "output.proofread.html" |
             "output.proofread.html.dir" |
             "output.proofread.pdf" |
-            "output.html.if" => {
+            "output.html.if" |
+            "output.json" => {
                 // Translate according to output.base_path
                 let base = self.get_path("output.base_path").unwrap();
                 Path::new(&base).join(path)
@@ -838,6 +983,18 @@ impl BookOptions {
         self.get(key)?.as_f32()
     }
 
+    /// Returns the options that are set to something else than their default value,
+    /// sorted by key
+    #[doc(hidden)]
+    pub fn get_modified(&self) -> Vec<(&str, &BookOption)> {
+        let mut modified: Vec<_> = self.options
+            .iter()
+            .filter(|&(key, value)| self.defaults.get(key) != Some(value))
+            .map(|(key, value)| (key.as_str(), value))
+            .collect();
+        modified.sort_by(|a, b| a.0.cmp(b.0));
+        modified
+    }
 
     /// Merges the other list of options to the first one
     ///
@@ -17,9 +17,10 @@
 
 use number::Number;
 use token::Token;
+use error::Source;
 
 /// Represents the content of a chapter.
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Chapter {
     /// The numbering scheme of this chapter.
     pub number: Number,
@@ -27,6 +28,11 @@ pub struct Chapter {
     pub filename: String,
     /// The (already parsed) content of this chapter
     pub content: Vec<Token>,
+    /// The Markdown source of this chapter, once includes are resolved, and the
+    /// source (file and line) of each of its lines
+    #[doc(hidden)]
+    #[serde(skip)]
+    pub source: Option<(String, Vec<Source>)>,
 }
 
 impl Chapter {
@@ -41,6 +47,7 @@ impl Chapter {
             number: number,
             filename: filename.into(),
             content: content,
+            source: None,
         }
     }
 }
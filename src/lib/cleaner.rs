@@ -21,12 +21,17 @@ use std::borrow::Cow;
 use crowbook_text_processing::clean;
 use crowbook_text_processing::FrenchFormatter;
 
+use token::Token;
+use dialogue::Dialogue;
+
 
 /// Contains cleaning parameters
 pub struct CleanerParams {
     pub smart_quotes: bool,
     pub ligature_guillemets: bool,
     pub ligature_dashes: bool,
+    /// Conventions for dialogues, if they must be applied
+    pub dialogue: Option<Dialogue>,
 }
 
 /// Trait for cleaning a string.
@@ -43,11 +48,45 @@ pub trait Cleaner: Sync {
     fn clean<'a>(&self, str: Cow<'a, str>, _latex: bool) -> Cow<'a, str> {
         str
     }
+
+    /// Applies rules that depend on the paragraph a text is in (or on the previous ones),
+    /// e.g. for dialogues. The default implementation does nothing.
+    ///
+    /// This is called once on the AST of each chapter, before `clean` is called by
+    /// the renderers.
+    fn clean_tokens(&self, _tokens: &mut [Token]) {}
+}
+
+/// Cleaner implementation that leaves text alone, except for the dialogue
+/// conventions if they are set
+pub struct Off {
+    dialogue: Option<Dialogue>,
+}
+
+impl Off {
+    /// New Off cleaner
+    pub fn new(dialogue: Option<Dialogue>) -> Off {
+        Off {
+            dialogue: dialogue,
+        }
+    }
 }
 
-/// Cleaner implementation that does nothing
-pub struct Off;
-impl Cleaner for Off {}
+impl Cleaner for Off {
+    fn clean<'a>(&self, s: Cow<'a, str>, latex: bool) -> Cow<'a, str> {
+        if latex && self.dialogue.is_some() {
+            nb_spaces_tex(s)
+        } else {
+            s
+        }
+    }
+
+    fn clean_tokens(&self, tokens: &mut [Token]) {
+        if let Some(ref dialogue) = self.dialogue {
+            dialogue.apply(tokens);
+        }
+    }
+}
 
 /// Default implementation of cleaner trait.
 ///
@@ -67,7 +106,7 @@ impl Default {
     
 impl Cleaner for Default {
     /// Remove unnecessary whitespaces
-    fn clean<'a>(&self, input: Cow<'a, str>, _: bool) -> Cow<'a, str> {
+    fn clean<'a>(&self, input: Cow<'a, str>, latex: bool) -> Cow<'a, str> {
         let mut s = clean::whitespaces(input);
         if self.params.smart_quotes {
             s = clean::quotes(s);
@@ -78,8 +117,17 @@ impl Cleaner for Default {
         if self.params.ligature_guillemets {
             s = clean::guillemets(s);
         }
+        if latex && self.params.dialogue.is_some() {
+            s = nb_spaces_tex(s);
+        }
         s
     }
+
+    fn clean_tokens(&self, tokens: &mut [Token]) {
+        if let Some(ref dialogue) = self.params.dialogue {
+            dialogue.apply(tokens);
+        }
+    }
 }
 
 /// Implementation for french 'cleaning'
@@ -109,10 +157,28 @@ impl French {
 impl Cleaner for French {
     /// Puts non breaking spaces before/after `:`, `;`, `?`, `!`, `«`, `»`, `—`
     fn clean<'a>(&self, s: Cow<'a, str>, latex: bool) -> Cow<'a, str> {
-        if latex {
+        if latex && self.params.dialogue.is_some() {
+            nb_spaces_tex(self.formatter.format_tex(s))
+        } else if latex {
             self.formatter.format_tex(s)
         } else {
             self.formatter.format(s)
         }
     }
+
+    fn clean_tokens(&self, tokens: &mut [Token]) {
+        if let Some(ref dialogue) = self.params.dialogue {
+            dialogue.apply(tokens);
+        }
+    }
+}
+
+/// Replaces non-breaking spaces with `~` for LaTeX output. Only used when dialogues
+/// are formatted, so the default output is left unchanged
+fn nb_spaces_tex<'a>(s: Cow<'a, str>) -> Cow<'a, str> {
+    if s.contains('\u{A0}') {
+        Cow::Owned(s.replace('\u{A0}', "~"))
+    } else {
+        s
+    }
 }
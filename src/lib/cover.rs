@@ -0,0 +1,190 @@
+// Copyright (C) 2017 Élisabeth HENRY.
+//
+// This file is part of Crowbook.
+//
+// Crowbook is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published
+// by the Free Software Foundation, either version 2.1 of the License, or
+// (at your option) any later version.
+//
+// Crowbook is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Generation of a typographic cover from the book's metadata, used
+//! when the `cover.generate` option is set and no `cover` is given.
+
+use book::{Book, compile_str};
+use error::{Error, Result};
+use parser::Parser;
+use text_view::view_as_text;
+use zipper::Zipper;
+
+use std::process::Command;
+
+/// Maximum number of characters on a line of the title
+const TITLE_LINE_LENGTH: usize = 18;
+
+/// A cover generated from the book's metadata
+pub struct GeneratedCover {
+    /// Content of the SVG cover
+    pub svg: String,
+    /// PNG version of the cover, if it could be generated
+    pub png: Option<Vec<u8>>,
+}
+
+impl GeneratedCover {
+    /// Generates the cover for a book, or returns `None` if the book
+    /// has a `cover` or `cover.generate` is not set.
+    pub fn new(book: &Book) -> Result<Option<GeneratedCover>> {
+        if !is_enabled(book) {
+            return Ok(None);
+        }
+        let svg = render_svg(book)?;
+        let png = match render_png(book, &svg) {
+            Ok(png) => Some(png),
+            Err(err) => {
+                warn!("{}", lformat!("could not convert generated cover to PNG, only using SVG: {error}",
+                                     error = err));
+                None
+            }
+        };
+        Ok(Some(GeneratedCover {
+            svg: svg,
+            png: png,
+        }))
+    }
+
+    /// Returns the file name the cover should be written to
+    ///
+    /// The PNG version is preferred, since not all readers support SVG covers.
+    pub fn file_name(&self) -> &'static str {
+        if self.png.is_some() {
+            "cover.png"
+        } else {
+            "cover.svg"
+        }
+    }
+
+    /// Returns the MIME type of the file returned by `content`
+    pub fn mime_type(&self) -> &'static str {
+        if self.png.is_some() {
+            "image/png"
+        } else {
+            "image/svg+xml"
+        }
+    }
+
+    /// Returns the content of the preferred version of the cover
+    pub fn content(&self) -> &[u8] {
+        match self.png {
+            Some(ref png) => png,
+            None => self.svg.as_bytes(),
+        }
+    }
+}
+
+/// Returns true if a cover must be generated for this book
+pub fn is_enabled(book: &Book) -> bool {
+    book.options.get_path("cover").is_err() && book.options.get_bool("cover.generate").unwrap()
+}
+
+/// Reads a colour option and returns it as six hexadecimal digits, without the leading '#'
+pub fn colour(book: &Book, key: &str) -> Result<String> {
+    let value = book.options.get_str(key).unwrap();
+    let hex = value.trim_left_matches('#');
+    let hex = if hex.len() == 3 {
+        hex.chars().flat_map(|c| vec![c, c]).collect::<String>()
+    } else {
+        hex.to_owned()
+    };
+    if hex.len() != 6 || !hex.chars().all(|c| c.is_digit(16)) {
+        return Err(Error::book_option(&book.source,
+                                      lformat!("invalid colour '{value}' for option '{key}', \
+                                                expected e.g. '#2c3e50'",
+                                               value = value,
+                                               key = key)));
+    }
+    Ok(hex.to_lowercase())
+}
+
+/// Split a title into lines of at most `TITLE_LINE_LENGTH` characters (unless a word is longer)
+fn split_title(title: &str) -> Vec<String> {
+    let mut lines = vec![];
+    let mut current = String::new();
+    for word in title.split_whitespace() {
+        if !current.is_empty() &&
+            current.chars().count() + word.chars().count() + 1 > TITLE_LINE_LENGTH {
+            lines.push(current);
+            current = String::new();
+        }
+        if !current.is_empty() {
+            current.push(' ');
+        }
+        current.push_str(word);
+    }
+    if !current.is_empty() {
+        lines.push(current);
+    }
+    lines
+}
+
+/// Renders the SVG cover using the `cover.generate.template` template
+fn render_svg(book: &Book) -> Result<String> {
+    let title = view_as_text(&Parser::new().parse_inline(book.options.get_str("title").unwrap())?);
+    let lines = split_title(&title);
+    let title_size = if lines.len() > 4 { 110 } else { 150 };
+    let line_height = title_size * 6 / 5;
+    let first_y = 1280 - (line_height * lines.len() as i32) / 2 + line_height / 2;
+    let subtitle_y = first_y + line_height * lines.len() as i32 + 60;
+
+    let template = compile_str(book.get_template("cover.generate.template")?.as_ref(),
+                               &book.source,
+                               "cover.generate.template")?;
+    let data = book.get_metadata(|s| Ok(s.to_owned()))?
+        .insert_str("background", colour(book, "cover.generate.background")?)
+        .insert_str("foreground", colour(book, "cover.generate.foreground")?)
+        .insert_bool("pattern", book.options.get_bool("cover.generate.pattern").unwrap())
+        .insert_str("title_size", format!("{}", title_size))
+        .insert_str("subtitle_y", format!("{}", subtitle_y))
+        .insert_vec("title_lines", |mut builder| {
+            for (i, line) in lines.iter().enumerate() {
+                let y = first_y + line_height * i as i32;
+                builder = builder.push_map(|map| {
+                    map.insert_str("text", line.as_str())
+                        .insert_str("y", format!("{}", y))
+                });
+            }
+            builder
+        })
+        .build();
+    let mut res = vec![];
+    template.render_data(&mut res, &data)?;
+    match String::from_utf8(res) {
+        Err(_) => panic!(lformat!("generated SVG for cover was not utf-8 valid")),
+        Ok(res) => Ok(res),
+    }
+}
+
+/// Converts the SVG cover to PNG using `cover.generate.command`
+fn render_png(book: &Book, svg: &str) -> Result<Vec<u8>> {
+    let command_name = book.options.get_str("cover.generate.command").unwrap();
+    let mut zipper = Zipper::new(&book.options.get_path("crowbook.temp_dir").unwrap())?;
+    zipper.write("cover.svg", svg.as_bytes(), false)?;
+    let mut command = Command::new(command_name);
+    command.args(&["-o", "cover.png", "cover.svg"]);
+    let mut png = vec![];
+    zipper.run_command(command, command_name, "cover.png", &mut png)?;
+    Ok(png)
+}
+
+#[test]
+fn split_title_lines() {
+    assert_eq!(split_title("A short title"), vec!["A short title"]);
+    assert_eq!(split_title("The very long title of a very long book"),
+               vec!["The very long", "title of a very", "long book"]);
+}
@@ -0,0 +1,390 @@
+// Copyright (C) 2017 Élisabeth HENRY.
+//
+// This file is part of Crowbook.
+//
+// Crowbook is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published
+// by the Free Software Foundation, either version 2.1 of the License, or
+// (at your option) any later version.
+//
+// Crowbook is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Typographic rules for dialogues in fiction (dialogue dashes and quotes).
+//!
+//! Contrary to the `Cleaner`, which works on isolated strings, these rules
+//! need to know where a paragraph starts and whether a quote is still open
+//! at the end of the previous one, so they are applied on the AST.
+
+use token::Token;
+
+/// Non-breaking space
+const NB_SPACE: char = '\u{A0}';
+
+/// Typographic conventions for dialogues
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Convention {
+    /// Dialogue dashes followed by a non-breaking space, « » quotes with “ ” inside
+    French,
+    /// Dialogue dashes attached to the words, « » quotes with “ ” then ‘ ’ inside
+    Spanish,
+    /// “ ” quotes with ‘ ’ inside, dashes are left alone
+    English,
+}
+
+impl Convention {
+    /// Returns the convention usually used for a language, if any
+    pub fn from_lang(lang: &str) -> Option<Convention> {
+        let lang = lang.to_lowercase();
+        if lang.starts_with("fr") {
+            Some(Convention::French)
+        } else if lang.starts_with("es") {
+            Some(Convention::Spanish)
+        } else if lang.starts_with("en") {
+            Some(Convention::English)
+        } else {
+            None
+        }
+    }
+
+    /// Opening and closing marks for each level of nested quotes
+    fn quotes(&self) -> &'static [(char, char)] {
+        match *self {
+            Convention::French => &[('«', '»'), ('“', '”')],
+            Convention::Spanish => &[('«', '»'), ('“', '”'), ('‘', '’')],
+            Convention::English => &[('“', '”'), ('‘', '’')],
+        }
+    }
+
+    /// Mark starting a paragraph when a quote continues from the previous one
+    pub fn reopening(&self) -> &'static str {
+        match *self {
+            Convention::French => "«",
+            Convention::Spanish => "»",
+            Convention::English => "“",
+        }
+    }
+}
+
+/// Applies dialogue conventions to the paragraphs of a chapter
+pub struct Dialogue {
+    convention: Convention,
+    reopening: Option<String>,
+}
+
+impl Dialogue {
+    /// Creates a new dialogue pass.
+    ///
+    /// `reopening` is the mark that starts paragraphs where a quote opened in a previous
+    /// paragraph continues (`None` to remove it).
+    pub fn new(convention: Convention, reopening: Option<String>) -> Dialogue {
+        Dialogue {
+            convention: convention,
+            reopening: reopening,
+        }
+    }
+
+    /// Applies dialogue conventions to a list of (block) tokens
+    pub fn apply(&self, tokens: &mut [Token]) {
+        let mut open = false;
+        self.apply_blocks(tokens, &mut open);
+    }
+
+    fn apply_blocks(&self, tokens: &mut [Token], open: &mut bool) {
+        for token in tokens.iter_mut() {
+            match *token {
+                Token::Paragraph(ref mut inner) => *open = self.apply_paragraph(inner, *open),
+                Token::Header(..) | Token::Rule | Token::Footnote(..) => *open = false,
+                ref mut token => {
+                    if token.is_code() {
+                        continue;
+                    }
+                    if let Some(inner) = token.inner_mut() {
+                        self.apply_blocks(inner, open);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Applies dialogue conventions to the content of a paragraph, and returns
+    /// whether a quote is still open at its end
+    fn apply_paragraph(&self, tokens: &mut [Token], continued: bool) -> bool {
+        let mut texts = vec![];
+        collect_texts(tokens, &mut texts);
+        let mut chars = vec![];
+        let mut owners = vec![];
+        for (i, text) in texts.iter().enumerate() {
+            match *text {
+                Some(ref s) => {
+                    for c in s.chars() {
+                        chars.push(c);
+                        owners.push(i);
+                    }
+                }
+                None => {
+                    chars.push('\n');
+                    owners.push(i);
+                }
+            }
+        }
+        let (out, open) = self.transform(&chars, &owners, continued);
+        let mut strings = vec![String::new(); texts.len()];
+        for (c, owner) in out {
+            strings[owner].push(c);
+        }
+        for (text, s) in texts.into_iter().zip(strings) {
+            if let Some(text) = text {
+                *text = s;
+            }
+        }
+        open
+    }
+
+    /// Transforms the characters of a paragraph (each one with the index of the
+    /// text it comes from)
+    fn transform(&self,
+                 chars: &[char],
+                 owners: &[usize],
+                 continued: bool)
+                 -> (Vec<(char, usize)>, bool) {
+        let french = self.convention == Convention::French;
+        let dashes = self.convention != Convention::English;
+        let quotes = self.convention.quotes();
+        let mut out: Vec<(char, usize)> = Vec::with_capacity(chars.len() + 8);
+        let mut depth = 0;
+        let mut i = skip_whitespace(chars, 0);
+
+        // A quote that continues from the previous paragraph is reopened
+        if continued && i < chars.len() && is_quote(chars[i]) {
+            let owner = owners[i];
+            if let Some(ref mark) = self.reopening {
+                for c in mark.chars() {
+                    out.push((c, owner));
+                }
+                if french && (mark == "«" || mark == "»") {
+                    out.push((NB_SPACE, owner));
+                }
+            }
+            depth = 1;
+            i = skip_whitespace(chars, i + 1);
+        }
+
+        // Dialogue dash at the beginning of the paragraph
+        let mut dialogue = false;
+        if dashes {
+            if let Some(len) = dash_len(chars, i) {
+                let owner = owners[i];
+                out.push(('—', owner));
+                if french {
+                    out.push((NB_SPACE, owner));
+                }
+                dialogue = true;
+                i = skip_whitespace(chars, i + len);
+            }
+        }
+
+        let mut incise = false;
+        while i < chars.len() {
+            let c = chars[i];
+            let owner = owners[i];
+            if c == '«' || c == '“' || (c == '"' && opens(chars, i)) {
+                let (mark, _) = quotes[if depth < quotes.len() { depth } else { quotes.len() - 1 }];
+                depth += 1;
+                out.push((mark, owner));
+                i = skip_whitespace(chars, i + 1);
+                if french && mark == '«' {
+                    out.push((NB_SPACE, owner));
+                }
+                continue;
+            }
+            if (c == '»' || c == '”' || c == '"') && depth > 0 {
+                depth -= 1;
+                let (_, mark) = quotes[if depth < quotes.len() { depth } else { quotes.len() - 1 }];
+                trim_spaces(&mut out);
+                if french && mark == '»' {
+                    out.push((NB_SPACE, owner));
+                }
+                out.push((mark, owner));
+                i += 1;
+                continue;
+            }
+            if dashes && (dialogue || depth > 0) {
+                if let Some(len) = dash_len(chars, i) {
+                    let spaced = i == 0 || chars[i - 1].is_whitespace();
+                    if spaced {
+                        let reply = i == 0 || chars[i - 1] == '\n' ||
+                                    ends_sentence(previous_char(chars, i));
+                        if reply || !incise {
+                            // Beginning of a new reply (interrupting the previous one)
+                            // or of an incise
+                            incise = !reply;
+                            trim_spaces(&mut out);
+                            if !out.is_empty() && out[out.len() - 1].0 != '\n' {
+                                out.push((' ', owner));
+                            }
+                            out.push(('—', owner));
+                            if french {
+                                out.push((NB_SPACE, owner));
+                            }
+                            i = skip_whitespace(chars, i + len);
+                        } else {
+                            // End of an incise
+                            incise = false;
+                            trim_spaces(&mut out);
+                            if french {
+                                out.push((NB_SPACE, owner));
+                            }
+                            out.push(('—', owner));
+                            i += len;
+                        }
+                        continue;
+                    } else if incise && c == '—' {
+                        // End of an incise attached to the previous word
+                        incise = false;
+                        if french {
+                            out.push((NB_SPACE, owner));
+                        }
+                        out.push(('—', owner));
+                        i += 1;
+                        continue;
+                    }
+                }
+            }
+            out.push((c, owner));
+            i += 1;
+        }
+        (out, depth > 0)
+    }
+}
+
+/// Collects the (mutable) texts of a paragraph, with `None` for line breaks
+fn collect_texts<'t>(tokens: &'t mut [Token], texts: &mut Vec<Option<&'t mut String>>) {
+    for token in tokens.iter_mut() {
+        match *token {
+            Token::Str(ref mut s) => texts.push(Some(s)),
+            Token::SoftBreak | Token::HardBreak => texts.push(None),
+            Token::Code(..) | Token::Footnote(..) | Token::Image(..) |
+            Token::StandaloneImage(..) => (),
+            ref mut token => {
+                if let Some(inner) = token.inner_mut() {
+                    collect_texts(inner, texts);
+                }
+            }
+        }
+    }
+}
+
+/// Returns the length of the dialogue dash starting at `i`, if there is one
+fn dash_len(chars: &[char], i: usize) -> Option<usize> {
+    let followed = |n: usize| i + n >= chars.len() || chars[i + n].is_whitespace();
+    match chars.get(i) {
+        Some(&'—') | Some(&'―') => Some(1),
+        Some(&'–') if followed(1) => Some(1),
+        Some(&'-') => {
+            let n = chars[i..].iter().take_while(|c| **c == '-').count();
+            if n <= 3 && followed(n) {
+                Some(n)
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Returns true if the `"` at `i` opens a quote
+fn opens(chars: &[char], i: usize) -> bool {
+    let after = i + 1 < chars.len() && !chars[i + 1].is_whitespace();
+    let before = i == 0 ||
+                 match chars[i - 1] {
+                     '(' | '[' | '—' | '«' | '“' => true,
+                     c => c.is_whitespace(),
+                 };
+    before && after
+}
+
+fn is_quote(c: char) -> bool {
+    match c {
+        '«' | '»' | '“' | '”' | '"' => true,
+        _ => false,
+    }
+}
+
+fn ends_sentence(c: Option<char>) -> bool {
+    match c {
+        Some('.') | Some('!') | Some('?') | Some('…') | Some('»') | Some('”') => true,
+        _ => false,
+    }
+}
+
+/// Returns the last non-whitespace character before `i`
+fn previous_char(chars: &[char], i: usize) -> Option<char> {
+    chars[..i].iter().rev().cloned().find(|c| !c.is_whitespace())
+}
+
+fn skip_whitespace(chars: &[char], mut i: usize) -> usize {
+    while i < chars.len() && chars[i].is_whitespace() && chars[i] != '\n' {
+        i += 1;
+    }
+    i
+}
+
+/// Removes spaces (but not line breaks) at the end of the output
+fn trim_spaces(out: &mut Vec<(char, usize)>) {
+    while out.last().map_or(false, |&(c, _)| c.is_whitespace() && c != '\n') {
+        out.pop();
+    }
+}
+
+#[cfg(test)]
+fn apply_str(convention: Convention, paragraphs: &[&str]) -> Vec<String> {
+    let dialogue = Dialogue::new(convention, Some(convention.reopening().to_owned()));
+    let mut tokens: Vec<Token> = paragraphs.iter()
+        .map(|s| Token::Paragraph(vec![Token::Str(s.to_string())]))
+        .collect();
+    dialogue.apply(&mut tokens);
+    tokens.into_iter()
+        .map(|token| match token {
+            Token::Paragraph(ref v) => {
+                match v[0] {
+                    Token::Str(ref s) => s.clone(),
+                    _ => unreachable!(),
+                }
+            }
+            _ => unreachable!(),
+        })
+        .collect()
+}
+
+#[test]
+fn dialogue_french() {
+    let result = apply_str(Convention::French,
+                           &["-- Je pense -- dit-il -- que oui. -- Non !",
+                             "«Il m'a dit \"bonjour\" hier,",
+                             "« et il est parti.»"]);
+    assert_eq!(result,
+               vec!["—\u{A0}Je pense —\u{A0}dit-il\u{A0}— que oui. —\u{A0}Non !",
+                    "«\u{A0}Il m'a dit “bonjour” hier,",
+                    "«\u{A0}et il est parti.\u{A0}»"]);
+}
+
+#[test]
+fn dialogue_spanish() {
+    let result = apply_str(Convention::Spanish,
+                           &["— Hola — dijo él —. ¿Qué tal? — Bien."]);
+    assert_eq!(result, vec!["—Hola —dijo él—. ¿Qué tal? —Bien."]);
+}
+
+#[test]
+fn dialogue_english() {
+    let result = apply_str(Convention::English,
+                           &["\"He said \"hello\" to me,", "\"and left.\"", "Not a - dialogue"]);
+    assert_eq!(result,
+               vec!["“He said ‘hello’ to me,", "“and left.”", "Not a - dialogue"]);
+}
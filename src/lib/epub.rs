@@ -20,6 +20,7 @@ use token::Token;
 use html::HtmlRenderer;
 use book::{Book, compile_str};
 use book::Header;
+use book::NotesPlacement;
 use templates::epub::*;
 use templates::epub3;
 use resource_handler;
@@ -28,16 +29,20 @@ use parser::Parser;
 use lang;
 use book_renderer::BookRenderer;
 use text_view::view_as_text;
+use zipper::Zipper;
+use hyphenate::{Hyphenator, SOFT_HYPHEN};
 
 use mustache::Template;
 use crowbook_text_processing::escape;
 use epub_builder::EpubBuilder;
 use epub_builder::EpubVersion;
 use epub_builder::EpubContent;
-use epub_builder::ZipCommand;
+use epub_builder::Zip;
 use epub_builder::ReferenceType;
+use epub_builder;
+use chrono::NaiveDateTime;
 
-use std::io::Write;
+use std::io::{Read, Write};
 use std::convert::{AsRef, AsMut};
 use std::fs;
 use std::fs::File;
@@ -65,6 +70,9 @@ impl<'a> EpubRenderer<'a> {
                                          .unwrap_or_else(|_| book.options.get_str("rendering.highlight.theme").unwrap()))?;
         html.handler.set_images_mapping(true);
         html.handler.set_base64(false);
+        html.hyphenator = Hyphenator::new(book, "epub.hyphenate");
+        html.output_formats = vec!["epub"];
+        html.notes_file = String::from("notes.xhtml");
         Ok(EpubRenderer {
             html: html,
             toc: vec![],
@@ -76,9 +84,17 @@ impl<'a> EpubRenderer<'a> {
     /// Render a book
     pub fn render_book(&mut self, to: &mut Write) -> Result<String> {
         // Initialize the EPUB builder
-        let mut zip = ZipCommand::new_in(self.html.book.options.get_path("crowbook.temp_dir")?)?;
-        zip.command(self.html.book.options.get_str("crowbook.zip.command")
-                    .unwrap());
+        let mut zipper = Zipper::new(&self.html.book.options.get_path("crowbook.temp_dir")?)?;
+        let timestamp = self.html.book.reproducible_timestamp();
+        zipper.set_timestamp(timestamp);
+        let zip = EpubZipper {
+            zipper: zipper,
+            command: self.html.book.options.get_str("crowbook.zip.command").unwrap().to_owned(),
+            fixed: timestamp.and_then(|t| NaiveDateTime::from_timestamp_opt(t, 0)).map(|date| {
+                (self.html.book.identifier(),
+                 date.format("%Y-%m-%dT%H:%M:%SZ").to_string())
+            }),
+        };
         let mut maker = EpubBuilder::new(zip)?;
         if self.html.book.options.get_i32("epub.version").unwrap() == 3 {
             maker.epub_version(EpubVersion::V30);
@@ -129,8 +145,24 @@ impl<'a> EpubRenderer<'a> {
         }
 
         // Write cover.xhtml (if needs be)
-        if self.html.book.options.get_path("cover").is_ok() {
-            let cover = self.render_cover()?;
+        let generated_cover = self.html.book.generated_cover();
+        if let Some(cover) = generated_cover {
+            maker.add_cover_image(cover.file_name(), cover.content(), cover.mime_type())?;
+        }
+        let cover_url = if let Ok(cover) = self.html.book.options.get_path("cover") {
+            // Check that cover can be found
+            if fs::metadata(&cover).is_err() {
+                return Err(Error::file_not_found(&self.html.book.source, lformat!("cover"), cover));
+            }
+            Some(self.html
+                 .handler
+                 .map_image(&self.html.source, Cow::Owned(cover))?
+                 .into_owned())
+        } else {
+            generated_cover.as_ref().map(|cover| cover.file_name().to_owned())
+        };
+        if let Some(cover_url) = cover_url {
+            let cover = self.render_cover(cover_url)?;
             let mut content = EpubContent::new("cover.xhtml", cover.as_bytes())
                 .reftype(ReferenceType::Cover);
             if toc_extras {
@@ -160,17 +192,34 @@ impl<'a> EpubRenderer<'a> {
             compile_str(self.html.book.get_template("epub.chapter.xhtml")?.as_ref(),
                         &self.html.book.source,
                         "epub.chapter.xhtml")?;
+        let chapters = self.html.split_chapters(part_filenamer);
         let mut rendered = vec![];
-        for (i, chapter) in self.html.book.chapters.iter().enumerate() {
-            let n = chapter.number;
-            let v = &chapter.content;
-            self.html.chapter_config(i, n, filenamer(i));
-            let this_chapter = self.render_chapter(v, &template_chapter)?;
-            rendered.push(this_chapter);
+        for (i, parts) in chapters.into_iter().enumerate() {
+            let n = self.html.book.chapters[i].number;
+            let mut raw_title = String::new();
+            for (j, (filename, v)) in parts.into_iter().enumerate() {
+                if j == 0 {
+                    self.html.chapter_config(i, n, filename.clone());
+                } else {
+                    self.html.set_filename(filename.clone());
+                    let new_chapter = match v.first() {
+                        Some(&Token::Header(1, _)) => true,
+                        _ => false,
+                    };
+                    if !new_chapter {
+                        // Continuation of the previous part: keep its title
+                        self.chapter_title = self.toc.last().cloned().unwrap_or_default();
+                        self.chapter_title_raw = raw_title.clone();
+                    }
+                }
+                let (this_chapter, this_raw_title) = self.render_chapter(v, &template_chapter)?;
+                raw_title = this_raw_title.clone();
+                rendered.push((filename, this_chapter, this_raw_title));
+            }
         }
 
-        for (i, (rendered_chapter, raw_title)) in rendered.into_iter().enumerate() {
-            let mut content = EpubContent::new(filenamer(i), rendered_chapter.as_bytes());
+        for (i, (filename, rendered_chapter, raw_title)) in rendered.into_iter().enumerate() {
+            let mut content = EpubContent::new(filename.as_str(), rendered_chapter.as_bytes());
             if i == 0 {
                 content = content.reftype(ReferenceType::Text);
             }
@@ -178,7 +227,7 @@ impl<'a> EpubRenderer<'a> {
             // horrible hack to add subtoc of this chapter to epub's toc
             // todo: find cleaner way
             for element in &self.html.toc.elements {
-                if element.url.contains(&filenamer(i)) {
+                if element.url.contains(&filename) {
                     content = content.title(escape::html(raw_title));
                     content.toc.children = element.children.clone();
                     break;
@@ -188,6 +237,28 @@ impl<'a> EpubRenderer<'a> {
         }
         self.html.source = Source::empty();
 
+        // Add a chapter with notes collected at the end of the book, if any
+        let notes = self.html.render_book_notes();
+        if !notes.is_empty() {
+            let title = lang::get_str(self.html.book.options.get_str("lang").unwrap(), "notes");
+            let notes = if self.html.book.options.get_i32("epub.version").unwrap() == 3 {
+                format!("<section epub:type = \"endnotes\">\n{}</section>\n", notes)
+            } else {
+                notes
+            };
+            let data = self.html
+                .book
+                .get_metadata(|s| self.render_vec(&Parser::new().parse_inline(s)?))?
+                .insert_str("content", notes)
+                .insert_str("chapter_title_raw", title.clone())
+                .insert_str("chapter_title", escape::html(title.clone()))
+                .build();
+            let mut res: Vec<u8> = vec![];
+            template_chapter.render_data(&mut res, &data)?;
+            maker.add_content(EpubContent::new("notes.xhtml", res.as_slice())
+                              .title(escape::html(title)))?;
+        }
+
         // Render the CSS file and write it
         let template_css =
             compile_str(self.html.book.get_template("epub.css").unwrap().as_ref(),
@@ -266,35 +337,22 @@ impl<'a> EpubRenderer<'a> {
         }
     }
 
-    /// Render cover.xhtml
-    fn render_cover(&mut self) -> Result<String> {
-        if let Ok(cover) = self.html.book.options.get_path("cover") {
-            // Check that cover can be found
-            if fs::metadata(&cover).is_err() {
-                return Err(Error::file_not_found(&self.html.book.source, lformat!("cover"), cover));
-
-            }
-            let epub3 = self.html.book.options.get_i32("epub.version").unwrap() == 3;
-            let template = compile_str(if epub3 { epub3::COVER } else { COVER },
-                                       &self.html.book.source,
-                                       "cover.xhtml")?;
-            let data = self.html
-                .book
-                .get_metadata(|s| self.render_vec(&Parser::new().parse_inline(s)?))?
-                .insert_str("cover",
-                            self.html
-                            .handler
-                            .map_image(&self.html.source, Cow::Owned(cover))?
-                            .into_owned())
-                .build();
-            let mut res: Vec<u8> = vec![];
-            template.render_data(&mut res, &data)?;
-            match String::from_utf8(res) {
-                Err(_) => panic!(lformat!("generated HTML for cover.xhtml was not utf-8 valid")),
-                Ok(res) => Ok(res),
-            }
-        } else {
-            panic!(lformat!("Why is this method called if cover is None???"));
+    /// Render cover.xhtml, given the URL of the cover image
+    fn render_cover(&mut self, cover: String) -> Result<String> {
+        let epub3 = self.html.book.options.get_i32("epub.version").unwrap() == 3;
+        let template = compile_str(if epub3 { epub3::COVER } else { COVER },
+                                   &self.html.book.source,
+                                   "cover.xhtml")?;
+        let data = self.html
+            .book
+            .get_metadata(|s| self.render_vec(&Parser::new().parse_inline(s)?))?
+            .insert_str("cover", cover)
+            .build();
+        let mut res: Vec<u8> = vec![];
+        template.render_data(&mut res, &data)?;
+        match String::from_utf8(res) {
+            Err(_) => panic!(lformat!("generated HTML for cover.xhtml was not utf-8 valid")),
+            Ok(res) => Ok(res),
         }
     }
 
@@ -441,7 +499,7 @@ impl<'a> EpubRenderer<'a> {
                 let content = if html.verbatim {
                     Cow::Borrowed(text.as_ref())
                 } else {
-                    escape::html(html.book.clean(text.as_ref(), false))
+                    escape::html(html.hyphenate(html.book.clean(text.as_ref(), false)))
                 };
                 let mut content = if html.first_letter {
                     html.first_letter = false;
@@ -468,11 +526,21 @@ impl<'a> EpubRenderer<'a> {
                     content
                 };
 
+                if content.contains(SOFT_HYPHEN) {
+                    // XHTML doesn't define the &shy; entity
+                    content = Cow::Owned(content.replace(SOFT_HYPHEN, "&#173;"));
+                }
                 if html.book.options.get_bool("epub.escape_nb_spaces").unwrap() {
                     content = escape::nnbsp(content);
                 }
                 Ok(content.into_owned())
             },
+            Token::TaskItem(checked, ref vec) => {
+                // Form elements aren't allowed in EPUB content
+                Ok(format!("<li class = \"task\"><span class = \"checkbox\">{}</span> {}</li>\n",
+                           if checked { "☒" } else { "☐" },
+                           this.render_vec(vec)?))
+            }
             Token::Header(1, ref vec) => {
                 {
                     let epub: &mut EpubRenderer = this.as_mut();
@@ -480,6 +548,19 @@ impl<'a> EpubRenderer<'a> {
                 }
                 HtmlRenderer::static_render_token(this, token)
             }
+            Token::Admonition(ref kind, ref title, ref vec) => {
+                // aside is not available in XHTML 1.1 (EPUB 2)
+                let epub3 = (this.as_ref() as &HtmlRenderer)
+                    .book
+                    .options
+                    .get_i32("epub.version")
+                    .unwrap() == 3;
+                HtmlRenderer::render_admonition(this,
+                                                if epub3 { "aside" } else { "div" },
+                                                kind,
+                                                title,
+                                                vec)
+            }
             Token::Footnote(ref vec) => {
                 let epub3 = (this.as_ref() as &HtmlRenderer)
                     .book
@@ -490,13 +571,22 @@ impl<'a> EpubRenderer<'a> {
                 let html: &mut HtmlRenderer = this.as_mut();
                 html.footnote_number += 1;
                 let number = html.footnote_number;
+                let (dest, source) = html.footnote_links(number);
                 let note_number = format!("<p class = \"note-number\">
-  <a href = \"#note-source-{}\">[{}]</a>
+  <a href = \"{}\">[{}]</a>
 </p>\n",
-                                          number,
+                                          source,
                                           number);
+                // Notes collected in a separate chapter are endnotes, which reading
+                // systems don't hide
+                let note_type = if html.notes_placement == NotesPlacement::Book {
+                    "endnote"
+                } else {
+                    "footnote"
+                };
                 let inner = if epub3 {
-                    format!("<aside epub:type = \"footnote\" id = \"note-dest-{}\">{}</aside>",
+                    format!("<aside epub:type = \"{}\" id = \"note-dest-{}\">{}</aside>",
+                            note_type,
                             number,
                             inner_content)
                 } else {
@@ -504,10 +594,10 @@ impl<'a> EpubRenderer<'a> {
                 };
                 html.add_footnote(note_number, inner);
 
-                Ok(format!("<a {} href = \"#note-dest-{}\"><sup id = \
+                Ok(format!("<a {} href = \"{}\"><sup id = \
                             \"note-source-{}\">[{}]</sup></a>",
                            if epub3 { "epub:type = \"noteref\"" } else { "" },
-                           number,
+                           dest,
                            number,
                            number))
             }
@@ -522,6 +612,79 @@ fn filenamer(i: usize) -> String {
     format!("chapter_{:03}.xhtml", i)
 }
 
+/// Generate a file name given the number of a chapter and of a part in this chapter
+fn part_filenamer(i: usize, j: usize) -> String {
+    if j == 0 {
+        filenamer(i)
+    } else {
+        format!("chapter_{:03}_{}.xhtml", i, j)
+    }
+}
+
+/// Allows `EpubBuilder` to write its files using a `Zipper`
+struct EpubZipper {
+    zipper: Zipper,
+    command: String,
+    /// Identifier and date to use in `content.opf` instead of the random/current ones
+    /// generated by `EpubBuilder`, if the build must be reproducible
+    fixed: Option<(String, String)>,
+}
+
+impl Zip for EpubZipper {
+    fn write_file<P: AsRef<Path>, R: Read>(&mut self, file: P, mut content: R) -> epub_builder::Result<()> {
+        let mut bytes = vec![];
+        content.read_to_end(&mut bytes)
+            .map_err(|e| format!("could not read content of {}: {}", file.as_ref().display(), e))?;
+        if let Some((ref identifier, ref date)) = self.fixed {
+            if file.as_ref() == Path::new("OEBPS/content.opf") {
+                bytes = fix_opf(&String::from_utf8_lossy(&bytes), identifier, date)?.into_bytes();
+            }
+        }
+        match self.zipper.write(file, &bytes, true) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(format!("{}", e).into()),
+        }
+    }
+
+    fn generate<W: Write>(&mut self, mut to: W) -> epub_builder::Result<()> {
+        match self.zipper.generate_epub(&self.command, &mut to) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(format!("{}", e).into()),
+        }
+    }
+}
+
+/// Replaces the identifier and dates generated by `EpubBuilder` in `content.opf`
+///
+/// Returns an error if one of them can't be found (e.g. if the template of
+/// `epub-builder` changed), since the build wouldn't be reproducible.
+fn fix_opf(opf: &str, identifier: &str, date: &str) -> ::std::result::Result<String, String> {
+    let opf = replace_between(opf, "<dc:identifier id=\"epub-id-1\">", "</dc:identifier>", identifier)?;
+    let opf = replace_between(&opf, "<dc:date>", "</dc:date>", date)?;
+    // Only EPUB 3 has a modification date
+    if opf.contains("dcterms:modified") {
+        replace_between(&opf, "<meta property=\"dcterms:modified\">", "</meta>", date)
+    } else {
+        Ok(opf)
+    }
+}
+
+/// Replace the content between the first occurence of `begin` and the following `end`
+fn replace_between(s: &str,
+                   begin: &str,
+                   end: &str,
+                   content: &str)
+                   -> ::std::result::Result<String, String> {
+    if let Some(i) = s.find(begin) {
+        let start = i + begin.len();
+        if let Some(j) = s[start..].find(end) {
+            return Ok(format!("{}{}{}", &s[..start], content, &s[start + j..]));
+        }
+    }
+    Err(format!("could not find '{}' in content.opf, the EPUB file can't be made reproducible",
+                begin))
+}
+
 
 derive_html!{EpubRenderer<'a>, EpubRenderer::static_render_token}
 
@@ -538,3 +701,63 @@ impl BookRenderer for Epub {
         Ok(())
     }
 }
+
+#[test]
+fn epub_zipper_fixed_opf() {
+    let opf = "<metadata><dc:identifier id=\"epub-id-1\">urn:uuid:random</dc:identifier>\
+               <dc:date>2017-12-01T10:00:00Z</dc:date>\
+               <meta property=\"dcterms:modified\">2017-12-01T10:00:00Z</meta></metadata>";
+    let temp_dir = ::std::env::temp_dir();
+    let zipper = Zipper::new(temp_dir.to_str().unwrap()).unwrap();
+    let mut zip = EpubZipper {
+        zipper: zipper,
+        command: String::from("zip"),
+        fixed: Some((String::from("urn:uuid:fixed"), String::from("1980-01-01T00:00:00Z"))),
+    };
+    zip.write_file("OEBPS/content.opf", opf.as_bytes()).unwrap();
+    zip.write_file("OEBPS/other.xhtml", opf.as_bytes()).unwrap();
+    assert_eq!(zip.zipper.read("OEBPS/content.opf").unwrap(),
+               "<metadata><dc:identifier id=\"epub-id-1\">urn:uuid:fixed</dc:identifier>\
+                <dc:date>1980-01-01T00:00:00Z</dc:date>\
+                <meta property=\"dcterms:modified\">1980-01-01T00:00:00Z</meta></metadata>");
+    // Other files are left alone
+    assert_eq!(zip.zipper.read("OEBPS/other.xhtml").unwrap(), opf);
+}
+
+#[cfg(test)]
+/// Keeps the files written by `EpubBuilder`
+struct CaptureZip(::std::rc::Rc<::std::cell::RefCell<Vec<(String, Vec<u8>)>>>);
+
+#[cfg(test)]
+impl Zip for CaptureZip {
+    fn write_file<P: AsRef<Path>, R: Read>(&mut self, file: P, mut content: R) -> epub_builder::Result<()> {
+        let mut bytes = vec![];
+        content.read_to_end(&mut bytes).unwrap();
+        self.0.borrow_mut().push((file.as_ref().to_string_lossy().into_owned(), bytes));
+        Ok(())
+    }
+
+    fn generate<W: Write>(&mut self, _: W) -> epub_builder::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn epub_fix_builder_opf() {
+    for &version in &[EpubVersion::V20, EpubVersion::V30] {
+        let files = ::std::rc::Rc::new(::std::cell::RefCell::new(vec![]));
+        let mut maker = EpubBuilder::new(CaptureZip(files.clone())).unwrap();
+        maker.epub_version(version);
+        maker.generate(Vec::<u8>::new()).unwrap();
+        let files = files.borrow();
+        let opf = files.iter().find(|&&(ref f, _)| f == "OEBPS/content.opf").unwrap();
+        let opf = fix_opf(&String::from_utf8_lossy(&opf.1), "urn:uuid:fixed", "1980-01-01T00:00:00Z")
+            .unwrap();
+        assert!(opf.contains(">urn:uuid:fixed</dc:identifier>"));
+        assert!(opf.contains("<dc:date>1980-01-01T00:00:00Z</dc:date>"));
+        if version == EpubVersion::V30 {
+            assert!(opf.contains("dcterms:modified\">1980-01-01T00:00:00Z</meta>"));
+        }
+    }
+    assert!(fix_opf("<metadata></metadata>", "urn:uuid:fixed", "1980-01-01T00:00:00Z").is_err());
+}
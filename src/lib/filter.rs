@@ -0,0 +1,260 @@
+// Copyright (C) 2017 Élisabeth HENRY.
+//
+// This file is part of Crowbook.
+//
+// Crowbook is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published
+// by the Free Software Foundation, either version 2.1 of the License, or
+// (at your option) any later version.
+//
+// Crowbook is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
+
+//! External filters, transforming the AST of chapters between parsing and rendering.
+
+use bookoptions::BookOptions;
+use error::{Error, Result, Source};
+use token::Token;
+
+use serde_json;
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+
+/// Formats that can have their own list of filters (`filters.<format>`)
+const FORMATS: &'static [&'static str] = &["html", "epub", "tex", "odt"];
+
+/// Runs the filters set in `options` on the tokens of a chapter.
+///
+/// The filters of `filters` are run first. If some formats have their own list of
+/// filters, they are run on a copy of the result for each of these formats, and
+/// the parts of the chapter that differ are wrapped in `Token::OnlyFor`.
+pub fn apply(options: &BookOptions,
+             root: &Path,
+             file: &str,
+             tokens: Vec<Token>)
+             -> Result<Vec<Token>> {
+    let filters = options.get_str_vec("filters").unwrap_or(&[]);
+    let tokens = run_all(filters, root, file, "", tokens)?;
+
+    let mut variants = vec![];
+    for format in FORMATS {
+        let filters = options.get_str_vec(&format!("filters.{}", format)).unwrap_or(&[]);
+        if !filters.is_empty() {
+            let filtered = run_all(filters, root, file, format, tokens.clone())?;
+            variants.push((*format, filtered));
+        }
+    }
+    if variants.is_empty() {
+        Ok(tokens)
+    } else {
+        Ok(merge(tokens, variants))
+    }
+}
+
+/// Runs a list of filters, in order
+fn run_all(filters: &[String],
+           root: &Path,
+           file: &str,
+           format: &str,
+           mut tokens: Vec<Token>)
+           -> Result<Vec<Token>> {
+    for filter in filters {
+        tokens = run(filter, root, file, format, &tokens)?;
+    }
+    Ok(tokens)
+}
+
+/// Runs a filter, giving it the tokens as JSON on its standard input and reading
+/// the modified ones on its standard output
+fn run(filter: &str, root: &Path, file: &str, format: &str, tokens: &[Token]) -> Result<Vec<Token>> {
+    let error = |msg: String| {
+        Error::parser(Source::new(file),
+                      lformat!("filter '{filter}': {error}",
+                               filter = filter,
+                               error = msg))
+    };
+
+    // A filter containing a path separator is relative to the book's directory,
+    // else it is searched in the PATH
+    let program = if Path::new(filter).components().count() > 1 {
+        root.join(filter)
+    } else {
+        PathBuf::from(filter)
+    };
+    let json = serde_json::to_vec(tokens)
+        .map_err(|err| error(lformat!("could not convert chapter to JSON: {error}",
+                                      error = err)))?;
+    let mut child = Command::new(&program)
+        .env("CROWBOOK_FILE", file)
+        .env("CROWBOOK_FORMAT", format)
+        .env("CROWBOOK_ROOT", root)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| error(lformat!("could not run it: {error}", error = err)))?;
+
+    // Write in another thread, so a filter writing its output before it has read all
+    // its input doesn't block
+    let mut stdin = child.stdin.take().unwrap();
+    let writer = thread::spawn(move || stdin.write_all(&json));
+    let output = child.wait_with_output()
+        .map_err(|err| error(lformat!("could not run it: {error}", error = err)))?;
+    let written = writer.join().unwrap();
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if !output.status.success() {
+        return Err(error(lformat!("it didn't return successfully: {output}",
+                                  output = stderr.trim())));
+    }
+    if !stderr.trim().is_empty() {
+        warn!("{}", lformat!("in {file}, filter '{filter}': {output}",
+                             file = file,
+                             filter = filter,
+                             output = stderr.trim()));
+    }
+    written.map_err(|err| error(lformat!("could not write chapter to it: {error}",
+                                         error = err)))?;
+    serde_json::from_slice(&output.stdout)
+        .map_err(|err| error(lformat!("could not parse its output: {error}",
+                                      error = err)))
+}
+
+/// Merges the versions of a chapter filtered for some formats with the one used by
+/// other formats, keeping the tokens at the start and the end that are the same in
+/// all versions and wrapping the ones that differ in `Token::OnlyFor`
+fn merge(mut default: Vec<Token>, variants: Vec<(&str, Vec<Token>)>) -> Vec<Token> {
+    let prefix = (0..default.len())
+        .take_while(|&i| variants.iter().all(|&(_, ref v)| v.get(i) == Some(&default[i])))
+        .count();
+
+    // If the first token that differs is a header of the same level in all versions,
+    // only its content is merged, so it is still a header (e.g. for the chapter title)
+    let level = match default.get(prefix) {
+        Some(&Token::Header(level, _)) => {
+            let same = variants.iter().all(|&(_, ref v)| match v.get(prefix) {
+                Some(&Token::Header(l, _)) => l == level,
+                _ => false,
+            });
+            if same { Some(level) } else { None }
+        }
+        _ => None,
+    };
+    if let Some(level) = level {
+        let rest = default.split_off(prefix + 1);
+        let mut header_variants = vec![];
+        let mut rest_variants = vec![];
+        for (format, mut tokens) in variants {
+            let rest = tokens.split_off(prefix + 1);
+            if let Some(Token::Header(_, inner)) = tokens.pop() {
+                header_variants.push((format, inner));
+            }
+            rest_variants.push((format, rest));
+        }
+        if let Some(Token::Header(_, inner)) = default.pop() {
+            default.push(Token::Header(level, merge(inner, header_variants)));
+        }
+        default.extend(merge(rest, rest_variants));
+        return default;
+    }
+
+    let suffix = (0..default.len() - prefix)
+        .take_while(|&j| {
+            variants.iter().all(|&(_, ref v)| {
+                v.len() - prefix > j && v[v.len() - 1 - j] == default[default.len() - 1 - j]
+            })
+        })
+        .count();
+    let others: Vec<String> = FORMATS.iter()
+        .filter(|f| variants.iter().all(|&(format, _)| format != **f))
+        .map(|f| f.to_string())
+        .collect();
+
+    let mut result = default;
+    let len = result.len();
+    let end = result.split_off(len - suffix);
+    let middle = result.split_off(prefix);
+    if !others.is_empty() && !middle.is_empty() {
+        result.push(Token::OnlyFor(others, middle));
+    }
+    for (format, mut tokens) in variants {
+        let len = tokens.len();
+        tokens.truncate(len - suffix);
+        let middle = tokens.split_off(prefix);
+        if !middle.is_empty() {
+            result.push(Token::OnlyFor(vec![format.to_owned()], middle));
+        }
+    }
+    result.extend(end);
+    result
+}
+
+#[test]
+fn filter_merge() {
+    let para = |s: &str| Token::Paragraph(vec![Token::Str(s.to_owned())]);
+    let default = vec![para("a"), para("b"), para("c")];
+    let variants = vec![("html", vec![para("a"), para("B"), para("B2"), para("c")])];
+    assert_eq!(merge(default, variants),
+               vec![para("a"),
+                    Token::OnlyFor(vec![String::from("epub"),
+                                        String::from("tex"),
+                                        String::from("odt")],
+                                   vec![para("b")]),
+                    Token::OnlyFor(vec![String::from("html")], vec![para("B"), para("B2")]),
+                    para("c")]);
+}
+
+#[test]
+fn filter_merge_header() {
+    let text = |s: &str| Token::Str(s.to_owned());
+    let para = |s: &str| Token::Paragraph(vec![text(s)]);
+    let others = vec![String::from("html"), String::from("epub"), String::from("odt")];
+    let default = vec![Token::Header(1, vec![text("Title")]), para("a"), para("b")];
+    let variants = vec![("tex", vec![Token::Header(1, vec![text("TITLE")]), para("a"), para("B")])];
+    assert_eq!(merge(default, variants),
+               vec![Token::Header(1,
+                                  vec![Token::OnlyFor(others.clone(), vec![text("Title")]),
+                                       Token::OnlyFor(vec![String::from("tex")],
+                                                      vec![text("TITLE")])]),
+                    para("a"),
+                    Token::OnlyFor(others, vec![para("b")]),
+                    Token::OnlyFor(vec![String::from("tex")], vec![para("B")])]);
+}
+
+#[cfg(unix)]
+#[test]
+fn filter_features_and_header_ids() {
+    use book::Book;
+    use number::Number;
+    use std::fs::{self, File};
+    use std::os::unix::fs::PermissionsExt;
+    use tests::TestDir;
+
+    let dir = TestDir::new("crowbook-test-filter-features");
+    let root = dir.path.clone();
+    let filter = root.join("filter.sh");
+    File::create(&filter)
+        .unwrap()
+        .write_all(b"#!/bin/sh\nsed -e 's/\"Paragraph\"/\"BlockQuote\"/' -e 's/Foo/Baz/'\n")
+        .unwrap();
+    fs::set_permissions(&filter, fs::Permissions::from_mode(0o755)).unwrap();
+
+    let mut book = Book::new();
+    book.root = root.clone();
+    book.options.set("filters", "[\"./filter.sh\"]").unwrap();
+    book.add_chapter_from_source(Number::Default, "# Foo\n\nBar".as_bytes())
+        .unwrap();
+    // Features and header identifiers are those of the filtered chapter
+    assert!(book.features.blockquote);
+    let content = format!("{:?}", book.chapters[0].content);
+    assert!(content.contains("Some(\"baz\")"));
+    assert!(!content.contains("\"foo\""));
+}
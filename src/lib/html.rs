@@ -18,19 +18,26 @@
 use error::{Result, Error, Source};
 use token::Token;
 use token::Data;
+use token::Alignment;
+use token::Attributes;
 use book::{Book, compile_str};
 use book::Header;
 use book::HeaderData;
+use book::NotesPlacement;
 use number::Number;
 use resource_handler::ResourceHandler;
 use renderer::Renderer;
 use parser::Parser;
 use syntax::Syntax;
 use lang;
+use text_view::view_as_text;
+use hyphenate::{Hyphenator, SOFT_HYPHEN};
+use html_parser::ATTRIBUTES;
 
 use std::borrow::Cow;
 use std::convert::{AsMut, AsRef};
 use std::fmt::Write;
+use std::mem;
 
 use crowbook_text_processing::escape;
 use numerals::roman::Roman;
@@ -53,13 +60,28 @@ pub enum Highlight {
 /// Used by EpubRenderer, HtmlSingleRenderer, HtmlDirRenderer
 pub struct HtmlRenderer<'a> {
     table_head: bool,
+    /// Alignment of the columns of the current table
+    table_alignments: Vec<Alignment>,
+    /// Current column of the current table
+    table_column: usize,
     #[doc(hidden)]
     pub verbatim: bool,
+    /// Inserts soft hyphens in text, if enabled
+    #[doc(hidden)]
+    pub hyphenator: Option<Hyphenator>,
+    /// Set when rendering content that must not be hyphenated (code, links)
+    #[doc(hidden)]
+    pub no_hyphenation: bool,
     current_par: u32,
     #[doc(hidden)]
     pub first_letter: bool,
     first_paragraph: bool,
     footnotes: Vec<(String, String)>,
+    /// Notes collected for the end of the book, grouped by chapter (link to
+    /// the chapter, title of the chapter, notes)
+    book_notes: Vec<(String, String, Vec<(String, String)>)>,
+    /// Link to and title of the current chapter, to group notes
+    notes_chapter: (String, String),
     filename: String,
 
     /// Book that must be rendered
@@ -68,6 +90,10 @@ pub struct HtmlRenderer<'a> {
     /// Proofread or not
     pub proofread: bool,
 
+    /// Output formats for which conditional and raw blocks are rendered
+    #[doc(hidden)]
+    pub output_formats: Vec<&'static str>,
+
     /// Current part, chapter (and subsection, subsubsection and so on)
     #[doc(hidden)]
     pub current_chapter: [i32; 7],
@@ -92,6 +118,15 @@ pub struct HtmlRenderer<'a> {
     #[doc(hidden)]
     pub footnote_number: u32,
 
+    /// Where footnotes are placed
+    #[doc(hidden)]
+    pub notes_placement: NotesPlacement,
+
+    /// File containing the notes when they are collected at the end of the book,
+    /// or an empty string if it is the same file as the content
+    #[doc(hidden)]
+    pub notes_file: String,
+
     /// Source for error messages
     #[doc(hidden)]
     pub source: Source,
@@ -137,6 +172,11 @@ impl<'a> HtmlRenderer<'a> {
     /// Creates a new HTML renderer
     pub fn new(book: &'a Book, theme: &str) -> Result<HtmlRenderer<'a>> {
         let (highlight, syntax) = Self::get_highlight(book, theme);
+        let notes_placement = if book.options.get_bool("html.side_notes").unwrap() {
+            NotesPlacement::Side
+        } else {
+            NotesPlacement::Page
+        };
 
         let mut html = HtmlRenderer {
             book: book,
@@ -148,27 +188,28 @@ impl<'a> HtmlRenderer<'a> {
             current_par: 0,
             current_hide: false,
             table_head: false,
+            table_alignments: vec![],
+            table_column: 0,
             footnote_number: 0,
             footnotes: vec![],
+            book_notes: vec![],
+            notes_chapter: (String::new(), String::new()),
+            notes_placement: book.notes_placement(notes_placement)?,
+            notes_file: String::new(),
             verbatim: false,
+            hyphenator: Hyphenator::new(book, "html.hyphenate"),
+            no_hyphenation: false,
             filename: String::new(),
             handler: ResourceHandler::new(),
             source: Source::empty(),
             first_letter: false,
             first_paragraph: true,
             proofread: false,
+            output_formats: vec!["html"],
             syntax: syntax,
             highlight: highlight,
-            part_template_html: compile_str(book.options
-                                            .get_str("html.part.template")
-                                            .unwrap(),
-                                            Source::empty(),
-                                            "html.part.template")?,
-            chapter_template_html: compile_str(book.options
-                                            .get_str("html.chapter.template")
-                                            .unwrap(),
-                                            Source::empty(),
-                                            "html.chapter.template")?,
+            part_template_html: header_template(book, "html.part.template")?,
+            chapter_template_html: header_template(book, "html.chapter.template")?,
 
         };
         html.handler.set_images_mapping(true);
@@ -182,6 +223,17 @@ impl<'a> HtmlRenderer<'a> {
         self.footnotes.push((number, content));
     }
 
+    /// Returns the links to the content of a footnote and back to its reference
+    #[doc(hidden)]
+    pub fn footnote_links(&self, number: u32) -> (String, String) {
+        if self.notes_placement == NotesPlacement::Book && !self.notes_file.is_empty() {
+            (format!("{}#note-dest-{}", self.notes_file, number),
+             format!("{}#note-source-{}", self.filename, number))
+        } else {
+            (format!("#note-dest-{}", number), format!("#note-source-{}", number))
+        }
+    }
+
     /// Configure the Renderer for this chapter
     #[doc(hidden)]
     pub fn chapter_config(&mut self, i: usize, n: Number, filename: String) {
@@ -210,6 +262,110 @@ impl<'a> HtmlRenderer<'a> {
         self.filename = filename;
     }
 
+    /// Sets the name of the file currently rendered, when a chapter
+    /// is split across multiple files
+    #[doc(hidden)]
+    pub fn set_filename(&mut self, filename: String) {
+        self.filename = filename;
+    }
+
+    /// Inserts soft hyphens (as unicode characters) in text, if hyphenation is
+    /// enabled and the text isn't part of code or of a link
+    #[doc(hidden)]
+    pub fn hyphenate<'s>(&self, text: Cow<'s, str>) -> Cow<'s, str> {
+        match self.hyphenator {
+            Some(ref hyphenator) if !self.no_hyphenation => Cow::Owned(hyphenator.hyphenate(&text)),
+            _ => text,
+        }
+    }
+
+    /// Splits a chapter into the parts that must be rendered in separate files,
+    /// according to `html.split.level` and `html.split.size`.
+    ///
+    /// A new part starts at each header of level `html.split.level` or
+    /// less, and at the first block following a part whose text is longer
+    /// than `html.split.size` characters.
+    #[doc(hidden)]
+    pub fn split_chapter<'t>(&self, tokens: &'t [Token]) -> Vec<&'t [Token]> {
+        let level = self.book.options.get_i32("html.split.level").unwrap();
+        let size = self.book.options.get_i32("html.split.size").unwrap();
+        let mut parts = vec![];
+        let mut start = 0;
+        let mut current_size = 0;
+        for (i, token) in tokens.iter().enumerate() {
+            if i > start {
+                let split_level = match *token {
+                    Token::Header(n, _) => level > 0 && n <= level,
+                    _ => false,
+                };
+                let split_size = size > 0 && current_size >= size as usize;
+                if split_level || split_size {
+                    parts.push(&tokens[start..i]);
+                    start = i;
+                    current_size = 0;
+                }
+            }
+            current_size += view_as_text(&tokens[i..i + 1]).chars().count();
+        }
+        parts.push(&tokens[start..]);
+        parts
+    }
+
+    /// Splits all the chapters of the book, and registers in the resource
+    /// handler the file each header ends up in, so links to them can be
+    /// resolved across files.
+    ///
+    /// Returns, for each chapter, the list of its parts along with the
+    /// file name they must be rendered to. `filenamer` takes the number of
+    /// the chapter and of the part in this chapter.
+    #[doc(hidden)]
+    pub fn split_chapters<F>(&mut self, filenamer: F) -> Vec<Vec<(String, &'a [Token])>>
+        where F: Fn(usize, usize) -> String
+    {
+        let book = self.book;
+        let mut link_number = self.link_number;
+        let mut chapters = vec![];
+        for (i, chapter) in book.chapters.iter().enumerate() {
+            let mut parts = vec![];
+            for (j, part) in self.split_chapter(&chapter.content).into_iter().enumerate() {
+                let filename = filenamer(i, j);
+                self.add_header_anchors(part, &filename, &mut link_number);
+                self.add_id_anchors(part, &filename);
+                parts.push((filename, part));
+            }
+            chapters.push(parts);
+        }
+        chapters
+    }
+
+    /// Registers the anchors of headers in `tokens`, in the same order they
+    /// will be numbered when rendered
+    fn add_header_anchors(&mut self, tokens: &[Token], filename: &str, link_number: &mut u32) {
+        for token in tokens {
+            if let Token::Header(_, _) = *token {
+                *link_number += 1;
+                self.handler.add_anchor(format!("link-{}", link_number), filename);
+            }
+            if let Some(inner) = token.inner() {
+                self.add_header_anchors(inner, filename, link_number);
+            }
+        }
+    }
+
+    /// Registers the identifiers (explicit or generated for headers) of the
+    /// elements in `tokens`, so links to them point to `filename`
+    #[doc(hidden)]
+    pub fn add_id_anchors(&mut self, tokens: &[Token], filename: &str) {
+        for token in tokens {
+            if let Some(id) = token.attributes().and_then(|a| a.id.as_ref()) {
+                self.handler.add_anchor(id.clone(), filename);
+            }
+            if let Some(inner) = token.inner() {
+                self.add_id_anchors(inner, filename);
+            }
+        }
+    }
+
     /// Renders a chapter to HTML
     pub fn render_html<T>(this: &mut T, tokens: &[Token], render_end_notes: bool) -> Result<String>
         where T: AsMut<HtmlRenderer<'a>> + AsRef<HtmlRenderer<'a>> + Renderer
@@ -225,6 +381,105 @@ impl<'a> HtmlRenderer<'a> {
         Ok(res)
     }
 
+    /// Renders an admonition in a `tag` element (`aside`, or `div` where it is
+    /// not available)
+    #[doc(hidden)]
+    pub fn render_admonition<T>(this: &mut T,
+                                tag: &str,
+                                kind: &str,
+                                title: &str,
+                                vec: &[Token])
+                                -> Result<String>
+        where T: AsMut<HtmlRenderer<'a>> + AsRef<HtmlRenderer<'a>> + Renderer
+    {
+        let title = if title.is_empty() {
+            lang::get_str(this.as_ref().book.options.get_str("lang").unwrap(), kind)
+        } else {
+            escape::html(this.as_ref().book.clean(title, false)).into_owned()
+        };
+        Ok(format!("<{tag} class = \"admonition {kind}\">
+<p class = \"admonition-title\">{title}</p>
+{content}</{tag}>\n",
+                   tag = tag,
+                   kind = kind,
+                   title = title,
+                   content = this.render_vec(vec)?))
+    }
+
+    /// Renders a verse block, numbering its lines every `numbers` lines (if not 0)
+    #[doc(hidden)]
+    pub fn render_verse<T>(this: &mut T, numbers: usize, vec: &[Token]) -> Result<String>
+        where T: AsMut<HtmlRenderer<'a>> + AsRef<HtmlRenderer<'a>> + Renderer
+    {
+        let mut content = String::new();
+        let mut n = 0;
+        for stanza in vec {
+            if let Token::Stanza(ref lines) = *stanza {
+                content.push_str("<p class = \"stanza\">\n");
+                for line in lines {
+                    if let Token::VerseLine(indent, ref inner) = *line {
+                        n += 1;
+                        let number = if numbers > 0 && n % numbers == 0 { Some(n) } else { None };
+                        content.push_str(&HtmlRenderer::render_verse_line(this,
+                                                                          indent,
+                                                                          number,
+                                                                          inner)?);
+                    } else {
+                        content.push_str(&this.render_token(line)?);
+                    }
+                }
+                content.push_str("</p>\n");
+            } else {
+                content.push_str(&this.render_token(stanza)?);
+            }
+        }
+        Ok(format!("<div class = \"verse\">\n{}</div>\n", content))
+    }
+
+    /// Renders a line of verse, with its indentation and an optional line number
+    #[doc(hidden)]
+    pub fn render_verse_line<T>(this: &mut T,
+                                indent: usize,
+                                number: Option<usize>,
+                                vec: &[Token])
+                                -> Result<String>
+        where T: AsMut<HtmlRenderer<'a>> + AsRef<HtmlRenderer<'a>> + Renderer
+    {
+        let style = if indent > 0 {
+            format!(" style = \"margin-left: {}em\"", indent as f32 / 2.0)
+        } else {
+            String::new()
+        };
+        let number = match number {
+            Some(n) => format!("<span class = \"verse-number\">{}</span>", n),
+            None => String::new(),
+        };
+        Ok(format!("<span class = \"verse-line\"{}>{}{}<br /></span>\n",
+                   style,
+                   number,
+                   this.render_vec(vec)?))
+    }
+
+    /// Renders a scene break (an horizontal rule), with the text or the image
+    /// set by `rendering.scene_break`
+    #[doc(hidden)]
+    pub fn render_scene_break<T>(this: &mut T) -> Result<String>
+        where T: AsMut<HtmlRenderer<'a>> + AsRef<HtmlRenderer<'a>> + Renderer
+    {
+        let book = this.as_ref().book;
+        let text = escape::html(book.clean(book.options.get_str("rendering.scene_break").unwrap(),
+                                           false))
+            .into_owned();
+        let content = if let Ok(image) = book.options.get_path("rendering.scene_break.image") {
+            let html: &mut HtmlRenderer = this.as_mut();
+            let url = html.handler.map_image(&html.source, Cow::Owned(image))?;
+            format!("<img src = \"{}\" alt = \"{}\" />", url, text)
+        } else {
+            text
+        };
+        Ok(format!("<p class = \"rule\">{}</p>\n", content))
+    }
+
     /// Renders a title (without `<h1>` tags), increasing header number beforehand
     #[doc(hidden)]
     pub fn render_title(&mut self, n: i32, vec: &[Token]) -> Result<HeaderData> {
@@ -269,10 +524,10 @@ impl<'a> HtmlRenderer<'a> {
 
     /// Renders a title, including `<h1>` tags and appropriate links
     #[doc(hidden)]
-    pub fn render_title_full(&mut self, n: i32, data: HeaderData) -> Result<String> {
+    pub fn render_title_full(&mut self, n: i32, id: &str, data: HeaderData) -> Result<String> {
         if n == 1 {
             if self.current_hide {
-                Ok(format!("<h1 id = \"link-{}\"></h1>", self.link_number))
+                Ok(format!("<h1 id = \"{}\"></h1>", id))
             } else {
                 let template = if self.current_part {
                     &self.part_template_html
@@ -287,16 +542,24 @@ impl<'a> HtmlRenderer<'a> {
                     .insert_str("header", data.header)
                     .insert_str("number", data.number)
                     .insert_str("link", format!("{}", self.link_number))
+                    .insert_str("id", id)
                     .insert_str("title", data.title)
                     .build();
                 let mut res = vec![];
                 template.render_data(&mut res, &data)?;
-                Ok(String::from_utf8(res)?)
+                let res = String::from_utf8(res)?;
+                // Templates written for previous versions only set the `link-N` anchor,
+                // so add the one used by links and the table of contents
+                if res.contains(&format!("'{}'", id)) || res.contains(&format!("\"{}\"", id)) {
+                    Ok(res)
+                } else {
+                    Ok(format!("<a id = \"{}\"></a>{}", id, res))
+                }
             }
         } else {
-            Ok(format!("<h{} id = \"link-{}\">{}</h{}>\n",
+            Ok(format!("<h{} id = \"{}\">{}</h{}>\n",
                        n,
-                       self.link_number,
+                       id,
                        data.text,
                        n))
         }
@@ -354,10 +617,10 @@ impl<'a> HtmlRenderer<'a> {
     }
 
 
-    /// Display side notes if option is to true
+    /// Display side notes if notes are placed in the margin
     #[doc(hidden)]
     pub fn render_side_notes(&mut self, res: &mut String) {
-        if self.book.options.get_bool("html.side_notes").unwrap() {
+        if self.notes_placement == NotesPlacement::Side {
             for (note_number, footnote) in self.footnotes.drain(..) {
                 write!(res,
                        "<div class = \"sidenote\">\n{} {}\n</div>\n",
@@ -367,43 +630,54 @@ impl<'a> HtmlRenderer<'a> {
         }
     }
 
-    /// Display end notes, if side_notes option is set to false
+    /// Display end notes, or keep them for the end of the book if notes
+    /// are collected there
     #[doc(hidden)]
     pub fn render_end_notes(&mut self, res: &mut String) {
         if !self.footnotes.is_empty() {
-
-            //             for (note_number, footnote) in self.footnotes.drain(..) {
-            //                 res.push_str(&format!("<div class = \"note\">
-            //  <p>{}</p>
-            // {}
-            // </div>\n",
-            //                                       note_number,
-            //                                       footnote));
-            //             }
-
+            if self.notes_placement == NotesPlacement::Book {
+                let notes = mem::replace(&mut self.footnotes, vec![]);
+                let (link, title) = self.notes_chapter.clone();
+                self.book_notes.push((link, title, notes));
+                return;
+            }
 
             write!(res,
                    "<div class = \"notes\">
  <h2 class = \"notes\">{}</h2>\n",
                    lang::get_str(self.book.options.get_str("lang").unwrap(),
                                  "notes")).unwrap();
-            res.push_str("<table class = \"notes\">\n");
-            for (note_number, footnote) in self.footnotes.drain(..) {
+            let notes = mem::replace(&mut self.footnotes, vec![]);
+            notes_table(res, notes);
+            res.push_str("</div>\n");
+        }
+    }
+
+    /// Renders the notes collected for the end of the book, grouped by chapter.
+    ///
+    /// Returns an empty string if there are none.
+    #[doc(hidden)]
+    pub fn render_book_notes(&mut self) -> String {
+        let mut res = String::new();
+        if self.book_notes.is_empty() {
+            return res;
+        }
+        write!(res,
+               "<div class = \"notes\">
+ <h1 class = \"notes\" id = \"notes\">{}</h1>\n",
+               lang::get_str(self.book.options.get_str("lang").unwrap(),
+                             "notes")).unwrap();
+        for (link, title, notes) in self.book_notes.drain(..) {
+            if !title.is_empty() {
                 write!(res,
-                       "<tr class = \"notes\">
- <td class = \"note-number\">
-  {}
- </td>
- <td class = \"note\">
-  {}
-  </td>
-</tr>\n",
-                                      note_number,
-                                      footnote).unwrap();
+                       " <h2 class = \"notes\"><a href = \"{}\">{}</a></h2>\n",
+                       link,
+                       title).unwrap();
             }
-            res.push_str("</table>\n");
-            res.push_str("</div>\n");
+            notes_table(&mut res, notes);
         }
+        res.push_str("</div>\n");
+        res
     }
 
     /// Renders a token
@@ -448,7 +722,13 @@ impl<'a> HtmlRenderer<'a> {
                 let mut content = if this.as_ref().verbatim {
                     Cow::Borrowed(text.as_ref())
                 } else {
-                    escape::html(this.as_ref().book.clean(text.as_ref(), false))
+                    let html: &HtmlRenderer = this.as_ref();
+                    let content = escape::html(html.hyphenate(html.book.clean(text.as_ref(), false)));
+                    if content.contains(SOFT_HYPHEN) {
+                        Cow::Owned(content.replace(SOFT_HYPHEN, "&shy;"))
+                    } else {
+                        content
+                    }
                 };
                 if this.as_ref().first_letter {
                     this.as_mut().first_letter = false;
@@ -476,14 +756,19 @@ impl<'a> HtmlRenderer<'a> {
                 let content = this.render_vec(vec)?;
                 this.as_mut().current_par += 1;
                 let par = this.as_ref().current_par;
-                Ok(format!("<p id = \"para-{}\"{}>{}</p>\n", par, class, content))
+                let output = format!("<p id = \"para-{}\"{}>{}</p>\n", par, class, content);
+                Ok(add_attributes(output, token.attributes()))
             }
             Token::Header(n, ref vec) => {
                 let data = this.as_mut().render_title(n, vec)?;
+                // Headers have an identifier when they are part of a book, but not
+                // necessarily when rendering some arbitrary Markdown
+                let anchor = match token.attributes().and_then(|a| a.id.as_ref()) {
+                    Some(id) => escape::html(id.as_str()).into_owned(),
+                    None => format!("link-{}", this.as_ref().link_number),
+                };
                 if n <= this.as_ref().book.options.get_i32("rendering.num_depth").unwrap() {
-                    let url = format!("{}#link-{}",
-                                      this.as_ref().filename,
-                                      this.as_ref().link_number);
+                    let url = format!("{}#{}", this.as_ref().filename, anchor);
                     if !this.as_ref().current_part {
                         this.as_mut().toc.add(TocElement::new(url, data.text.clone())
                                               .level(n));
@@ -493,16 +778,42 @@ impl<'a> HtmlRenderer<'a> {
                                               .level(n - 1));
                     }
                 }
-                Ok(this.as_mut().render_title_full(n, data)?)
+                if n == 1 {
+                    let link = format!("{}#{}", this.as_ref().filename, anchor);
+                    this.as_mut().notes_chapter = (link, data.text.clone());
+                }
+                let output = this.as_mut().render_title_full(n, &anchor, data)?;
+                // The identifier is already set by render_title_full. Other attributes
+                // go on the header itself, since templates can add other elements
+                let attributes = token.attributes().map(|a| Attributes { id: None, ..a.clone() });
+                let element = format!("h{}", n);
+                Ok(add_attributes_to(output, Some(&element), attributes.as_ref()))
             }
             Token::Emphasis(ref vec) => Ok(format!("<em>{}</em>", this.render_vec(vec)?)),
             Token::Strong(ref vec) => Ok(format!("<b>{}</b>", this.render_vec(vec)?)),
-            Token::Code(ref vec) => Ok(format!("<code>{}</code>", this.render_vec(vec)?)),
+            Token::Code(ref vec) => {
+                let no_hyphenation = this.as_ref().no_hyphenation;
+                this.as_mut().no_hyphenation = true;
+                let content = this.render_vec(vec);
+                this.as_mut().no_hyphenation = no_hyphenation;
+                Ok(format!("<code>{}</code>", content?))
+            }
             Token::Subscript(ref vec) => Ok(format!("<sub>{}</sub>", this.render_vec(vec)?)),
             Token::Superscript(ref vec) => Ok(format!("<sup>{}</sup>", this.render_vec(vec)?)),
+            Token::Strikethrough(ref vec) => Ok(format!("<del>{}</del>", this.render_vec(vec)?)),
             Token::BlockQuote(ref vec) => {
                 Ok(format!("<blockquote>{}</blockquote>\n", this.render_vec(vec)?))
             }
+            Token::Admonition(ref kind, ref title, ref vec) => {
+                HtmlRenderer::render_admonition(this, "aside", kind, title, vec)
+            }
+            Token::Verse(numbers, ref vec) => HtmlRenderer::render_verse(this, numbers, vec),
+            Token::Stanza(ref vec) => {
+                Ok(format!("<p class = \"stanza\">\n{}</p>\n", this.render_vec(vec)?))
+            }
+            Token::VerseLine(indent, ref vec) => {
+                HtmlRenderer::render_verse_line(this, indent, None, vec)
+            }
             Token::CodeBlock(ref language, ref vec) => {
                 this.as_mut().verbatim = true;
                 let s = this.render_vec(vec)?;
@@ -518,7 +829,7 @@ impl<'a> HtmlRenderer<'a> {
                 this.as_mut().verbatim = false;
                 Ok(output)
             }
-            Token::Rule => Ok(String::from("<p class = \"rule\">***</p>\n")),
+            Token::Rule => HtmlRenderer::render_scene_break(this),
             Token::SoftBreak => Ok(String::from(" ")),
             Token::HardBreak => Ok(String::from("<br />\n")),
             Token::List(ref vec) => Ok(format!("<ul>\n{}</ul>\n", this.render_vec(vec)?)),
@@ -532,14 +843,28 @@ impl<'a> HtmlRenderer<'a> {
                            this.render_vec(vec)?))
             }
             Token::Item(ref vec) => Ok(format!("<li>{}</li>\n", this.render_vec(vec)?)),
+            Token::TaskItem(checked, ref vec) => {
+                Ok(format!("<li class = \"task\"><input type = \"checkbox\" disabled = \"disabled\"{} /> {}</li>\n",
+                           if checked { " checked = \"checked\"" } else { "" },
+                           this.render_vec(vec)?))
+            }
+            Token::DefinitionList(ref vec) => Ok(format!("<dl>\n{}</dl>\n", this.render_vec(vec)?)),
+            Token::DefinitionTerm(ref vec) => Ok(format!("<dt>{}</dt>\n", this.render_vec(vec)?)),
+            Token::DefinitionDescription(ref vec) => {
+                Ok(format!("<dd>{}</dd>\n", this.render_vec(vec)?))
+            }
             Token::Link(ref url, ref title, ref vec) => {
                 let url = escape::html(url.as_ref());
                 let url = if ResourceHandler::is_local(&url) {
-                    Cow::Owned(this.as_ref().handler.get_link(&url).to_owned())
+                    Cow::Owned(this.as_ref().handler.get_link(&url).into_owned())
                 } else {
                     url
                 };
 
+                let no_hyphenation = this.as_ref().no_hyphenation;
+                this.as_mut().no_hyphenation = true;
+                let content = this.render_vec(vec);
+                this.as_mut().no_hyphenation = no_hyphenation;
                 Ok(format!("<a href = \"{}\"{}>{}</a>",
                            url,
                            if title.is_empty() {
@@ -547,7 +872,7 @@ impl<'a> HtmlRenderer<'a> {
                            } else {
                                format!(" title = \"{}\"", title)
                            },
-                           this.render_vec(vec)?))
+                           content?))
             }
             Token::Image(ref url, ref title, ref alt) |
             Token::StandaloneImage(ref url, ref title, ref alt) => {
@@ -555,34 +880,54 @@ impl<'a> HtmlRenderer<'a> {
                 let html: &mut HtmlRenderer = this.as_mut();
                 let url = html.handler.map_image(&html.source, url.as_ref())?;
 
-                if token.is_image() {
-                    Ok(format!("<img src = \"{}\" title = \"{}\" alt = \"{}\" />",
-                               url,
-                               title,
-                               content))
+                let output = if token.is_image() {
+                    format!("<img src = \"{}\" title = \"{}\" alt = \"{}\" />",
+                            url,
+                            title,
+                            content)
                 } else {
-                    Ok(format!("<div class = \"image\">
+                    format!("<div class = \"image\">
   <img src = \"{}\" title = \"{}\" alt = \
-                                \"{}\" />
+                             \"{}\" />
 </div>",
-                               url,
-                               title,
-                               content))
-                }
+                            url,
+                            title,
+                            content)
+                };
+                Ok(add_attributes(output, token.attributes()))
             }
-            Token::Table(_, ref vec) => {
+            Token::Table(ref alignments, ref vec) => {
+                this.as_mut().table_alignments = alignments.clone();
                 Ok(format!("<div class = \"table\">
     <table>\n{}
     </table>
 </div>\n",
                            this.render_vec(vec)?))
             }
-            Token::TableRow(ref vec) => Ok(format!("<tr>\n{}</tr>\n", this.render_vec(vec)?)),
+            Token::TableRow(ref vec) => {
+                this.as_mut().table_column = 0;
+                Ok(format!("<tr>\n{}</tr>\n", this.render_vec(vec)?))
+            }
             Token::TableCell(ref vec) => {
                 let tag = if this.as_ref().table_head { "th" } else { "td" };
-                Ok(format!("<{}>{}</{}>", tag, this.render_vec(vec)?, tag))
+                let style = {
+                    let html = this.as_mut();
+                    let alignment = html.table_alignments
+                        .get(html.table_column)
+                        .cloned()
+                        .unwrap_or(Alignment::Default);
+                    html.table_column += 1;
+                    match alignment {
+                        Alignment::Default => "",
+                        Alignment::Left => " style = \"text-align: left\"",
+                        Alignment::Center => " style = \"text-align: center\"",
+                        Alignment::Right => " style = \"text-align: right\"",
+                    }
+                };
+                Ok(format!("<{}{}>{}</{}>", tag, style, this.render_vec(vec)?, tag))
             }
             Token::TableHead(ref vec) => {
+                this.as_mut().table_column = 0;
                 this.as_mut().table_head = true;
                 let s = this.render_vec(vec)?;
                 this.as_mut().table_head = false;
@@ -593,10 +938,12 @@ impl<'a> HtmlRenderer<'a> {
                 let number = this.as_ref().footnote_number;
                 assert!(!vec.is_empty());
 
+                let (dest, source) = this.as_ref().footnote_links(number);
+
                 let note_number = format!("<p class = \"note-number\">
-  <a href = \"#note-source-{}\">[{}]</a>
+  <a href = \"{}\">[{}]</a>
 </p>\n",
-                                          number,
+                                          source,
                                           number);
 
                 let inner = format!("<aside id = \"note-dest-{}\">{}</aside>",
@@ -604,12 +951,31 @@ impl<'a> HtmlRenderer<'a> {
                                     this.render_vec(vec)?);
                 this.as_mut().footnotes.push((note_number, inner));
 
-                Ok(format!("<a href = \"#note-dest-{}\"><sup id = \
+                Ok(format!("<a href = \"{}\"><sup id = \
                             \"note-source-{}\">[{}]</sup></a>",
-                           number,
+                           dest,
                            number,
                            number))
             }
+            Token::Attributes(_) => Ok(String::new()),
+            Token::Span(ref attributes, ref vec) => {
+                let output = format!("<span>{}</span>", this.render_vec(vec)?);
+                Ok(add_attributes(output, Some(attributes)))
+            }
+            Token::OnlyFor(_, ref vec) => {
+                if token.is_rendered_in(&this.as_ref().output_formats) {
+                    this.render_vec(vec)
+                } else {
+                    Ok(String::new())
+                }
+            }
+            Token::RawBlock(_, ref content) => {
+                if token.is_rendered_in(&this.as_ref().output_formats) {
+                    Ok(content.clone())
+                } else {
+                    Ok(String::new())
+                }
+            }
             Token::__NonExhaustive => unreachable!(),
         }
     }
@@ -736,6 +1102,151 @@ impl<'a> Renderer for HtmlRenderer<'a> {
     }
 }
 
+/// Compiles a chapter or part template, warning if it still uses the `link` variable
+/// (the `link-N` anchor of previous versions) instead of `id`
+fn header_template(book: &Book, key: &str) -> Result<Template> {
+    let template = book.options.get_str(key).unwrap();
+    if template.contains("link}}") {
+        warn!("{}", lformat!("{key}: the 'link' variable is deprecated, use 'id' for the \
+                              identifier of the header instead",
+                             key = key));
+    }
+    compile_str(template, Source::empty(), key)
+}
+
+/// Writes a table of notes (number, content)
+fn notes_table(res: &mut String, notes: Vec<(String, String)>) {
+    res.push_str("<table class = \"notes\">\n");
+    for (note_number, footnote) in notes {
+        write!(res,
+               "<tr class = \"notes\">
+ <td class = \"note-number\">
+  {}
+ </td>
+ <td class = \"note\">
+  {}
+  </td>
+</tr>\n",
+               note_number,
+               footnote).unwrap();
+    }
+    res.push_str("</table>\n");
+}
+
+/// Adds attributes (set with the `{#id .class key=value}` syntax) to the first
+/// tag of some rendered HTML.
+///
+/// The id replaces the existing one, if any, and classes are added to the existing ones.
+/// Other attributes are ignored (with a warning) if their name isn't valid, if they
+/// could change the behaviour or the content of the element (event handlers, `src`,
+/// `href`, `style`) or if the element already sets them.
+#[doc(hidden)]
+pub fn add_attributes(html: String, attributes: Option<&Attributes>) -> String {
+    add_attributes_to(html, None, attributes)
+}
+
+/// Same as `add_attributes`, but adds the attributes to the first `element` (e.g. `h1`)
+/// if there is one, instead of to the first tag
+fn add_attributes_to(html: String, element: Option<&str>, attributes: Option<&Attributes>) -> String {
+    let attributes = match attributes {
+        Some(attributes) => attributes,
+        None => return html,
+    };
+    let begin = element.and_then(|name| find_element(&html, name)).or_else(|| html.find('<'));
+    let (begin, end) = match begin.and_then(|begin| html[begin..].find('>').map(|n| (begin, begin + n))) {
+        Some((begin, end)) => (begin, end),
+        None => return html,
+    };
+    // Self-closing tag
+    let end = if html[..end].ends_with('/') { end - 1 } else { end };
+    let original = html[begin..end].trim_right().to_owned();
+    let mut tag = original.clone();
+    if let Some(ref id) = attributes.id {
+        tag = set_attribute(&tag, "id", &escape::html(id.as_str()), false);
+    }
+    if !attributes.classes.is_empty() {
+        tag = set_attribute(&tag, "class", &escape::html(attributes.classes.join(" ")), true);
+    }
+    for &(ref key, ref value) in &attributes.pairs {
+        if !is_valid_attribute(key) || find_attribute(&original, key).is_some() {
+            warn!("{}", lformat!("ignoring attribute '{key}': it is not a valid or allowed \
+                                  HTML attribute",
+                                 key = key));
+            continue;
+        }
+        tag = set_attribute(&tag, key, &escape::html(value.as_str()), false);
+    }
+    let separator = if html[end..].starts_with('/') { " " } else { "" };
+    format!("{}{}{}{}", &html[..begin], tag, separator, &html[end..])
+}
+
+/// Returns the position of the first opening tag of an element in some HTML
+fn find_element(html: &str, name: &str) -> Option<usize> {
+    let opening = format!("<{}", name);
+    let mut search = 0;
+    while let Some(n) = html[search..].find(opening.as_str()) {
+        let begin = search + n;
+        search = begin + opening.len();
+        match html[search..].chars().next() {
+            Some(c) if c.is_whitespace() || c == '>' || c == '/' => return Some(begin),
+            _ => (),
+        }
+    }
+    None
+}
+
+/// Checks that an attribute is one of the attributes kept from inline HTML, or a
+/// `data-*` attribute
+fn is_valid_attribute(name: &str) -> bool {
+    if ATTRIBUTES.contains(&name) {
+        return true;
+    }
+    name.starts_with("data-") && name.len() > 5 &&
+    name[5..].chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' ||
+                               c == '.' || c == '-')
+}
+
+/// Sets the value of an attribute in an opening tag (without its closing `>`), or
+/// appends `value` to the existing one if `append` is true
+fn set_attribute(tag: &str, name: &str, value: &str, append: bool) -> String {
+    match find_attribute(tag, name) {
+        Some((value_begin, value_end)) => {
+            let value = if append {
+                format!("{} {}", &tag[value_begin..value_end], value)
+            } else {
+                value.to_owned()
+            };
+            format!("{}{}{}", &tag[..value_begin], value, &tag[value_end..])
+        }
+        None => format!("{} {} = \"{}\"", tag, name, value),
+    }
+}
+
+/// Returns the position of the value of an attribute in an opening tag, if it is set
+fn find_attribute(tag: &str, name: &str) -> Option<(usize, usize)> {
+    let mut search = 0;
+    // Look for an existing attribute, e.g. `name = "value"` or `name='value'`
+    while let Some(n) = tag[search..].find(name) {
+        let start = search + n;
+        search = start + name.len();
+        if !tag[..start].ends_with(char::is_whitespace) {
+            continue;
+        }
+        let rest = tag[search..].trim_left();
+        if !rest.starts_with('=') {
+            continue;
+        }
+        let rest = rest[1..].trim_left();
+        let quote = match rest.chars().next() {
+            Some(c) if c == '"' || c == '\'' => c,
+            _ => continue,
+        };
+        let value_begin = tag.len() - rest.len() + 1;
+        return tag[value_begin..].find(quote).map(|n| (value_begin, value_begin + n));
+    }
+    None
+}
+
 
 /// This macro automatically generates AsRef and AsMut implementations
 /// for a type, to itself and to HtmlRenderer. Type must have a .html element
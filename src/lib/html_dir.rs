@@ -26,6 +26,7 @@ use renderer::Renderer;
 use parser::Parser;
 use book_renderer::BookRenderer;
 use text_view::view_as_text;
+use lang;
 
 use std::io;
 use std::io::Read;
@@ -37,6 +38,8 @@ use std::path::PathBuf;
 use std::borrow::Cow;
 use std::convert::{AsRef, AsMut};
 
+use epub_builder::TocElement;
+
 
 /// Multiple files HTML renderer
 ///
@@ -54,12 +57,14 @@ impl<'a> HtmlDirRenderer<'a> {
                                          .unwrap_or_else(|_| book.options.get_str("rendering.highlight.theme").unwrap()))?;
         html.handler.set_images_mapping(true);
         html.handler.set_base64(false);
+        html.notes_file = String::from("notes.html");
         Ok(HtmlDirRenderer { html: html })
     }
 
     /// Set aproofreading to true
     pub fn proofread(mut self) -> HtmlDirRenderer<'a> {
         self.html.proofread = true;
+        self.html.output_formats.push("proofread");
         self
     }
 
@@ -179,9 +184,12 @@ impl<'a> HtmlDirRenderer<'a> {
     fn write_html(&mut self) -> Result<()> {
         let mut chapters = vec![];
         
+        let mut files = vec![];
         let mut titles = vec![];
         let mut titles_raw = vec![];
-        for (i, chapter) in self.html.book.chapters.iter().enumerate() {
+        let split_chapters = self.html.split_chapters(part_filenamer);
+        for (i, parts) in split_chapters.into_iter().enumerate() {
+            let chapter = &self.html.book.chapters[i];
             let n = chapter.number;
             let v = &chapter.content;
             self.html.chapter_config(i, n, filenamer(i));
@@ -220,13 +228,27 @@ impl<'a> HtmlDirRenderer<'a> {
                     }
                 }
             }
-            titles.push(title);
-            titles_raw.push(title_raw);
-
-            let chapter = HtmlRenderer::render_html(self, v, true);
-            chapters.push(chapter);
+            for (filename, part) in parts {
+                self.html.set_filename(filename.clone());
+                let chapter = HtmlRenderer::render_html(self, part, true);
+                chapters.push(chapter);
+                files.push(filename);
+                titles.push(title.clone());
+                titles_raw.push(title_raw.clone());
+            }
         }
         self.html.source = Source::empty();
+
+        // Add a page with notes collected at the end of the book, if any
+        let notes = self.html.render_book_notes();
+        if !notes.is_empty() {
+            let title = lang::get_str(self.html.book.options.get_str("lang").unwrap(), "notes");
+            self.html.toc.add(TocElement::new(String::from("notes.html"), title.clone()));
+            chapters.push(Ok(notes));
+            files.push(String::from("notes.html"));
+            titles.push(title.clone());
+            titles_raw.push(title);
+        }
         let toc = self.html.toc.render(false);
 
         // render all chapters
@@ -241,7 +263,7 @@ impl<'a> HtmlDirRenderer<'a> {
     « {}
   </a>
 </p>",
-                        filenamer(i - 1),
+                        files[i - 1],
                         titles[i - 1])
             } else {
                 String::new()
@@ -253,7 +275,7 @@ impl<'a> HtmlDirRenderer<'a> {
     {} »
   </a>
 </p>",
-                        filenamer(i + 1),
+                        files[i + 1],
                         titles[i + 1])
             } else {
                 String::new()
@@ -287,7 +309,7 @@ impl<'a> HtmlDirRenderer<'a> {
             let data = mapbuilder.build();
             let mut res = vec![];
             template.render_data(&mut res, &data)?;
-            self.write_file(&filenamer(i), &res)?;
+            self.write_file(&files[i], &res)?;
         }
 
         let mut content = if let Ok(cover) = self.html.book.options.get_path("cover") {
@@ -302,6 +324,13 @@ impl<'a> HtmlDirRenderer<'a> {
                     self.html.book.options.get_str("title").unwrap(),
                     self.html.handler.map_image(&self.html.book.source, Cow::Owned(cover))?
                     .as_ref())
+        } else if let Some(cover) = self.html.book.generated_cover() {
+            self.write_file(cover.file_name(), cover.content())?;
+            format!("<div id = \"cover\">
+  <img class = \"cover\" alt = \"{}\" src = \"{}\" />
+</div>",
+                    self.html.book.options.get_str("title").unwrap(),
+                    cover.file_name())
         } else {
             String::new()
         };
@@ -447,6 +476,15 @@ fn filenamer(i: usize) -> String {
     format!("chapter_{:03}.html", i)
 }
 
+/// Generate a file name given the number of a chapter and of a part in this chapter
+fn part_filenamer(i: usize, j: usize) -> String {
+    if j == 0 {
+        filenamer(i)
+    } else {
+        format!("chapter_{:03}_{}.html", i, j)
+    }
+}
+
 derive_html!{HtmlDirRenderer<'a>, HtmlRenderer::static_render_token}
 
 pub struct HtmlDir {}
@@ -251,6 +251,7 @@ return crowbook_return_variable.replace(/<\\/ul><ul>/g, '');\n",
             content.push_str(chapter);
         }
         self.html.render_end_notes(&mut content);
+        content.push_str(&self.html.render_book_notes());
 
 
         // Render the CSS
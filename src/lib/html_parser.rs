@@ -0,0 +1,360 @@
+// Copyright (C) 2017 Élisabeth HENRY.
+//
+// This file is part of Crowbook.
+//
+// Crowbook is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published
+// by the Free Software Foundation, either version 2.1 of the License, or
+// (at your option) any later version.
+//
+// Crowbook is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Conversion of a (safe) subset of HTML to tokens, so it can be rendered
+//! in all formats and not only in HTML.
+
+use token::{Token, Attributes};
+
+use std::mem;
+use std::char;
+
+/// Elements that are converted to inline tokens
+const INLINE: &'static [&'static str] = &["em", "i", "strong", "b", "code", "sup", "sub", "del",
+                                          "s", "strike", "span"];
+
+/// Elements that are converted to block tokens
+const BLOCK: &'static [&'static str] = &["p", "blockquote"];
+
+/// Void elements
+const VOID: &'static [&'static str] = &["br", "hr"];
+
+/// Attributes that are kept, besides `id` and `class` (other ones, such as `style`
+/// or event handlers, are dropped)
+pub const ATTRIBUTES: &'static [&'static str] = &["lang", "title", "dir"];
+
+/// An HTML tag
+#[derive(Debug, PartialEq)]
+pub enum Tag {
+    /// Opening tag, with its (lowercase) name and attributes
+    Open(String, Attributes),
+    /// Closing tag, with its (lowercase) name
+    Close(String),
+    /// Void element, e.g. `<br>` or `<hr />`
+    Void(String),
+}
+
+/// Returns true if the element `name` can be converted to an inline token
+pub fn is_inline(name: &str) -> bool {
+    INLINE.contains(&name)
+}
+
+/// Parses a single HTML tag.
+///
+/// Returns `None` if it is not valid or not one of the supported elements.
+pub fn parse_tag(s: &str) -> Option<Tag> {
+    let s = s.trim();
+    if !s.starts_with('<') || !s.ends_with('>') || s.len() < 3 {
+        return None;
+    }
+    let inner = &s[1..s.len() - 1];
+    if inner.starts_with('/') {
+        let name = inner[1..].trim().to_lowercase();
+        if is_inline(&name) || BLOCK.contains(&name.as_str()) {
+            return Some(Tag::Close(name));
+        } else {
+            return None;
+        }
+    }
+    let self_closing = inner.ends_with('/');
+    let inner = inner.trim_right_matches('/');
+    let n = inner.find(char::is_whitespace).unwrap_or(inner.len());
+    let name = inner[..n].to_lowercase();
+    if VOID.contains(&name.as_str()) {
+        Some(Tag::Void(name))
+    } else if !self_closing && (is_inline(&name) || BLOCK.contains(&name.as_str())) {
+        match parse_attributes(&inner[n..]) {
+            Some(attributes) => Some(Tag::Open(name, attributes)),
+            None => None,
+        }
+    } else {
+        None
+    }
+}
+
+/// Parses the attributes of an HTML tag, e.g. `class = "foo bar" lang=fr`
+///
+/// Only `id`, `class` and the attributes of `ATTRIBUTES` are kept.
+fn parse_attributes(s: &str) -> Option<Attributes> {
+    let mut attributes = Attributes::default();
+    let mut chars = s.chars().peekable();
+    loop {
+        while chars.peek().map_or(false, |c| c.is_whitespace()) {
+            chars.next();
+        }
+        let mut name = String::new();
+        while let Some(&c) = chars.peek() {
+            if c.is_whitespace() || c == '=' {
+                break;
+            }
+            name.push(c);
+            chars.next();
+        }
+        if name.is_empty() {
+            break;
+        }
+        while chars.peek().map_or(false, |c| c.is_whitespace()) {
+            chars.next();
+        }
+        let mut value = String::new();
+        if chars.peek() == Some(&'=') {
+            chars.next();
+            while chars.peek().map_or(false, |c| c.is_whitespace()) {
+                chars.next();
+            }
+            match chars.peek().cloned() {
+                Some(quote) if quote == '"' || quote == '\'' => {
+                    chars.next();
+                    let mut closed = false;
+                    while let Some(c) = chars.next() {
+                        if c == quote {
+                            closed = true;
+                            break;
+                        }
+                        value.push(c);
+                    }
+                    if !closed {
+                        return None;
+                    }
+                }
+                _ => {
+                    while let Some(&c) = chars.peek() {
+                        if c.is_whitespace() {
+                            break;
+                        }
+                        value.push(c);
+                        chars.next();
+                    }
+                }
+            }
+        }
+        let value = decode_entities(&value);
+        let name = name.to_lowercase();
+        match name.as_ref() {
+            "id" => attributes.id = Some(value),
+            "class" => {
+                attributes.classes.extend(value.split_whitespace().map(|s| s.to_owned()))
+            }
+            name if ATTRIBUTES.contains(&name) => attributes.pairs.push((name.to_owned(), value)),
+            _ => (),
+        }
+    }
+    Some(attributes)
+}
+
+/// Replaces the most common HTML entities by the corresponding characters
+pub fn decode_entities(s: &str) -> String {
+    let mut result = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(n) = rest.find('&') {
+        result.push_str(&rest[..n]);
+        rest = &rest[n..];
+        let decoded = match rest.find(';') {
+            Some(end) => {
+                let entity = &rest[1..end];
+                let c = match entity {
+                    "amp" => Some('&'),
+                    "lt" => Some('<'),
+                    "gt" => Some('>'),
+                    "quot" => Some('"'),
+                    "apos" => Some('\''),
+                    "nbsp" => Some('\u{A0}'),
+                    _ if entity.starts_with("#x") || entity.starts_with("#X") => {
+                        u32::from_str_radix(&entity[2..], 16).ok().and_then(char::from_u32)
+                    }
+                    _ if entity.starts_with('#') => {
+                        entity[1..].parse::<u32>().ok().and_then(char::from_u32)
+                    }
+                    _ => None,
+                };
+                c.map(|c| (c, end + 1))
+            }
+            None => None,
+        };
+        match decoded {
+            Some((c, len)) => {
+                result.push(c);
+                rest = &rest[len..];
+            }
+            None => {
+                result.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+/// Returns the token corresponding to a void element
+pub fn void_token(name: &str) -> Token {
+    match name {
+        "hr" => Token::Rule,
+        _ => Token::HardBreak,
+    }
+}
+
+/// Returns the token corresponding to an element with its content
+pub fn element_token(name: &str, attributes: Attributes, mut content: Vec<Token>) -> Token {
+    let has_attributes = attributes != Attributes::default();
+    let token = match name {
+        "em" | "i" => Token::Emphasis(content),
+        "strong" | "b" => Token::Strong(content),
+        "code" => Token::Code(content),
+        "sup" => Token::Superscript(content),
+        "sub" => Token::Subscript(content),
+        "del" | "s" | "strike" => Token::Strikethrough(content),
+        "blockquote" => Token::BlockQuote(content),
+        "p" => {
+            if has_attributes {
+                content.push(Token::Attributes(attributes));
+            }
+            return Token::Paragraph(content);
+        }
+        _ => return Token::Span(attributes, content),
+    };
+    if has_attributes && name != "blockquote" {
+        Token::Span(attributes, vec![token])
+    } else {
+        token
+    }
+}
+
+/// Converts a block of HTML to tokens.
+///
+/// Returns `None` if it contains elements that are not supported, or if they are
+/// not properly nested.
+pub fn parse_block(html: &str) -> Option<Vec<Token>> {
+    let mut stack: Vec<(String, Attributes, Vec<Token>)> = vec![];
+    let mut current = vec![];
+    let mut rest = html;
+    while !rest.is_empty() {
+        if rest.starts_with('<') {
+            let end = match rest.find('>') {
+                Some(end) => end,
+                None => return None,
+            };
+            match parse_tag(&rest[..end + 1]) {
+                Some(Tag::Open(name, attributes)) => {
+                    let parent = mem::replace(&mut current, vec![]);
+                    stack.push((name, attributes, parent));
+                }
+                Some(Tag::Close(name)) => {
+                    let (open, attributes, parent) = match stack.pop() {
+                        Some(element) => element,
+                        None => return None,
+                    };
+                    if open != name {
+                        return None;
+                    }
+                    let content = mem::replace(&mut current, parent);
+                    let content = if name == "blockquote" {
+                        blocks(content)
+                    } else {
+                        trim_breaks(content)
+                    };
+                    current.push(element_token(&name, attributes, content));
+                }
+                Some(Tag::Void(name)) => current.push(void_token(&name)),
+                None => return None,
+            }
+            rest = &rest[end + 1..];
+        } else {
+            let end = rest.find('<').unwrap_or(rest.len());
+            let text = decode_entities(&rest[..end]);
+            for (i, line) in text.split('\n').enumerate() {
+                if i > 0 {
+                    current.push(Token::SoftBreak);
+                }
+                if !line.is_empty() {
+                    current.push(Token::Str(line.to_owned()));
+                }
+            }
+            rest = &rest[end..];
+        }
+    }
+    if stack.is_empty() {
+        Some(blocks(current))
+    } else {
+        None
+    }
+}
+
+/// Removes the line breaks at the beginning and the end of a list of tokens
+fn trim_breaks(mut tokens: Vec<Token>) -> Vec<Token> {
+    while tokens.last() == Some(&Token::SoftBreak) {
+        tokens.pop();
+    }
+    let n = tokens.iter().position(|t| *t != Token::SoftBreak).unwrap_or(tokens.len());
+    tokens.split_off(n)
+}
+
+/// Puts the inline tokens that are not in a block element in paragraphs
+fn blocks(tokens: Vec<Token>) -> Vec<Token> {
+    let mut result = vec![];
+    let mut inline = vec![];
+    for token in tokens {
+        match token {
+            Token::Paragraph(..) | Token::BlockQuote(..) | Token::Rule => {
+                let inline = trim_breaks(mem::replace(&mut inline, vec![]));
+                if !is_blank(&inline) {
+                    result.push(Token::Paragraph(inline));
+                }
+                result.push(token);
+            }
+            token => inline.push(token),
+        }
+    }
+    let inline = trim_breaks(inline);
+    if !is_blank(&inline) {
+        result.push(Token::Paragraph(inline));
+    }
+    result
+}
+
+/// Returns true if tokens only contain whitespace
+fn is_blank(tokens: &[Token]) -> bool {
+    tokens.iter().all(|t| match *t {
+        Token::SoftBreak => true,
+        Token::Str(ref s) => s.trim().is_empty(),
+        _ => false,
+    })
+}
+
+#[test]
+fn html_parse_tag() {
+    assert_eq!(parse_tag("<br/>"), Some(Tag::Void(String::from("br"))));
+    assert_eq!(parse_tag("</EM>"), Some(Tag::Close(String::from("em"))));
+    let attributes = Attributes {
+        id: Some(String::from("x")),
+        classes: vec![String::from("a"), String::from("b")],
+        pairs: vec![(String::from("lang"), String::from("fr"))],
+    };
+    assert_eq!(parse_tag("<span id=x class = 'a b' lang=\"fr\">"),
+               Some(Tag::Open(String::from("span"), attributes)));
+    assert_eq!(parse_tag("<span onclick=x style='color: red' TITLE=t>"),
+               Some(Tag::Open(String::from("span"),
+                              Attributes {
+                                  pairs: vec![(String::from("title"), String::from("t"))],
+                                  ..Attributes::default()
+                              })));
+    assert_eq!(parse_tag("<span onclick=x>"),
+               Some(Tag::Open(String::from("span"), Attributes::default())));
+    assert_eq!(parse_tag("<script>"), None);
+    assert_eq!(parse_tag("<span class=\"a>"), None);
+    assert_eq!(decode_entities("a &amp; b &#233; &unknown; &"), "a & b é &unknown; &");
+}
@@ -18,7 +18,7 @@
 use error::{Error, Result, Source};
 use html::HtmlRenderer;
 use html::Highlight;
-use book::{Book, compile_str};
+use book::{Book, NotesPlacement, compile_str};
 use token::Token;
 use templates::img;
 use renderer::Renderer;
@@ -53,6 +53,7 @@ impl<'a> HtmlSingleRenderer<'a> {
     /// Set aproofreading to true
     pub fn proofread(mut self) -> HtmlSingleRenderer<'a> {
         self.html.proofread = true;
+        self.html.output_formats.push("proofread");
         self
     }
 
@@ -86,12 +87,19 @@ impl<'a> HtmlSingleRenderer<'a> {
         let mut titles = vec![];
         let mut chapters = vec![];
         let render_notes_chapter =
-            self.html.book.options.get_bool("html.standalone.one_chapter").unwrap();
+            self.html.book.options.get_bool("html.standalone.one_chapter").unwrap() ||
+            self.html.notes_placement == NotesPlacement::Chapter ||
+            self.html.notes_placement == NotesPlacement::Book;
 
         for (i, chapter) in self.html.book.chapters.iter().enumerate() {
             self.html.handler.add_link(chapter.filename.as_ref(),
                                        format!("#chapter-{}", i));
         }
+        // Everything is in the same file, so links to anchors don't need a file name
+        let book = self.html.book;
+        for chapter in &book.chapters {
+            self.html.add_id_anchors(&chapter.content, "");
+        }
         
         for (i, chapter) in self.html.book.chapters.iter().enumerate() {
             let n = chapter.number;
@@ -163,6 +171,7 @@ impl<'a> HtmlSingleRenderer<'a> {
             }
         }
         self.html.render_end_notes(&mut content);
+        content.push_str(&self.html.render_book_notes());
 
 
         let toc = self.html.toc.render(false);
@@ -236,6 +245,12 @@ impl<'a> HtmlSingleRenderer<'a> {
                 let favicon = self.html.handler.map_image(&self.html.book.source, favicon)?;
                 mapbuilder = mapbuilder.insert_str("favicon", format!("<link rel = \"icon\" href = \"{}\">", favicon));
             }
+        if let Some(cover) = self.html.book.generated_cover() {
+            let cover = format!("data:{};base64,{}",
+                                cover.mime_type(),
+                                cover.content().to_base64(base64::STANDARD));
+            mapbuilder = mapbuilder.insert_str("generated_cover", cover);
+        }
         if !self.html.toc.is_empty() {
             mapbuilder = mapbuilder.insert_bool("has_toc", true);
             mapbuilder = mapbuilder.insert_str("toc", toc)
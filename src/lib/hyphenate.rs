@@ -0,0 +1,196 @@
+// Copyright (C) 2017 Élisabeth HENRY.
+//
+// This file is part of Crowbook.
+//
+// Crowbook is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published
+// by the Free Software Foundation, either version 2.1 of the License, or
+// (at your option) any later version.
+//
+// Crowbook is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Insertion of soft hyphens in text, for renderers that can't rely on
+//! the hyphenation of the reading software.
+
+use book::Book;
+
+use hyphenation;
+use hyphenation::{Corpus, Hyphenation, Language};
+
+/// Unicode soft hyphen
+pub const SOFT_HYPHEN: char = '\u{AD}';
+
+/// Inserts soft hyphens in words, according to the book's language
+pub struct Hyphenator {
+    corpus: Corpus,
+    min_length: usize,
+    left_min: usize,
+    right_min: usize,
+}
+
+/// Returns the hyphenation patterns matching a language tag (e.g. `fr`, `fr-FR` or
+/// `en_US`), if there are some
+pub fn language(tag: &str) -> Option<Language> {
+    let tag = tag.trim().to_lowercase().replace('_', "-");
+    let (primary, region) = match tag.find('-') {
+        Some(n) => (&tag[..n], &tag[n + 1..]),
+        None => (tag.as_str(), ""),
+    };
+    let lang = match (primary, region) {
+        ("en", "us") => Language::English_US,
+        ("en", _) => Language::English_GB,
+        ("de", "ch") => Language::German_Swiss,
+        ("de", _) => Language::German_1996,
+        ("nn", _) => Language::Norwegian_Nynorsk,
+        ("no", _) | ("nb", _) => Language::Norwegian_Bokmal,
+        ("sr", _) => Language::Serbian_Cyrillic,
+        ("af", _) => Language::Afrikaans,
+        ("bg", _) => Language::Bulgarian,
+        ("ca", _) => Language::Catalan,
+        ("cs", _) | ("cz", _) => Language::Czech,
+        ("cy", _) => Language::Welsh,
+        ("da", _) => Language::Danish,
+        ("el", _) => Language::Greek_Mono,
+        ("eo", _) => Language::Esperanto,
+        ("es", _) => Language::Spanish,
+        ("et", _) => Language::Estonian,
+        ("eu", _) => Language::Basque,
+        ("fi", _) => Language::Finnish,
+        ("fr", _) => Language::French,
+        ("ga", _) => Language::Irish,
+        ("gl", _) => Language::Galician,
+        ("hr", _) => Language::Croatian,
+        ("hu", _) => Language::Hungarian,
+        ("hy", _) => Language::Armenian,
+        ("id", _) => Language::Indonesian,
+        ("is", _) => Language::Icelandic,
+        ("it", _) => Language::Italian,
+        ("la", _) => Language::Latin,
+        ("lt", _) => Language::Lithuanian,
+        ("lv", _) => Language::Latvian,
+        ("nl", _) => Language::Dutch,
+        ("oc", _) => Language::Occitan,
+        ("pl", _) => Language::Polish,
+        ("pt", _) => Language::Portuguese,
+        ("ro", _) => Language::Romanian,
+        ("ru", _) => Language::Russian,
+        ("sk", _) => Language::Slovak,
+        ("sl", _) => Language::Slovenian,
+        ("sv", _) => Language::Swedish,
+        ("tr", _) | ("tk", _) => Language::Turkish,
+        ("uk", _) => Language::Ukrainian,
+        _ => return None,
+    };
+    Some(lang)
+}
+
+impl Hyphenator {
+    /// Creates a new hyphenator if the (boolean) option `key` is set.
+    ///
+    /// The minimal lengths are read from `key.min_length`, `key.left_min` and
+    /// `key.right_min`, or from the `html.hyphenate.*` options if they aren't set.
+    ///
+    /// Returns `None` if hyphenation is disabled, or (with a warning) if no patterns
+    /// are available for the book's language.
+    pub fn new(book: &Book, key: &str) -> Option<Hyphenator> {
+        if !book.options.get_bool(key).unwrap() {
+            return None;
+        }
+        let lang = book.options.get_str("lang").unwrap();
+        let lang = match language(lang) {
+            Some(lang) => lang,
+            None => {
+                warn!("{}",
+                      lformat!("{key}: no hyphenation patterns for language '{lang}', soft \
+                                hyphens won't be inserted",
+                               key = key,
+                               lang = lang));
+                return None;
+            }
+        };
+        let corpus = match hyphenation::load(lang) {
+            Ok(corpus) => corpus,
+            Err(_) => {
+                warn!("{}",
+                      lformat!("{key}: could not load hyphenation patterns, soft hyphens won't \
+                                be inserted",
+                               key = key));
+                return None;
+            }
+        };
+        let get = |name: &str| {
+            let n = book.options
+                .get_i32(&format!("{}.{}", key, name))
+                .unwrap_or_else(|_| book.options.get_i32(&format!("html.hyphenate.{}", name)).unwrap());
+            if n < 0 { 0 } else { n as usize }
+        };
+        Some(Hyphenator {
+            corpus: corpus,
+            min_length: get("min_length"),
+            left_min: get("left_min"),
+            right_min: get("right_min"),
+        })
+    }
+
+    /// Inserts soft hyphens (as the unicode character) in all words of `text`
+    /// that are long enough.
+    pub fn hyphenate(&self, text: &str) -> String {
+        let mut result = String::with_capacity(text.len());
+        let mut word_start = None;
+        for (i, c) in text.char_indices() {
+            if c.is_alphabetic() {
+                if word_start.is_none() {
+                    word_start = Some(i);
+                }
+            } else {
+                if let Some(start) = word_start.take() {
+                    self.hyphenate_word(&text[start..i], &mut result);
+                }
+                result.push(c);
+            }
+        }
+        if let Some(start) = word_start {
+            self.hyphenate_word(&text[start..], &mut result);
+        }
+        result
+    }
+
+    /// Pushes `word` to `result`, with soft hyphens at hyphenation opportunities
+    fn hyphenate_word(&self, word: &str, result: &mut String) {
+        let len = word.chars().count();
+        if len < self.min_length {
+            result.push_str(word);
+            return;
+        }
+        let mut last = 0;
+        for i in word.opportunities(&self.corpus) {
+            if !word.is_char_boundary(i) {
+                continue;
+            }
+            let before = word[..i].chars().count();
+            if before < self.left_min || len - before < self.right_min || i <= last {
+                continue;
+            }
+            result.push_str(&word[last..i]);
+            result.push(SOFT_HYPHEN);
+            last = i;
+        }
+        result.push_str(&word[last..]);
+    }
+}
+
+#[test]
+fn hyphenate_language() {
+    assert_eq!(language("fr"), Some(Language::French));
+    assert_eq!(language("fr-FR"), Some(Language::French));
+    assert_eq!(language("en_US"), Some(Language::English_US));
+    assert_eq!(language("EN"), Some(Language::English_GB));
+    assert_eq!(language("ru"), Some(Language::Russian));
+    assert_eq!(language("ja"), None);
+}
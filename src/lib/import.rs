@@ -0,0 +1,2146 @@
+// Copyright (C) 2017 Élisabeth HENRY.
+//
+// This file is part of Crowbook.
+//
+// Crowbook is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published
+// by the Free Software Foundation, either version 2.1 of the License, or
+// (at your option) any later version.
+//
+// Crowbook is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Import of EPUB, ODT and DOCX documents into a Crowbook project.
+
+use error::{Error, Result, Source};
+use parser::escape_block_markers;
+
+use zip::ZipArchive;
+use xml::reader::{ParserConfig, XmlEvent};
+
+use std::cmp;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{Read, Seek, Write};
+use std::mem;
+use std::path::Path;
+
+/// Imports a document (EPUB, ODT or DOCX) into a new Crowbook project.
+///
+/// The content of the document is converted to Markdown and split into chapters at
+/// its top-level headings. The chapters (`chapter_01.md`, `chapter_02.md`, ...) and the
+/// images of the document (in `images/`) are written in the directory of `book`, then
+/// the book configuration file `book` is created, with the metadata of the document and
+/// the list of chapters.
+///
+/// Existing files are never overwritten: an error is returned instead.
+///
+/// Returns the number of chapters.
+pub fn import_document<P: AsRef<Path>, Q: AsRef<Path>>(document: P, book: Q) -> Result<usize> {
+    let document = document.as_ref();
+    let book = book.as_ref();
+    let name = format!("{}", document.display());
+    let extension = document.extension()
+        .map(|s| s.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    let file = File::open(document)
+        .map_err(|_| Error::file_not_found(Source::empty(), lformat!("document to import"), name.clone()))?;
+    let zip = ZipArchive::new(file)
+        .map_err(|err| Error::default(Source::new(name.as_str()),
+                                      lformat!("could not read document as a zip archive: {error}",
+                                               error = err)))?;
+    let mut archive = Archive {
+        zip: zip,
+        file: name.clone(),
+    };
+    let imported = match extension.as_str() {
+        "epub" => import_epub(&mut archive)?,
+        "odt" => import_odt(&mut archive)?,
+        "docx" => import_docx(&mut archive)?,
+        _ => {
+            return Err(Error::default(Source::new(name.as_str()),
+                                      lformat!("could not import document: unsupported format, \
+                                                expected an EPUB, ODT or DOCX file")));
+        }
+    };
+    write_project(&mut archive, imported, book)
+}
+
+/// Writes the chapters, images and book configuration file of an imported document
+fn write_project<R: Read + Seek>(archive: &mut Archive<R>,
+                                 imported: Imported,
+                                 book: &Path)
+                                 -> Result<usize> {
+    let dir = match book.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => Path::new(".").to_path_buf(),
+    };
+    let images = imported.markdown.images.clone();
+    let chapters = imported.markdown.chapters();
+    let names: Vec<String> = (0..chapters.len())
+        .map(|i| format!("chapter_{:02}.md", i + 1))
+        .collect();
+
+    let mut files = vec![book.to_path_buf()];
+    files.extend(names.iter().map(|name| dir.join(name)));
+    files.extend(images.iter().map(|&(_, ref name)| dir.join("images").join(name)));
+    for file in &files {
+        if fs::metadata(file).is_ok() {
+            return Err(Error::default(Source::empty(),
+                                      lformat!("could not import document: file {file} already exists",
+                                               file = file.display())));
+        }
+    }
+
+    let mut directories = vec![dir.clone()];
+    if !images.is_empty() {
+        directories.push(dir.join("images"));
+    }
+    for directory in directories {
+        fs::create_dir_all(&directory)
+            .map_err(|err| Error::default(Source::empty(),
+                                          lformat!("could not create directory {path}: {error}",
+                                                   path = directory.display(),
+                                                   error = err)))?;
+    }
+    for (path, name) in images {
+        match archive.read(&path) {
+            Ok(data) => write_file(&dir.join("images").join(name), &data)?,
+            Err(err) => warn!("{}", err),
+        }
+    }
+    for (name, &(_, ref content)) in names.iter().zip(chapters.iter()) {
+        write_file(&dir.join(name), content.as_bytes())?;
+    }
+
+    let mut config = String::new();
+    for &(key, ref value) in &imported.metadata {
+        config.push_str(&format!("{}: {}\n", key, yaml_string(value)));
+    }
+    config.push_str(&lformat!("
+## Output formats
+
+# Uncomment and fill to generate files
+# output.html: some_file.html
+# output.epub: some_file.epub
+# output.pdf: some_file.pdf
+
+# Or uncomment the following to generate PDF, HTML and EPUB files based on this file's name
+# output: [pdf, epub, html]
+"));
+    config.push_str(&lformat!("\n## List of chapters\n"));
+    for (name, &(numbered, _)) in names.iter().zip(chapters.iter()) {
+        config.push_str(&format!("{} {}\n", if numbered { "+" } else { "-" }, name));
+    }
+    write_file(book, config.as_bytes())?;
+    Ok(chapters.len())
+}
+
+/// Writes the content of a new file
+fn write_file(path: &Path, content: &[u8]) -> Result<()> {
+    let mut file = File::create(path)
+        .map_err(|err| Error::default(Source::empty(),
+                                      lformat!("could not create file {file}: {error}",
+                                               file = path.display(),
+                                               error = err)))?;
+    file.write_all(content)
+        .map_err(|err| Error::default(Source::empty(),
+                                      lformat!("could not write to file {file}: {error}",
+                                               file = path.display(),
+                                               error = err)))
+}
+
+/// Quotes a string for the YAML book configuration file
+fn yaml_string(s: &str) -> String {
+    format!("\"{}\"",
+            s.replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n"))
+}
+
+/// Result of the conversion of a document
+struct Imported {
+    metadata: Vec<(&'static str, String)>,
+    markdown: Markdown,
+}
+
+/// Document to import, read as a zip archive
+struct Archive<R: Read + Seek = File> {
+    zip: ZipArchive<R>,
+    file: String,
+}
+
+impl<R: Read + Seek> Archive<R> {
+    /// Returns true if the archive contains a file
+    fn contains(&mut self, path: &str) -> bool {
+        self.zip.by_name(path).is_ok()
+    }
+
+    /// Reads a file of the archive
+    fn read(&mut self, path: &str) -> Result<Vec<u8>> {
+        let mut content = vec![];
+        let result = match self.zip.by_name(path) {
+            Ok(mut file) => {
+                file.read_to_end(&mut content)
+                    .map(|_| ())
+                    .map_err(|err| format!("{}", err))
+            }
+            Err(err) => Err(format!("{}", err)),
+        };
+        result.map(|_| content)
+            .map_err(|err| Error::default(Source::new(self.file.as_str()),
+                                          lformat!("could not read {path}: {error}",
+                                                   path = path,
+                                                   error = err)))
+    }
+
+    /// Reads and parses a XML file of the archive
+    fn read_xml(&mut self, path: &str) -> Result<Element> {
+        let content = self.read(path)?;
+        let source = format!("{}:{}", self.file, path);
+        parse_xml(&String::from_utf8_lossy(&content), &source)
+    }
+}
+
+/// A node of a XML document
+#[derive(Debug, Clone)]
+enum Node {
+    Element(Element),
+    Text(String),
+}
+
+/// A XML element. Names of elements and attributes don't include their namespaces.
+#[derive(Debug, Clone)]
+struct Element {
+    name: String,
+    attributes: Vec<(String, String)>,
+    children: Vec<Node>,
+}
+
+impl Element {
+    /// Returns the value of an attribute
+    fn attr(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|&&(ref key, _)| key == name)
+            .map(|&(_, ref value)| value.as_str())
+    }
+
+    /// Returns the child elements
+    fn elements(&self) -> Vec<&Element> {
+        self.children
+            .iter()
+            .filter_map(|node| match *node {
+                Node::Element(ref element) => Some(element),
+                Node::Text(_) => None,
+            })
+            .collect()
+    }
+
+    /// Returns the child elements with a given name
+    fn children_named(&self, name: &str) -> Vec<&Element> {
+        self.elements()
+            .into_iter()
+            .filter(|element| element.name == name)
+            .collect()
+    }
+
+    /// Returns the first child element with a given name
+    fn child(&self, name: &str) -> Option<&Element> {
+        self.elements()
+            .into_iter()
+            .find(|element| element.name == name)
+    }
+
+    /// Returns the first descendant element with a given name
+    fn find(&self, name: &str) -> Option<&Element> {
+        for element in self.elements() {
+            if element.name == name {
+                return Some(element);
+            }
+            if let Some(found) = element.find(name) {
+                return Some(found);
+            }
+        }
+        None
+    }
+
+    /// Returns all the descendant elements with a given name
+    fn find_all<'a>(&'a self, name: &str, result: &mut Vec<&'a Element>) {
+        for element in self.elements() {
+            if element.name == name {
+                result.push(element);
+            } else {
+                element.find_all(name, result);
+            }
+        }
+    }
+
+    /// Returns the text content of the element
+    fn text(&self) -> String {
+        let mut text = String::new();
+        for node in &self.children {
+            match *node {
+                Node::Text(ref s) => text.push_str(s),
+                Node::Element(ref element) => text.push_str(&element.text()),
+            }
+        }
+        text
+    }
+}
+
+/// Parses a XML document and returns its root element
+fn parse_xml(content: &str, source: &str) -> Result<Element> {
+    // The only HTML entity that is commonly found in XHTML files
+    let content = content.replace("&nbsp;", "&#160;");
+    let reader = ParserConfig::new()
+        .trim_whitespace(false)
+        .whitespace_to_characters(true)
+        .cdata_to_characters(true)
+        .ignore_comments(true)
+        .coalesce_characters(true)
+        .create_reader(content.as_bytes());
+    let mut stack: Vec<Element> = vec![];
+    for event in reader {
+        match event {
+            Ok(XmlEvent::StartElement { name, attributes, .. }) => {
+                stack.push(Element {
+                    name: name.local_name,
+                    attributes: attributes.into_iter()
+                        .map(|attribute| (attribute.name.local_name, attribute.value))
+                        .collect(),
+                    children: vec![],
+                });
+            }
+            Ok(XmlEvent::EndElement { .. }) => {
+                // The XML parser checks that elements are balanced
+                let element = stack.pop().unwrap();
+                match stack.last_mut() {
+                    Some(parent) => parent.children.push(Node::Element(element)),
+                    None => return Ok(element),
+                }
+            }
+            Ok(XmlEvent::Characters(text)) => {
+                if let Some(parent) = stack.last_mut() {
+                    parent.children.push(Node::Text(text));
+                }
+            }
+            Ok(_) => {}
+            Err(err) => {
+                return Err(Error::parser(Source::new(source),
+                                         lformat!("could not parse XML: {error}", error = err)));
+            }
+        }
+    }
+    Err(Error::parser(Source::new(source),
+                      lformat!("could not parse XML: document is empty")))
+}
+
+/// Returns the directory part of a path in the archive
+fn parent_dir(path: &str) -> String {
+    match path.rfind('/') {
+        Some(n) => path[..n].to_owned(),
+        None => String::new(),
+    }
+}
+
+/// Resolves a link relative to a directory of the archive
+fn resolve_path(dir: &str, link: &str) -> String {
+    let link = match link.find(|c: char| c == '#' || c == '?') {
+        Some(n) => &link[..n],
+        None => link,
+    };
+    let mut components: Vec<&str> = vec![];
+    let full = if link.starts_with('/') {
+        link.to_owned()
+    } else {
+        format!("{}/{}", dir, link)
+    };
+    for component in full.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => {
+                components.pop();
+            }
+            _ => components.push(component),
+        }
+    }
+    components.join("/")
+}
+
+/// Decodes the percent-encoded characters of a link
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut result = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() &&
+           (bytes[i + 1] as char).is_digit(16) && (bytes[i + 2] as char).is_digit(16) {
+            result.push(u8::from_str_radix(&s[i + 1..i + 3], 16).unwrap());
+            i += 3;
+            continue;
+        }
+        result.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&result).into_owned()
+}
+
+/// Formatting of a span of text
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+struct Style {
+    italic: bool,
+    bold: bool,
+    code: bool,
+}
+
+/// A span of text, either plain text or already converted to Markdown (`raw`)
+#[derive(Debug, Clone)]
+struct Span {
+    text: String,
+    raw: bool,
+    style: Style,
+}
+
+/// Adds plain text to a list of spans, collapsing whitespace
+fn push_text(spans: &mut Vec<Span>, text: &str, style: Style) {
+    let mut collapsed = String::with_capacity(text.len());
+    let mut space = match spans.last() {
+        Some(span) => span.text.ends_with(' ') || span.text.ends_with('\n'),
+        None => true,
+    };
+    for c in text.chars() {
+        if c == ' ' || c == '\t' || c == '\n' || c == '\r' {
+            if !space {
+                collapsed.push(' ');
+            }
+            space = true;
+        } else {
+            collapsed.push(c);
+            space = false;
+        }
+    }
+    if collapsed.is_empty() {
+        return;
+    }
+    if let Some(span) = spans.last_mut() {
+        if !span.raw && span.style == style {
+            span.text.push_str(&collapsed);
+            return;
+        }
+    }
+    spans.push(Span {
+        text: collapsed,
+        raw: false,
+        style: style,
+    });
+}
+
+/// Returns a span containing Markdown
+fn raw(text: String, style: Style) -> Span {
+    Span {
+        text: text,
+        raw: true,
+        style: style,
+    }
+}
+
+/// Returns a hard line break
+fn line_break() -> Span {
+    raw(String::from("\\\n"), Style::default())
+}
+
+/// Returns a link to an external URL
+fn link(content: &[Span], url: &str, style: Style) -> Span {
+    let url = url.replace(' ', "%20").replace('(', "%28").replace(')', "%29");
+    let text = single_line(&render_inlines(content));
+    if text.is_empty() {
+        raw(format!("<{}>", url), style)
+    } else {
+        raw(format!("[{}]({})", text, url), style)
+    }
+}
+
+/// Escapes the characters that have a meaning in Markdown
+fn escape(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' | '*' | '_' | '`' | '[' | ']' | '<' => result.push('\\'),
+            // Avoids variable substitution and includes
+            '{' if result.ends_with('{') => {
+                result.pop();
+                result.push_str("\\{");
+            }
+            _ => {}
+        }
+        result.push(c);
+    }
+    result
+}
+
+/// Returns a code span
+fn code_span(text: &str) -> String {
+    let mut longest = 0;
+    let mut current = 0;
+    for c in text.chars() {
+        if c == '`' {
+            current += 1;
+            longest = cmp::max(longest, current);
+        } else {
+            current = 0;
+        }
+    }
+    let fence = "`".repeat(longest + 1);
+    if text.starts_with('`') || text.ends_with('`') {
+        format!("{} {} {}", fence, text, fence)
+    } else {
+        format!("{}{}{}", fence, text, fence)
+    }
+}
+
+/// Returns the emphasis markers corresponding to a style
+fn markers(style: Style) -> Vec<&'static str> {
+    let mut markers = vec![];
+    if style.bold {
+        markers.push("**");
+    }
+    if style.italic {
+        markers.push("*");
+    }
+    markers
+}
+
+/// Closes the emphasis markers after the `keep` first ones, moving trailing spaces
+/// and line breaks after them
+fn close_markers(out: &mut String, open: &mut Vec<&'static str>, keep: usize) {
+    if open.len() <= keep {
+        return;
+    }
+    let mut len = out.trim_right_matches(' ').len();
+    if out[..len].ends_with("\\\n") {
+        len -= 2;
+    }
+    let trail = out[len..].to_owned();
+    out.truncate(len);
+    while open.len() > keep {
+        out.push_str(open.pop().unwrap());
+    }
+    out.push_str(&trail);
+}
+
+/// Renders a list of spans to Markdown
+fn render_inlines(spans: &[Span]) -> String {
+    let mut out = String::new();
+    let mut open: Vec<&'static str> = vec![];
+    for span in spans {
+        let text = if span.raw {
+            span.text.clone()
+        } else if span.style.code {
+            code_span(&span.text)
+        } else {
+            escape(&span.text)
+        };
+        if text.trim().is_empty() {
+            out.push_str(&text);
+            continue;
+        }
+        let wanted = markers(span.style);
+        let keep = open.iter().take_while(|marker| wanted.contains(*marker)).count();
+        close_markers(&mut out, &mut open, keep);
+        let lead = text.len() - text.trim_left().len();
+        out.push_str(&text[..lead]);
+        for marker in wanted {
+            if !open.contains(&marker) {
+                out.push_str(marker);
+                open.push(marker);
+            }
+        }
+        out.push_str(&text[lead..]);
+    }
+    close_markers(&mut out, &mut open, 0);
+    // Line breaks are meaningless at the start or the end of a paragraph
+    loop {
+        let len = out.trim_right_matches(' ').len();
+        out.truncate(len);
+        if out.ends_with("\\\n") {
+            let len = out.len() - 2;
+            out.truncate(len);
+        } else {
+            break;
+        }
+    }
+    let mut start = out.len() - out.trim_left_matches(' ').len();
+    while out[start..].starts_with("\\\n") {
+        start += 2;
+        start = out.len() - out[start..].trim_left_matches(' ').len();
+    }
+    out[start..].to_owned()
+}
+
+/// Replaces line breaks by spaces, for contexts where they are not allowed
+fn single_line(s: &str) -> String {
+    s.replace("\\\n", " ").replace('\n', " ")
+}
+
+/// A top-level block of Markdown
+#[derive(Debug, Clone)]
+enum Block {
+    Heading(usize, String),
+    Text(String),
+    List(String),
+}
+
+impl Block {
+    fn to_markdown(&self) -> String {
+        match *self {
+            Block::Heading(level, ref text) => format!("{} {}", "#".repeat(level), text),
+            Block::Text(ref text) | Block::List(ref text) => text.clone(),
+        }
+    }
+}
+
+/// Joins blocks into a Markdown string
+fn join_blocks(blocks: &[Block]) -> String {
+    blocks.iter()
+        .map(|block| block.to_markdown())
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+/// Returns a paragraph, or `None` if it is empty
+fn paragraph(spans: &[Span]) -> Option<Block> {
+    let text = render_inlines(spans);
+    if text.is_empty() {
+        None
+    } else {
+        let lines: Vec<_> = text.split('\n').map(escape_block_markers).collect();
+        Some(Block::Text(lines.join("\n")))
+    }
+}
+
+/// Returns a heading, or `None` if it is empty
+fn heading(level: usize, spans: &[Span]) -> Option<Block> {
+    let mut text = single_line(&render_inlines(spans));
+    if text.ends_with('#') {
+        text.pop();
+        text.push_str("\\#");
+    }
+    if text.is_empty() {
+        None
+    } else {
+        Some(Block::Heading(cmp::min(level, 6), text))
+    }
+}
+
+/// Returns a block quote containing some blocks
+fn quote(blocks: Vec<Block>) -> Option<Block> {
+    if blocks.is_empty() {
+        return None;
+    }
+    let lines: Vec<_> = join_blocks(&blocks)
+        .lines()
+        .map(|line| if line.is_empty() {
+            String::from(">")
+        } else {
+            format!("> {}", line)
+        })
+        .collect();
+    Some(Block::Text(lines.join("\n")))
+}
+
+/// Returns a list, each item containing some blocks
+fn list(items: Vec<Vec<Block>>, ordered: bool) -> Option<Block> {
+    if items.is_empty() {
+        return None;
+    }
+    // A list is tight if its items only contain a paragraph and nested lists
+    let tight = items.iter().all(|item| {
+        item.iter()
+            .filter(|block| match **block {
+                Block::List(_) => false,
+                _ => true,
+            })
+            .count() <= 1
+    });
+    let mut result = vec![];
+    for (i, item) in items.iter().enumerate() {
+        let marker = if ordered {
+            format!("{}. ", i + 1)
+        } else {
+            String::from("* ")
+        };
+        let content = item.iter()
+            .map(|block| block.to_markdown())
+            .collect::<Vec<_>>()
+            .join(if tight { "\n" } else { "\n\n" });
+        let mut s = marker.clone();
+        for (j, line) in content.lines().enumerate() {
+            if j > 0 {
+                s.push('\n');
+                if !line.is_empty() {
+                    s.push_str(&" ".repeat(marker.len()));
+                }
+            }
+            s.push_str(line);
+        }
+        result.push(s.trim_right().to_owned());
+    }
+    Some(Block::List(result.join(if tight { "\n" } else { "\n\n" })))
+}
+
+/// Returns a list from a sequence of (level, ordered, content) items, the way lists
+/// are stored in DOCX documents
+fn flat_list(items: Vec<(usize, bool, String)>) -> Option<Block> {
+    if items.is_empty() {
+        return None;
+    }
+    let mut lines = vec![];
+    let mut numbers: Vec<usize> = vec![];
+    for (level, ordered, text) in items {
+        let level = cmp::min(level, numbers.len());
+        numbers.truncate(level + 1);
+        if numbers.len() == level {
+            numbers.push(0);
+        }
+        numbers[level] += 1;
+        let marker = if ordered {
+            format!("{}.", numbers[level])
+        } else {
+            String::from("*")
+        };
+        let indent = " ".repeat(4 * level);
+        let continuation = format!("\n{}{}", indent, " ".repeat(marker.len() + 1));
+        lines.push(format!("{}{} {}", indent, marker, text.replace('\n', &continuation)));
+    }
+    Some(Block::List(lines.join("\n")))
+}
+
+/// Returns the content of a table cell
+fn table_cell(spans: &[Span]) -> String {
+    single_line(&render_inlines(spans)).replace('|', "\\|")
+}
+
+/// Returns a table, the first row being used as header
+fn table(rows: Vec<Vec<String>>) -> Option<Block> {
+    let columns = rows.iter().map(|row| row.len()).max().unwrap_or(0);
+    if columns == 0 {
+        return None;
+    }
+    let mut lines = vec![];
+    for (i, row) in rows.iter().enumerate() {
+        let mut line = String::from("|");
+        for j in 0..columns {
+            line.push(' ');
+            if let Some(cell) = row.get(j) {
+                line.push_str(cell);
+            }
+            line.push_str(" |");
+        }
+        lines.push(line);
+        if i == 0 {
+            lines.push(format!("|{}", "---|".repeat(columns)));
+        }
+    }
+    Some(Block::Text(lines.join("\n")))
+}
+
+/// Returns a fenced code block
+fn code_block(text: &str) -> Block {
+    let mut longest = 0;
+    for line in text.lines() {
+        let trimmed = line.trim_left();
+        longest = cmp::max(longest, trimmed.len() - trimmed.trim_left_matches('`').len());
+    }
+    let fence = "`".repeat(cmp::max(3, longest + 1));
+    Block::Text(format!("{}\n{}\n{}", fence, text.trim_matches('\n'), fence))
+}
+
+/// Markdown content of an imported document
+struct Markdown {
+    /// Top-level blocks
+    blocks: Vec<Block>,
+    /// Content of the footnotes, referenced as `[^1]`, `[^2]`, ...
+    notes: Vec<Vec<Block>>,
+    /// Images, as (path in the archive, name of the extracted file)
+    images: Vec<(String, String)>,
+}
+
+impl Markdown {
+    fn new() -> Markdown {
+        Markdown {
+            blocks: vec![],
+            notes: vec![],
+            images: vec![],
+        }
+    }
+
+    /// Adds a footnote and returns the reference to it
+    fn note(&mut self, content: Vec<Block>) -> Span {
+        self.notes.push(content);
+        raw(format!("[^{}]", self.notes.len()), Style::default())
+    }
+
+    /// Registers an image of the archive and returns the name of the extracted file
+    fn add_image(&mut self, path: String) -> String {
+        let existing = self.images
+            .iter()
+            .find(|&&(ref p, _)| *p == path)
+            .map(|&(_, ref name)| name.clone());
+        if let Some(name) = existing {
+            return name;
+        }
+        let base: String = path.rsplit('/')
+            .next()
+            .unwrap()
+            .chars()
+            .map(|c| if c.is_alphanumeric() || c == '.' || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            })
+            .collect();
+        let mut name = base.clone();
+        let mut n = 1;
+        while self.images.iter().any(|&(_, ref other)| *other == name) {
+            n += 1;
+            name = format!("{}_{}", n, base);
+        }
+        self.images.push((path, name.clone()));
+        name
+    }
+
+    /// Registers an image of the archive and returns the Markdown to display it
+    fn image(&mut self, path: String, alt: &str) -> String {
+        let name = self.add_image(path);
+        format!("![{}](images/{})", single_line(&escape(alt.trim())), name)
+    }
+
+    /// Splits the document into chapters at its top-level headings.
+    ///
+    /// Returns, for each chapter, whether it is numbered (i.e. starts with a heading)
+    /// and its content, with the definitions of the footnotes it references.
+    fn chapters(self) -> Vec<(bool, String)> {
+        let top = self.blocks
+            .iter()
+            .filter_map(|block| match *block {
+                Block::Heading(level, _) => Some(level),
+                _ => None,
+            })
+            .min()
+            .unwrap_or(1);
+        let mut chapters: Vec<(bool, Vec<Block>)> = vec![];
+        for block in self.blocks {
+            let block = match block {
+                Block::Heading(level, text) => Block::Heading(level + 1 - top, text),
+                block => block,
+            };
+            let starts = match block {
+                Block::Heading(1, _) => true,
+                _ => false,
+            };
+            if starts || chapters.is_empty() {
+                chapters.push((starts, vec![]));
+            }
+            chapters.last_mut().unwrap().1.push(block);
+        }
+        let notes: Vec<String> = self.notes
+            .iter()
+            .map(|note| {
+                join_blocks(note)
+                    .lines()
+                    .enumerate()
+                    .map(|(i, line)| if i == 0 || line.is_empty() {
+                        line.to_owned()
+                    } else {
+                        format!("    {}", line)
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            })
+            .collect();
+        chapters.into_iter()
+            .map(|(numbered, blocks)| {
+                let mut content = join_blocks(&blocks);
+                for (i, note) in notes.iter().enumerate() {
+                    let label = format!("[^{}]", i + 1);
+                    if content.contains(label.as_str()) {
+                        content.push_str(&format!("\n\n{}: {}", label, note));
+                    }
+                }
+                content.push('\n');
+                (numbered, content)
+            })
+            .collect()
+    }
+}
+
+/// Adds the metadata found in the children of an element
+fn add_metadata(metadata: &mut Vec<(&'static str, String)>,
+                element: &Element,
+                keys: &[(&'static str, &str)]) {
+    for &(key, name) in keys {
+        if metadata.iter().any(|&(k, _)| k == key) {
+            continue;
+        }
+        if let Some(child) = element.child(name) {
+            let value = child.text().trim().to_owned();
+            if !value.is_empty() {
+                metadata.push((key, value));
+            }
+        }
+    }
+}
+
+/// Imports an EPUB document
+fn import_epub<R: Read + Seek>(archive: &mut Archive<R>) -> Result<Imported> {
+    let container = archive.read_xml("META-INF/container.xml")?;
+    let opf_path = match container.find("rootfile").and_then(|rootfile| rootfile.attr("full-path")) {
+        Some(path) => path.to_owned(),
+        None => {
+            return Err(Error::parser(Source::new(archive.file.as_str()),
+                                     lformat!("could not find the package document of the EPUB file")));
+        }
+    };
+    let opf = archive.read_xml(&opf_path)?;
+    let base = parent_dir(&opf_path);
+
+    let mut metadata = vec![];
+    if let Some(element) = opf.find("metadata") {
+        add_metadata(&mut metadata,
+                     element,
+                     &[("author", "creator"),
+                       ("title", "title"),
+                       ("lang", "language"),
+                       ("subject", "subject"),
+                       ("description", "description"),
+                       ("license", "rights")]);
+    }
+
+    let mut manifest: HashMap<String, String> = HashMap::new();
+    let mut cover = None;
+    if let Some(element) = opf.find("manifest") {
+        for item in element.children_named("item") {
+            if let (Some(id), Some(href)) = (item.attr("id"), item.attr("href")) {
+                let path = resolve_path(&base, &percent_decode(href));
+                if item.attr("properties").map_or(false, |p| p.contains("cover-image")) {
+                    cover = Some(path.clone());
+                }
+                manifest.insert(id.to_owned(), path);
+            }
+        }
+    }
+    if cover.is_none() {
+        // EPUB 2 cover, set with <meta name = "cover" content = "id" />
+        if let Some(element) = opf.find("metadata") {
+            for meta in element.children_named("meta") {
+                if meta.attr("name") == Some("cover") {
+                    cover = meta.attr("content").and_then(|id| manifest.get(id)).cloned();
+                }
+            }
+        }
+    }
+
+    let mut documents = vec![];
+    if let Some(spine) = opf.find("spine") {
+        for itemref in spine.children_named("itemref") {
+            if itemref.attr("linear") == Some("no") {
+                continue;
+            }
+            if let Some(path) = itemref.attr("idref").and_then(|id| manifest.get(id)) {
+                let document = archive.read_xml(path)?;
+                documents.push((path.clone(), document));
+            }
+        }
+    }
+
+    let mut xhtml = Xhtml {
+        md: Markdown::new(),
+        notes: HashMap::new(),
+        dir: String::new(),
+        cover: cover.clone(),
+        in_note: false,
+    };
+    let mut refs = HashSet::new();
+    for &(_, ref document) in &documents {
+        collect_note_refs(document, &mut refs);
+    }
+    for &(_, ref document) in &documents {
+        collect_notes(document, &refs, &mut xhtml.notes);
+    }
+    for &(ref path, ref document) in &documents {
+        xhtml.dir = parent_dir(path);
+        if let Some(body) = document.find("body") {
+            let blocks = xhtml.blocks(body);
+            xhtml.md.blocks.extend(blocks);
+        }
+    }
+    let mut markdown = xhtml.md;
+    if let Some(cover) = cover {
+        let name = markdown.add_image(cover);
+        metadata.push(("cover", format!("images/{}", name)));
+    }
+    Ok(Imported {
+        metadata: metadata,
+        markdown: markdown,
+    })
+}
+
+/// Collects the ids targeted by links marked as note references with `epub:type`
+fn collect_note_refs(element: &Element, refs: &mut HashSet<String>) {
+    for child in element.elements() {
+        let is_ref = child.name == "a" && child.attr("type").map_or(false, |t| t.contains("noteref"));
+        match child.attr("href").and_then(|href| href.find('#').map(|n| &href[n + 1..])) {
+            Some(target) if is_ref => {
+                refs.insert(target.to_owned());
+            }
+            _ => (),
+        }
+        collect_note_refs(child, refs);
+    }
+}
+
+/// Collects the footnotes of a XHTML document: elements marked as such with `epub:type`,
+/// and elements targeted by a note reference
+fn collect_notes(element: &Element, refs: &HashSet<String>, notes: &mut HashMap<String, Element>) {
+    for child in element.elements() {
+        let is_note = child.attr("type").map_or(false, |t| {
+            t.contains("footnote") || t.contains("endnote") || t.contains("rearnote")
+        });
+        match child.attr("id") {
+            Some(id) if is_note || refs.contains(id) => {
+                notes.insert(id.to_owned(), child.clone());
+            }
+            _ => collect_notes(child, refs, notes),
+        }
+    }
+}
+
+/// Returns true if a HTML element is a block
+fn is_html_block(name: &str) -> bool {
+    match name {
+        "p" | "div" | "section" | "article" | "header" | "footer" | "main" | "aside" |
+        "blockquote" | "ul" | "ol" | "li" | "h1" | "h2" | "h3" | "h4" | "h5" | "h6" | "pre" |
+        "table" | "hr" | "figure" | "figcaption" | "dl" | "dt" | "dd" | "nav" | "body" |
+        "center" | "address" | "hgroup" | "head" | "script" | "style" => true,
+        _ => false,
+    }
+}
+
+/// Returns true if the text of a link looks like a footnote number
+fn is_note_number(text: &str) -> bool {
+    let text = text.trim().trim_matches(|c: char| c == '[' || c == ']' || c == '(' || c == ')' || c == '.');
+    !text.is_empty() && text.chars().all(|c| c.is_digit(10))
+}
+
+/// Converter from XHTML (EPUB documents)
+struct Xhtml {
+    md: Markdown,
+    /// Footnotes, by id
+    notes: HashMap<String, Element>,
+    /// Directory of the current document in the archive
+    dir: String,
+    /// Path of the cover image in the archive, which is not included in the content
+    cover: Option<String>,
+    /// Set when converting a footnote
+    in_note: bool,
+}
+
+impl Xhtml {
+    /// Converts the content of an element to blocks
+    fn blocks(&mut self, element: &Element) -> Vec<Block> {
+        let mut blocks = vec![];
+        let mut spans = vec![];
+        for node in &element.children {
+            match *node {
+                Node::Text(ref text) => push_text(&mut spans, text, Style::default()),
+                Node::Element(ref child) => {
+                    if is_html_block(&child.name) {
+                        blocks.extend(paragraph(&spans));
+                        spans.clear();
+                        blocks.extend(self.block(child));
+                    } else {
+                        self.inlines(child, Style::default(), &mut spans);
+                    }
+                }
+            }
+        }
+        blocks.extend(paragraph(&spans));
+        blocks
+    }
+
+    /// Converts a block element
+    fn block(&mut self, element: &Element) -> Vec<Block> {
+        if let Some(id) = element.attr("id") {
+            if self.notes.contains_key(id) {
+                return vec![];
+            }
+        }
+        let mut spans = vec![];
+        match element.name.as_ref() {
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                let level = element.name[1..].parse::<usize>().unwrap();
+                self.inlines_children(element, Style::default(), &mut spans);
+                heading(level, &spans).into_iter().collect()
+            }
+            "p" => {
+                self.inlines_children(element, Style::default(), &mut spans);
+                paragraph(&spans).into_iter().collect()
+            }
+            "blockquote" => {
+                let blocks = self.blocks(element);
+                quote(blocks).into_iter().collect()
+            }
+            "ul" | "ol" => {
+                let items = element.children_named("li")
+                    .into_iter()
+                    .map(|item| self.blocks(item))
+                    .collect();
+                list(items, element.name == "ol").into_iter().collect()
+            }
+            "pre" => vec![code_block(&element.text())],
+            "table" => {
+                let mut rows = vec![];
+                element.find_all("tr", &mut rows);
+                let rows = rows.into_iter()
+                    .map(|row| {
+                        row.elements()
+                            .into_iter()
+                            .filter(|cell| cell.name == "td" || cell.name == "th")
+                            .map(|cell| {
+                                let mut spans = vec![];
+                                self.inlines_children(cell, Style::default(), &mut spans);
+                                table_cell(&spans)
+                            })
+                            .collect()
+                    })
+                    .collect();
+                table(rows).into_iter().collect()
+            }
+            "hr" => vec![Block::Text(String::from("***"))],
+            "head" | "nav" | "script" | "style" => vec![],
+            _ => self.blocks(element),
+        }
+    }
+
+    /// Converts the children of an element to spans
+    fn inlines_children(&mut self, element: &Element, style: Style, spans: &mut Vec<Span>) {
+        for node in &element.children {
+            match *node {
+                Node::Text(ref text) => push_text(spans, text, style),
+                Node::Element(ref child) => self.inlines(child, style, spans),
+            }
+        }
+    }
+
+    /// Converts an inline element to spans
+    fn inlines(&mut self, element: &Element, style: Style, spans: &mut Vec<Span>) {
+        if let Some(id) = element.attr("id") {
+            if self.notes.contains_key(id) {
+                return;
+            }
+        }
+        let mut style = style;
+        match element.name.as_ref() {
+            "em" | "i" | "cite" | "dfn" | "var" => style.italic = true,
+            "strong" | "b" => style.bold = true,
+            "code" | "kbd" | "samp" | "tt" => style.code = true,
+            "span" => {
+                if let Some(css) = element.attr("style") {
+                    if css.contains("italic") {
+                        style.italic = true;
+                    }
+                    if css.contains("bold") {
+                        style.bold = true;
+                    }
+                }
+            }
+            "br" => {
+                spans.push(line_break());
+                return;
+            }
+            "img" | "image" => {
+                if let Some(src) = element.attr("src").or_else(|| element.attr("href")) {
+                    self.image(src, element.attr("alt").unwrap_or(""), style, spans);
+                }
+                return;
+            }
+            "a" => {
+                self.link(element, style, spans);
+                return;
+            }
+            "script" | "style" => return,
+            _ => {}
+        }
+        if is_html_block(&element.name) {
+            push_text(spans, " ", style);
+        }
+        self.inlines_children(element, style, spans);
+    }
+
+    /// Converts an image
+    fn image(&mut self, src: &str, alt: &str, style: Style, spans: &mut Vec<Span>) {
+        if src.contains("://") || src.starts_with("data:") {
+            return;
+        }
+        let path = resolve_path(&self.dir, &percent_decode(src));
+        if self.cover.as_ref() == Some(&path) {
+            return;
+        }
+        let text = self.md.image(path, alt);
+        spans.push(raw(text, style));
+    }
+
+    /// Converts a link, which can be a reference to a footnote
+    fn link(&mut self, element: &Element, style: Style, spans: &mut Vec<Span>) {
+        let href = element.attr("href").unwrap_or("").to_owned();
+        let target = match href.find('#') {
+            Some(n) => href[n + 1..].to_owned(),
+            None => String::new(),
+        };
+        let note = self.notes.get(&target).cloned();
+        if let Some(note) = note {
+            let in_note = self.in_note;
+            self.in_note = true;
+            let blocks = self.blocks(&note);
+            self.in_note = in_note;
+            spans.push(self.md.note(blocks));
+        } else if self.in_note && href.contains('#') && is_note_number(&element.text()) {
+            // Link from a footnote back to its reference
+        } else if href.contains("://") || href.starts_with("mailto:") {
+            let mut content = vec![];
+            self.inlines_children(element, Style::default(), &mut content);
+            spans.push(link(&content, &href, style));
+        } else {
+            // Links inside the document would be broken once split in chapters
+            self.inlines_children(element, style, spans);
+        }
+    }
+}
+
+/// Imports an ODT document
+fn import_odt<R: Read + Seek>(archive: &mut Archive<R>) -> Result<Imported> {
+    let content = archive.read_xml("content.xml")?;
+    let mut odt = Odt {
+        md: Markdown::new(),
+        styles: HashMap::new(),
+        ordered_lists: vec![],
+    };
+    if archive.contains("styles.xml") {
+        let styles = archive.read_xml("styles.xml")?;
+        odt.add_styles(&styles);
+    }
+    odt.add_styles(&content);
+
+    let mut metadata = vec![];
+    if archive.contains("meta.xml") {
+        let meta = archive.read_xml("meta.xml")?;
+        if let Some(element) = meta.find("meta") {
+            add_metadata(&mut metadata,
+                         element,
+                         &[("author", "creator"),
+                           ("author", "initial-creator"),
+                           ("title", "title"),
+                           ("lang", "language"),
+                           ("subject", "subject"),
+                           ("description", "description")]);
+        }
+    }
+
+    if let Some(text) = content.find("body").and_then(|body| body.child("text")) {
+        let blocks = odt.blocks(text);
+        odt.md.blocks = blocks;
+    }
+    Ok(Imported {
+        metadata: metadata,
+        markdown: odt.md,
+    })
+}
+
+/// Style of an ODT document
+struct OdtStyle {
+    parent: Option<String>,
+    italic: Option<bool>,
+    bold: Option<bool>,
+    code: bool,
+    quote: bool,
+}
+
+/// Converter from ODT
+struct Odt {
+    md: Markdown,
+    styles: HashMap<String, OdtStyle>,
+    /// Names of the list styles that are numbered
+    ordered_lists: Vec<String>,
+}
+
+impl Odt {
+    /// Reads the styles defined in a document
+    fn add_styles(&mut self, root: &Element) {
+        let mut styles = vec![];
+        root.find_all("style", &mut styles);
+        for style in styles {
+            let name = match style.attr("name") {
+                Some(name) => name,
+                None => continue,
+            };
+            let display = style.attr("display-name").unwrap_or(name);
+            let properties = style.child("text-properties");
+            let font = properties.and_then(|p| p.attr("font-name")).unwrap_or("");
+            self.styles.insert(name.to_owned(),
+                               OdtStyle {
+                                   parent: style.attr("parent-style-name").map(|s| s.to_owned()),
+                                   italic: properties.and_then(|p| p.attr("font-style"))
+                                       .map(|s| s == "italic" || s == "oblique"),
+                                   bold: properties.and_then(|p| p.attr("font-weight"))
+                                       .map(|s| s == "bold" || s.parse::<u32>().map_or(false, |w| w >= 600)),
+                                   code: name.contains("Preformatted") || name.contains("Source_20_Text") ||
+                                         font.contains("Mono") || font.contains("Courier"),
+                                   quote: display.starts_with("Quot"),
+                               });
+        }
+        let mut lists = vec![];
+        root.find_all("list-style", &mut lists);
+        for list in lists {
+            let numbered = list.elements()
+                .first()
+                .map_or(false, |level| level.name == "list-level-style-number");
+            if let (true, Some(name)) = (numbered, list.attr("name")) {
+                self.ordered_lists.push(name.to_owned());
+            }
+        }
+    }
+
+    /// Returns the chain of styles from a style to its ancestors
+    fn style_chain(&self, name: Option<&str>) -> Vec<&OdtStyle> {
+        let mut chain = vec![];
+        let mut current = name.map(|s| s.to_owned());
+        while let Some(name) = current {
+            if chain.len() > 10 {
+                break;
+            }
+            match self.styles.get(&name) {
+                Some(style) => {
+                    chain.push(style);
+                    current = style.parent.clone();
+                }
+                None => break,
+            }
+        }
+        chain
+    }
+
+    /// Applies a style to the formatting of a span
+    fn style(&self, name: Option<&str>, style: Style) -> Style {
+        let mut style = style;
+        for s in self.style_chain(name).into_iter().rev() {
+            if let Some(italic) = s.italic {
+                style.italic = italic;
+            }
+            if let Some(bold) = s.bold {
+                style.bold = bold;
+            }
+            if s.code {
+                style.code = true;
+            }
+        }
+        style
+    }
+
+    /// Converts the content of an element to blocks
+    fn blocks(&mut self, element: &Element) -> Vec<Block> {
+        let mut blocks = vec![];
+        for child in element.elements() {
+            match child.name.as_ref() {
+                "h" => {
+                    let level = child.attr("outline-level")
+                        .and_then(|level| level.parse().ok())
+                        .unwrap_or(1);
+                    let spans = self.paragraph(child);
+                    blocks.extend(heading(level, &spans));
+                }
+                "p" => {
+                    let spans = self.paragraph(child);
+                    let block = paragraph(&spans);
+                    let is_quote = self.style_chain(child.attr("style-name"))
+                        .iter()
+                        .any(|style| style.quote);
+                    if is_quote {
+                        blocks.extend(block.and_then(|block| quote(vec![block])));
+                    } else {
+                        blocks.extend(block);
+                    }
+                }
+                "list" => {
+                    let ordered = child.attr("style-name")
+                        .map_or(false, |name| self.ordered_lists.iter().any(|list| list == name));
+                    let items = child.elements()
+                        .into_iter()
+                        .filter(|item| item.name == "list-item" || item.name == "list-header")
+                        .map(|item| self.blocks(item))
+                        .collect();
+                    blocks.extend(list(items, ordered));
+                }
+                "table" => {
+                    let mut rows = vec![];
+                    child.find_all("table-row", &mut rows);
+                    let rows = rows.into_iter()
+                        .map(|row| {
+                            row.elements()
+                                .into_iter()
+                                .filter(|cell| cell.name == "table-cell" || cell.name == "covered-table-cell")
+                                .map(|cell| {
+                                    let mut spans = vec![];
+                                    self.inlines_children(cell, Style::default(), &mut spans);
+                                    table_cell(&spans)
+                                })
+                                .collect()
+                        })
+                        .collect();
+                    blocks.extend(table(rows));
+                }
+                "frame" => {
+                    let mut spans = vec![];
+                    self.inlines(child, Style::default(), &mut spans);
+                    blocks.extend(paragraph(&spans));
+                }
+                "table-of-content" | "alphabetical-index" | "illustration-index" | "bibliography" |
+                "sequence-decls" | "variable-decls" | "user-field-decls" | "forms" |
+                "tracked-changes" | "soft-page-break" => {}
+                _ => blocks.extend(self.blocks(child)),
+            }
+        }
+        blocks
+    }
+
+    /// Converts the content of a paragraph or heading
+    fn paragraph(&mut self, element: &Element) -> Vec<Span> {
+        let style = self.style(element.attr("style-name"), Style::default());
+        let mut spans = vec![];
+        self.inlines_children(element, style, &mut spans);
+        spans
+    }
+
+    /// Converts the children of an element to spans
+    fn inlines_children(&mut self, element: &Element, style: Style, spans: &mut Vec<Span>) {
+        for node in &element.children {
+            match *node {
+                Node::Text(ref text) => push_text(spans, text, style),
+                Node::Element(ref child) => self.inlines(child, style, spans),
+            }
+        }
+    }
+
+    /// Converts an inline element to spans
+    fn inlines(&mut self, element: &Element, style: Style, spans: &mut Vec<Span>) {
+        match element.name.as_ref() {
+            "span" => {
+                let style = self.style(element.attr("style-name"), style);
+                self.inlines_children(element, style, spans);
+            }
+            "s" | "tab" => push_text(spans, " ", style),
+            "line-break" => spans.push(line_break()),
+            "a" => {
+                let href = element.attr("href").unwrap_or("");
+                if href.contains("://") || href.starts_with("mailto:") {
+                    let mut content = vec![];
+                    self.inlines_children(element, Style::default(), &mut content);
+                    spans.push(link(&content, href, style));
+                } else {
+                    self.inlines_children(element, style, spans);
+                }
+            }
+            "note" => {
+                if let Some(body) = element.child("note-body") {
+                    let blocks = self.blocks(body);
+                    spans.push(self.md.note(blocks));
+                }
+            }
+            "frame" => {
+                let alt = element.child("title")
+                    .or_else(|| element.child("desc"))
+                    .map(|alt| alt.text())
+                    .unwrap_or_default();
+                for child in element.elements() {
+                    match child.name.as_ref() {
+                        "image" => {
+                            let href = child.attr("href").unwrap_or("");
+                            if !href.is_empty() && !href.contains("://") {
+                                let text = self.md.image(resolve_path("", &percent_decode(href)), &alt);
+                                spans.push(raw(text, style));
+                            }
+                        }
+                        "text-box" => self.inlines_children(child, style, spans),
+                        _ => {}
+                    }
+                }
+            }
+            "annotation" | "annotation-end" | "note-citation" | "soft-page-break" | "bookmark" |
+            "bookmark-start" | "bookmark-end" | "change" | "change-start" | "change-end" => {}
+            _ => self.inlines_children(element, style, spans),
+        }
+    }
+}
+
+/// Imports a DOCX document
+fn import_docx<R: Read + Seek>(archive: &mut Archive<R>) -> Result<Imported> {
+    let document = archive.read_xml("word/document.xml")?;
+    let mut docx = Docx {
+        md: Markdown::new(),
+        styles: HashMap::new(),
+        relationships: HashMap::new(),
+        numbering: vec![],
+        footnotes: HashMap::new(),
+        endnotes: HashMap::new(),
+        title: None,
+        subtitle: None,
+    };
+    if archive.contains("word/_rels/document.xml.rels") {
+        let rels = archive.read_xml("word/_rels/document.xml.rels")?;
+        for rel in rels.children_named("Relationship") {
+            if let (Some(id), Some(target)) = (rel.attr("Id"), rel.attr("Target")) {
+                docx.relationships.insert(id.to_owned(), target.to_owned());
+            }
+        }
+    }
+    if archive.contains("word/styles.xml") {
+        let styles = archive.read_xml("word/styles.xml")?;
+        docx.add_styles(&styles);
+    }
+    if archive.contains("word/numbering.xml") {
+        let numbering = archive.read_xml("word/numbering.xml")?;
+        docx.add_numbering(&numbering);
+    }
+    for &(file, footnotes) in &[("word/footnotes.xml", true), ("word/endnotes.xml", false)] {
+        if archive.contains(file) {
+            let notes = archive.read_xml(file)?;
+            for note in notes.elements() {
+                // Skip separators and continuation notices
+                if note.attr("type").map_or(false, |t| t != "normal") {
+                    continue;
+                }
+                if let Some(id) = note.attr("id") {
+                    let map = if footnotes {
+                        &mut docx.footnotes
+                    } else {
+                        &mut docx.endnotes
+                    };
+                    map.insert(id.to_owned(), note.clone());
+                }
+            }
+        }
+    }
+
+    let mut metadata = vec![];
+    if archive.contains("docProps/core.xml") {
+        let core = archive.read_xml("docProps/core.xml")?;
+        add_metadata(&mut metadata,
+                     &core,
+                     &[("author", "creator"),
+                       ("title", "title"),
+                       ("lang", "language"),
+                       ("subject", "subject"),
+                       ("description", "description")]);
+    }
+
+    if let Some(body) = document.child("body") {
+        let blocks = docx.blocks(body);
+        docx.md.blocks = blocks;
+    }
+    for &(key, ref value) in &[("title", docx.title.take()), ("subtitle", docx.subtitle.take())] {
+        if let Some(ref value) = *value {
+            if !metadata.iter().any(|&(k, _)| k == key) {
+                metadata.push((key, value.clone()));
+            }
+        }
+    }
+    Ok(Imported {
+        metadata: metadata,
+        markdown: docx.md,
+    })
+}
+
+/// Reads a DOCX toggle property (e.g. `<w:b/>` or `<w:i w:val = "0"/>`)
+fn on_off(element: Option<&Element>) -> Option<bool> {
+    element.map(|element| match element.attr("val") {
+        Some("0") | Some("false") | Some("off") | Some("none") => false,
+        _ => true,
+    })
+}
+
+/// Style of a DOCX document
+struct DocxStyle {
+    name: String,
+    based_on: Option<String>,
+    heading: Option<usize>,
+    italic: Option<bool>,
+    bold: Option<bool>,
+}
+
+/// Converter from DOCX
+struct Docx {
+    md: Markdown,
+    styles: HashMap<String, DocxStyle>,
+    /// Targets of the relationships (images and links), by id
+    relationships: HashMap<String, String>,
+    /// Numbering instances, as (id, level, ordered)
+    numbering: Vec<(String, String, bool)>,
+    footnotes: HashMap<String, Element>,
+    endnotes: HashMap<String, Element>,
+    /// Content of the paragraphs with the "Title" and "Subtitle" styles
+    title: Option<String>,
+    subtitle: Option<String>,
+}
+
+impl Docx {
+    /// Reads the styles of the document
+    fn add_styles(&mut self, root: &Element) {
+        for style in root.children_named("style") {
+            let id = match style.attr("styleId") {
+                Some(id) => id,
+                None => continue,
+            };
+            let name = style.child("name")
+                .and_then(|name| name.attr("val"))
+                .unwrap_or(id)
+                .to_lowercase();
+            let properties = style.child("rPr");
+            self.styles.insert(id.to_owned(),
+                               DocxStyle {
+                                   heading: style.child("pPr")
+                                       .and_then(|p| p.child("outlineLvl"))
+                                       .and_then(|level| level.attr("val"))
+                                       .and_then(|level| level.parse::<usize>().ok())
+                                       .map(|level| level + 1)
+                                       .or_else(|| if name.starts_with("heading ") {
+                                           name[8..].parse().ok()
+                                       } else {
+                                           None
+                                       }),
+                                   name: name,
+                                   based_on: style.child("basedOn")
+                                       .and_then(|based_on| based_on.attr("val"))
+                                       .map(|s| s.to_owned()),
+                                   italic: on_off(properties.and_then(|p| p.child("i"))),
+                                   bold: on_off(properties.and_then(|p| p.child("b"))),
+                               });
+        }
+    }
+
+    /// Reads the numbering definitions, to know which lists are ordered
+    fn add_numbering(&mut self, root: &Element) {
+        let mut abstracts: HashMap<String, Vec<(String, bool)>> = HashMap::new();
+        for definition in root.children_named("abstractNum") {
+            let levels = definition.children_named("lvl")
+                .into_iter()
+                .map(|level| {
+                    let format = level.child("numFmt")
+                        .and_then(|format| format.attr("val"))
+                        .unwrap_or("bullet");
+                    (level.attr("ilvl").unwrap_or("0").to_owned(),
+                     format != "bullet" && format != "none")
+                })
+                .collect();
+            if let Some(id) = definition.attr("abstractNumId") {
+                abstracts.insert(id.to_owned(), levels);
+            }
+        }
+        for num in root.children_named("num") {
+            let definition = num.child("abstractNumId")
+                .and_then(|id| id.attr("val"))
+                .and_then(|id| abstracts.get(id));
+            if let (Some(id), Some(levels)) = (num.attr("numId"), definition) {
+                for &(ref level, ordered) in levels {
+                    self.numbering.push((id.to_owned(), level.clone(), ordered));
+                }
+            }
+        }
+    }
+
+    /// Returns the chain of styles from a style to its ancestors
+    fn style_chain(&self, id: Option<&str>) -> Vec<&DocxStyle> {
+        let mut chain = vec![];
+        let mut current = id.map(|s| s.to_owned());
+        while let Some(id) = current {
+            if chain.len() > 10 {
+                break;
+            }
+            match self.styles.get(&id) {
+                Some(style) => {
+                    chain.push(style);
+                    current = style.based_on.clone();
+                }
+                None => break,
+            }
+        }
+        chain
+    }
+
+    /// Applies a style to the formatting of a span
+    fn style(&self, id: Option<&str>, style: Style) -> Style {
+        let mut style = style;
+        for s in self.style_chain(id).into_iter().rev() {
+            if let Some(italic) = s.italic {
+                style.italic = italic;
+            }
+            if let Some(bold) = s.bold {
+                style.bold = bold;
+            }
+        }
+        style
+    }
+
+    /// Converts the content of the body (or of a note) to blocks
+    fn blocks(&mut self, container: &Element) -> Vec<Block> {
+        let mut blocks = vec![];
+        let mut items = vec![];
+        for child in container.elements() {
+            match child.name.as_ref() {
+                "p" => {
+                    let properties = child.child("pPr");
+                    let style_id = properties.and_then(|p| p.child("pStyle"))
+                        .and_then(|style| style.attr("val"));
+                    let numbering = properties.and_then(|p| p.child("numPr"));
+                    let num_id = numbering.and_then(|n| n.child("numId"))
+                        .and_then(|id| id.attr("val"))
+                        .unwrap_or("0");
+                    let level = numbering.and_then(|n| n.child("ilvl"))
+                        .and_then(|level| level.attr("val"))
+                        .unwrap_or("0");
+                    let base = self.style(style_id, Style::default());
+                    let mut spans = vec![];
+                    self.runs(child, base, &mut spans);
+                    if num_id != "0" {
+                        let ordered = self.numbering
+                            .iter()
+                            .any(|&(ref id, ref l, ordered)| id == num_id && l == level && ordered);
+                        items.push((level.parse().unwrap_or(0), ordered, render_inlines(&spans)));
+                        continue;
+                    }
+                    blocks.extend(flat_list(mem::replace(&mut items, vec![])));
+                    let (name, level) = match self.style_chain(style_id).first() {
+                        Some(style) => (style.name.clone(), self.heading_level(style_id)),
+                        None => (String::new(), None),
+                    };
+                    if name == "title" || name == "subtitle" {
+                        let text = child.text().trim().to_owned();
+                        let field = if name == "title" {
+                            &mut self.title
+                        } else {
+                            &mut self.subtitle
+                        };
+                        if field.is_none() && !text.is_empty() {
+                            *field = Some(text);
+                        }
+                    } else if let Some(level) = level {
+                        blocks.extend(heading(level, &spans));
+                    } else if name.contains("quote") {
+                        blocks.extend(paragraph(&spans).and_then(|block| quote(vec![block])));
+                    } else {
+                        blocks.extend(paragraph(&spans));
+                    }
+                }
+                "tbl" => {
+                    blocks.extend(flat_list(mem::replace(&mut items, vec![])));
+                    let rows = child.children_named("tr")
+                        .into_iter()
+                        .map(|row| {
+                            row.children_named("tc")
+                                .into_iter()
+                                .map(|cell| {
+                                    let mut spans = vec![];
+                                    for p in cell.children_named("p") {
+                                        push_text(&mut spans, " ", Style::default());
+                                        self.runs(p, Style::default(), &mut spans);
+                                    }
+                                    table_cell(&spans)
+                                })
+                                .collect()
+                        })
+                        .collect();
+                    blocks.extend(table(rows));
+                }
+                "sdt" => {
+                    blocks.extend(flat_list(mem::replace(&mut items, vec![])));
+                    if let Some(content) = child.child("sdtContent") {
+                        blocks.extend(self.blocks(content));
+                    }
+                }
+                _ => {}
+            }
+        }
+        blocks.extend(flat_list(items));
+        blocks
+    }
+
+    /// Returns the heading level of a paragraph style, if it is a heading
+    fn heading_level(&self, id: Option<&str>) -> Option<usize> {
+        self.style_chain(id)
+            .iter()
+            .filter_map(|style| style.heading)
+            .next()
+    }
+
+    /// Converts the runs of a paragraph (or of a hyperlink, ...) to spans
+    fn runs(&mut self, element: &Element, style: Style, spans: &mut Vec<Span>) {
+        for child in element.elements() {
+            match child.name.as_ref() {
+                "r" => self.run(child, style, spans),
+                "hyperlink" => {
+                    let target = child.attr("id")
+                        .and_then(|id| self.relationships.get(id))
+                        .cloned()
+                        .unwrap_or_default();
+                    if target.contains("://") || target.starts_with("mailto:") {
+                        let mut content = vec![];
+                        self.runs(child, Style::default(), &mut content);
+                        spans.push(link(&content, &target, style));
+                    } else {
+                        self.runs(child, style, spans);
+                    }
+                }
+                "pPr" | "del" | "moveFrom" => {}
+                _ => self.runs(child, style, spans),
+            }
+        }
+    }
+
+    /// Converts a run to spans
+    fn run(&mut self, run: &Element, style: Style, spans: &mut Vec<Span>) {
+        let mut style = style;
+        if let Some(properties) = run.child("rPr") {
+            let id = properties.child("rStyle").and_then(|style| style.attr("val"));
+            if id.is_some() {
+                style = self.style(id, style);
+            }
+            if let Some(italic) = on_off(properties.child("i")) {
+                style.italic = italic;
+            }
+            if let Some(bold) = on_off(properties.child("b")) {
+                style.bold = bold;
+            }
+        }
+        for child in run.elements() {
+            match child.name.as_ref() {
+                "t" => push_text(spans, &child.text(), style),
+                "tab" => push_text(spans, " ", style),
+                "noBreakHyphen" => push_text(spans, "-", style),
+                "br" | "cr" => {
+                    if child.attr("type").map_or(true, |t| t == "textWrapping") {
+                        spans.push(line_break());
+                    }
+                }
+                "footnoteReference" | "endnoteReference" => {
+                    let note = {
+                        let notes = if child.name == "footnoteReference" {
+                            &self.footnotes
+                        } else {
+                            &self.endnotes
+                        };
+                        child.attr("id").and_then(|id| notes.get(id)).cloned()
+                    };
+                    if let Some(note) = note {
+                        let blocks = self.blocks(&note);
+                        spans.push(self.md.note(blocks));
+                    }
+                }
+                "drawing" | "pict" => self.drawing(child, style, spans),
+                _ => {}
+            }
+        }
+    }
+
+    /// Converts an image
+    fn drawing(&mut self, element: &Element, style: Style, spans: &mut Vec<Span>) {
+        let id = element.find("blip")
+            .and_then(|blip| blip.attr("embed"))
+            .or_else(|| element.find("imagedata").and_then(|data| data.attr("id")));
+        let target = id.and_then(|id| self.relationships.get(id)).cloned();
+        if let Some(target) = target {
+            if target.contains("://") {
+                return;
+            }
+            let path = resolve_path("word", &percent_decode(&target));
+            let alt = element.find("docPr")
+                .and_then(|properties| properties.attr("descr").or_else(|| properties.attr("title")))
+                .unwrap_or("");
+            let text = self.md.image(path, alt);
+            spans.push(raw(text, style));
+        }
+    }
+}
+
+#[test]
+fn import_render_inlines() {
+    let mut spans = vec![];
+    let italic = Style { italic: true, ..Style::default() };
+    let bold_italic = Style { bold: true, ..italic };
+    push_text(&mut spans, "  Some *plain*   ", Style::default());
+    push_text(&mut spans, " text, ", italic);
+    push_text(&mut spans, "bold ", bold_italic);
+    push_text(&mut spans, "{{title}}", Style::default());
+    assert_eq!(render_inlines(&spans), "Some \\*plain\\* *text, **bold*** \\{{title}}");
+}
+
+#[test]
+fn import_xhtml() {
+    let doc = parse_xml(r#"<html xmlns="http://www.w3.org/1999/xhtml" xmlns:epub="http://www.idpf.org/2007/ops">
+<body>
+<h2>First</h2>
+<p>Some <em>text</em> with a note<a epub:type="noteref" href="notes.xhtml#n1">1</a>.</p>
+<ul><li>one</li><li>two<ol><li>nested</li></ol></li></ul>
+<h3>Section</h3>
+<p><img src="../images/a%20b.png" alt="An image"/></p>
+<table><tr><th>A</th><th>B</th></tr><tr><td>1|2</td><td><b>3</b></td></tr></table>
+<h2>Second</h2>
+<p># Not a title</p>
+<aside epub:type="footnote" id="n1"><p><a href="#r1">1</a> The note.</p></aside>
+</body>
+</html>"#,
+                        "test.xhtml")
+        .unwrap();
+    let mut xhtml = Xhtml {
+        md: Markdown::new(),
+        notes: HashMap::new(),
+        dir: String::from("OEBPS/text"),
+        cover: None,
+        in_note: false,
+    };
+    collect_notes(&doc, &HashSet::new(), &mut xhtml.notes);
+    let blocks = xhtml.blocks(doc.find("body").unwrap());
+    xhtml.md.blocks = blocks;
+    assert_eq!(xhtml.md.images,
+               vec![(String::from("OEBPS/images/a b.png"), String::from("a_b.png"))]);
+    let chapters = xhtml.md.chapters();
+    assert_eq!(chapters.len(), 2);
+    assert_eq!(chapters[0].1,
+               "# First
+
+Some *text* with a note[^1].
+
+* one
+* two
+  1. nested
+
+## Section
+
+![An image](images/a_b.png)
+
+| A | B |
+|---|---|
+| 1\\|2 | **3** |
+
+[^1]: The note.
+");
+    assert_eq!(chapters[1], (true, String::from("# Second\n\n\\# Not a title\n")));
+}
+
+#[test]
+fn import_xhtml_notes() {
+    let doc = parse_xml(r#"<html xmlns="http://www.w3.org/1999/xhtml" xmlns:epub="http://www.idpf.org/2007/ops">
+<body>
+<h1>Title</h1>
+<p>Text<a epub:type="noteref" href="#n1">1</a>.</p>
+<aside id="sidebar"><p>A sidebar.</p></aside>
+<aside id="n1"><p>The note.</p></aside>
+</body>
+</html>"#,
+                        "test.xhtml")
+        .unwrap();
+    let mut xhtml = Xhtml {
+        md: Markdown::new(),
+        notes: HashMap::new(),
+        dir: String::new(),
+        cover: None,
+        in_note: false,
+    };
+    let mut refs = HashSet::new();
+    collect_note_refs(&doc, &mut refs);
+    collect_notes(&doc, &refs, &mut xhtml.notes);
+    assert_eq!(xhtml.notes.len(), 1);
+    let blocks = xhtml.blocks(doc.find("body").unwrap());
+    xhtml.md.blocks = blocks;
+    let chapters = xhtml.md.chapters();
+    // The unreferenced aside is kept as a normal block
+    assert_eq!(chapters[0].1, "# Title\n\nText[^1].\n\nA sidebar.\n\n[^1]: The note.\n");
+}
+
+#[cfg(test)]
+/// Creates an archive containing some files, in memory
+fn test_archive(name: &str, files: &[(&str, &[u8])]) -> Archive<::std::io::Cursor<Vec<u8>>> {
+    use std::io::Cursor;
+    use zip::{CompressionMethod, ZipWriter};
+    use zip::write::FileOptions;
+
+    let mut zip = ZipWriter::new(Cursor::new(vec![]));
+    for &(file, content) in files {
+        zip.start_file(file,
+                       FileOptions::default().compression_method(CompressionMethod::Stored))
+            .unwrap();
+        zip.write_all(content).unwrap();
+    }
+    let data = zip.finish().unwrap().into_inner();
+    Archive {
+        zip: ZipArchive::new(Cursor::new(data)).unwrap(),
+        file: name.to_owned(),
+    }
+}
+
+#[cfg(test)]
+const TEST_ODT_CONTENT: &'static str = r#"<office:document-content xmlns:office="urn:oasis:names:tc:opendocument:xmlns:office:1.0" xmlns:style="urn:oasis:names:tc:opendocument:xmlns:style:1.0" xmlns:text="urn:oasis:names:tc:opendocument:xmlns:text:1.0" xmlns:table="urn:oasis:names:tc:opendocument:xmlns:table:1.0" xmlns:draw="urn:oasis:names:tc:opendocument:xmlns:drawing:1.0" xmlns:fo="urn:oasis:names:tc:opendocument:xmlns:xsl-fo-compatible:1.0" xmlns:xlink="http://www.w3.org/1999/xlink">
+<office:automatic-styles>
+<style:style style:name="T1" style:family="text"><style:text-properties fo:font-style="italic"/></style:style>
+<text:list-style style:name="L1"><text:list-level-style-number text:level="1"/></text:list-style>
+</office:automatic-styles>
+<office:body><office:text>
+<text:h text:outline-level="1">First</text:h>
+<text:p>Some <text:span text:style-name="T1">text</text:span> with a note<text:note text:id="ftn1"><text:note-citation>1</text:note-citation><text:note-body><text:p>The note.</text:p></text:note-body></text:note>.</text:p>
+<text:list text:style-name="L1"><text:list-item><text:p>one</text:p></text:list-item><text:list-item><text:p>two</text:p></text:list-item></text:list>
+<text:h text:outline-level="2">Section</text:h>
+<table:table><table:table-row><table:table-cell><text:p>A</text:p></table:table-cell><table:table-cell><text:p>B</text:p></table:table-cell></table:table-row><table:table-row><table:table-cell><text:p>1</text:p></table:table-cell><table:table-cell><text:p>2</text:p></table:table-cell></table:table-row></table:table>
+<text:p><draw:frame><draw:image xlink:href="Pictures/a.png"/></draw:frame></text:p>
+<text:h text:outline-level="1">Second</text:h>
+<text:p>End.</text:p>
+</office:text></office:body>
+</office:document-content>"#;
+
+#[cfg(test)]
+const TEST_ODT_META: &'static str = r#"<office:document-meta xmlns:office="urn:oasis:names:tc:opendocument:xmlns:office:1.0" xmlns:dc="http://purl.org/dc/elements/1.1/">
+<office:meta><dc:title>My book</dc:title></office:meta>
+</office:document-meta>"#;
+
+#[test]
+fn import_odt_document() {
+    let mut archive = test_archive("crowbook-test-import-odt.odt",
+                                   &[("content.xml", TEST_ODT_CONTENT.as_bytes()),
+                                     ("meta.xml", TEST_ODT_META.as_bytes())]);
+    let imported = import_odt(&mut archive).unwrap();
+    assert_eq!(imported.metadata, vec![("title", String::from("My book"))]);
+    assert_eq!(imported.markdown.images,
+               vec![(String::from("Pictures/a.png"), String::from("a.png"))]);
+    let chapters = imported.markdown.chapters();
+    assert_eq!(chapters.len(), 2);
+    assert_eq!(chapters[0],
+               (true,
+                String::from("# First
+
+Some *text* with a note[^1].
+
+1. one
+2. two
+
+## Section
+
+| A | B |
+|---|---|
+| 1 | 2 |
+
+![](images/a.png)
+
+[^1]: The note.
+")));
+    assert_eq!(chapters[1], (true, String::from("# Second\n\nEnd.\n")));
+}
+
+#[test]
+fn import_docx_document() {
+    let ns = r#"xmlns:w="http://schemas.openxmlformats.org/wordprocessingml/2006/main""#;
+    let styles = format!(r#"<w:styles {}>
+<w:style w:type="paragraph" w:styleId="Title"><w:name w:val="Title"/></w:style>
+<w:style w:type="paragraph" w:styleId="Heading1"><w:name w:val="heading 1"/></w:style>
+<w:style w:type="paragraph" w:styleId="Quote"><w:name w:val="Quote"/></w:style>
+</w:styles>"#,
+                         ns);
+    let numbering = format!(r#"<w:numbering {}>
+<w:abstractNum w:abstractNumId="0"><w:lvl w:ilvl="0"><w:numFmt w:val="decimal"/></w:lvl></w:abstractNum>
+<w:num w:numId="1"><w:abstractNumId w:val="0"/></w:num>
+</w:numbering>"#,
+                            ns);
+    let footnotes = format!(r#"<w:footnotes {}>
+<w:footnote w:type="separator" w:id="-1"><w:p><w:r><w:separator/></w:r></w:p></w:footnote>
+<w:footnote w:id="1"><w:p><w:r><w:t>The note.</w:t></w:r></w:p></w:footnote>
+</w:footnotes>"#,
+                            ns);
+    let rels = r#"<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">
+<Relationship Id="rId1" Target="https://example.com" TargetMode="External"/>
+</Relationships>"#;
+    let document = format!(r#"<w:document {} xmlns:r="http://schemas.openxmlformats.org/officeDocument/2006/relationships"><w:body>
+<w:p><w:pPr><w:pStyle w:val="Title"/></w:pPr><w:r><w:t>My book</w:t></w:r></w:p>
+<w:p><w:pPr><w:pStyle w:val="Heading1"/></w:pPr><w:r><w:t>First</w:t></w:r></w:p>
+<w:p><w:r><w:t xml:space="preserve">Some </w:t></w:r><w:r><w:rPr><w:i/></w:rPr><w:t>text</w:t></w:r><w:r><w:t xml:space="preserve"> with a </w:t></w:r><w:hyperlink r:id="rId1"><w:r><w:t>link</w:t></w:r></w:hyperlink><w:r><w:footnoteReference w:id="1"/></w:r><w:r><w:t>.</w:t></w:r></w:p>
+<w:p><w:pPr><w:numPr><w:ilvl w:val="0"/><w:numId w:val="1"/></w:numPr></w:pPr><w:r><w:t>one</w:t></w:r></w:p>
+<w:p><w:pPr><w:numPr><w:ilvl w:val="0"/><w:numId w:val="1"/></w:numPr></w:pPr><w:r><w:t>two</w:t></w:r></w:p>
+<w:p><w:pPr><w:pStyle w:val="Quote"/></w:pPr><w:r><w:t>Quoted</w:t></w:r></w:p>
+<w:tbl><w:tr><w:tc><w:p><w:r><w:t>A</w:t></w:r></w:p></w:tc><w:tc><w:p><w:r><w:t>B</w:t></w:r></w:p></w:tc></w:tr><w:tr><w:tc><w:p><w:r><w:t>1</w:t></w:r></w:p></w:tc><w:tc><w:p><w:r><w:t>2</w:t></w:r></w:p></w:tc></w:tr></w:tbl>
+<w:p><w:pPr><w:pStyle w:val="Heading1"/></w:pPr><w:r><w:t>Second</w:t></w:r></w:p>
+<w:p><w:r><w:t>End.</w:t></w:r></w:p>
+</w:body></w:document>"#,
+                           ns);
+    let mut archive = test_archive("crowbook-test-import-docx.docx",
+                                   &[("word/document.xml", document.as_bytes()),
+                                     ("word/styles.xml", styles.as_bytes()),
+                                     ("word/numbering.xml", numbering.as_bytes()),
+                                     ("word/footnotes.xml", footnotes.as_bytes()),
+                                     ("word/_rels/document.xml.rels", rels.as_bytes())]);
+    let imported = import_docx(&mut archive).unwrap();
+    assert_eq!(imported.metadata, vec![("title", String::from("My book"))]);
+    let chapters = imported.markdown.chapters();
+    assert_eq!(chapters.len(), 2);
+    assert_eq!(chapters[0],
+               (true,
+                String::from("# First
+
+Some *text* with a [link](https://example.com)[^1].
+
+1. one
+2. two
+
+> Quoted
+
+| A | B |
+|---|---|
+| 1 | 2 |
+
+[^1]: The note.
+")));
+    assert_eq!(chapters[1], (true, String::from("# Second\n\nEnd.\n")));
+}
+
+#[test]
+fn import_write_project() {
+    use std::env;
+
+    let dir = env::temp_dir().join("crowbook-test-import");
+    let _ = fs::remove_dir_all(&dir);
+    let mut archive = test_archive("crowbook-test-import.odt",
+                                   &[("content.xml", TEST_ODT_CONTENT.as_bytes()),
+                                     ("meta.xml", TEST_ODT_META.as_bytes()),
+                                     ("Pictures/a.png", &b"png"[..])]);
+    let book = dir.join("book.book");
+    let imported = import_odt(&mut archive).unwrap();
+    assert_eq!(write_project(&mut archive, imported, &book).unwrap(), 2);
+    let read = |path: &Path| {
+        let mut content = String::new();
+        File::open(path).unwrap().read_to_string(&mut content).unwrap();
+        content
+    };
+    let config = read(&book);
+    assert!(config.starts_with("title: \"My book\"\n"));
+    assert!(config.ends_with("+ chapter_01.md\n+ chapter_02.md\n"));
+    assert_eq!(read(&dir.join("chapter_02.md")), "# Second\n\nEnd.\n");
+    assert_eq!(read(&dir.join("images").join("a.png")), "png");
+
+    // Existing files are never overwritten, and nothing is written if one exists
+    fs::remove_file(&book).unwrap();
+    File::create(dir.join("chapter_02.md")).unwrap();
+    let imported = import_odt(&mut archive).unwrap();
+    assert!(write_project(&mut archive, imported, &book).is_err());
+    assert!(fs::metadata(&book).is_err());
+    assert_eq!(read(&dir.join("chapter_02.md")), "");
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,520 @@
+// Copyright (C) 2017 Élisabeth HENRY.
+//
+// This file is part of Crowbook.
+//
+// Crowbook is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published
+// by the Free Software Foundation, either version 2.1 of the License, or
+// (at your option) any later version.
+//
+// Crowbook is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Resolution of include directives in Markdown sources, to include other
+//! Markdown files or (parts of) source code files.
+
+use error::{Error, Result, Source};
+use bookoptions::BookOptions;
+use variables;
+use misc;
+
+use std::fs;
+use std::fs::File;
+use std::io::Read;
+use std::mem;
+use std::path::{Path, PathBuf};
+
+/// Beginning of an include directive
+const DIRECTIVE: &'static str = "{{#include ";
+
+/// Replaces the include directives of `content`, the content of `file` (relative to `root`).
+///
+/// Directives must be on their own line, outside of code blocks:
+///
+/// * `{{#include chapter.md}}` includes another Markdown file (which can itself
+///   contain include directives);
+/// * `{{#include code.rs}}` includes a file as a code block, with the language
+///   detected from its extension;
+/// * `{{#include code.rs:10:20}}` only includes lines 10 to 20 (either bound
+///   can be omitted, and `code.rs:10` only includes line 10);
+/// * `{{#include code.rs:name}}` only includes the lines between the ones containing
+///   `ANCHOR: name` and `ANCHOR_END: name` (`name` can only contain letters, digits,
+///   `_` and `-`).
+///
+/// Paths are relative to the directory of the including file. Variables are
+/// substituted in included Markdown files, using `options`, and the relative
+/// targets of their inline links and images (`[text](target)`) and of their link
+/// definitions (`[label]: target`) are rewritten so they stay relative to the
+/// included file.
+///
+/// Also returns the source (file and line) of each line of the resolved content.
+pub fn resolve_includes(root: &Path,
+                        options: &BookOptions,
+                        file: &str,
+                        content: String)
+                        -> Result<(String, Vec<Source>)> {
+    if !content.contains(DIRECTIVE) {
+        let lines = (1..content.lines().count() + 1).map(|i| line_source(file, i)).collect();
+        return Ok((content, lines));
+    }
+    let mut stack = vec![canonical(root, Path::new(file))];
+    resolve(root, options, file, &content, &mut stack)
+}
+
+/// Returns the source pointing to a line of a file
+fn line_source(file: &str, line: usize) -> Source {
+    let mut source = Source::new(file);
+    source.set_line(line as u32);
+    source
+}
+
+/// Returns a path that can be compared to detect include cycles
+fn canonical(root: &Path, path: &Path) -> PathBuf {
+    let path = root.join(path);
+    fs::canonicalize(&path).unwrap_or(path)
+}
+
+/// If a (trimmed) line opens or closes a code block, returns the fence
+fn fence_run(line: &str) -> Option<&str> {
+    for c in &['`', '~'] {
+        let n = line.len() - line.trim_left_matches(*c).len();
+        if n >= 3 {
+            return Some(&line[..n]);
+        }
+    }
+    None
+}
+
+/// Returns true if a (trimmed) line starts a list item
+fn is_list_item(line: &str) -> bool {
+    let digits = line.len() - line.trim_left_matches(|c: char| c.is_digit(10)).len();
+    let rest = &line[digits..];
+    let marker = if digits > 0 {
+        rest.starts_with('.') || rest.starts_with(')')
+    } else {
+        rest.starts_with('-') || rest.starts_with('*') || rest.starts_with('+')
+    };
+    marker && rest[1..].starts_with(char::is_whitespace)
+}
+
+/// Keeps track of code blocks, fenced or indented, while reading Markdown line by line
+pub struct CodeBlocks {
+    /// Fence of the current fenced code block, if any
+    fence: Option<String>,
+    /// Whether the previous line was in an indented code block
+    indented: bool,
+    /// Whether the previous line was in a list (where indented lines are not code)
+    list: bool,
+    /// Whether the previous line was blank
+    blank: bool,
+}
+
+impl CodeBlocks {
+    /// Creates a new `CodeBlocks`, at the beginning of some content
+    pub fn new() -> CodeBlocks {
+        CodeBlocks {
+            fence: None,
+            indented: false,
+            list: false,
+            blank: true,
+        }
+    }
+
+    /// Returns true if `line`, the next line of the content, is part of a code block
+    /// (including the lines opening and closing fenced code blocks)
+    pub fn is_code(&mut self, line: &str) -> bool {
+        let trimmed = line.trim();
+        let blank = trimmed.is_empty();
+        let previous_blank = mem::replace(&mut self.blank, blank);
+        let indented = line.starts_with("    ") || line.starts_with('\t');
+        if self.fence.is_none() {
+            if self.indented && (indented || blank) ||
+               indented && !blank && previous_blank && !self.list {
+                self.indented = true;
+                return true;
+            }
+            self.indented = false;
+        }
+        if let Some(run) = fence_run(trimmed) {
+            let closing = match self.fence {
+                Some(ref fence) => run.starts_with(fence.as_str()) && run.len() == trimmed.len(),
+                None => false,
+            };
+            if closing {
+                self.fence = None;
+            } else if self.fence.is_none() {
+                self.fence = Some(run.to_owned());
+            }
+            return true;
+        }
+        if self.fence.is_some() {
+            return true;
+        }
+        if !blank {
+            self.list = is_list_item(trimmed) || self.list && (indented || !previous_blank);
+        }
+        false
+    }
+}
+
+/// Resolves the include directives in the content of a file, returning the
+/// resolved content and the source of each of its lines
+fn resolve(root: &Path,
+           options: &BookOptions,
+           file: &str,
+           content: &str,
+           stack: &mut Vec<PathBuf>)
+           -> Result<(String, Vec<Source>)> {
+    let mut result = String::with_capacity(content.len());
+    let mut lines = vec![];
+    let mut code = CodeBlocks::new();
+    for (i, line) in content.lines().enumerate() {
+        let trimmed = line.trim();
+        let source = line_source(file, i + 1);
+        // Don't resolve directives inside code blocks
+        if !code.is_code(line) && trimmed.starts_with(DIRECTIVE) && trimmed.ends_with("}}") {
+            let argument = trimmed[DIRECTIVE.len()..trimmed.len() - 2].trim();
+            let (included, included_lines) = include(root,
+                                                     options,
+                                                     file,
+                                                     argument,
+                                                     &source,
+                                                     stack)?;
+            result.push_str(&included);
+            lines.extend(included_lines);
+        } else {
+            result.push_str(line);
+            result.push('\n');
+            lines.push(source);
+        }
+    }
+    Ok((result, lines))
+}
+
+/// Returns the content that must replace an include directive, and the source of
+/// each of its lines
+fn include(root: &Path,
+           options: &BookOptions,
+           file: &str,
+           argument: &str,
+           source: &Source,
+           stack: &mut Vec<PathBuf>)
+           -> Result<(String, Vec<Source>)> {
+    let (path, selection) = split_selection(argument);
+    let directory = Path::new(path).parent().map(|p| p.to_path_buf());
+    let path = match Path::new(file).parent() {
+        Some(dir) => dir.join(path),
+        None => PathBuf::from(path),
+    };
+    let full_path = root.join(&path);
+    let mut f = File::open(&full_path)
+        .map_err(|_| {
+            Error::file_not_found(source,
+                                  lformat!("include directive"),
+                                  format!("{}", full_path.display()))
+        })?;
+    let mut content = String::new();
+    f.read_to_string(&mut content)
+        .map_err(|_| {
+            Error::parser(source,
+                          lformat!("file {file} contains invalid UTF-8",
+                                   file = misc::normalize(&full_path)))
+        })?;
+    let (content, numbers) = select(&content, selection, source)?;
+
+    let extension = path.extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("")
+        .to_lowercase();
+    match extension.as_ref() {
+        "md" | "markdown" | "mdown" | "mkd" => {
+            let canonical = canonical(root, &path);
+            if stack.contains(&canonical) {
+                let chain = stack.iter()
+                    .chain(Some(&canonical))
+                    .map(misc::normalize)
+                    .collect::<Vec<_>>()
+                    .join(" -> ");
+                return Err(Error::file_not_found(source,
+                                                 lformat!("include directive (circular include: {chain})",
+                                                          chain = chain),
+                                                 format!("{}", full_path.display())));
+            }
+            let file = path.to_string_lossy();
+            let content = variables::substitute(options, &file, content)?;
+            stack.push(canonical);
+            let result = resolve(root, options, &file, &content, stack);
+            stack.pop();
+            let (result, lines) = result?;
+            // Lines of the resolved content are numbered from the start of the selection
+            let lines = lines.into_iter()
+                .map(|mut line| {
+                    if line.file.as_ref().map(|f| f.as_str()) == Some(&*file) {
+                        if let Some(n) = line.line {
+                            line.set_line(numbers[n as usize - 1] as u32);
+                        }
+                    }
+                    line
+                })
+                .collect();
+            match directory {
+                Some(ref dir) if dir != Path::new("") => Ok((rebase_links(&result, dir), lines)),
+                _ => Ok((result, lines)),
+            }
+        }
+        _ => {
+            // Use a fence longer than any sequence of backticks in the code
+            let mut longest = 0;
+            let mut current = 0;
+            for c in content.chars() {
+                if c == '`' {
+                    current += 1;
+                    if current > longest {
+                        longest = current;
+                    }
+                } else {
+                    current = 0;
+                }
+            }
+            let fence = "`".repeat(if longest >= 3 { longest + 1 } else { 3 });
+            // Fences point to the include directive, and code to the included file
+            let file = path.to_string_lossy();
+            let mut lines = vec![source.clone()];
+            lines.extend(numbers.iter().map(|&n| line_source(&file, n)));
+            lines.push(source.clone());
+            Ok((format!("{fence}{language}\n{code}{fence}\n",
+                        fence = fence,
+                        language = language(&extension),
+                        code = content),
+                lines))
+        }
+    }
+}
+
+/// Splits the argument of an include directive into the path and the selection, if
+/// any: a line range (`:10`, `:10:20`, `:10:`...) or a region name (`:name`) at the
+/// end of the argument. Other colons (e.g. in `C:\code\main.rs`) are part of the path.
+fn split_selection(argument: &str) -> (&str, Option<&str>) {
+    let is_number = |s: &str| s.chars().all(|c| c.is_digit(10));
+    let n = match argument.rfind(':') {
+        Some(n) => n,
+        None => return (argument.trim(), None),
+    };
+    let suffix = &argument[n + 1..];
+    let start = if is_number(suffix) {
+        // Line range: possibly preceded by the first line
+        match argument[..n].rfind(':') {
+            Some(m) if is_number(&argument[m + 1..n]) && !(m + 1 == n && suffix.is_empty()) => m,
+            _ => n,
+        }
+    } else if suffix.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '-') {
+        n
+    } else {
+        return (argument.trim(), None);
+    };
+    (argument[..start].trim(), Some(argument[start + 1..].trim()))
+}
+
+/// Rewrites the relative targets of links and images in Markdown content, outside
+/// of code blocks, so they are relative to the including file instead of to `dir`
+fn rebase_links(content: &str, dir: &Path) -> String {
+    let dir = dir.to_string_lossy().replace('\\', "/");
+    let mut result = String::with_capacity(content.len());
+    let mut code = CodeBlocks::new();
+    for line in content.lines() {
+        let trimmed = line.trim();
+        if code.is_code(line) {
+            result.push_str(line);
+        } else if trimmed.starts_with('[') && trimmed.contains("]:") {
+            // Link definition
+            let n = line.find("]:").unwrap() + 2;
+            let spaces = line[n..].len() - line[n..].trim_left().len();
+            result.push_str(&line[..n + spaces]);
+            result.push_str(&rebase_link(&line[n + spaces..], &dir));
+        } else {
+            let mut rest = line;
+            while let Some(n) = rest.find("](") {
+                result.push_str(&rest[..n + 2]);
+                rest = &rest[n + 2..];
+                let end = rest.find(|c: char| c == ')' || c.is_whitespace()).unwrap_or(rest.len());
+                result.push_str(&rebase_link(&rest[..end], &dir));
+                rest = &rest[end..];
+            }
+            result.push_str(rest);
+        }
+        result.push('\n');
+    }
+    result
+}
+
+/// Prefixes a link target with `dir` if it is relative
+fn rebase_link(target: &str, dir: &str) -> String {
+    let (open, url) = if target.starts_with('<') {
+        ("<", &target[1..])
+    } else {
+        ("", target)
+    };
+    if url.is_empty() || url.starts_with('#') || url.starts_with('/') || url.contains(':') {
+        target.to_owned()
+    } else {
+        format!("{}{}/{}", open, dir, url)
+    }
+}
+
+/// Returns the language of a code file, according to its extension
+fn language(extension: &str) -> &str {
+    match extension {
+        "rs" => "rust",
+        "py" => "python",
+        "rb" => "ruby",
+        "js" => "javascript",
+        "ts" => "typescript",
+        "sh" | "bash" => "bash",
+        "h" => "c",
+        "cc" | "cxx" | "hpp" => "cpp",
+        "hs" => "haskell",
+        "ml" | "mli" => "ocaml",
+        "pl" => "perl",
+        "yml" => "yaml",
+        "tex" => "latex",
+        "txt" => "",
+        _ => extension,
+    }
+}
+
+/// Returns true if `line` contains `marker` (e.g. `ANCHOR:`) followed by `name`
+fn is_anchor(line: &str, marker: &str, name: Option<&str>) -> bool {
+    match line.find(marker) {
+        Some(n) => {
+            match name {
+                Some(name) => line[n + marker.len()..].split_whitespace().next() == Some(name),
+                None => true,
+            }
+        }
+        None => false,
+    }
+}
+
+/// Returns the lines of `content` selected by a line range or a region name,
+/// without the lines containing anchors, and their line numbers in `content`
+fn select(content: &str,
+          selection: Option<&str>,
+          source: &Source)
+          -> Result<(String, Vec<usize>)> {
+    let lines: Vec<(usize, &str)> = content.lines().enumerate().map(|(i, l)| (i + 1, l)).collect();
+    let selected: &[(usize, &str)] = match selection {
+        None | Some("") => &lines[..],
+        Some(selection) if selection.chars().all(|c| c.is_digit(10) || c == ':') => {
+            let parse = |s: &str, default: usize| -> Result<usize> {
+                if s.is_empty() {
+                    Ok(default)
+                } else {
+                    s.parse::<usize>()
+                        .map_err(|_| {
+                            Error::parser(source,
+                                          lformat!("invalid line range '{range}' in include \
+                                                    directive",
+                                                   range = selection))
+                        })
+                }
+            };
+            let (begin, end) = match selection.find(':') {
+                Some(n) => (parse(&selection[..n], 1)?, parse(&selection[n + 1..], lines.len())?),
+                None => {
+                    let n = parse(selection, 1)?;
+                    (n, n)
+                }
+            };
+            if begin == 0 || begin > end || end > lines.len() {
+                return Err(Error::parser(source,
+                                         lformat!("invalid line range '{range}' in include \
+                                                   directive: file has {n} lines",
+                                                  range = selection,
+                                                  n = lines.len())));
+            }
+            &lines[begin - 1..end]
+        }
+        Some(region) => {
+            let begin = lines.iter().position(|&(_, l)| is_anchor(l, "ANCHOR:", Some(region)));
+            let end = lines.iter().position(|&(_, l)| is_anchor(l, "ANCHOR_END:", Some(region)));
+            match (begin, end) {
+                (Some(begin), Some(end)) if begin < end => &lines[begin + 1..end],
+                _ => {
+                    return Err(Error::parser(source,
+                                             lformat!("could not find region '{region}' \
+                                                       in included file",
+                                                      region = region)));
+                }
+            }
+        }
+    };
+    let mut result = String::new();
+    let mut numbers = vec![];
+    for &(n, line) in selected {
+        if is_anchor(line, "ANCHOR:", None) || is_anchor(line, "ANCHOR_END:", None) {
+            continue;
+        }
+        result.push_str(line);
+        result.push('\n');
+        numbers.push(n);
+    }
+    Ok((result, numbers))
+}
+
+#[test]
+fn include_select() {
+    let source = Source::empty();
+    let code = "fn main() {\n    // ANCHOR: hello\n    println!(\"Hello\");\n    // ANCHOR_END: hello\n}\n";
+    assert_eq!(select(code, None, &source).unwrap(),
+               (String::from("fn main() {\n    println!(\"Hello\");\n}\n"), vec![1, 3, 5]));
+    assert_eq!(select(code, Some("hello"), &source).unwrap(),
+               (String::from("    println!(\"Hello\");\n"), vec![3]));
+    assert_eq!(select(code, Some("1"), &source).unwrap(),
+               (String::from("fn main() {\n"), vec![1]));
+    assert_eq!(select(code, Some("3:"), &source).unwrap(),
+               (String::from("    println!(\"Hello\");\n}\n"), vec![3, 5]));
+    assert!(select(code, Some("4:2"), &source).is_err());
+    assert!(select(code, Some("goodbye"), &source).is_err());
+}
+
+#[test]
+fn include_code_blocks() {
+    let content = "text\n\n    code\n\n    more code\ntext\n    lazy continuation\n\n\
+                   - item\n\n    continuation\n\n~~~\nfenced\n~~~\n";
+    let mut code = CodeBlocks::new();
+    let lines: Vec<bool> = content.lines().map(|l| code.is_code(l)).collect();
+    assert_eq!(lines,
+               [false, false, true, true, true, false, false, false, false, false, false, false,
+                true, true, true]);
+}
+
+#[test]
+fn include_split_selection() {
+    assert_eq!(split_selection("code.rs"), ("code.rs", None));
+    assert_eq!(split_selection("code.rs:10"), ("code.rs", Some("10")));
+    assert_eq!(split_selection("code.rs:10:20"), ("code.rs", Some("10:20")));
+    assert_eq!(split_selection("code.rs:10:"), ("code.rs", Some("10:")));
+    assert_eq!(split_selection("code.rs::20"), ("code.rs", Some(":20")));
+    assert_eq!(split_selection("code.rs:hello"), ("code.rs", Some("hello")));
+    assert_eq!(split_selection("C:\\code\\x.rs"), ("C:\\code\\x.rs", None));
+    assert_eq!(split_selection("C:\\code\\x.rs:3:5"), ("C:\\code\\x.rs", Some("3:5")));
+    assert_eq!(split_selection("dir:with:colons/x.md"), ("dir:with:colons/x.md", None));
+}
+
+#[test]
+fn include_rebase_links() {
+    let content = "![image](img.png) and [link](<other.md#x>), [web](http://a.b)\n\
+                   [anchor](#top)\n\
+                   \n    [code](code.md)\n\n\
+                   [ref]: notes.md\n";
+    assert_eq!(rebase_links(content, Path::new("sub")),
+               "![image](sub/img.png) and [link](<sub/other.md#x>), [web](http://a.b)\n\
+                [anchor](#top)\n\
+                \n    [code](code.md)\n\n\
+                [ref]: sub/notes.md\n");
+}
@@ -0,0 +1,133 @@
+// Copyright (C) 2017 Élisabeth HENRY.
+//
+// This file is part of Crowbook.
+//
+// Crowbook is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published
+// by the Free Software Foundation, either version 2.1 of the License, or
+// (at your option) any later version.
+//
+// Crowbook is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
+
+//! JSON representation of a parsed book (options and AST of the chapters), so
+//! external tools can inspect or transform it before it is rendered.
+
+use book::Book;
+use book_renderer::BookRenderer;
+use bookoption::BookOption;
+use bookoptions::BookOptions;
+use chapter::Chapter;
+use error::{Error, Result, Source};
+use parser::Features;
+
+use serde_json;
+use serde_json::{Value, Number};
+use yaml_rust::Yaml;
+
+use std::collections::BTreeMap;
+use std::io;
+
+/// JSON representation of a book, when it is written
+#[derive(Serialize)]
+struct BookAstRef<'a> {
+    /// Version of Crowbook that generated it
+    crowbook: &'static str,
+    options: BTreeMap<String, Value>,
+    features: Features,
+    chapters: &'a [Chapter],
+}
+
+/// JSON representation of a book, when it is read
+#[derive(Deserialize)]
+pub struct BookAst {
+    /// Options that are not set to their default value
+    pub options: BTreeMap<String, Value>,
+    /// Features used in the content of the book
+    pub features: Features,
+    /// Chapters, with their (already parsed) content
+    pub chapters: Vec<Chapter>,
+}
+
+/// Reads the JSON representation of a book
+pub fn read_ast<R: io::Read>(source: R) -> Result<BookAst> {
+    serde_json::from_reader(source)
+        .map_err(|err| Error::config_parser(Source::empty(),
+                                            lformat!("could not parse JSON: {error}",
+                                                     error = err)))
+}
+
+/// Converts a JSON value to the YAML value used to set an option
+pub fn json_to_yaml(value: &Value) -> Yaml {
+    match *value {
+        Value::Null => Yaml::Null,
+        Value::Bool(b) => Yaml::Boolean(b),
+        Value::Number(ref n) => {
+            match n.as_i64() {
+                Some(i) => Yaml::Integer(i),
+                None => Yaml::Real(format!("{}", n)),
+            }
+        }
+        Value::String(ref s) => Yaml::String(s.clone()),
+        Value::Array(ref values) => Yaml::Array(values.iter().map(json_to_yaml).collect()),
+        Value::Object(_) => Yaml::BadValue,
+    }
+}
+
+/// Converts an option to a JSON value
+fn option_to_json(option: &BookOption) -> Value {
+    match *option {
+        BookOption::String(ref s) |
+        BookOption::Path(ref s) => Value::String(s.clone()),
+        BookOption::Bool(b) => Value::Bool(b),
+        BookOption::Char(c) => Value::String(c.to_string()),
+        BookOption::Int(i) => Value::from(i),
+        // Goes through a string so e.g. 1.1 doesn't become 1.100000023841858
+        BookOption::Float(f) => {
+            format!("{}", f)
+                .parse()
+                .ok()
+                .and_then(Number::from_f64)
+                .map_or(Value::Null, Value::Number)
+        }
+        BookOption::StringVec(ref v) => {
+            Value::Array(v.iter().map(|s| Value::String(s.clone())).collect())
+        }
+        BookOption::__NonExhaustive => unreachable!(),
+    }
+}
+
+/// Returns the options that are not set to their default value
+fn options_to_json(options: &BookOptions) -> BTreeMap<String, Value> {
+    options.get_modified()
+        .into_iter()
+        .map(|(key, option)| (key.to_owned(), option_to_json(option)))
+        .collect()
+}
+
+/// Renderer writing the JSON representation of a book
+pub struct Json;
+
+impl BookRenderer for Json {
+    fn auto_path(&self, book_name: &str) -> Result<String> {
+        Ok(format!("{}.json", book_name))
+    }
+
+    fn render(&self, book: &Book, to: &mut io::Write) -> Result<()> {
+        let ast = BookAstRef {
+            crowbook: env!("CARGO_PKG_VERSION"),
+            options: options_to_json(&book.options),
+            features: book.features,
+            chapters: &book.chapters,
+        };
+        serde_json::to_writer_pretty(to, &ast)
+            .map_err(|err| Error::render(&book.source,
+                                         lformat!("problem when writing JSON: {error}",
+                                                  error = err)))
+    }
+}
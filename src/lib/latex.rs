@@ -15,17 +15,25 @@
 // You should have received ba copy of the GNU Lesser General Public License
 // along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
 
-use book::{Book, compile_str};
+use book::{Book, Header, NotesPlacement, compile_str};
 use number::Number;
 use error::{Error, Result, Source};
 use token::Token;
 use token::Data;
-use zipper::Zipper;
+use token::Alignment;
+use token::Attributes;
+use zipper::{Zipper, TexPipeline};
 use resource_handler::ResourceHandler;
 use renderer::Renderer;
 use parser::Parser;
 use book_renderer::BookRenderer;
 use syntax::Syntax;
+use cover;
+use print::PrintSettings;
+use latex_log::{self, SourceMap, SourceText};
+use text_view::view_as_text;
+use lang;
+use pdf::PdfRenderer;
 
 use crowbook_text_processing::escape;
 
@@ -36,6 +44,11 @@ use std::io;
 use std::io::Read;
 use std::fmt::Write;
 use std::borrow::Cow;
+use std::process::Command;
+
+/// Files the SVG ornament for scene breaks is written to, and converted to
+const SCENE_BREAK_SVG: &'static str = "images/scene_break.svg";
+const SCENE_BREAK_PDF: &'static str = "images/scene_break.pdf";
 
 
 /// LaTeX renderer
@@ -52,11 +65,19 @@ pub struct LatexRenderer<'a> {
     syntax: Option<Syntax>,
     hyperref: bool,
     enum_level: usize,
+    /// Whether to insert comments indicating the source of content
+    source_markers: bool,
+    /// Content of the current chapter's source file
+    source_text: Option<SourceText<'a>>,
+    /// Where footnotes are placed
+    notes_placement: NotesPlacement,
+    /// SVG ornament for scene breaks, that must be converted to PDF
+    scene_break_svg: Option<String>,
 }
 
 impl<'a> LatexRenderer<'a> {
     /// Creates new LatexRenderer
-    pub fn new(book: &'a Book) -> LatexRenderer<'a> {
+    pub fn new(book: &'a Book) -> Result<LatexRenderer<'a>> {
         let mut handler = ResourceHandler::new();
         handler.set_images_mapping(true);
         let syntax = if book.options.get_str("rendering.highlight").unwrap() == "syntect"
@@ -67,7 +88,7 @@ impl<'a> LatexRenderer<'a> {
         } else {
             None
         };
-        LatexRenderer {
+        Ok(LatexRenderer {
             book: book,
             current_chapter: Number::Default,
             handler: handler,
@@ -80,7 +101,11 @@ impl<'a> LatexRenderer<'a> {
             syntax: syntax,
             hyperref: book.options.get_bool("tex.hyperref").unwrap(),
             enum_level: 0,
-        }
+            source_markers: false,
+            source_text: None,
+            notes_placement: book.notes_placement(NotesPlacement::Page)?,
+            scene_break_svg: None,
+        })
     }
 
     /// Set proofreading to true
@@ -90,13 +115,71 @@ impl<'a> LatexRenderer<'a> {
         self
     }
 
+    /// Returns the LaTeX code displayed for scene breaks (horizontal rules)
+    fn scene_break(&mut self) -> Result<String> {
+        match self.book.options.get_path("rendering.scene_break.image") {
+            Ok(image) => {
+                let file = if image.to_lowercase().ends_with(".svg") {
+                    if fs::metadata(&image).is_err() {
+                        return Err(Error::file_not_found(&self.book.source,
+                                                         lformat!("image"),
+                                                         image));
+                    }
+                    self.scene_break_svg = Some(image);
+                    String::from(SCENE_BREAK_PDF)
+                } else {
+                    self.handler.map_image(&self.book.source, Cow::Owned(image))?.into_owned()
+                };
+                Ok(format!("\\includegraphics[height=1.5em]{{{}}}", file))
+            }
+            Err(_) => {
+                let text = self.book.options.get_str("rendering.scene_break").unwrap();
+                Ok(self.book.clean(escape::tex(text), true).into_owned())
+            }
+        }
+    }
+
+    /// Warns that an SVG scene break image is not converted when only generating
+    /// LaTeX code, so it must be converted manually
+    fn warn_scene_break_svg(&self) {
+        if let Some(ref svg) = self.scene_break_svg {
+            warn!("{}", lformat!("rendering.scene_break.image: LaTeX can't include SVG images, \
+                                  so {svg} must be converted to {pdf} (in the directory of the \
+                                  LaTeX file) to compile it",
+                                 svg = svg,
+                                 pdf = SCENE_BREAK_PDF));
+        }
+    }
+
+    /// Output formats for which conditional and raw blocks are rendered
+    fn output_formats(&self) -> &'static [&'static str] {
+        if self.proofread {
+            &["tex", "proofread"]
+        } else {
+            &["tex"]
+        }
+    }
+
     /// Render pdf to a file
     pub fn render_pdf(&mut self, to: &mut io::Write) -> Result<String> {
+        self.source_markers = true;
+        // Check print options before spending time on rendering
+        let pdfx = PrintSettings::new(self.book)?.map_or(false, |print| print.pdfx.is_some());
         let content = self.render_book()?;
         debug!("{}", lformat!("Attempting to run LaTeX on generated file"));
         let mut zipper = Zipper::new(&self.book.options.get_path("crowbook.temp_dir")
                                      .unwrap())?;
+        zipper.set_timestamp(self.book.reproducible_timestamp());
         zipper.write("result.tex", content.as_bytes(), false)?;
+        if pdfx {
+            // Metadata required by the pdfx package
+            let mut xmpdata = String::new();
+            for &(key, option) in &[("Title", "title"), ("Author", "author"), ("Language", "lang")] {
+                let value = view_as_text(&Parser::new().parse_inline(self.book.options.get_str(option).unwrap())?);
+                writeln!(xmpdata, "\\{}{{{}}}", key, escape::tex(value))?;
+            }
+            zipper.write("result.xmpdata", xmpdata.as_bytes(), false)?;
+        }
 
         // write image files
         for (source, dest) in self.handler.images_mapping() {
@@ -114,10 +197,142 @@ impl<'a> LatexRenderer<'a> {
             zipper.write(dest, &content, true)?;
         }
 
+        // convert the SVG ornament for scene breaks, since LaTeX can't include it directly
+        if let Some(ref svg) = self.scene_break_svg {
+            let mut content = vec![];
+            File::open(svg)
+                .and_then(|mut f| f.read_to_end(&mut content))
+                .map_err(|_| Error::file_not_found(&self.source, lformat!("image"), svg.clone()))?;
+            zipper.write(SCENE_BREAK_SVG, &content, true)?;
+            let command_name = self.book.options.get_str("rendering.scene_break.command").unwrap();
+            let mut command = Command::new(command_name);
+            command.args(&["-f", "pdf", "-o", SCENE_BREAK_PDF, SCENE_BREAK_SVG]);
+            zipper.run_command(command, command_name, SCENE_BREAK_PDF, &mut io::sink())?;
+        }
+
+
+        let pipeline = TexPipeline {
+            command: self.book.options.get_str("tex.command").unwrap(),
+            latexmk: self.book.options.get_bool("tex.latexmk").unwrap(),
+            bibliography: self.book.options.get_str("tex.bibliography.command").unwrap(),
+            index: self.book.options.get_str("tex.index.command").unwrap(),
+            max_passes: self.book.options.get_i32("tex.max_passes").unwrap(),
+        };
+        let result = zipper.generate_pdf(&pipeline, "result.tex", to);
+        if self.book.options.get_bool("tex.keep_build_dir").unwrap() {
+            zipper.set_keep(true);
+            info!("{}", lformat!("LaTeX build directory was kept in {path}",
+                                 path = zipper.path().display()));
+        }
+        let log = zipper.read("result.log").unwrap_or_default();
+        let map = SourceMap::new(&content);
+        let mut errors = vec![];
+        for message in latex_log::parse(&log) {
+            let source = message.tex_line
+                .and_then(|n| map.get(n))
+                .cloned()
+                .unwrap_or_else(|| Source::new("result.tex"));
+            match message.kind {
+                latex_log::Kind::OverfullBox => {
+                    warn!("{}", lformat!("{source}: LaTeX: {message}",
+                                         source = source,
+                                         message = message.message));
+                }
+                latex_log::Kind::Error => errors.push(Error::render(source, message.message)),
+            }
+        }
+        match result {
+            Ok(output) => Ok(output),
+            Err(err) => {
+                if errors.is_empty() {
+                    return Err(err);
+                }
+                // Report all errors, but return the first one
+                for error in &errors[1..] {
+                    error!("{}", lformat!("LaTeX error: {error}", error = error));
+                }
+                Err(errors.swap_remove(0))
+            }
+        }
+    }
+
+    /// Returns the command setting the chapter (or part) name displayed in
+    /// running headers and footers
+    ///
+    /// The number is displayed from the LaTeX counter, so it is always the one
+    /// set by `\chapter` or `\part`.
+    fn running_header_mark(&mut self, title: &[Token]) -> Result<String> {
+        let part = self.current_chapter.is_part();
+        let title = self.render_vec(title)?;
+        let text = if self.current_chapter.is_numbered() &&
+            self.book.options.get_i32("rendering.num_depth").unwrap() >= 1 {
+            let book = self.book;
+            let (header, counter, roman) = if part {
+                (Header::Part, "part", "rendering.part.roman_numerals")
+            } else {
+                (Header::Chapter, "chapter", "rendering.chapter.roman_numerals")
+            };
+            let number = format!("\\{}{{{}}}",
+                                 if book.options.get_bool(roman).unwrap() { "Roman" } else { "arabic" },
+                                 counter);
+            book.get_header_with_number(header, number, title, |s| {
+                    self.render_vec(&Parser::new().parse_inline(s)?)
+                })?
+                .text
+        } else {
+            title
+        };
+        Ok(format!("\\crowbookset{}{{{}}}\n", if part { "part" } else { "chapter" }, text))
+    }
 
-        zipper.generate_pdf(self.book.options.get_str("tex.command").unwrap(),
-                            "result.tex",
-                            to)
+    /// Puts content in the environments associated to the classes of an element
+    /// by `tex.environments`
+    fn wrap_environments(&self, content: String, attributes: Option<&Attributes>) -> String {
+        let attributes = match attributes {
+            Some(attributes) => attributes,
+            None => return content,
+        };
+        let mappings = self.book.options.get_str_vec("tex.environments").unwrap_or(&[]);
+        let mut content = content;
+        for class in &attributes.classes {
+            let environment = mappings.iter()
+                .filter_map(|m| {
+                    let (name, environment) = match m.find('=') {
+                        Some(n) => (m[..n].trim(), m[n + 1..].trim()),
+                        None => (m.trim(), m.trim()),
+                    };
+                    if name == class.as_str() { Some(environment) } else { None }
+                })
+                .next();
+            if let Some(environment) = environment {
+                content = format!("\\begin{{{env}}}\n{content}\\end{{{env}}}\n\n",
+                                  env = environment,
+                                  content = content);
+            }
+        }
+        content
+    }
+
+    /// Returns a comment indicating the approximate source of some content,
+    /// if the book is rendered to PDF
+    fn source_marker(&mut self, tokens: &[Token]) -> String {
+        if !self.source_markers {
+            return String::new();
+        }
+        let file = match self.source.file {
+            Some(ref file) => file.clone(),
+            None => return String::new(),
+        };
+        let located = match self.source_text {
+            Some(ref mut text) => text.locate(&view_as_text(tokens)),
+            None => None,
+        };
+        match located {
+            Some(&Source { file: Some(ref file), line: Some(line) }) => {
+                latex_log::marker(file, line as usize)
+            }
+            _ => latex_log::marker(&file, 1),
+        }
     }
 
     /// Render latex in a string
@@ -138,6 +353,7 @@ impl<'a> LatexRenderer<'a> {
 
         for (i, chapter) in self.book.chapters.iter().enumerate() {
             self.handler.add_link(chapter.filename.as_ref(), format!("chapter-{}", i));
+            add_header_anchors(&mut self.handler, &chapter.content);
         }
         
         for (i, chapter) in self.book.chapters.iter().enumerate() {
@@ -145,17 +361,44 @@ impl<'a> LatexRenderer<'a> {
             self.current_chapter = n;
             let v = &chapter.content;
             self.source = Source::new(chapter.filename.as_str());
+            if self.source_markers {
+                self.source_text = chapter.source
+                    .as_ref()
+                    .map(|&(ref content, ref lines)| SourceText::new(content, lines));
+            }
+            let has_notes = has_footnotes(v);
             let mut offset = 0;
             if !v.is_empty() && v[0].is_header() {
                 content.push_str(&self.render_token(&v[0])?);
                 offset = 1;
+                if has_notes && self.notes_placement == NotesPlacement::Book {
+                    // Group notes by chapter in the final Notes chapter
+                    if let Token::Header(1, ref title) = v[0] {
+                        write!(content,
+                               "\\addtoendnotes{{\\protect\\mdnoteschapter{{{}}}}}\n",
+                               self.render_vec(title)?)?;
+                    }
+                }
             }
             write!(content,
                    "\\label{{chapter-{}}}\n",
                    i)?;
             content.push_str(&self.render_vec(&v[offset..])?);
+            if has_notes && self.notes_placement == NotesPlacement::Chapter {
+                content.push_str("\\theendnotes\n\\setcounter{endnote}{0}\n");
+            }
         }
         self.source = Source::empty();
+        if self.notes_placement == NotesPlacement::Book &&
+            self.book.chapters.iter().any(|c| has_footnotes(&c.content)) {
+            content.push_str(if self.is_short {
+                "\\renewcommand{\\enoteheading}{\\section*{\\notesname}}\n"
+            } else {
+                "\\renewcommand{\\enoteheading}{\\chapter*{\\notesname}\
+                 \\addcontentsline{toc}{chapter}{\\notesname}}\n"
+            });
+            content.push_str("\\theendnotes\n");
+        }
 
 
         let tex_lang = String::from(match self.book.options.get_str("lang").unwrap() {
@@ -206,6 +449,9 @@ impl<'a> LatexRenderer<'a> {
             }
         });
 
+        let scene_break = self.scene_break()?;
+        let has_ornament = self.book.options.get_path("rendering.scene_break.image").is_ok();
+
         let template = compile_str(self.book.get_template("tex.template")?.as_ref(),
                                    &self.book.source,
                                    "tex.template")?;
@@ -218,8 +464,35 @@ impl<'a> LatexRenderer<'a> {
             .insert_bool("use_url", self.book.features.url)
             .insert_bool("use_tables", self.book.features.table)
             .insert_bool("use_codeblocks", self.book.features.codeblock)
-            .insert_bool("use_images", self.book.features.image)
-            .insert_str("tex_lang", tex_lang);
+            .insert_bool("use_images", self.book.features.image || has_ornament)
+            .insert_bool("use_strikethrough", self.book.features.strikethrough)
+            .insert_bool("use_task_lists", self.book.features.task_list)
+            .insert_bool("use_admonitions", self.book.features.admonition)
+            .insert_bool("use_verse", self.book.features.verse)
+            .insert_bool("endnotes", self.notes_placement == NotesPlacement::Chapter ||
+                         self.notes_placement == NotesPlacement::Book)
+            .insert_bool("side_notes", self.notes_placement == NotesPlacement::Side)
+            .insert_str("tex_lang", tex_lang)
+            .insert_str("scene_break", scene_break);
+
+        // Running headers and footers
+        let layout_data = self.book.get_metadata(|s| self.render_vec(&Parser::new().parse_inline(s)?))?
+            .insert_str("page", "\\thepage")
+            .insert_str("chapter", "\\crowbookchapter")
+            .insert_str("part", "\\crowbookpart")
+            .build();
+        for &(option, key) in &[("pdf.header.odd", "header_odd"),
+                                ("pdf.header.even", "header_even"),
+                                ("pdf.footer.odd", "footer_odd"),
+                                ("pdf.footer.even", "footer_even")] {
+            let layout = self.book.get_running_layout(option, &layout_data)?;
+            for (i, position) in ["left", "center", "right"].iter().enumerate() {
+                data = data.insert_str(format!("{}_{}", key, position), layout[i].as_str());
+            }
+        }
+        if self.book.options.get_bool("pdf.header.chapter_pages").unwrap() {
+            data = data.insert_bool("header_chapter_pages", true);
+        }
         if let Ok(tex_tmpl_add) = self.book.options.get_str("tex.template.add") {
             data = data.insert_str("additional_code", tex_tmpl_add);
         }
@@ -242,6 +515,21 @@ impl<'a> LatexRenderer<'a> {
             .insert_str("margin_top", self.book.options.get_str("tex.margin.top").unwrap());
 
         
+        if let Some(print) = PrintSettings::new(self.book)? {
+            data = data.insert_str("print_geometry", print.geometry);
+            if let Some(crop) = print.crop {
+                data = data.insert_str("print_crop", crop);
+            }
+            if let Some(pdfx) = print.pdfx {
+                data = data.insert_str("print_pdfx", pdfx);
+            }
+        }
+
+        if cover::is_enabled(self.book) {
+            data = data.insert_bool("generated_cover", true)
+                .insert_str("cover_background", cover::colour(self.book, "cover.generate.background")?)
+                .insert_str("cover_foreground", cover::colour(self.book, "cover.generate.foreground")?);
+        }
         if self.book.options.get_bool("rendering.initials") == Ok(true) {
             data = data.insert_bool("initials", true);
         }
@@ -313,10 +601,12 @@ impl<'a> Renderer for LatexRenderer<'a> {
                         self.first_letter = true;
                     }
                 }
-                Ok(format!("{}\n\n", self.render_vec(vec)?))
+                let marker = self.source_marker(vec);
+                let content = format!("{}\n\n", self.render_vec(vec)?);
+                Ok(format!("{}{}", marker, self.wrap_environments(content, token.attributes())))
             }
             Token::Header(n, ref vec) => {
-                let mut content = String::new();
+                let mut content = self.source_marker(vec);
                 if n == 1 {
                     self.first_paragraph = true;
                     if self.current_chapter == Number::Hidden {
@@ -329,6 +619,10 @@ impl<'a> Renderer for LatexRenderer<'a> {
                         content.push_str(r"\setcounter{chapter}{");
                         write!(content, "{}", n - 1)?;
                         content.push_str("}\n");
+                    } else if let Number::SpecifiedPart(n) = self.current_chapter {
+                        content.push_str(r"\setcounter{part}{");
+                        write!(content, "{}", n - 1)?;
+                        content.push_str("}\n");
                     }
                 }
                 match n {
@@ -358,6 +652,12 @@ impl<'a> Renderer for LatexRenderer<'a> {
                 content.push_str(r"{");
                 content.push_str(&self.render_vec(vec)?);
                 content.push_str("}\n");
+                if let Some(id) = token.attributes().and_then(|a| a.id.as_ref()) {
+                    write!(content, "\\label{{{}}}\n", label(id))?;
+                }
+                if n == 1 {
+                    content.push_str(&self.running_header_mark(vec)?);
+                }
                 Ok(content)
             }
             Token::Emphasis(ref vec) => Ok(format!("\\emph{{{}}}", self.render_vec(vec)?)),
@@ -366,11 +666,64 @@ impl<'a> Renderer for LatexRenderer<'a> {
                                                insert_breaks(&self.render_vec(vec)?))),
             Token::Superscript(ref vec) => Ok(format!("\\textsuperscript{{{}}}", self.render_vec(vec)?)),
             Token::Subscript(ref vec) => Ok(format!("\\textsubscript{{{}}}", self.render_vec(vec)?)),
+            Token::Strikethrough(ref vec) => Ok(format!("\\sout{{{}}}", self.render_vec(vec)?)),
             Token::BlockQuote(ref vec) => {
                 Ok(format!("\\begin{{mdblockquote}}\n{}\n\\end{{mdblockquote}}\n",
                            self.render_vec(vec)?))
             }
+            Token::Admonition(ref kind, ref title, ref vec) => {
+                let title = if title.is_empty() {
+                    escape::tex(lang::get_str(self.book.options.get_str("lang").unwrap(), kind))
+                        .into_owned()
+                } else {
+                    self.book.clean(escape::tex(title.as_str()), true).into_owned()
+                };
+                let colour = match kind.as_ref() {
+                    "tip" => "green",
+                    "important" => "violet",
+                    "warning" => "orange",
+                    "caution" => "red",
+                    _ => "blue",
+                };
+                Ok(format!("\\begin{{mdadmonition}}{{{}}}{{{}}}\n{}\n\\end{{mdadmonition}}\n",
+                           colour,
+                           title,
+                           self.render_vec(vec)?))
+            }
+            Token::Verse(numbers, ref vec) => {
+                let mut content = String::new();
+                for stanza in vec {
+                    if !content.is_empty() {
+                        content.push('\n');
+                    }
+                    content.push_str(&self.render_token(stanza)?);
+                }
+                Ok(format!("\\setcounter{{poemline}}{{0}}\\poemlines{{{}}}
+\\begin{{verse}}
+{}\\end{{verse}}\n",
+                           numbers,
+                           content))
+            }
+            Token::Stanza(ref vec) => {
+                let lines = vec.iter()
+                    .map(|line| self.render_token(line))
+                    .collect::<Result<Vec<_>>>()?;
+                // The last line of a stanza ends with \\!
+                Ok(format!("{}\\\\!\n", lines.join("\\\\\n")))
+            }
+            Token::VerseLine(indent, ref vec) => {
+                let content = self.render_vec(vec)?;
+                if indent > 0 {
+                    Ok(format!("\\hspace*{{{}em}}{}", indent as f32 / 2.0, content))
+                } else if content.starts_with('[') || content.starts_with('*') {
+                    // Else it would be read as an argument of the previous \\
+                    Ok(format!("{{}}{}", content))
+                } else {
+                    Ok(content)
+                }
+            }
             Token::CodeBlock(ref language, ref vec) => {
+                let marker = self.source_marker(vec);
                 self.escape = false;
                 let mut res = self.render_vec(vec)?;
                 // Remove trailing newline
@@ -386,9 +739,9 @@ impl<'a> Renderer for LatexRenderer<'a> {
 \\end{{spverbatim}}",
                             code = res)
                 };
-                res = format!("\\begin{{mdcodeblock}}
+                res = format!("{}\\begin{{mdcodeblock}}
 {}
-\\end{{mdcodeblock}}", res);
+\\end{{mdcodeblock}}", marker, res);
                 Ok(res)
             }
             Token::Rule => Ok(String::from("\\mdrule\n")),
@@ -426,10 +779,31 @@ impl<'a> Renderer for LatexRenderer<'a> {
                 Ok(result)
             },
             Token::Item(ref vec) => Ok(format!("\\item {}\n", self.render_vec(vec)?)),
+            Token::TaskItem(checked, ref vec) => {
+                Ok(format!("\\item[{}] {}\n",
+                           if checked { "$\\boxtimes$" } else { "$\\square$" },
+                           self.render_vec(vec)?))
+            }
+            Token::DefinitionList(ref vec) => {
+                Ok(format!("\\begin{{description}}\n{}\\end{{description}}\n",
+                           self.render_vec(vec)?))
+            }
+            Token::DefinitionTerm(ref vec) => Ok(format!("\\item[{{{}}}] ", self.render_vec(vec)?)),
+            // A blank line separates multiple descriptions of the same term
+            Token::DefinitionDescription(ref vec) => Ok(format!("{}\n\n", self.render_vec(vec)?)),
             Token::Link(ref url, _, ref vec) => {
                 let content = self.render_vec(vec)?;
 
-                if self.hyperref && self.handler.contains_link(url) {
+                // Only links to a header of the book (`#id` or `chapter.md#id`) use its anchor
+                let anchor = match url.find('#') {
+                    Some(n) if ResourceHandler::is_local(url) &&
+                               (n == 0 || self.handler.contains_link(&url[..n])) &&
+                               self.handler.contains_anchor(&url[n + 1..]) => Some(&url[n + 1..]),
+                    _ => None,
+                };
+                if let (true, Some(anchor)) = (self.hyperref, anchor) {
+                    Ok(format!("\\hyperref[{}]{{{}}}", label(anchor), content))
+                } else if self.hyperref && self.handler.contains_link(url) {
                     Ok(format!("\\hyperref[{}]{{{}}}", escape::tex(self.handler.get_link(url)), content))
                 } else {
                     let url = escape::tex(url.as_ref());
@@ -448,8 +822,9 @@ impl<'a> Renderer for LatexRenderer<'a> {
             Token::StandaloneImage(ref url, _, _) => {
                 if ResourceHandler::is_local(url) {
                     let img = self.handler.map_image(&self.source, url.as_ref())?;
-                    Ok(format!("\\mdstandaloneimage{{{}}}\n",
-                               img))
+                    let content = format!("\\mdstandaloneimage{{{}}}\n",
+                                          img);
+                    Ok(self.wrap_environments(content, token.attributes()))
 
                 } else {
                     debug!("{}", lformat!("LaTeX ({source}): image '{url}' doesn't seem to be \
@@ -472,12 +847,22 @@ impl<'a> Renderer for LatexRenderer<'a> {
                 }
             }
             Token::Footnote(ref vec) => {
-                Ok(format!("\\protect\\footnote{{{}}}", self.render_vec(vec)?))
+                let command = match self.notes_placement {
+                    NotesPlacement::Page => "footnote",
+                    NotesPlacement::Chapter | NotesPlacement::Book => "endnote",
+                    NotesPlacement::Side => "mdsidenote",
+                };
+                Ok(format!("\\protect\\{}{{{}}}", command, self.render_vec(vec)?))
             }
-            Token::Table(n, ref vec) => {
+            Token::Table(ref alignments, ref vec) => {
                 let mut cols = String::new();
-                for _ in 0..n {
-                    cols.push_str("|X");
+                for alignment in alignments {
+                    cols.push_str(match *alignment {
+                        Alignment::Default => "|X",
+                        Alignment::Left => "|>{\\raggedright\\arraybackslash}X",
+                        Alignment::Center => "|>{\\centering\\arraybackslash}X",
+                        Alignment::Right => "|>{\\raggedleft\\arraybackslash}X",
+                    });
                 }
                 cols.push_str("|");
                 Ok(format!("\\begin{{mdtable}}{{{}}}
@@ -496,7 +881,8 @@ impl<'a> Renderer for LatexRenderer<'a> {
                     .join(" & ");
                 res.push_str("\\\\ \n");
                 if let Token::TableHead(_) = *token {
-                    res.push_str("\\hline\n");
+                    // Repeat the header on each page if the table is split
+                    res.push_str("\\hline\n\\endhead\n");
                 }
                 Ok(res)
             }
@@ -525,11 +911,66 @@ impl<'a> Renderer for LatexRenderer<'a> {
                 }
             }
 
+            Token::Attributes(_) => Ok(String::new()),
+            Token::Span(ref attributes, ref vec) => {
+                let content = self.render_vec(vec)?;
+                if attributes.has_class("smallcaps") {
+                    Ok(format!("\\textsc{{{}}}", content))
+                } else {
+                    Ok(content)
+                }
+            }
+            Token::OnlyFor(_, ref vec) => {
+                if token.is_rendered_in(self.output_formats()) {
+                    self.render_vec(vec)
+                } else {
+                    Ok(String::new())
+                }
+            }
+            Token::RawBlock(_, ref content) => {
+                if token.is_rendered_in(self.output_formats()) {
+                    Ok(content.clone())
+                } else {
+                    Ok(String::new())
+                }
+            }
             Token::__NonExhaustive => unreachable!(),
         }
     }
 }
 
+/// Registers the identifiers of headers, so links to them can use `\hyperref`
+fn add_header_anchors(handler: &mut ResourceHandler, tokens: &[Token]) {
+    for token in tokens {
+        if let Token::Header(..) = *token {
+            if let Some(id) = token.attributes().and_then(|a| a.id.as_ref()) {
+                handler.add_anchor(id.clone(), "");
+            }
+        } else if let Some(inner) = token.inner() {
+            add_header_anchors(handler, inner);
+        }
+    }
+}
+
+/// Returns true if some tokens contain footnotes
+fn has_footnotes(tokens: &[Token]) -> bool {
+    tokens.iter().any(|token| match *token {
+        Token::Footnote(_) => true,
+        _ => token.inner().map_or(false, has_footnotes),
+    })
+}
+
+/// Returns a LaTeX label corresponding to an identifier
+fn label(id: &str) -> String {
+    id.chars()
+        .map(|c| if c.is_alphanumeric() || c == '-' || c == '_' || c == ':' || c == '.' {
+            c
+        } else {
+            '-'
+        })
+        .collect()
+}
+
 pub struct Latex;
 pub struct ProofLatex;
 pub struct Pdf;
@@ -542,8 +983,9 @@ impl BookRenderer for Latex {
     }
     
     fn render(&self, book: &Book, to: &mut io::Write) -> Result<()> {
-        let mut latex = LatexRenderer::new(book);
+        let mut latex = LatexRenderer::new(book)?;
         let result = latex.render_book()?;
+        latex.warn_scene_break_svg();
         to.write_all(result.as_bytes())
             .map_err(|e| {
                 Error::render(&book.source,
@@ -559,8 +1001,9 @@ impl BookRenderer for ProofLatex {
     }
     
     fn render(&self, book: &Book, to: &mut io::Write) -> Result<()> {
-        let mut latex = LatexRenderer::new(book).proofread();
+        let mut latex = LatexRenderer::new(book)?.proofread();
         let result = latex.render_book()?;
+        latex.warn_scene_break_svg();
         to.write_all(result.as_bytes())
             .map_err(|e| {
                 Error::render(&book.source,
@@ -570,13 +1013,34 @@ impl BookRenderer for ProofLatex {
     }
 }
 
+/// Returns true if the PDF must be generated by the native renderer instead of LaTeX
+fn use_native_engine(book: &Book) -> Result<bool> {
+    match book.options.get_str("pdf.engine").unwrap() {
+        "latex" => Ok(false),
+        "native" => Ok(true),
+        engine => {
+            Err(Error::book_option(&book.source,
+                                   lformat!("pdf.engine: unknown engine '{engine}', expected \
+                                             'latex' or 'native'",
+                                            engine = engine)))
+        }
+    }
+}
+
 impl BookRenderer for Pdf {
     fn auto_path(&self, book_name: &str) -> Result<String> {
         Ok(format!("{}.pdf", book_name))
     }
     
     fn render(&self, book: &Book, to: &mut io::Write) -> Result<()> {
-        LatexRenderer::new(book)
+        if use_native_engine(book)? {
+            if PrintSettings::new(book)?.is_some() {
+                warn!("{}", lformat!("PDF: tex.print.* options are ignored by the native PDF \
+                                      engine"));
+            }
+            return PdfRenderer::new(book)?.render_book(to);
+        }
+        LatexRenderer::new(book)?
             .render_pdf(to)?;
         Ok(())
     }
@@ -588,7 +1052,13 @@ impl BookRenderer for ProofPdf {
     }
     
     fn render(&self, book: &Book, to: &mut io::Write) -> Result<()> {
-        LatexRenderer::new(book)
+        if use_native_engine(book)? {
+            return Err(Error::book_option(&book.source,
+                                          lformat!("output.proofread.pdf: proofreading is not \
+                                                    supported by the native PDF engine, set \
+                                                    pdf.engine to 'latex'")));
+        }
+        LatexRenderer::new(book)?
             .proofread()
             .render_pdf(to)?;
         Ok(())
@@ -0,0 +1,250 @@
+// Copyright (C) 2017 Élisabeth HENRY.
+//
+// This file is part of Crowbook.
+//
+// Crowbook is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published
+// by the Free Software Foundation, either version 2.1 of the License, or
+// (at your option) any later version.
+//
+// Crowbook is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Parsing of LaTeX logs, and mapping of the generated LaTeX file back to
+//! the Markdown sources, so errors can be reported where the user can fix them.
+
+use error::Source;
+
+/// Prefix of the comments inserted in the generated LaTeX file to
+/// indicate where content comes from
+const MARKER: &'static str = "%crowbook-source: ";
+
+/// Returns a LaTeX comment indicating the source of the content that follows
+pub fn marker(file: &str, line: usize) -> String {
+    format!("{}{}:{}\n", MARKER, file, line)
+}
+
+/// Markdown source of a chapter (once includes are resolved), used to find
+/// the approximate source of a token's content
+pub struct SourceText<'a> {
+    content: &'a str,
+    /// Source (file and line) of each line of `content`
+    lines: &'a [Source],
+    /// Position (in bytes) where the last located content was found
+    pos: usize,
+}
+
+impl<'a> SourceText<'a> {
+    /// Creates a new `SourceText`
+    pub fn new(content: &'a str, lines: &'a [Source]) -> SourceText<'a> {
+        SourceText {
+            content: content,
+            lines: lines,
+            pos: 0,
+        }
+    }
+
+    /// Returns the approximate source (file and line) of `text` (the content of a
+    /// token, as text), if it is known.
+    ///
+    /// The search is based on the first word of `text` that isn't likely to be
+    /// altered by Markdown syntax, and starts after the previously located content,
+    /// since tokens are mostly rendered in order.
+    pub fn locate(&mut self, text: &str) -> Option<&'a Source> {
+        let word = text.split_whitespace()
+            .find(|w| w.chars().count() >= 3 && w.chars().all(|c| c.is_alphanumeric()))
+            .or_else(|| text.split_whitespace().next());
+        if let Some(word) = word {
+            let found = self.content[self.pos..]
+                .find(word)
+                .map(|i| i + self.pos)
+                // e.g. footnotes are defined after they are used
+                .or_else(|| self.content.find(word));
+            if let Some(pos) = found {
+                self.pos = pos;
+            }
+        }
+        let line = self.content[..self.pos].matches('\n').count();
+        self.lines.get(line)
+    }
+}
+
+/// Maps lines of the generated LaTeX file to the Markdown sources, using
+/// the markers it contains
+pub struct SourceMap {
+    /// Line of the LaTeX file where a marker is, and the source it points to
+    markers: Vec<(usize, Source)>,
+}
+
+impl SourceMap {
+    /// Creates a source map from the content of a LaTeX file
+    pub fn new(tex: &str) -> SourceMap {
+        let mut markers = vec![];
+        for (i, line) in tex.lines().enumerate() {
+            if !line.starts_with(MARKER) {
+                continue;
+            }
+            let location = &line[MARKER.len()..];
+            let mut source = Source::new(location);
+            if let Some(n) = location.rfind(':') {
+                if let Ok(number) = location[n + 1..].parse::<u32>() {
+                    source = Source::new(&location[..n]);
+                    source.set_line(number);
+                }
+            }
+            markers.push((i + 1, source));
+        }
+        SourceMap { markers: markers }
+    }
+
+    /// Returns the source of a line of the LaTeX file, if it is known
+    pub fn get(&self, tex_line: usize) -> Option<&Source> {
+        self.markers
+            .iter()
+            .take_while(|&&(line, _)| line <= tex_line)
+            .last()
+            .map(|&(_, ref source)| source)
+    }
+}
+
+/// The kind of a LaTeX log message
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Kind {
+    /// An error (the PDF generation failed)
+    Error,
+    /// An overfull box (text exceeding the margins)
+    OverfullBox,
+}
+
+/// A message from a LaTeX log
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogMessage {
+    /// The kind of message
+    pub kind: Kind,
+    /// The line of the LaTeX file the message refers to, if it is known
+    pub tex_line: Option<usize>,
+    /// The message
+    pub message: String,
+}
+
+/// Parses the leading digits of a string
+fn leading_number(s: &str) -> Option<usize> {
+    let digits: String = s.chars().take_while(|c| c.is_digit(10)).collect();
+    digits.parse().ok()
+}
+
+/// Parses a LaTeX log and returns the errors and overfull boxes it contains
+pub fn parse(log: &str) -> Vec<LogMessage> {
+    let lines: Vec<&str> = log.lines().collect();
+    let mut messages = vec![];
+    for (i, line) in lines.iter().enumerate() {
+        if line.starts_with("! ") {
+            // The line of the error is given afterwards, as "l.42 <context>"
+            let tex_line = lines[i + 1..]
+                .iter()
+                .take(20)
+                .filter(|l| l.starts_with("l."))
+                .filter_map(|l| leading_number(&l[2..]))
+                .next();
+            messages.push(LogMessage {
+                kind: Kind::Error,
+                tex_line: tex_line,
+                message: line[2..].trim().to_owned(),
+            });
+        } else if line.starts_with("Overfull \\") {
+            let (message, tex_line) = match line.find(" at line") {
+                Some(n) => {
+                    let rest = line[n..].trim_left_matches(" at lines ").trim_left_matches(" at line ");
+                    let message = line[..n]
+                        .trim_right_matches(" in paragraph")
+                        .trim_right_matches(" in alignment")
+                        .trim_right_matches(" detected");
+                    (message, leading_number(rest))
+                }
+                None => (*line, None),
+            };
+            messages.push(LogMessage {
+                kind: Kind::OverfullBox,
+                tex_line: tex_line,
+                message: message.trim().to_owned(),
+            });
+        } else if let Some(n) = line.find(".tex:") {
+            // Errors in the "file:line: message" format (-file-line-error)
+            let rest = &line[n + 5..];
+            if let Some(tex_line) = leading_number(rest) {
+                if let Some(m) = rest.find(": ") {
+                    messages.push(LogMessage {
+                        kind: Kind::Error,
+                        tex_line: Some(tex_line),
+                        message: rest[m + 2..].trim().to_owned(),
+                    });
+                }
+            }
+        }
+    }
+    messages
+}
+
+#[test]
+fn parse_log() {
+    let log = r"(./result.tex
+! Undefined control sequence.
+l.42 \foo
+         bar
+Overfull \hbox (12.5pt too wide) in paragraph at lines 50--52
+[]\TU/lmr/m/n/10 Some text|
+./result.tex:60: Missing $ inserted.
+)";
+    let messages = parse(log);
+    assert_eq!(messages,
+               vec![LogMessage {
+                        kind: Kind::Error,
+                        tex_line: Some(42),
+                        message: String::from("Undefined control sequence."),
+                    },
+                    LogMessage {
+                        kind: Kind::OverfullBox,
+                        tex_line: Some(50),
+                        message: String::from("Overfull \\hbox (12.5pt too wide)"),
+                    },
+                    LogMessage {
+                        kind: Kind::Error,
+                        tex_line: Some(60),
+                        message: String::from("Missing $ inserted."),
+                    }]);
+}
+
+#[test]
+fn source_map() {
+    let tex = format!("\\begin{{document}}\n{}Some text\n\n{}\\chapter{{Foo}}\nMore text\n",
+                      marker("chapter_1.md", 3),
+                      marker("chapter_2.md", 1));
+    let map = SourceMap::new(&tex);
+    assert_eq!(map.get(1), None);
+    assert_eq!(format!("{}", map.get(3).unwrap()), "chapter_1.md:3");
+    assert_eq!(format!("{}", map.get(6).unwrap()), "chapter_2.md:1");
+}
+
+#[test]
+fn source_text_locate() {
+    let content = "# Title\n\nSome *emphasized* text.\n\nAnother paragraph[^1]\n\n[^1]: A note\n";
+    // The third line comes from an included file
+    let lines: Vec<_> = (1..8)
+        .map(|i| {
+            let mut source = Source::new(if i == 3 { "included.md" } else { "chapter.md" });
+            source.set_line(i);
+            source
+        })
+        .collect();
+    let mut text = SourceText::new(content, &lines);
+    let mut locate = |s| format!("{}", text.locate(s).unwrap());
+    assert_eq!(locate("Title"), "chapter.md:1");
+    assert_eq!(locate("Some emphasized text."), "included.md:3");
+    assert_eq!(locate("A note"), "chapter.md:7");
+    assert_eq!(locate("Another paragraph"), "chapter.md:5");
+}
@@ -116,7 +116,10 @@ extern crate crowbook_intl_runtime;
 extern crate numerals;
 extern crate epub_builder;
 extern crate uuid;
+extern crate chrono;
 extern crate hyphenation;
+extern crate zip;
+extern crate xml;
 #[macro_use]
 extern crate log;
 #[macro_use]
@@ -134,11 +137,8 @@ extern crate hyper;
 extern crate url;
 #[cfg(feature = "proofread")]
 extern crate caribon;
-#[cfg(feature = "proofread")]
 extern crate serde;
-#[cfg(feature = "proofread")]
 extern crate serde_json;
-#[cfg(feature = "proofread")]
 #[macro_use]
 extern crate serde_derive;
 #[cfg(feature = "syntect")]
@@ -154,12 +154,15 @@ pub use bookoptions::BookOptions;
 pub use error::{Result, Error, Source};
 pub use token::Token;
 pub use token::Data;
+pub use token::Alignment;
+pub use token::Attributes;
 pub use number::Number;
 pub use resource_handler::ResourceHandler;
 pub use renderer::Renderer;
 pub use book_renderer::BookRenderer;
 pub use chapter::Chapter;
 pub use stats::Stats;
+pub use import::import_document;
 
 #[macro_use]
 #[doc(hidden)]
@@ -171,10 +174,12 @@ mod error;
 mod book;
 mod epub;
 mod latex;
+mod latex_log;
 mod odt;
 mod parser;
 mod token;
 mod cleaner;
+mod dialogue;
 mod chapter;
 mod number;
 mod resource_handler;
@@ -186,6 +191,13 @@ mod html_single;
 mod html_if;
 mod syntax;
 mod stats;
+mod hyphenate;
+mod include;
+mod variables;
+mod html_parser;
+mod import;
+mod json;
+mod filter;
 
 #[cfg(feature = "binary")]
 mod style;
@@ -203,6 +215,10 @@ use book_bars_stubs as book_bars;
 
 
 mod zipper;
+mod cover;
+mod pdf;
+mod pdf_writer;
+mod print;
 mod templates;
 mod bookoption;
 mod misc;
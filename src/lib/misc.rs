@@ -22,6 +22,7 @@ use token::Token;
 use std;
 use std::path::{Path, PathBuf};
 use std::io::Result;
+use uuid::Uuid;
 
 /// Try to canonicalize a path using std::fs::canonicalize, and returns the
 /// unmodified path if it fails (e.g. if the path doesn't exist (yet))
@@ -63,3 +64,79 @@ pub fn insert_title(tokens: &mut Vec<Token>) {
     }
     tokens.insert(0, Token::Header(1, vec!()));
 }
+
+/// Returns an identifier derived from a text, e.g. "Bob's new Title!" becomes
+/// "bobs-new-title".
+pub fn slugify(s: &str) -> String {
+    let mut slug = String::with_capacity(s.len());
+    let mut dash = false;
+    for c in s.chars() {
+        if c.is_alphanumeric() {
+            if dash && !slug.is_empty() {
+                slug.push('-');
+            }
+            dash = false;
+            slug.extend(c.to_lowercase());
+        } else if c != '\'' && c != '’' && c != '\u{AD}' {
+            dash = true;
+        }
+    }
+    slug
+}
+
+/// Returns true if `id` has the same form as the identifiers generated by
+/// renderers, e.g. `chapter-3` or `para-12`
+pub fn is_generated_id(id: &str) -> bool {
+    for prefix in &["chapter-", "link-", "para-", "note-source-", "note-dest-"] {
+        if id.starts_with(prefix) {
+            let rest = &id[prefix.len()..];
+            return !rest.is_empty() && rest.chars().all(|c| c.is_digit(10));
+        }
+    }
+    false
+}
+
+/// Hash a string with FNV-1a.
+///
+/// Unlike `std`'s `DefaultHasher`, the result is guaranteed not to change
+/// between runs or compiler versions, so it can be used for identifiers
+/// that must stay the same across builds.
+pub fn stable_hash(s: &str, seed: u64) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325 ^ seed;
+    for b in s.bytes() {
+        hash ^= b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+/// Returns an URN containing an UUID derived from a string.
+///
+/// It uses the same layout as a name-based (version 5) UUID, but the name
+/// is hashed with `stable_hash`.
+pub fn stable_uuid(name: &str) -> String {
+    let mut bytes = [0u8; 16];
+    for (i, seed) in [0u64, 1u64].iter().enumerate() {
+        let hash = stable_hash(name, *seed);
+        for j in 0..8 {
+            bytes[i * 8 + j] = (hash >> (j * 8)) as u8;
+        }
+    }
+    bytes[6] = (bytes[6] & 0x0f) | 0x50;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let uuid = Uuid::from_bytes(&bytes).unwrap();
+    format!("{}", uuid.urn())
+}
+
+#[test]
+fn misc_stable_uuid() {
+    let uuid = stable_uuid("Some title\nSome author\n");
+    assert_eq!(uuid, stable_uuid("Some title\nSome author\n"));
+    assert!(uuid != stable_uuid("Other title\nSome author\n"));
+    assert!(uuid.starts_with("urn:uuid:"));
+    // Version and variant bits of a name-based UUID
+    let uuid = &uuid["urn:uuid:".len()..];
+    assert_eq!(uuid.len(), 36);
+    assert_eq!(&uuid[14..15], "5");
+    assert!(["8", "9", "a", "b"].contains(&&uuid[19..20]));
+}
@@ -4,7 +4,7 @@
 /// library users should **not** do exhaustive matchs on the variants,
 /// since it is possible new variants will be added without being
 /// considered a breaking change
-#[derive(Debug, PartialEq, Clone, Copy)]
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
 pub enum Number {
     /// Chapter's title is hidden
     Hidden,
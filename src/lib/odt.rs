@@ -1,11 +1,12 @@
-use token::Token;
-use book::{Book, compile_str};
+use token::{Token, Alignment};
+use book::{Book, NotesPlacement, compile_str};
 use number::Number;
 use error::Result;
 use templates::odt;
 use zipper::Zipper;
 use parser::Parser;
 use book_renderer::BookRenderer;
+use lang;
 
 use crowbook_text_processing::escape;
 
@@ -20,16 +21,35 @@ pub struct OdtRenderer<'a> {
     current_hide: bool,
     current_chapter: i32,
     automatic_styles: String,
+    table_number: usize,
+    table_head: bool,
+    /// Alignment of the columns of the current table
+    table_alignments: Vec<Alignment>,
+    /// Current column of the current table
+    table_column: usize,
+    /// Current footnote number
+    footnote_number: u32,
+    /// Where footnotes are placed
+    notes_placement: NotesPlacement,
+    /// Whether the current content is inside an admonition
+    admonition: bool,
 }
 
 impl<'a> OdtRenderer<'a> {
     /// Creates a new OdtRenderer
-    pub fn new(book: &'a Book) -> OdtRenderer {
-        OdtRenderer {
+    pub fn new(book: &'a Book) -> Result<OdtRenderer> {
+        Ok(OdtRenderer {
             book: book,
             current_chapter: 1,
             current_numbering: book.options.get_i32("rendering.num_depth").unwrap(),
             current_hide: false,
+            table_number: 0,
+            table_head: false,
+            table_alignments: vec![],
+            table_column: 0,
+            footnote_number: 0,
+            notes_placement: book.notes_placement(NotesPlacement::Page)?,
+            admonition: false,
             automatic_styles: String::from("
 <style:style style:name=\"T1\" \
                                             style:family=\"text\">
@@ -45,8 +65,72 @@ impl<'a> OdtRenderer<'a> {
                                             fo:font-weight=\"bold\" \
                                             style:font-weight-asian=\"bold\" \
                                             style:font-weight-complex=\"bold\"/>
+</style:style>
+\
+                                            <style:style style:name=\"T3\" \
+                                            style:family=\"text\">
+  <style:text-properties \
+                                            style:text-line-through-style=\"solid\"/>
+</style:style>
+\
+                                            <style:style style:name=\"Description\" \
+                                            style:family=\"paragraph\" \
+                                            style:parent-style-name=\"Text_20_body\">
+  <style:paragraph-properties fo:margin-left=\"1cm\"/>
+</style:style>
+\
+                                            <style:style style:name=\"TableLeft\" \
+                                            style:family=\"paragraph\" \
+                                            style:parent-style-name=\"Text_20_body\">
+  <style:paragraph-properties fo:text-align=\"start\"/>
+</style:style>
+\
+                                            <style:style style:name=\"TableCenter\" \
+                                            style:family=\"paragraph\" \
+                                            style:parent-style-name=\"Text_20_body\">
+  <style:paragraph-properties fo:text-align=\"center\"/>
+</style:style>
+\
+                                            <style:style style:name=\"TableRight\" \
+                                            style:family=\"paragraph\" \
+                                            style:parent-style-name=\"Text_20_body\">
+  <style:paragraph-properties fo:text-align=\"end\"/>
+</style:style>
+\
+                                            <style:style style:name=\"Admonition\" \
+                                            style:family=\"paragraph\" \
+                                            style:parent-style-name=\"Text_20_body\">
+  <style:paragraph-properties fo:margin-left=\"0.5cm\" \
+                                            fo:margin-right=\"0.5cm\" \
+                                            fo:padding=\"0.1cm\" \
+                                            fo:background-color=\"#eeeeee\"/>
+</style:style>
+\
+                                            <style:style style:name=\"AdmonitionTitle\" \
+                                            style:family=\"paragraph\" \
+                                            style:parent-style-name=\"Admonition\">
+  <style:text-properties fo:font-weight=\"bold\" \
+                                            style:font-weight-asian=\"bold\" \
+                                            style:font-weight-complex=\"bold\"/>
+</style:style>
+\
+                                            <style:style style:name=\"Verse\" \
+                                            style:family=\"paragraph\" \
+                                            style:parent-style-name=\"Text_20_body\">
+  <style:paragraph-properties fo:margin-left=\"1cm\" \
+                                            fo:text-align=\"start\"/>
+</style:style>
+\
+                                            <style:style style:name=\"SceneBreak\" \
+                                            style:family=\"paragraph\" \
+                                            style:parent-style-name=\"Text_20_body\">
+  <style:paragraph-properties fo:text-align=\"center\" \
+                                            fo:text-indent=\"0cm\" \
+                                            fo:margin-top=\"0.4cm\" \
+                                            fo:margin-bottom=\"0.4cm\" \
+                                            fo:keep-with-next=\"always\"/>
 </style:style>"),
-        }
+        })
     }
 
     /// Renders a full book
@@ -63,6 +147,7 @@ impl<'a> OdtRenderer<'a> {
 
         let mut zipper =
             Zipper::new(&self.book.options.get_path("crowbook.temp_dir").unwrap())?;
+        zipper.set_timestamp(self.book.reproducible_timestamp());
 
         // Write template.odt there
         zipper.write("template.odt", odt::ODT, false)?;
@@ -83,10 +168,11 @@ impl<'a> OdtRenderer<'a> {
         if self.book.features.blockquote { missing.push(lformat!("blockquotes")); }
         if self.book.features.codeblock { missing.push(lformat!("codeblocks")); }
         if self.book.features.ordered_list { missing.push(lformat!("ordered lists")); }
-        if self.book.features.footnote { missing.push(lformat!("footnotes")); }
-        if self.book.features.table { missing.push(lformat!("tables")); }
         if self.book.features.superscript { missing.push(lformat!("superscript")); }
         if self.book.features.subscript { missing.push(lformat!("subscript")); }
+        if self.book.options.get_path("rendering.scene_break.image").is_ok() {
+            missing.push(lformat!("scene break images"));
+        }
 
         if !missing.is_empty() {
             let missing = missing.join(", ");
@@ -155,9 +241,38 @@ impl<'a> OdtRenderer<'a> {
         match *token {
             Token::Str(ref text) => escape::html(self.book.clean(text.as_str(), false)).into_owned(),
             Token::Paragraph(ref vec) => {
-                format!("<text:p text:style-name=\"Text_20_body\">{}</text:p>\n",
+                format!("<text:p text:style-name=\"{}\">{}</text:p>\n",
+                        if self.admonition { "Admonition" } else { "Text_20_body" },
                         self.render_vec(vec))
             }
+            Token::Admonition(ref kind, ref title, ref vec) => {
+                let title = if title.is_empty() {
+                    escape::html(lang::get_str(self.book.options.get_str("lang").unwrap(), kind))
+                        .into_owned()
+                } else {
+                    escape::html(self.book.clean(title.as_str(), false)).into_owned()
+                };
+                let admonition = self.admonition;
+                self.admonition = true;
+                let content = self.render_vec(vec);
+                self.admonition = admonition;
+                format!("<text:p text:style-name=\"AdmonitionTitle\">{}</text:p>\n{}",
+                        title,
+                        content)
+            }
+            Token::Verse(_, ref vec) => self.render_vec(vec),
+            Token::Stanza(ref vec) => {
+                let lines: Vec<_> = vec.iter().map(|line| self.parse_token(line)).collect();
+                format!("<text:p text:style-name=\"Verse\">{}</text:p>\n",
+                        lines.join("<text:line-break/>"))
+            }
+            Token::VerseLine(indent, ref vec) => {
+                if indent > 0 {
+                    format!("<text:s text:c=\"{}\"/>{}", indent, self.render_vec(vec))
+                } else {
+                    self.render_vec(vec)
+                }
+            }
             Token::Header(n, ref vec) => {
                 if n == 1 && self.current_hide {
                     return String::new();
@@ -192,6 +307,20 @@ impl<'a> OdtRenderer<'a> {
                 format!("<text:list-item>\n<text:p>{}</text:p></text:list-item>",
                         self.render_vec(vec))
             }
+            Token::TaskItem(checked, ref vec) => {
+                format!("<text:list-item>\n<text:p>{} {}</text:p></text:list-item>",
+                        if checked { "☒" } else { "☐" },
+                        self.render_vec(vec))
+            }
+            Token::DefinitionList(ref vec) => self.render_vec(vec),
+            Token::DefinitionTerm(ref vec) => {
+                format!("<text:p text:style-name=\"Text_20_body\"><text:span text:style-name=\"T2\">{}</text:span></text:p>\n",
+                        self.render_vec(vec))
+            }
+            Token::DefinitionDescription(ref vec) => {
+                format!("<text:p text:style-name=\"Description\">{}</text:p>\n",
+                        self.render_vec(vec))
+            }
             Token::Link(ref url, _, ref vec) => {
                 format!("<text:a xlink:type=\"simple\"  xlink:href=\"{}\">{}</text:a>",
                         url,
@@ -202,27 +331,94 @@ impl<'a> OdtRenderer<'a> {
                         self.render_vec(vec))
             }
             Token::Subscript(ref vec) | Token::Superscript(ref vec) => self.render_vec(vec),
+            Token::Strikethrough(ref vec) => {
+                format!("<text:span text:style-name=\"T3\">{}</text:span>",
+                        self.render_vec(vec))
+            }
             Token::BlockQuote(ref vec) |
             Token::CodeBlock(_, ref vec) => {
                 format!("<text:p text:style-name=\"Text_20_Body\">{}</text:p>\n",
                         self.render_vec(vec))
             }
             Token::SoftBreak | Token::HardBreak => String::from(" "),
-            Token::Rule => String::from("<text:p /><text:p>***</text:p><text:p />"),
+            Token::Rule => {
+                let text = self.book.options.get_str("rendering.scene_break").unwrap();
+                format!("<text:p text:style-name=\"SceneBreak\">{}</text:p>\n",
+                        escape::html(self.book.clean(text, false)))
+            }
             Token::Image(_, _, _) |
             Token::StandaloneImage(_, _, _) => {
                 String::from(" ")
             }
-            Token::Table(_, _) |
-            Token::TableHead(_) |
-            Token::TableRow(_) |
-            Token::TableCell(_) => {
-                String::from(" ")
+            Token::Table(ref alignments, ref vec) => {
+                self.table_number += 1;
+                self.table_alignments = alignments.clone();
+                format!("<table:table table:name=\"Table{}\">
+<table:table-column table:number-columns-repeated=\"{}\"/>
+{}</table:table>\n",
+                        self.table_number,
+                        alignments.len(),
+                        self.render_vec(vec))
             }
-            Token::Footnote(_) => {
-                String::new()
+            Token::TableHead(ref vec) => {
+                self.table_column = 0;
+                self.table_head = true;
+                let s = self.render_vec(vec);
+                self.table_head = false;
+                format!("<table:table-header-rows><table:table-row>{}</table:table-row></table:table-header-rows>\n",
+                        s)
+            }
+            Token::TableRow(ref vec) => {
+                self.table_column = 0;
+                format!("<table:table-row>{}</table:table-row>\n", self.render_vec(vec))
+            }
+            Token::TableCell(ref vec) => {
+                let style = match self.table_alignments.get(self.table_column) {
+                    Some(&Alignment::Left) => "TableLeft",
+                    Some(&Alignment::Center) => "TableCenter",
+                    Some(&Alignment::Right) => "TableRight",
+                    _ => "Text_20_body",
+                };
+                self.table_column += 1;
+                let mut content = self.render_vec(vec);
+                if self.table_head {
+                    content = format!("<text:span text:style-name=\"T2\">{}</text:span>", content);
+                }
+                format!("<table:table-cell office:value-type=\"string\"><text:p text:style-name=\"{}\">{}</text:p></table:table-cell>",
+                        style,
+                        content)
+            }
+            Token::Footnote(ref vec) => {
+                self.footnote_number += 1;
+                // ODT has no margin notes, and endnotes are collected at the end of the document
+                let class = match self.notes_placement {
+                    NotesPlacement::Chapter | NotesPlacement::Book => "endnote",
+                    NotesPlacement::Page | NotesPlacement::Side => "footnote",
+                };
+                format!("<text:note text:id=\"note{n}\" text:note-class=\"{class}\">\
+                         <text:note-citation>{n}</text:note-citation>\
+                         <text:note-body>{body}</text:note-body></text:note>",
+                        n = self.footnote_number,
+                        class = class,
+                        body = self.render_vec(vec))
+            }
+            Token::Annotation(_, ref vec) |
+            Token::Span(_, ref vec) => self.render_vec(vec),
+            Token::Attributes(_) => String::new(),
+            Token::OnlyFor(_, ref vec) => {
+                if token.is_rendered_in(&["odt"]) {
+                    self.render_vec(vec)
+                } else {
+                    String::new()
+                }
+            }
+            Token::RawBlock(_, ref content) => {
+                if token.is_rendered_in(&["odt"]) {
+                    content.clone()
+                } else {
+                    String::new()
+                }
             }
-            Token::Annotation(_, ref vec) => self.render_vec(vec),
             Token::__NonExhaustive => unreachable!(),
         }
     }
@@ -237,7 +433,7 @@ impl BookRenderer for Odt {
     }
     
     fn render(&self, book: &Book, to: &mut Write) -> Result<()> {
-        OdtRenderer::new(book)
+        OdtRenderer::new(book)?
             .render_book(to)?;
         Ok(())
     }
@@ -15,9 +15,12 @@
 // You should have received ba copy of the GNU Lesser General Public License
 // along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
 
-use token::Token;
+use token::{Token, Alignment, Attributes};
 use error::{Result, Error, Source};
 use book::Book;
+use html_parser;
+use html_parser::Tag as HtmlTag;
+use text_view::view_as_text;
 
 use std::mem;
 use std::fs::File;
@@ -28,11 +31,12 @@ use std::collections::HashMap;
 use std::ops::BitOr;
 
 use cmark::{Parser as CMParser, Event, Tag, Options, OPTION_ENABLE_FOOTNOTES, OPTION_ENABLE_TABLES};
+use cmark::Alignment as CMAlignment;
 
 
 
 
-#[derive(Debug, Copy, Clone, PartialEq)]
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
 /// The list of features used in a document.
 pub struct Features {
     pub image: bool,
@@ -44,6 +48,11 @@ pub struct Features {
     pub url: bool,
     pub subscript: bool,
     pub superscript: bool,
+    pub strikethrough: bool,
+    pub task_list: bool,
+    pub definition_list: bool,
+    pub admonition: bool,
+    pub verse: bool,
 }
 
 impl Features {
@@ -59,6 +68,44 @@ impl Features {
             url: false,
             subscript: false,
             superscript: false,
+            strikethrough: false,
+            task_list: false,
+            definition_list: false,
+            admonition: false,
+            verse: false,
+        }
+    }
+
+    /// Returns the features used by a list of tokens, e.g. once they have been
+    /// transformed by filters
+    pub fn of(tokens: &[Token]) -> Features {
+        let mut features = Features::new();
+        features.add_tokens(tokens);
+        features
+    }
+
+    fn add_tokens(&mut self, tokens: &[Token]) {
+        for token in tokens {
+            match *token {
+                Token::Image(..) | Token::StandaloneImage(..) => self.image = true,
+                Token::BlockQuote(_) => self.blockquote = true,
+                Token::CodeBlock(..) => self.codeblock = true,
+                Token::OrderedList(..) => self.ordered_list = true,
+                Token::Footnote(_) => self.footnote = true,
+                Token::Table(..) => self.table = true,
+                Token::Link(..) => self.url = true,
+                Token::Subscript(_) => self.subscript = true,
+                Token::Superscript(_) => self.superscript = true,
+                Token::Strikethrough(_) => self.strikethrough = true,
+                Token::TaskItem(..) => self.task_list = true,
+                Token::DefinitionList(_) => self.definition_list = true,
+                Token::Admonition(..) => self.admonition = true,
+                Token::Verse(..) => self.verse = true,
+                _ => (),
+            }
+            if let Some(inner) = token.inner() {
+                self.add_tokens(inner);
+            }
         }
     }
 }
@@ -78,6 +125,11 @@ impl BitOr for Features {
             url: self.url | rhs.url,
             subscript: self.subscript | rhs.subscript,
             superscript: self.superscript | rhs.superscript,
+            strikethrough: self.strikethrough | rhs.strikethrough,
+            task_list: self.task_list | rhs.task_list,
+            definition_list: self.definition_list | rhs.definition_list,
+            admonition: self.admonition | rhs.admonition,
+            verse: self.verse | rhs.verse,
         }
     }
 }
@@ -115,6 +167,7 @@ pub struct Parser {
 
     html_as_text: bool,
     superscript: bool,
+    verse_numbers: usize,
 }
 
 impl Parser {
@@ -126,6 +179,7 @@ impl Parser {
             features: Features::new(),
             html_as_text: true,
             superscript: false,
+            verse_numbers: 0,
         }
     }
 
@@ -134,6 +188,8 @@ impl Parser {
         let mut parser = Parser::new();
         parser.html_as_text = book.options.get_bool("crowbook.html_as_text").unwrap();
         parser.superscript = book.options.get_bool("crowbook.markdown.superscript").unwrap();
+        let verse_numbers = book.options.get_i32("rendering.verse.line_numbers").unwrap();
+        parser.verse_numbers = if verse_numbers > 0 { verse_numbers as usize } else { 0 };
         parser
     }
     
@@ -169,10 +225,7 @@ impl Parser {
 
     /// Parse a string and returns an AST  an Error.
     pub fn parse(&mut self, s: &str) -> Result<Vec<Token>> {
-        let mut opts = Options::empty();
-        opts.insert(OPTION_ENABLE_TABLES);
-        opts.insert(OPTION_ENABLE_FOOTNOTES);
-        let mut p = CMParser::new_ext(s, opts);
+        let mut p = CMParser::new_ext(s, cmark_options());
 
 
         let mut res = vec![];
@@ -180,10 +233,21 @@ impl Parser {
 
         self.parse_footnotes(&mut res)?;
 
+        // Must be done before collapsing, as it relies on line breaks
+        self.find_definition_lists(&mut res);
+        self.find_admonitions(&mut res);
+
         collapse(&mut res);
 
+        // Must be done before looking for standalone images, as it removes
+        // the attributes following an image
+        self.find_attributes(&mut res);
+
         find_standalone(&mut res);
 
+        self.find_task_items(&mut res);
+        self.parse_strikethrough_vec(&mut res);
+
         // Transform superscript and subscript
         if self.superscript {
             self.parse_super_vec(&mut res);
@@ -246,6 +310,9 @@ impl Parser {
                 Token::Code(ref mut vec) |
                 Token::BlockQuote(ref mut vec) |
                 Token::CodeBlock(_, ref mut vec) |
+                Token::Verse(_, ref mut vec) |
+                Token::Stanza(ref mut vec) |
+                Token::VerseLine(_, ref mut vec) |
                 Token::List(ref mut vec) |
                 Token::OrderedList(_, ref mut vec) |
                 Token::Item(ref mut vec) |
@@ -261,6 +328,337 @@ impl Parser {
         Ok(())
     }
     
+    /// Replaces paragraphs using the definition list syntax, i.e.:
+    ///
+    /// ```text
+    /// Term
+    /// : Description
+    /// ```
+    ///
+    /// by definition lists, and merges consecutive definition lists.
+    fn find_definition_lists(&mut self, v: &mut Vec<Token>) {
+        let mut i = 0;
+        while i < v.len() {
+            let list = match v[i] {
+                Token::Paragraph(ref inner) => definition_list(inner),
+                _ => None,
+            };
+            if list.is_none() && !v[i].is_code() {
+                if let Some(ref mut inner) = v[i].inner_mut() {
+                    self.find_definition_lists(inner);
+                }
+            }
+            if let Some(mut list) = list {
+                self.features.definition_list = true;
+                let merged = if i > 0 {
+                    if let Token::DefinitionList(ref mut previous) = v[i - 1] {
+                        previous.append(&mut list);
+                        true
+                    } else {
+                        false
+                    }
+                } else {
+                    false
+                };
+                if merged {
+                    v.remove(i);
+                    continue;
+                }
+                v[i] = Token::DefinitionList(list);
+            }
+            i += 1;
+        }
+    }
+
+    /// Replaces list items starting with `[ ]` or `[x]` by task items
+    fn find_task_items(&mut self, v: &mut Vec<Token>) {
+        for token in v.iter_mut() {
+            let task = if let Token::Item(ref mut inner) = *token {
+                // In "loose" lists, the content of items is inside a paragraph
+                let loose = match inner.first() {
+                    Some(&Token::Paragraph(_)) => true,
+                    _ => false,
+                };
+                let checked = if loose {
+                    if let Token::Paragraph(ref mut par) = inner[0] {
+                        remove_checkbox(par)
+                    } else {
+                        unreachable!()
+                    }
+                } else {
+                    remove_checkbox(inner)
+                };
+                checked.map(|checked| (checked, mem::replace(inner, vec![])))
+            } else {
+                None
+            };
+            if let Some((checked, inner)) = task {
+                self.features.task_list = true;
+                *token = Token::TaskItem(checked, inner);
+            }
+            if !token.is_code() {
+                if let Some(ref mut inner) = token.inner_mut() {
+                    self.find_task_items(inner);
+                }
+            }
+        }
+    }
+
+    /// Replaces block quotes starting with `[!KIND]` (and an optional title on
+    /// the same line) by admonitions, e.g.:
+    ///
+    /// ```text
+    /// > [!WARNING] Be careful
+    /// > This might break things.
+    /// ```
+    fn find_admonitions(&mut self, v: &mut Vec<Token>) {
+        for token in v.iter_mut() {
+            let admonition = if let Token::BlockQuote(ref mut inner) = *token {
+                admonition_header(inner).map(|(kind, title)| {
+                    (kind, title, mem::replace(inner, vec![]))
+                })
+            } else {
+                None
+            };
+            if let Some((kind, title, inner)) = admonition {
+                self.features.admonition = true;
+                *token = Token::Admonition(kind, title, inner);
+            }
+            if !token.is_code() {
+                if let Some(ref mut inner) = token.inner_mut() {
+                    self.find_admonitions(inner);
+                }
+            }
+        }
+    }
+
+    /// Parses the content of a verse block: each line of the source is a line of
+    /// verse, and stanzas are separated by blank lines
+    fn parse_verse(&mut self, numbers: usize, content: &str) -> Result<Token> {
+        self.features.verse = true;
+        let mut stanzas = vec![];
+        let mut lines = vec![];
+        for line in content.lines() {
+            let text = line.trim_left();
+            if text.trim().is_empty() {
+                if !lines.is_empty() {
+                    stanzas.push(Token::Stanza(mem::replace(&mut lines, vec![])));
+                }
+                continue;
+            }
+            let indent: usize = line[..line.len() - text.len()]
+                .chars()
+                .map(|c| if c == '\t' { 4 } else { 1 })
+                .sum();
+            let escaped = escape_block_markers(text.trim_right());
+            let mut p = CMParser::new_ext(&escaped, cmark_options());
+            let mut res = vec![];
+            self.parse_events(&mut p, &mut res, None)?;
+            // Only keep the inline content
+            let mut inline = vec![];
+            for token in res {
+                match token {
+                    Token::Paragraph(v) => inline.extend(v),
+                    token => inline.push(token),
+                }
+            }
+            lines.push(Token::VerseLine(indent, inline));
+        }
+        if !lines.is_empty() {
+            stanzas.push(Token::Stanza(lines));
+        }
+        Ok(Token::Verse(numbers, stanzas))
+    }
+
+    /// Looks for attributes (`{#id .class key=value}`) at the end of headers and
+    /// paragraphs, after images, and in spans (`[some text]{.class}`)
+    fn find_attributes(&mut self, v: &mut Vec<Token>) {
+        for token in v.iter_mut() {
+            if !token.is_code() {
+                if let Some(ref mut inner) = token.inner_mut() {
+                    self.find_attributes(inner);
+                }
+            }
+            match *token {
+                Token::Paragraph(ref mut inner) |
+                Token::Header(_, ref mut inner) => add_trailing_attributes(inner),
+                _ => (),
+            }
+        }
+
+        // Attributes directly following an image
+        let mut i = 0;
+        while i + 1 < v.len() {
+            let found = match (&v[i], &v[i + 1]) {
+                (&Token::Image(..), &Token::Str(ref s)) => leading_attributes(s),
+                _ => None,
+            };
+            if let Some((end, attributes)) = found {
+                if let Token::Image(_, _, ref mut alt) = v[i] {
+                    alt.push(Token::Attributes(attributes));
+                }
+                let rest = if let Token::Str(ref s) = v[i + 1] {
+                    s[end..].to_owned()
+                } else {
+                    unreachable!()
+                };
+                if rest.is_empty() {
+                    v.remove(i + 1);
+                } else {
+                    v[i + 1] = Token::Str(rest);
+                }
+            }
+            i += 1;
+        }
+
+        self.find_spans(v);
+    }
+
+    /// Looks for spans with attributes (`[some text]{.class}`) in a vector of tokens
+    fn find_spans(&mut self, v: &mut Vec<Token>) {
+        let mut i = 0;
+        while i < v.len() {
+            let found = match v[i] {
+                Token::Str(ref s) => find_span_end(s),
+                _ => None,
+            };
+            let (end, close, attributes) = match found {
+                Some(found) => found,
+                None => {
+                    i += 1;
+                    continue;
+                }
+            };
+            // Look for the opening bracket, in this Str or in a previous one
+            let open = (0..i + 1)
+                .rev()
+                .filter_map(|j| match v[j] {
+                    Token::Str(ref s) => {
+                        let s = if j == i { &s[..end] } else { s.as_str() };
+                        s.rfind('[').map(|b| (j, b))
+                    }
+                    _ => None,
+                })
+                .next();
+            let (j, open) = match open {
+                Some(open) => open,
+                None => {
+                    i += 1;
+                    continue;
+                }
+            };
+
+            let mut inner = v.split_off(j);
+            let mut rest = inner.split_off(i - j + 1);
+            let last = inner.len() - 1;
+            let (last_part, post) = if let Token::Str(ref s) = inner[last] {
+                (s[..end].to_owned(), s[close + 1..].to_owned())
+            } else {
+                unreachable!()
+            };
+            inner[last] = Token::Str(last_part);
+            let (pre, first) = if let Token::Str(ref s) = inner[0] {
+                (s[..open].to_owned(), s[open + 1..].to_owned())
+            } else {
+                unreachable!()
+            };
+            inner[0] = Token::Str(first);
+            inner.retain(|t| *t != Token::Str(String::new()));
+
+            if !pre.is_empty() {
+                v.push(Token::Str(pre));
+            }
+            v.push(Token::Span(attributes, inner));
+            i = v.len();
+            if !post.is_empty() {
+                v.push(Token::Str(post));
+            }
+            v.append(&mut rest);
+        }
+    }
+
+    /// Looks for strikethrough (`~~text~~`) in a vector of tokens
+    fn parse_strikethrough_vec(&mut self, v: &mut Vec<Token>) {
+        for token in v.iter_mut() {
+            if !token.is_code() {
+                if let Some(ref mut inner) = token.inner_mut() {
+                    self.parse_strikethrough_vec(inner);
+                }
+            }
+        }
+
+        let mut i = 0;
+        while i < v.len() {
+            let open = match v[i] {
+                Token::Str(ref s) => s.find("~~"),
+                _ => None,
+            };
+            let open = match open {
+                Some(open) => open,
+                None => {
+                    i += 1;
+                    continue;
+                }
+            };
+            // Look for the closing delimiter, in this Str or in a following one
+            let close = (i..v.len())
+                .filter_map(|j| match v[j] {
+                    Token::Str(ref s) => {
+                        let start = if j == i { open + 2 } else { 0 };
+                        s[start..].find("~~").map(|k| (j, k + start))
+                    }
+                    _ => None,
+                })
+                .next();
+            let (j, close) = match close {
+                Some((j, close)) if j > i || close > open + 2 => (j, close),
+                _ => {
+                    i += 1;
+                    continue;
+                }
+            };
+
+            let mut inner = v.split_off(i);
+            let mut rest = inner.split_off(j - i + 1);
+            let (pre, post) = if j == i {
+                let s = if let Token::Str(ref s) = inner[0] {
+                    s.clone()
+                } else {
+                    unreachable!()
+                };
+                inner[0] = Token::Str(s[open + 2..close].to_owned());
+                (s[..open].to_owned(), s[close + 2..].to_owned())
+            } else {
+                let last = inner.len() - 1;
+                let (pre, first) = if let Token::Str(ref s) = inner[0] {
+                    (s[..open].to_owned(), s[open + 2..].to_owned())
+                } else {
+                    unreachable!()
+                };
+                let (last_part, post) = if let Token::Str(ref s) = inner[last] {
+                    (s[..close].to_owned(), s[close + 2..].to_owned())
+                } else {
+                    unreachable!()
+                };
+                inner[0] = Token::Str(first);
+                inner[last] = Token::Str(last_part);
+                inner.retain(|t| *t != Token::Str(String::new()));
+                (pre, post)
+            };
+
+            self.features.strikethrough = true;
+            if !pre.is_empty() {
+                v.push(Token::Str(pre));
+            }
+            v.push(Token::Strikethrough(inner));
+            i = v.len();
+            if !post.is_empty() {
+                v.push(Token::Str(post));
+            }
+            v.append(&mut rest);
+        }
+    }
+
     /// Looks for super script in a vector of tokens
     fn parse_super_vec(&mut self, v: &mut Vec<Token>) {
         for i in 0..v.len() {
@@ -326,10 +724,67 @@ impl Parser {
                         v: &mut Vec<Token>,
                         current_tag: Option<&Tag>)
                         -> Result<()> {
+        let mut pending = Pending::default();
+        self.parse_events_in(p, v, current_tag, &mut pending)?;
+        // Unclosed HTML elements are left as they are
+        while let Some(i) = pending.conditionals.pop() {
+            warn!("{}", lformat!("in {file}, conditional block is not closed by \
+                                  '<!-- end only -->'",
+                                 file = self.source));
+            let content = v.split_off(i + 1);
+            if let Token::OnlyFor(_, ref mut inner) = v[i] {
+                *inner = content;
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_events_in<'a>(&mut self,
+                           p: &mut CMParser<'a>,
+                           v: &mut Vec<Token>,
+                           current_tag: Option<&Tag>,
+                           pending: &mut Pending)
+                           -> Result<()> {
         while let Some(event) = p.next() {
+            let block = if let Event::Html(_) = event { true } else { false };
             match event {
                 Event::Html(text) | Event::InlineHtml(text) => {
-                    if self.html_as_text {
+                    let found = directive(&text);
+                    if let Some((directive, rest)) = found {
+                        match directive {
+                            Directive::Only(formats) => {
+                                let formats = formats.iter().map(|f| self.format_name(f)).collect();
+                                pending.conditionals.push(v.len());
+                                v.push(Token::OnlyFor(formats, vec![]));
+                            }
+                            Directive::EndOnly => {
+                                match pending.conditionals.pop() {
+                                    Some(i) => {
+                                        pending.html.retain(|e| e.0 < i);
+                                        let content = v.split_off(i + 1);
+                                        if let Token::OnlyFor(_, ref mut inner) = v[i] {
+                                            *inner = content;
+                                        }
+                                    }
+                                    None => {
+                                        warn!("{}",
+                                              lformat!("in {file}, found '<!-- end only -->' \
+                                                        without matching '<!-- only: ... -->', \
+                                                        ignoring it",
+                                                       file = self.source));
+                                    }
+                                }
+                            }
+                        }
+                        // An HTML block lasts until a blank line, so it might contain
+                        // Markdown that follows the directive
+                        if !rest.trim().is_empty() {
+                            let mut p = CMParser::new_ext(&rest, cmark_options());
+                            self.parse_events_in(&mut p, v, None, pending)?;
+                        }
+                    } else if self.parse_html(&text, block, v, pending) {
+                        // HTML has been converted to tokens
+                    } else if self.html_as_text {
                         v.push(Token::Str(text.into_owned()));
                     } else {
                         debug!("{}", lformat!("ignoring HTML block '{}'", text));
@@ -358,6 +813,94 @@ impl Parser {
         Ok(())
     }
 
+    /// Converts a safe subset of HTML to tokens.
+    ///
+    /// Returns false if `html` is not part of this subset and must be
+    /// handled as raw HTML.
+    fn parse_html(&mut self,
+                  html: &str,
+                  block: bool,
+                  v: &mut Vec<Token>,
+                  pending: &mut Pending)
+                  -> bool {
+        // Blocks of HTML are complete, while inline HTML only contains a tag
+        if block {
+            return match html_parser::parse_block(html) {
+                Some(tokens) => {
+                    for token in &tokens {
+                        self.html_features(token);
+                    }
+                    v.extend(tokens);
+                    true
+                }
+                None => false,
+            };
+        }
+        match html_parser::parse_tag(html) {
+            Some(HtmlTag::Open(name, attributes)) => {
+                if !html_parser::is_inline(&name) {
+                    return false;
+                }
+                // Left as is if the element is never closed
+                let placeholder = if self.html_as_text { html.to_owned() } else { String::new() };
+                pending.html.push((v.len(), name, attributes));
+                v.push(Token::Str(placeholder));
+                true
+            }
+            Some(HtmlTag::Close(name)) => {
+                match pending.html.iter().rposition(|e| e.1 == name) {
+                    Some(n) => {
+                        let (i, name, attributes) = pending.html.remove(n);
+                        pending.html.truncate(n);
+                        let content = v.split_off(i + 1);
+                        let token = html_parser::element_token(&name, attributes, content);
+                        self.html_features(&token);
+                        v[i] = token;
+                        true
+                    }
+                    None => false,
+                }
+            }
+            Some(HtmlTag::Void(ref name)) if name == "br" => {
+                v.push(Token::HardBreak);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Updates the features used by the document for a token converted from HTML
+    fn html_features(&mut self, token: &Token) {
+        match *token {
+            Token::Superscript(_) => self.features.superscript = true,
+            Token::Subscript(_) => self.features.subscript = true,
+            Token::Strikethrough(_) => self.features.strikethrough = true,
+            Token::BlockQuote(_) => self.features.blockquote = true,
+            _ => (),
+        }
+        if let Some(inner) = token.inner() {
+            for token in inner {
+                self.html_features(token);
+            }
+        }
+    }
+
+    /// Returns the normalized name of an output format used in a conditional or raw block
+    fn format_name(&self, name: &str) -> String {
+        let name = name.to_lowercase();
+        match name.as_ref() {
+            "latex" | "pdf" => String::from("tex"),
+            "html" | "epub" | "tex" | "odt" | "proofread" => name.clone(),
+            _ => {
+                warn!("{}", lformat!("in {file}, '{format}' is not a valid output format for \
+                                      conditional or raw blocks",
+                                     file = self.source,
+                                     format = name));
+                name.clone()
+            }
+        }
+    }
+
     fn parse_tag<'a>(&mut self,
                      p: &mut CMParser<'a>,
                      v: &mut Vec<Token>,
@@ -397,13 +940,52 @@ impl Parser {
                 Token::BlockQuote(res)
             },
             Tag::CodeBlock(language) => {
-                self.features.codeblock = true;
-                Token::CodeBlock(language.into_owned(), res)
+                let language = language.trim();
+                if language.starts_with("{=") && language.ends_with('}') && language.len() > 3 {
+                    let format = self.format_name(language[2..language.len() - 1].trim());
+                    let mut content = String::new();
+                    for token in res {
+                        if let Token::Str(s) = token {
+                            content.push_str(&s);
+                        }
+                    }
+                    Token::RawBlock(format, content)
+                } else if language == "verse" || language.starts_with("verse ") {
+                    let numbers = language[5..].trim();
+                    let numbers = if numbers.is_empty() {
+                        self.verse_numbers
+                    } else {
+                        numbers.parse::<usize>()
+                            .map_err(|_| {
+                                Error::parser(&self.source,
+                                              lformat!("could not parse '{value}' as the interval \
+                                                        of line numbers of a verse block",
+                                                       value = numbers))
+                            })?
+                    };
+                    let mut content = String::new();
+                    for token in res {
+                        if let Token::Str(s) = token {
+                            content.push_str(&s);
+                        }
+                    }
+                    self.parse_verse(numbers, &content)?
+                } else {
+                    self.features.codeblock = true;
+                    Token::CodeBlock(language.to_owned(), res)
+                }
             },
             Tag::Table(v) => {
                 self.features.table = true;
-                // TODO: actually use v's alignments
-                Token::Table(v.len() as i32, res)
+                let alignments = v.iter()
+                    .map(|a| match *a {
+                        CMAlignment::None => Alignment::Default,
+                        CMAlignment::Left => Alignment::Left,
+                        CMAlignment::Center => Alignment::Center,
+                        CMAlignment::Right => Alignment::Right,
+                    })
+                    .collect();
+                Token::Table(alignments, res)
             },
             Tag::TableHead => Token::TableHead(res),
             Tag::TableRow => Token::TableRow(res),
@@ -513,6 +1095,338 @@ fn parse_super_sub(s: &str, c: u8) -> Option<Vec<Token>> {
     return None;
 }
 
+/// Escapes the markers at the beginning of a line (e.g. of verse) that would make it
+/// a block (header, list, quote, ...) instead of inline text
+pub fn escape_block_markers(line: &str) -> String {
+    let first = match line.chars().next() {
+        Some(c) => c,
+        None => return String::new(),
+    };
+    let rest = &line[first.len_utf8()..];
+    let escape = match first {
+        '#' | '>' => true,
+        '`' | '~' => rest.starts_with(first),
+        '-' | '+' | '*' => {
+            rest.is_empty() || rest.starts_with(' ') ||
+            line.chars().all(|c| c == first || c == ' ')
+        }
+        '_' => line.chars().all(|c| c == '_' || c == ' '),
+        '[' => line.contains("]:"),
+        _ => false,
+    };
+    if escape {
+        return format!("\\{}", line);
+    }
+    // Ordered list marker, e.g. "1. " or "1) "
+    let digits = line.len() - line.trim_left_matches(|c: char| c.is_digit(10)).len();
+    if digits > 0 && digits <= 9 {
+        let after = &line[digits..];
+        if (after.starts_with('.') || after.starts_with(')')) &&
+           (after.len() == 1 || after[1..].starts_with(' ')) {
+            return format!("{}\\{}", &line[..digits], after);
+        }
+    }
+    line.to_owned()
+}
+
+/// Options of pulldown-cmark's parser
+fn cmark_options() -> Options {
+    let mut opts = Options::empty();
+    opts.insert(OPTION_ENABLE_TABLES);
+    opts.insert(OPTION_ENABLE_FOOTNOTES);
+    opts
+}
+
+/// Elements that have been opened but not closed yet at a given level
+#[derive(Default)]
+struct Pending {
+    /// Indices of the conditional blocks
+    conditionals: Vec<usize>,
+    /// Indices, names and attributes of HTML elements
+    html: Vec<(usize, String, Attributes)>,
+}
+
+/// A directive hidden in an HTML comment
+enum Directive {
+    /// `<!-- only: format1 format2 -->`, starts a conditional block
+    Only(Vec<String>),
+    /// `<!-- end only -->`, ends a conditional block
+    EndOnly,
+}
+
+/// If `html` starts with a directive, returns it and the remaining text
+fn directive(html: &str) -> Option<(Directive, String)> {
+    let s = html.trim_left();
+    if !s.starts_with("<!--") {
+        return None;
+    }
+    let end = match s.find("-->") {
+        Some(end) => end,
+        None => return None,
+    };
+    let comment = s[4..end].trim();
+    let rest = s[end + 3..].to_owned();
+    if comment.starts_with("only:") {
+        let formats: Vec<_> = comment[5..]
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|f| !f.is_empty())
+            .map(|f| f.to_owned())
+            .collect();
+        if formats.is_empty() {
+            None
+        } else {
+            Some((Directive::Only(formats), rest))
+        }
+    } else if comment.split_whitespace().collect::<Vec<_>>() == ["end", "only"] {
+        Some((Directive::EndOnly, rest))
+    } else {
+        None
+    }
+}
+
+/// Parses the content of an attribute block, e.g. `#id .class key=value`.
+///
+/// Returns `None` if it isn't valid.
+fn parse_attributes(s: &str) -> Option<Attributes> {
+    let mut words = vec![];
+    let mut word = String::new();
+    let mut quoted = false;
+    for c in s.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => {
+                if !word.is_empty() {
+                    words.push(mem::replace(&mut word, String::new()));
+                }
+            }
+            c => word.push(c),
+        }
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    if quoted || words.is_empty() {
+        return None;
+    }
+
+    let mut attributes = Attributes::default();
+    for word in words {
+        if word.starts_with('#') && word.len() > 1 && attributes.id.is_none() {
+            attributes.id = Some(word[1..].to_owned());
+        } else if word.starts_with('.') && word.len() > 1 {
+            attributes.classes.push(word[1..].to_owned());
+        } else {
+            match word.find('=') {
+                Some(n) if n > 0 => {
+                    attributes.pairs.push((word[..n].to_owned(), word[n + 1..].to_owned()))
+                }
+                _ => return None,
+            }
+        }
+    }
+    Some(attributes)
+}
+
+/// If `s` starts with an attribute block, returns the position where it ends,
+/// and the attributes
+fn leading_attributes(s: &str) -> Option<(usize, Attributes)> {
+    if !s.starts_with('{') {
+        return None;
+    }
+    let end = match s.find('}') {
+        Some(end) => end,
+        None => return None,
+    };
+    parse_attributes(&s[1..end]).map(|attributes| (end + 1, attributes))
+}
+
+/// If `s` ends with an attribute block (separated from the text before by
+/// whitespace), returns the position where it starts, and the attributes
+fn trailing_attributes(s: &str) -> Option<(usize, Attributes)> {
+    let s = s.trim_right();
+    if !s.ends_with('}') {
+        return None;
+    }
+    let begin = match s.rfind('{') {
+        Some(begin) => begin,
+        None => return None,
+    };
+    if begin > 0 && !s[..begin].ends_with(char::is_whitespace) {
+        return None;
+    }
+    parse_attributes(&s[begin + 1..s.len() - 1]).map(|attributes| (begin, attributes))
+}
+
+/// Finds the end of a span with attributes (`]{...}`) in `s`, and returns the
+/// position of the closing bracket, the position of the closing brace and
+/// the attributes
+fn find_span_end(s: &str) -> Option<(usize, usize, Attributes)> {
+    for (p, _) in s.match_indices("]{") {
+        if let Some(n) = s[p + 2..].find('}') {
+            let close = p + 2 + n;
+            if let Some(attributes) = parse_attributes(&s[p + 2..close]) {
+                return Some((p, close, attributes));
+            }
+        }
+    }
+    None
+}
+
+/// Moves the attributes at the end of the content of a paragraph or header to
+/// a `Token::Attributes` at the end of it
+fn add_trailing_attributes(inner: &mut Vec<Token>) {
+    let found = match inner.last() {
+        Some(&Token::Str(ref s)) => trailing_attributes(s),
+        _ => None,
+    };
+    let (begin, attributes) = match found {
+        Some(found) => found,
+        None => return,
+    };
+    let text = if let Some(&Token::Str(ref s)) = inner.last() {
+        s[..begin].trim_right().to_owned()
+    } else {
+        unreachable!()
+    };
+    if text.is_empty() && inner.len() == 1 {
+        // Don't create an element containing only attributes
+        return;
+    }
+    inner.pop();
+    if !text.is_empty() {
+        inner.push(Token::Str(text));
+    } else if inner.last() == Some(&Token::SoftBreak) {
+        // Attributes were on their own line
+        inner.pop();
+    }
+    inner.push(Token::Attributes(attributes));
+}
+
+/// Returns the content of a definition list if the content of a paragraph uses
+/// this syntax, or `None`.
+fn definition_list(inner: &[Token]) -> Option<Vec<Token>> {
+    let mut lines: Vec<Vec<Token>> = vec![vec![]];
+    for token in inner {
+        if *token == Token::SoftBreak {
+            lines.push(vec![]);
+        } else {
+            let line = lines.last_mut().unwrap();
+            // Text is sometimes split in multiple events
+            if let (Some(&mut Token::Str(ref mut dest)), &Token::Str(ref s)) = (line.last_mut(),
+                                                                               token) {
+                dest.push_str(s);
+                continue;
+            }
+            line.push(token.clone());
+        }
+    }
+    let is_description = |line: &[Token]| match line.first() {
+        Some(&Token::Str(ref s)) => s.starts_with(": "),
+        _ => false,
+    };
+    if lines.len() < 2 || is_description(&lines[0]) || !is_description(&lines[1]) {
+        return None;
+    }
+
+    let mut list = vec![];
+    let mut description: Option<Vec<Token>> = None;
+    for mut line in lines {
+        if is_description(&line) {
+            if let Some(d) = description.take() {
+                list.push(Token::DefinitionDescription(d));
+            }
+            let s = if let Token::Str(ref s) = line[0] {
+                s[2..].trim_left().to_owned()
+            } else {
+                unreachable!()
+            };
+            if s.is_empty() {
+                line.remove(0);
+            } else {
+                line[0] = Token::Str(s);
+            }
+            description = Some(line);
+        } else if let Some(ref mut d) = description {
+            // Continuation of the previous description (a new term must be
+            // separated by a blank line)
+            d.push(Token::SoftBreak);
+            d.append(&mut line);
+        } else {
+            list.push(Token::DefinitionTerm(line));
+        }
+    }
+    if let Some(d) = description {
+        list.push(Token::DefinitionDescription(d));
+    }
+    Some(list)
+}
+
+/// If a list item's content starts with a checkbox (`[ ]`, `[x]` or `[X]`), removes
+/// it and returns whether it is checked.
+fn remove_checkbox(inner: &mut Vec<Token>) -> Option<bool> {
+    let (checked, rest) = if let Some(&Token::Str(ref s)) = inner.first() {
+        let checked = if s.starts_with("[ ]") {
+            false
+        } else if s.starts_with("[x]") || s.starts_with("[X]") {
+            true
+        } else {
+            return None;
+        };
+        let rest = &s[3..];
+        if !rest.is_empty() && !rest.starts_with(' ') {
+            return None;
+        }
+        (checked, rest.trim_left().to_owned())
+    } else {
+        return None;
+    };
+    if rest.is_empty() {
+        inner.remove(0);
+    } else {
+        inner[0] = Token::Str(rest);
+    }
+    Some(checked)
+}
+
+/// Kinds of admonitions
+const ADMONITIONS: &'static [&'static str] = &["note", "tip", "important", "warning", "caution"];
+
+/// If the content of a block quote starts with an admonition marker (`[!NOTE]`),
+/// removes its first line and returns the kind and the title of the admonition
+fn admonition_header(inner: &mut Vec<Token>) -> Option<(String, String)> {
+    let (kind, title, len) = match inner.first() {
+        Some(&Token::Paragraph(ref par)) => {
+            let len = par.iter().position(|t| *t == Token::SoftBreak).unwrap_or(par.len());
+            let line = view_as_text(&par[..len]);
+            let line = line.trim();
+            if !line.starts_with("[!") {
+                return None;
+            }
+            let end = match line.find(']') {
+                Some(end) => end,
+                None => return None,
+            };
+            let kind = line[2..end].to_lowercase();
+            if !ADMONITIONS.contains(&kind.as_str()) {
+                return None;
+            }
+            (kind, line[end + 1..].trim().to_owned(), len)
+        }
+        _ => return None,
+    };
+    let empty = if let Token::Paragraph(ref mut par) = inner[0] {
+        let len = if len < par.len() { len + 1 } else { len };
+        par.drain(..len);
+        par.is_empty()
+    } else {
+        unreachable!()
+    };
+    if empty {
+        inner.remove(0);
+    }
+    Some((kind, title))
+}
+
 /// Replace consecutives Strs by a Str of both, collapse soft breaks to previous std and so on
 fn collapse(ast: &mut Vec<Token>) {
     let mut i = 0;
@@ -0,0 +1,1770 @@
+// Copyright (C) 2017 Élisabeth HENRY.
+//
+// This file is part of Crowbook.
+//
+// Crowbook is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published
+// by the Free Software Foundation, either version 2.1 of the License, or
+// (at your option) any later version.
+//
+// Crowbook is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Native PDF renderer, that lays out the book itself instead of
+//! relying on a LaTeX installation.
+
+use book::{Book, Header, NotesPlacement};
+use error::{Error, Result, Source};
+use token::{Token, Alignment};
+use number::Number;
+use parser::Parser;
+use text_view::view_as_text;
+use lang;
+use hyphenate;
+use resource_handler::ResourceHandler;
+use pdf_writer::{self, PdfWriter, Font, Image};
+
+use hyphenation;
+use hyphenation::{Corpus, Hyphenation};
+
+use std::io::{Read, Write};
+use std::fmt::Write as FmtWrite;
+use std::fs::File;
+use std::collections::BTreeSet;
+use std::mem;
+use std::cmp;
+
+/// Languages whose text can be displayed with the WinAnsi encoding of the standard
+/// PDF fonts
+const LATIN1_LANGUAGES: &'static [&'static str] = &["af", "ca", "da", "de", "en", "es", "eu",
+                                                    "fi", "fo", "fr", "ga", "gl", "id", "is",
+                                                    "it", "ms", "nb", "nl", "nn", "no", "pt",
+                                                    "sq", "sv", "sw"];
+
+/// Ratio between the height of a line and the font size
+const LEADING: f32 = 1.3;
+
+/// Number of points in a millimeter
+const MM: f32 = 72.0 / 25.4;
+
+/// Output formats for which conditional blocks are rendered
+const OUTPUT_FORMATS: &'static [&'static str] = &["tex"];
+
+/// A piece of text with a given style
+#[derive(Debug, Clone)]
+struct Fragment {
+    text: String,
+    font: Font,
+    size: f32,
+    /// Vertical offset, for superscript and subscript
+    rise: f32,
+    /// Footnote that is referenced by this fragment
+    footnote: Option<usize>,
+    /// Whether the text is struck through
+    strike: bool,
+}
+
+impl Fragment {
+    fn width(&self) -> f32 {
+        self.font.width(&self.text, self.size)
+    }
+}
+
+/// An element of a paragraph
+#[derive(Debug, Clone)]
+enum Item {
+    /// A word, possibly made of fragments with different styles
+    Word(Vec<Fragment>),
+    /// An (inter-word) space
+    Space,
+    /// A forced line break
+    Break,
+}
+
+/// Current style of inline text
+#[derive(Debug, Clone, Copy)]
+struct Style {
+    bold: bool,
+    italic: bool,
+    mono: bool,
+    size: f32,
+    rise: f32,
+    strike: bool,
+}
+
+impl Style {
+    fn font(&self) -> Font {
+        if self.mono {
+            Font::Mono
+        } else {
+            Font::Regular.with_style(self.bold, self.italic)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Align {
+    Left,
+    Center,
+    Right,
+    Justify,
+}
+
+/// How to lay out a paragraph
+#[derive(Debug, Clone, Copy)]
+struct ParStyle {
+    align: Align,
+    /// Indentation of the first line
+    first_indent: f32,
+    /// Left and right margins, relative to the text area
+    left: f32,
+    right: f32,
+    /// Whether the last line must be kept on the same page than the next block
+    keep_with_next: bool,
+}
+
+/// A line of text, ready to be placed on a page
+#[derive(Debug, Clone)]
+struct Line {
+    /// Fragments, with their horizontal position relative to the left of the text area
+    fragments: Vec<(f32, Fragment)>,
+    height: f32,
+    /// Distance from the top of the line to the baseline
+    ascent: f32,
+    footnotes: Vec<usize>,
+    keep_with_next: bool,
+}
+
+impl Line {
+    fn empty(size: f32) -> Line {
+        Line {
+            fragments: vec![],
+            height: size * LEADING,
+            ascent: size,
+            footnotes: vec![],
+            keep_with_next: false,
+        }
+    }
+}
+
+/// Laid out content, before it is split into pages
+#[derive(Debug, Clone)]
+enum Block {
+    Line(Line),
+    /// Vertical space (ignored at the top of a page)
+    Space(f32),
+    /// Image number, horizontal position, width and height
+    Image(usize, f32, f32, f32),
+    /// A table row: cells (horizontal position and lines), height, whether
+    /// a rule must be drawn below it
+    Row(Vec<(f32, Vec<Line>)>, f32, bool),
+    PageBreak,
+    /// Beginning of a chapter (or part): title, level in the TOC, whether
+    /// it must appear in the TOC
+    Chapter(String, i32, bool),
+}
+
+impl Block {
+    fn height(&self) -> f32 {
+        match *self {
+            Block::Line(ref line) => line.height,
+            Block::Space(h) | Block::Image(_, _, _, h) | Block::Row(_, h, _) => h,
+            Block::PageBreak | Block::Chapter(..) => 0.0,
+        }
+    }
+
+    fn footnotes(&self) -> Vec<usize> {
+        match *self {
+            Block::Line(ref line) => line.footnotes.clone(),
+            Block::Row(ref cells, _, _) => {
+                cells.iter()
+                    .flat_map(|&(_, ref lines)| lines.iter().flat_map(|l| l.footnotes.clone()))
+                    .collect()
+            }
+            _ => vec![],
+        }
+    }
+}
+
+/// Content of a page
+#[derive(Debug, Clone)]
+struct Page {
+    /// Blocks, with their vertical position relative to the top of the text area
+    blocks: Vec<(f32, Block)>,
+    footnotes: Vec<usize>,
+    /// Current chapter and part, for running headers and footers
+    chapter: String,
+    part: String,
+    /// Whether a chapter begins on this page
+    opening: bool,
+}
+
+impl Page {
+    fn new(chapter: &str, part: &str) -> Page {
+        Page {
+            blocks: vec![],
+            footnotes: vec![],
+            chapter: chapter.to_owned(),
+            part: part.to_owned(),
+            opening: false,
+        }
+    }
+}
+
+/// Native PDF renderer
+pub struct PdfRenderer<'a> {
+    book: &'a Book,
+    source: Source,
+    corpus: Option<Corpus>,
+    /// Page width and height
+    width: f32,
+    height: f32,
+    margin: f32,
+    /// Base font size
+    size: f32,
+    footnotes: Vec<Vec<Line>>,
+    images: Vec<Image>,
+    /// Entries of the table of contents: level, title, index of the page in the content
+    toc: Vec<(i32, String, usize)>,
+    missing_chars: BTreeSet<char>,
+    first_paragraph: bool,
+    current_part: bool,
+    current_numbered: bool,
+    current_hidden: bool,
+    chapter_number: i32,
+    part_number: i32,
+    /// Where footnotes are placed (margin notes are rendered as footnotes)
+    notes_placement: NotesPlacement,
+    /// Notes of the current chapter, when they are placed at the end of
+    /// chapters or of the book
+    end_notes: Vec<Vec<Token>>,
+    end_note_number: usize,
+    /// Title of the current chapter, to group notes
+    notes_chapter: String,
+    /// Notes collected for the end of the book
+    book_notes: Vec<Block>,
+    /// Index (in `images`) and aspect ratio of the image displayed for scene breaks
+    scene_break: Option<(usize, f32)>,
+}
+
+impl<'a> PdfRenderer<'a> {
+    /// Creates a new native PDF renderer
+    pub fn new(book: &'a Book) -> Result<PdfRenderer<'a>> {
+        let lang = book.options.get_str("lang").unwrap();
+        let primary = lang.split(|c| c == '-' || c == '_').next().unwrap().to_lowercase();
+        if !LATIN1_LANGUAGES.contains(&primary.as_str()) {
+            return Err(Error::book_option(&book.source,
+                                          lformat!("pdf.engine: the native PDF engine only \
+                                                    supports languages written with Latin-1 \
+                                                    characters, not '{lang}', set pdf.engine to \
+                                                    'latex'",
+                                                   lang = lang)));
+        }
+        let (width, height) = match book.options.get_str("pdf.paper.size").unwrap() {
+            "a4" => (210.0 * MM, 297.0 * MM),
+            "a5" => (148.0 * MM, 210.0 * MM),
+            "a6" => (105.0 * MM, 148.0 * MM),
+            "letter" => (612.0, 792.0),
+            "legal" => (612.0, 1008.0),
+            size => {
+                return Err(Error::book_option(&book.source,
+                                              lformat!("pdf.paper.size: unsupported paper size \
+                                                        '{size}', expected 'a4', 'a5', 'a6', \
+                                                        'letter' or 'legal'",
+                                                       size = size)));
+            }
+        };
+        let corpus = match hyphenate::language(book.options.get_str("lang").unwrap())
+            .ok_or(())
+            .and_then(|lang| hyphenation::load(lang).map_err(|_| ())) {
+            Ok(corpus) => Some(corpus),
+            Err(_) => {
+                warn!("{}", lformat!("PDF: could not load hyphenation patterns, words won't be \
+                                      hyphenated"));
+                None
+            }
+        };
+        let mut images = vec![];
+        let scene_break = match scene_break_image(book)? {
+            Some(image) => {
+                let ratio = image.width as f32 / image.height as f32;
+                images.push(image);
+                Some((0, ratio))
+            }
+            None => None,
+        };
+        Ok(PdfRenderer {
+            book: book,
+            source: Source::empty(),
+            corpus: corpus,
+            width: width,
+            height: height,
+            margin: book.options.get_f32("pdf.margin").unwrap() * MM,
+            size: book.options.get_f32("pdf.font.size").unwrap(),
+            footnotes: vec![],
+            images: images,
+            toc: vec![],
+            missing_chars: BTreeSet::new(),
+            first_paragraph: true,
+            current_part: false,
+            current_numbered: true,
+            current_hidden: false,
+            chapter_number: 0,
+            part_number: 0,
+            notes_placement: book.notes_placement(NotesPlacement::Page)?,
+            end_notes: vec![],
+            end_note_number: 0,
+            notes_chapter: String::new(),
+            book_notes: vec![],
+            scene_break: scene_break,
+        })
+    }
+
+    /// Width of the text area
+    fn text_width(&self) -> f32 {
+        self.width - 2.0 * self.margin
+    }
+
+    /// Height of the text area (without running headers and footers)
+    fn text_height(&self) -> f32 {
+        self.height - 2.0 * self.margin - 3.0 * self.size
+    }
+
+    /// Renders the book and writes the resulting PDF
+    pub fn render_book(&mut self, to: &mut Write) -> Result<()> {
+        // Lay out the content of the book
+        let mut blocks = vec![];
+        for (i, chapter) in self.book.chapters.iter().enumerate() {
+            self.source = Source::new(chapter.filename.as_str());
+            self.chapter_config(i, chapter.number);
+            self.render_blocks(&chapter.content, &mut blocks, 0.0)?;
+            self.render_end_notes(&mut blocks)?;
+            self.check_missing_chars()?;
+        }
+        self.source = self.book.source.clone();
+        if !self.book_notes.is_empty() {
+            let title = lang::get_str(self.book.options.get_str("lang").unwrap(), "notes");
+            blocks.push(Block::PageBreak);
+            blocks.push(Block::Chapter(title.clone(), 1, true));
+            blocks.push(Block::Space(self.text_height() / 8.0));
+            let size = self.size;
+            self.push_title(&title, 2.0 * size, Align::Center, &mut blocks);
+            blocks.push(Block::Space(2.0 * size));
+            blocks.extend(mem::replace(&mut self.book_notes, vec![]));
+        }
+        let content = self.paginate(blocks, true);
+
+        let title = self.title_page()?;
+        let title = self.paginate(title, false);
+
+        // The number of pages of the TOC depends on the number of entries
+        // but not on the page numbers, so one iteration is enough
+        let mut toc = vec![];
+        if self.book.options.get_bool("rendering.inline_toc").unwrap() {
+            let placeholder = self.toc_blocks(0);
+            let offset = title.len() + self.paginate(placeholder, false).len();
+            toc = self.paginate(self.toc_blocks(offset), false);
+        }
+
+        self.check_missing_chars()?;
+
+        let mut pages = title;
+        let title_pages = pages.len();
+        pages.extend(toc);
+        pages.extend(content);
+        let pdf = self.write_pdf(&pages, title_pages)?;
+        to.write_all(&pdf)
+            .map_err(|e| {
+                Error::render(&self.book.source,
+                              lformat!("problem when writing PDF: {error}", error = e))
+            })
+    }
+
+    /// Configures the renderer for a new chapter
+    fn chapter_config(&mut self, i: usize, n: Number) {
+        self.first_paragraph = true;
+        self.current_part = n.is_part();
+        self.current_numbered = n.is_numbered() &&
+            self.book.options.get_i32("rendering.num_depth").unwrap() >= 1;
+        self.current_hidden = n.is_hidden();
+        match n {
+            Number::Specified(n) => self.chapter_number = n - 1,
+            Number::SpecifiedPart(n) => self.part_number = n - 1,
+            _ => {}
+        }
+        debug!("{}", lformat!("PDF: laying out chapter {n}", n = i));
+    }
+
+    /// Lays out the title page
+    fn title_page(&mut self) -> Result<Vec<Block>> {
+        let mut blocks = vec![Block::Space(self.text_height() / 4.0)];
+        let centered = ParStyle {
+            align: Align::Center,
+            first_indent: 0.0,
+            left: 0.0,
+            right: 0.0,
+            keep_with_next: false,
+        };
+        let size = self.size;
+        let metadata = [("author", 1.3, false, false),
+                        ("title", 2.2, true, false),
+                        ("subtitle", 1.4, false, true)];
+        for &(key, ratio, bold, italic) in &metadata {
+            if let Ok(s) = self.book.options.get_str(key) {
+                let tokens = Parser::new().parse_inline(s)?;
+                let mut style = Style {
+                    bold: bold,
+                    italic: italic,
+                    mono: false,
+                    size: size * ratio,
+                    rise: 0.0,
+                    strike: false,
+                };
+                let mut items = vec![];
+                self.inline(&tokens, &mut style, &mut items)?;
+                for line in self.break_lines(&items, &centered, size * ratio) {
+                    blocks.push(Block::Line(line));
+                }
+                blocks.push(Block::Space(size * 2.0));
+            }
+        }
+        Ok(blocks)
+    }
+
+    /// Lays out the table of contents, given the number of pages before the content
+    fn toc_blocks(&mut self, offset: usize) -> Vec<Block> {
+        let size = self.size;
+        let mut blocks = vec![];
+        let title = lang::get_str(self.book.options.get_str("lang").unwrap(), "toc");
+        self.push_title(&title, 1.6 * size, Align::Center, &mut blocks);
+        blocks.push(Block::Space(size));
+        let number_width = Font::Regular.width("0000", size);
+        let entries = self.toc.clone();
+        for (level, title, page) in entries {
+            let indent = cmp::max(level - 1, 0) as f32 * 1.5 * size;
+            let style = ParStyle {
+                align: Align::Left,
+                first_indent: 0.0,
+                left: indent,
+                right: number_width,
+                keep_with_next: false,
+            };
+            let font = if level <= 0 { Font::Bold } else { Font::Regular };
+            let items = self.text_items(&title, font, size);
+            let mut lines = self.break_lines(&items, &style, size);
+            if let Some(line) = lines.last_mut() {
+                let number = format!("{}", page + offset + 1);
+                let x = self.text_width() - Font::Regular.width(&number, size);
+                line.fragments.push((x,
+                                     Fragment {
+                                         text: number,
+                                         font: Font::Regular,
+                                         size: size,
+                                         rise: 0.0,
+                                         footnote: None,
+                                         strike: false,
+                                     }));
+            }
+            blocks.extend(lines.into_iter().map(Block::Line));
+        }
+        blocks.push(Block::PageBreak);
+        blocks
+    }
+
+    /// Lays out a simple title (without inline formatting)
+    fn push_title(&mut self, title: &str, size: f32, align: Align, blocks: &mut Vec<Block>) {
+        let style = ParStyle {
+            align: align,
+            first_indent: 0.0,
+            left: 0.0,
+            right: 0.0,
+            keep_with_next: true,
+        };
+        let items = self.text_items(title, Font::Bold, size);
+        for line in self.break_lines(&items, &style, size) {
+            blocks.push(Block::Line(line));
+        }
+    }
+
+    /// Converts a raw string to items
+    fn text_items(&mut self, text: &str, font: Font, size: f32) -> Vec<Item> {
+        let mut items = vec![];
+        let mut style = Style {
+            bold: font == Font::Bold || font == Font::BoldItalic,
+            italic: font == Font::Italic || font == Font::BoldItalic,
+            mono: font == Font::Mono,
+            size: size,
+            rise: 0.0,
+            strike: false,
+        };
+        self.push_text(text, &mut style, &mut items);
+        items
+    }
+
+    /// Lays out a list of block tokens
+    fn render_blocks(&mut self, tokens: &[Token], blocks: &mut Vec<Block>, indent: f32) -> Result<()> {
+        let mut inline = vec![];
+        for token in tokens {
+            if is_block(token) {
+                if !inline.is_empty() {
+                    let v = mem::replace(&mut inline, vec![]);
+                    self.render_paragraph(&v, blocks, indent, None)?;
+                }
+                self.render_block(token, blocks, indent)?;
+            } else {
+                inline.push(token.clone());
+            }
+        }
+        if !inline.is_empty() {
+            self.render_paragraph(&inline, blocks, indent, None)?;
+        }
+        Ok(())
+    }
+
+    /// Lays out a paragraph, with an optional list marker
+    fn render_paragraph(&mut self,
+                        tokens: &[Token],
+                        blocks: &mut Vec<Block>,
+                        indent: f32,
+                        marker: Option<String>)
+                        -> Result<()> {
+        let size = self.size;
+        let mut items = vec![];
+        let mut style = self.base_style();
+        let mut first_indent = if self.first_paragraph { 0.0 } else { 1.5 * size };
+        if let Some(marker) = marker {
+            let width = Font::Regular.width(&marker, size) + Font::Regular.width(" ", size);
+            first_indent = -width;
+            self.push_text(&marker, &mut style, &mut items);
+            items.push(Item::Space);
+        }
+        self.first_paragraph = false;
+        self.inline(tokens, &mut style, &mut items)?;
+        let par_style = ParStyle {
+            align: Align::Justify,
+            first_indent: first_indent,
+            left: indent,
+            right: 0.0,
+            keep_with_next: false,
+        };
+        for line in self.break_lines(&items, &par_style, size) {
+            blocks.push(Block::Line(line));
+        }
+        Ok(())
+    }
+
+    fn base_style(&self) -> Style {
+        Style {
+            bold: false,
+            italic: false,
+            mono: false,
+            size: self.size,
+            rise: 0.0,
+            strike: false,
+        }
+    }
+
+    /// Lays out a block token
+    fn render_block(&mut self, token: &Token, blocks: &mut Vec<Block>, indent: f32) -> Result<()> {
+        let size = self.size;
+        match *token {
+            Token::Paragraph(ref vec) => self.render_paragraph(vec, blocks, indent, None)?,
+            Token::Header(n, ref vec) => self.render_header(n, vec, blocks)?,
+            Token::BlockQuote(ref vec) => {
+                blocks.push(Block::Space(size * 0.5));
+                self.first_paragraph = true;
+                self.render_blocks(vec, blocks, indent + 2.0 * size)?;
+                blocks.push(Block::Space(size * 0.5));
+            }
+            Token::Admonition(ref kind, ref title, ref vec) => {
+                let title = if title.is_empty() {
+                    lang::get_str(self.book.options.get_str("lang").unwrap(), kind)
+                } else {
+                    self.book.clean(title.as_str(), false).into_owned()
+                };
+                blocks.push(Block::Space(size * 0.5));
+                let style = ParStyle {
+                    align: Align::Left,
+                    first_indent: 0.0,
+                    left: indent + 2.0 * size,
+                    right: 0.0,
+                    keep_with_next: true,
+                };
+                let items = self.text_items(&title, Font::Bold, size);
+                for line in self.break_lines(&items, &style, size) {
+                    blocks.push(Block::Line(line));
+                }
+                self.first_paragraph = true;
+                self.render_blocks(vec, blocks, indent + 2.0 * size)?;
+                blocks.push(Block::Space(size * 0.5));
+            }
+            Token::CodeBlock(_, ref vec) => {
+                let code_size = size * 0.85;
+                let text = view_as_text(vec).replace('\t', "    ");
+                let max_chars = ((self.text_width() - indent) / (0.6 * code_size)) as usize;
+                blocks.push(Block::Space(size * 0.5));
+                for line in text.trim_right_matches('\n').lines() {
+                    self.check_chars(line);
+                    let chars: Vec<char> = line.chars().collect();
+                    let chunks: Vec<String> = if chars.is_empty() {
+                        vec![String::new()]
+                    } else {
+                        chars.chunks(cmp::max(max_chars, 1)).map(|c| c.iter().cloned().collect()).collect()
+                    };
+                    for chunk in chunks {
+                        let mut line = Line::empty(code_size);
+                        line.fragments.push((indent,
+                                             Fragment {
+                                                 text: chunk,
+                                                 font: Font::Mono,
+                                                 size: code_size,
+                                                 rise: 0.0,
+                                                 footnote: None,
+                                                 strike: false,
+                                             }));
+                        blocks.push(Block::Line(line));
+                    }
+                }
+                blocks.push(Block::Space(size * 0.5));
+            }
+            Token::Verse(numbers, ref vec) => {
+                blocks.push(Block::Space(size * 0.5));
+                let mut n = 0;
+                for stanza in vec {
+                    let lines = match *stanza {
+                        Token::Stanza(ref lines) => lines,
+                        _ => continue,
+                    };
+                    for line in lines {
+                        let (line_indent, inner) = match *line {
+                            Token::VerseLine(line_indent, ref inner) => (line_indent, inner),
+                            _ => continue,
+                        };
+                        n += 1;
+                        // Lines that are too long continue with a hanging indent
+                        let par_style = ParStyle {
+                            align: Align::Left,
+                            first_indent: -size,
+                            left: indent + 3.0 * size + line_indent as f32 * size * 0.5,
+                            right: 2.0 * size,
+                            keep_with_next: false,
+                        };
+                        let mut style = self.base_style();
+                        let mut items = vec![];
+                        self.inline(inner, &mut style, &mut items)?;
+                        let mut laid_out = self.break_lines(&items, &par_style, size);
+                        if numbers > 0 && n % numbers == 0 {
+                            if let Some(first) = laid_out.first_mut() {
+                                let number = format!("{}", n);
+                                let number_size = size * 0.8;
+                                let x = self.text_width() -
+                                        Font::Regular.width(&number, number_size);
+                                first.fragments.push((x,
+                                                      Fragment {
+                                                          text: number,
+                                                          font: Font::Regular,
+                                                          size: number_size,
+                                                          rise: 0.0,
+                                                          footnote: None,
+                                                          strike: false,
+                                                      }));
+                            }
+                        }
+                        blocks.extend(laid_out.into_iter().map(Block::Line));
+                    }
+                    blocks.push(Block::Space(size * LEADING));
+                }
+                self.first_paragraph = true;
+            }
+            Token::Rule => {
+                blocks.push(Block::Space(size * 0.5));
+                match self.scene_break {
+                    Some((image, ratio)) => {
+                        let height = size * 1.5;
+                        let width = height * ratio;
+                        let x = (self.text_width() - width) / 2.0;
+                        blocks.push(Block::Image(image, x, width, height));
+                    }
+                    None => {
+                        let text = self.book
+                            .clean(self.book.options.get_str("rendering.scene_break").unwrap(),
+                                   false)
+                            .into_owned();
+                        let mut line = Line::empty(size);
+                        let x = (self.text_width() - Font::Regular.width(&text, size)) / 2.0;
+                        line.fragments.push((x,
+                                             Fragment {
+                                                 text: text,
+                                                 font: Font::Regular,
+                                                 size: size,
+                                                 rise: 0.0,
+                                                 footnote: None,
+                                                 strike: false,
+                                             }));
+                        // Don't leave a scene break at the bottom of a page
+                        line.keep_with_next = true;
+                        blocks.push(Block::Line(line));
+                    }
+                }
+                blocks.push(Block::Space(size * 0.5));
+                self.first_paragraph = true;
+            }
+            Token::List(ref vec) |
+            Token::OrderedList(_, ref vec) => {
+                let mut number = if let Token::OrderedList(n, _) = *token { n } else { 0 };
+                for item in vec {
+                    match *item {
+                        Token::Item(ref content) => {
+                            let marker = if let Token::OrderedList(..) = *token {
+                                let marker = format!("{}.", number);
+                                number += 1;
+                                marker
+                            } else {
+                                String::from("\u{2022}")
+                            };
+                            self.render_item(content, blocks, indent + 1.5 * size, marker)?;
+                        }
+                        Token::TaskItem(checked, ref content) => {
+                            let marker = String::from(if checked { "[x]" } else { "[ ]" });
+                            self.render_item(content, blocks, indent + 1.5 * size, marker)?;
+                        }
+                        _ => {}
+                    }
+                }
+                blocks.push(Block::Space(size * 0.3));
+            }
+            Token::DefinitionList(ref vec) => {
+                for item in vec {
+                    match *item {
+                        Token::DefinitionTerm(ref content) => {
+                            self.first_paragraph = true;
+                            let strong = [Token::Strong(content.clone())];
+                            self.render_paragraph(&strong, blocks, indent, None)?;
+                        }
+                        Token::DefinitionDescription(ref content) => {
+                            self.first_paragraph = true;
+                            self.render_paragraph(content, blocks, indent + 2.0 * size, None)?;
+                        }
+                        _ => {}
+                    }
+                }
+                blocks.push(Block::Space(size * 0.3));
+            }
+            Token::Table(ref alignments, ref rows) => self.render_table(alignments, rows, blocks, indent)?,
+            Token::StandaloneImage(ref url, _, ref alt) => {
+                self.render_image(url, alt, blocks, indent)?
+            }
+            Token::OnlyFor(_, ref vec) => {
+                if token.is_rendered_in(OUTPUT_FORMATS) {
+                    self.render_blocks(vec, blocks, indent)?;
+                }
+            }
+            // Raw LaTeX code can't be rendered without LaTeX
+            Token::RawBlock(..) => {
+                if token.is_rendered_in(OUTPUT_FORMATS) {
+                    warn!("{}", lformat!("PDF ({source}): raw LaTeX block ignored, since it \
+                                          can't be rendered by the native engine (set \
+                                          pdf.engine to 'latex' to include it)",
+                                         source = self.source));
+                }
+            }
+            _ => self.render_paragraph(&[token.clone()], blocks, indent, None)?,
+        }
+        Ok(())
+    }
+
+    /// Lays out an item of a list
+    fn render_item(&mut self,
+                   content: &[Token],
+                   blocks: &mut Vec<Block>,
+                   indent: f32,
+                   marker: String)
+                   -> Result<()> {
+        // The first paragraph (or inline content) gets the marker
+        let (first, rest): (Vec<Token>, &[Token]) = match content.first() {
+            Some(&Token::Paragraph(ref vec)) => (vec.clone(), &content[1..]),
+            _ => {
+                let n = content.iter().position(is_block).unwrap_or(content.len());
+                (content[..n].to_vec(), &content[n..])
+            }
+        };
+        self.first_paragraph = true;
+        self.render_paragraph(&first, blocks, indent, Some(marker))?;
+        self.first_paragraph = true;
+        self.render_blocks(rest, blocks, indent)
+    }
+
+    /// Lays out a header
+    fn render_header(&mut self, n: i32, vec: &[Token], blocks: &mut Vec<Block>) -> Result<()> {
+        let size = self.size;
+        self.first_paragraph = true;
+        let title = view_as_text(vec);
+        if n == 1 {
+            blocks.push(Block::PageBreak);
+            let header = if self.current_part {
+                Header::Part
+            } else {
+                Header::Chapter
+            };
+            let text = if self.current_numbered {
+                let number = if self.current_part {
+                    self.part_number += 1;
+                    if self.book.options.get_bool("rendering.part.reset_counter").unwrap() {
+                        self.chapter_number = 0;
+                    }
+                    self.part_number
+                } else {
+                    self.chapter_number += 1;
+                    self.chapter_number
+                };
+                self.book
+                    .get_header(header, number, title.clone(), |s| {
+                        Ok(view_as_text(&Parser::new().parse_inline(s)?))
+                    })?
+                    .text
+            } else {
+                title.clone()
+            };
+            let level = if self.current_part { 0 } else { 1 };
+            self.notes_chapter = text.clone();
+            blocks.push(Block::Chapter(text.clone(), level, !self.current_hidden));
+            if !self.current_hidden {
+                blocks.push(Block::Space(self.text_height() / 8.0));
+                self.push_title(&text, 2.0 * size, Align::Center, blocks);
+                blocks.push(Block::Space(2.0 * size));
+            }
+            if self.current_part {
+                blocks.push(Block::PageBreak);
+            }
+        } else {
+            let ratio = match n {
+                2 => 1.5,
+                3 => 1.25,
+                _ => 1.1,
+            };
+            blocks.push(Block::Space(size));
+            self.push_title(&title, ratio * size, Align::Left, blocks);
+            blocks.push(Block::Space(size * 0.5));
+        }
+        Ok(())
+    }
+
+    /// Lays out an image in its own paragraph
+    fn render_image(&mut self,
+                    url: &str,
+                    alt: &[Token],
+                    blocks: &mut Vec<Block>,
+                    indent: f32)
+                    -> Result<()> {
+        if !ResourceHandler::is_local(url) {
+            warn!("{}", lformat!("PDF ({source}): image {file} is not a local file, \
+                                  displaying its description instead",
+                                 source = self.source,
+                                 file = url));
+            return self.render_paragraph(&[Token::Emphasis(alt.to_vec())], blocks, indent, None);
+        }
+        let mut data = vec![];
+        File::open(url)
+            .and_then(|mut f| f.read_to_end(&mut data))
+            .map_err(|_| Error::file_not_found(&self.source, lformat!("image"), url.to_owned()))?;
+        match Image::new(data) {
+            Some(image) => {
+                // Consider images have a resolution of 96 DPI
+                let mut width = image.width as f32 * 0.75;
+                let mut height = image.height as f32 * 0.75;
+                let max_width = (self.text_width() - indent) * 0.8;
+                let max_height = self.text_height() * 0.8;
+                if width > max_width {
+                    height *= max_width / width;
+                    width = max_width;
+                }
+                if height > max_height {
+                    width *= max_height / height;
+                    height = max_height;
+                }
+                let x = indent + (self.text_width() - indent - width) / 2.0;
+                self.images.push(image);
+                blocks.push(Block::Space(self.size * 0.5));
+                blocks.push(Block::Image(self.images.len() - 1, x, width, height));
+                blocks.push(Block::Space(self.size * 0.5));
+            }
+            None => {
+                warn!("{}", lformat!("PDF ({source}): image {file} is not in a supported format \
+                                      (JPEG, or PNG without transparency nor interlacing), \
+                                      displaying its description instead",
+                                     source = self.source,
+                                     file = url));
+                self.render_paragraph(&[Token::Emphasis(alt.to_vec())], blocks, indent, None)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Lays out a table, giving the same width to all columns
+    fn render_table(&mut self,
+                    alignments: &[Alignment],
+                    rows: &[Token],
+                    blocks: &mut Vec<Block>,
+                    indent: f32)
+                    -> Result<()> {
+        let size = self.size;
+        let n = cmp::max(alignments.len(), 1);
+        let column_width = (self.text_width() - indent) / n as f32;
+        let padding = size * 0.5;
+        blocks.push(Block::Space(size * 0.5));
+        for row in rows {
+            let (cells, head) = match *row {
+                Token::TableHead(ref cells) => (cells, true),
+                Token::TableRow(ref cells) => (cells, false),
+                _ => continue,
+            };
+            let mut laid_out = vec![];
+            let mut height: f32 = 0.0;
+            for (i, cell) in cells.iter().enumerate() {
+                let x = indent + i as f32 * column_width;
+                let mut style = self.base_style();
+                style.bold = head;
+                let mut items = vec![];
+                if let Some(inner) = cell.inner() {
+                    self.inline(inner, &mut style, &mut items)?;
+                }
+                let align = match alignments.get(i) {
+                    Some(&Alignment::Center) => Align::Center,
+                    Some(&Alignment::Right) => Align::Right,
+                    _ => Align::Left,
+                };
+                let par_style = ParStyle {
+                    align: align,
+                    first_indent: 0.0,
+                    left: x,
+                    right: self.text_width() - x - column_width + padding,
+                    keep_with_next: false,
+                };
+                let lines = self.break_lines(&items, &par_style, size);
+                height = height.max(lines.iter().map(|l| l.height).sum());
+                laid_out.push((0.0, lines));
+            }
+            blocks.push(Block::Row(laid_out, height.max(size * LEADING), head));
+        }
+        blocks.push(Block::Space(size * 0.5));
+        self.first_paragraph = true;
+        Ok(())
+    }
+
+    /// Adds text to a list of items, splitting it into words
+    fn push_text(&mut self, text: &str, style: &mut Style, items: &mut Vec<Item>) {
+        self.check_chars(text);
+        for c in text.chars() {
+            if c == '\u{AD}' {
+                continue;
+            }
+            if c.is_whitespace() && c != '\u{A0}' && c != '\u{202F}' {
+                match items.last() {
+                    Some(&Item::Word(_)) => items.push(Item::Space),
+                    _ => {}
+                }
+                continue;
+            }
+            let font = style.font();
+            let new_word = match items.last() {
+                Some(&Item::Word(_)) => false,
+                _ => true,
+            };
+            if new_word {
+                items.push(Item::Word(vec![]));
+            }
+            if let Some(&mut Item::Word(ref mut fragments)) = items.last_mut() {
+                let same_style = match fragments.last() {
+                    Some(f) => {
+                        f.font == font && f.size == style.size && f.rise == style.rise &&
+                            f.strike == style.strike && f.footnote.is_none()
+                    }
+                    None => false,
+                };
+                if !same_style {
+                    fragments.push(Fragment {
+                        text: String::new(),
+                        font: font,
+                        size: style.size,
+                        rise: style.rise,
+                        footnote: None,
+                        strike: style.strike,
+                    });
+                }
+                fragments.last_mut().unwrap().text.push(c);
+            }
+        }
+    }
+
+    /// Records characters that can't be displayed with the standard fonts
+    fn check_chars(&mut self, text: &str) {
+        for c in text.chars() {
+            if c != '\u{AD}' && !c.is_whitespace() && pdf_writer::encode_char(c).is_none() {
+                self.missing_chars.insert(c);
+            }
+        }
+    }
+
+    /// Returns an error if some characters (of the current chapter) can't be displayed
+    /// with the standard fonts, instead of replacing them with '?'
+    fn check_missing_chars(&self) -> Result<()> {
+        if self.missing_chars.is_empty() {
+            return Ok(());
+        }
+        let chars: String = self.missing_chars.iter().cloned().collect();
+        Err(Error::render(&self.source,
+                          lformat!("the following characters are not supported by the native \
+                                    PDF renderer, which only supports Latin-1 text: {chars}; \
+                                    set 'pdf.engine' to 'latex' to render this book",
+                                   chars = chars)))
+    }
+
+    /// Converts inline tokens to items
+    fn inline(&mut self, tokens: &[Token], style: &mut Style, items: &mut Vec<Item>) -> Result<()> {
+        for token in tokens {
+            let saved = *style;
+            match *token {
+                Token::Str(ref text) => {
+                    let text = self.book.clean(text.as_str(), false);
+                    self.push_text(&text, style, items);
+                }
+                Token::SoftBreak => items.push(Item::Space),
+                Token::HardBreak => items.push(Item::Break),
+                Token::Emphasis(ref vec) => {
+                    style.italic = !style.italic;
+                    self.inline(vec, style, items)?;
+                }
+                Token::Strong(ref vec) => {
+                    style.bold = true;
+                    self.inline(vec, style, items)?;
+                }
+                Token::Code(ref vec) => {
+                    style.mono = true;
+                    self.inline(vec, style, items)?;
+                }
+                Token::Strikethrough(ref vec) => {
+                    style.strike = true;
+                    self.inline(vec, style, items)?;
+                }
+                Token::Superscript(ref vec) => {
+                    style.rise += style.size * 0.35;
+                    style.size *= 0.7;
+                    self.inline(vec, style, items)?;
+                }
+                Token::Subscript(ref vec) => {
+                    style.rise -= style.size * 0.15;
+                    style.size *= 0.7;
+                    self.inline(vec, style, items)?;
+                }
+                Token::Image(_, _, ref vec) => {
+                    style.italic = !style.italic;
+                    self.inline(vec, style, items)?;
+                }
+                Token::Footnote(ref vec) => {
+                    let (text, footnote) = match self.notes_placement {
+                        NotesPlacement::Chapter | NotesPlacement::Book => {
+                            self.end_notes.push(vec.clone());
+                            self.end_note_number += 1;
+                            (format!("{}", self.end_note_number), None)
+                        }
+                        NotesPlacement::Page | NotesPlacement::Side => {
+                            let number = self.add_footnote(vec)?;
+                            (format!("{}", number + 1), Some(number))
+                        }
+                    };
+                    let fragment = Fragment {
+                        text: text,
+                        font: Font::Regular,
+                        size: style.size * 0.7,
+                        rise: style.size * 0.35,
+                        footnote: footnote,
+                        strike: false,
+                    };
+                    match items.last_mut() {
+                        Some(&mut Item::Word(ref mut fragments)) => fragments.push(fragment),
+                        _ => items.push(Item::Word(vec![fragment])),
+                    }
+                }
+                Token::Paragraph(ref vec) => {
+                    if !items.is_empty() {
+                        items.push(Item::Break);
+                    }
+                    self.inline(vec, style, items)?;
+                }
+                Token::OnlyFor(_, ref vec) => {
+                    if token.is_rendered_in(OUTPUT_FORMATS) {
+                        self.inline(vec, style, items)?;
+                    }
+                }
+                Token::__NonExhaustive => unreachable!(),
+                _ => {
+                    if let Some(inner) = token.inner() {
+                        self.inline(inner, style, items)?;
+                    }
+                }
+            }
+            *style = saved;
+        }
+        Ok(())
+    }
+
+    /// Lays out the notes of the current chapter, either at the end of the chapter
+    /// or in the notes collected for the end of the book
+    fn render_end_notes(&mut self, blocks: &mut Vec<Block>) -> Result<()> {
+        if self.end_notes.is_empty() {
+            return Ok(());
+        }
+        let size = self.size;
+        let notes = mem::replace(&mut self.end_notes, vec![]);
+        let first = self.end_note_number - notes.len();
+        let title = if self.notes_placement == NotesPlacement::Book {
+            self.notes_chapter.clone()
+        } else {
+            lang::get_str(self.book.options.get_str("lang").unwrap(), "notes")
+        };
+        let mut note_blocks = vec![Block::Space(size)];
+        self.push_title(&title, 1.25 * size, Align::Left, &mut note_blocks);
+        note_blocks.push(Block::Space(size * 0.5));
+        for (i, note) in notes.iter().enumerate() {
+            self.first_paragraph = true;
+            let marker = format!("{}.", first + i + 1);
+            match note.split_first() {
+                Some((&Token::Paragraph(ref vec), rest)) => {
+                    self.render_paragraph(vec, &mut note_blocks, 2.0 * size, Some(marker))?;
+                    self.render_blocks(rest, &mut note_blocks, 2.0 * size)?;
+                }
+                _ => {
+                    self.render_paragraph(&[], &mut note_blocks, 2.0 * size, Some(marker))?;
+                    self.render_blocks(note, &mut note_blocks, 2.0 * size)?;
+                }
+            }
+        }
+        if self.notes_placement == NotesPlacement::Book {
+            self.book_notes.extend(note_blocks);
+        } else {
+            blocks.extend(note_blocks);
+        }
+        Ok(())
+    }
+
+    /// Lays out a footnote and returns its number
+    fn add_footnote(&mut self, tokens: &[Token]) -> Result<usize> {
+        let size = self.size * 0.8;
+        let number = self.footnotes.len();
+        // Reserve the number now, in case this footnote contains other footnotes
+        self.footnotes.push(vec![]);
+        let mut style = self.base_style();
+        style.size = size;
+        let mut items = vec![];
+        self.push_text(&format!("{}.", number + 1), &mut style, &mut items);
+        items.push(Item::Space);
+        self.inline(tokens, &mut style, &mut items)?;
+        let par_style = ParStyle {
+            align: Align::Justify,
+            first_indent: 0.0,
+            left: 0.0,
+            right: 0.0,
+            keep_with_next: false,
+        };
+        self.footnotes[number] = self.break_lines(&items, &par_style, size);
+        Ok(number)
+    }
+
+    /// Tries to hyphenate a word so its first part fits in `room`
+    fn hyphenate(&self, word: &[Fragment], room: f32) -> Option<(Vec<Fragment>, Vec<Fragment>)> {
+        let corpus = match self.corpus {
+            Some(ref corpus) => corpus,
+            None => return None,
+        };
+        // Only hyphenate words made of a single fragment (plus, possibly, a footnote)
+        let (fragment, rest) = match word.split_first() {
+            Some((fragment, rest)) if rest.iter().all(|f| f.footnote.is_some()) => (fragment, rest),
+            _ => return None,
+        };
+        if fragment.font == Font::Mono || fragment.footnote.is_some() {
+            return None;
+        }
+        let text = fragment.text.as_str();
+        let mut opportunities = text.opportunities(corpus);
+        opportunities.reverse();
+        for i in opportunities {
+            let mut left = fragment.clone();
+            left.text = format!("{}-", &text[..i]);
+            if left.width() <= room {
+                let mut right = fragment.clone();
+                right.text = text[i..].to_owned();
+                let mut right = vec![right];
+                right.extend_from_slice(rest);
+                return Some((vec![left], right));
+            }
+        }
+        None
+    }
+
+    /// Breaks items into lines, using a greedy algorithm
+    fn break_lines(&self, items: &[Item], style: &ParStyle, size: f32) -> Vec<Line> {
+        let space = Font::Regular.width(" ", size);
+        let mut lines = vec![];
+        let mut words: Vec<Vec<Fragment>> = vec![];
+        let mut line_width = 0.0;
+        for item in items {
+            match *item {
+                Item::Space => {}
+                Item::Break => {
+                    let first = lines.is_empty();
+                    lines.push(self.make_line(mem::replace(&mut words, vec![]), style, size, first, false));
+                    line_width = 0.0;
+                }
+                Item::Word(ref word) => {
+                    let mut pending = Some(word.clone());
+                    while let Some(word) = pending.take() {
+                        let first = lines.is_empty();
+                        let available = self.text_width() - style.left - style.right -
+                            if first { style.first_indent } else { 0.0 };
+                        let word_width: f32 = word.iter().map(|f| f.width()).sum();
+                        let sep = if words.is_empty() { 0.0 } else { space };
+                        if line_width + sep + word_width <= available {
+                            line_width += sep + word_width;
+                            words.push(word);
+                        } else if let Some((left, right)) = self.hyphenate(&word, available - line_width - sep) {
+                            words.push(left);
+                            lines.push(self.make_line(mem::replace(&mut words, vec![]), style, size, first, true));
+                            line_width = 0.0;
+                            pending = Some(right);
+                        } else if words.is_empty() {
+                            // Word is too long for a line anyway
+                            line_width = word_width;
+                            words.push(word);
+                        } else {
+                            lines.push(self.make_line(mem::replace(&mut words, vec![]), style, size, first, true));
+                            line_width = 0.0;
+                            pending = Some(word);
+                        }
+                    }
+                }
+            }
+        }
+        if !words.is_empty() {
+            let first = lines.is_empty();
+            lines.push(self.make_line(words, style, size, first, false));
+        }
+        if style.keep_with_next {
+            if let Some(line) = lines.last_mut() {
+                line.keep_with_next = true;
+            }
+        }
+        lines
+    }
+
+    /// Positions the words of a line
+    fn make_line(&self,
+                 words: Vec<Vec<Fragment>>,
+                 style: &ParStyle,
+                 size: f32,
+                 first: bool,
+                 justify: bool)
+                 -> Line {
+        let space = Font::Regular.width(" ", size);
+        let indent = if first { style.first_indent } else { 0.0 };
+        let available = self.text_width() - style.left - style.right - indent;
+        let natural: f32 = words.iter()
+            .map(|w| w.iter().map(|f| f.width()).sum::<f32>())
+            .sum::<f32>() + space * (cmp::max(words.len(), 1) - 1) as f32;
+        let mut x = style.left + indent;
+        let mut space = space;
+        match style.align {
+            Align::Center => x += ((available - natural) / 2.0).max(0.0),
+            Align::Right => x += (available - natural).max(0.0),
+            Align::Justify if justify && words.len() > 1 && natural < available => {
+                space += (available - natural) / (words.len() - 1) as f32;
+            }
+            _ => {}
+        }
+        let mut line = Line::empty(size);
+        for word in words {
+            for fragment in word {
+                let width = fragment.width();
+                if fragment.size * LEADING > line.height {
+                    line.height = fragment.size * LEADING;
+                    line.ascent = fragment.size;
+                }
+                if let Some(n) = fragment.footnote {
+                    line.footnotes.push(n);
+                }
+                line.fragments.push((x, fragment));
+                x += width;
+            }
+            x += space;
+        }
+        line
+    }
+
+    /// Adds to some footnotes the ones they contain themselves, recursively
+    fn with_nested_footnotes(&self, footnotes: Vec<usize>) -> Vec<usize> {
+        let mut result = vec![];
+        let mut pending = footnotes;
+        pending.reverse();
+        while let Some(n) = pending.pop() {
+            result.push(n);
+            let nested = self.footnotes[n].iter().flat_map(|l| l.footnotes.clone());
+            let mut nested: Vec<usize> = nested.collect();
+            nested.reverse();
+            pending.extend(nested);
+        }
+        result
+    }
+
+    /// Height taken by some footnotes
+    fn footnotes_height(&self, footnotes: &[usize]) -> f32 {
+        footnotes.iter()
+            .map(|n| self.footnotes[*n].iter().map(|l| l.height).sum::<f32>())
+            .sum()
+    }
+
+    /// Splits blocks into pages
+    ///
+    /// If `content` is true, chapters are recorded in the table of contents
+    fn paginate(&mut self, blocks: Vec<Block>, content: bool) -> Vec<Page> {
+        let text_height = self.text_height();
+        let separator = self.size;
+        let mut pages = vec![];
+        let mut chapter = String::new();
+        let mut part = String::new();
+        let mut page = Page::new(&chapter, &part);
+        let mut y = 0.0;
+        let mut footnotes_height = 0.0;
+        for i in 0..blocks.len() {
+            match blocks[i] {
+                Block::PageBreak => {
+                    if !page.blocks.is_empty() {
+                        pages.push(mem::replace(&mut page, Page::new(&chapter, &part)));
+                        y = 0.0;
+                        footnotes_height = 0.0;
+                    }
+                    continue;
+                }
+                Block::Chapter(ref title, level, in_toc) => {
+                    if level == 0 {
+                        part = title.clone();
+                        chapter = String::new();
+                    } else {
+                        chapter = title.clone();
+                    }
+                    if page.blocks.is_empty() {
+                        page.chapter = chapter.clone();
+                        page.part = part.clone();
+                        page.opening = true;
+                    }
+                    if content && in_toc {
+                        self.toc.push((level, title.clone(), pages.len()));
+                    }
+                    continue;
+                }
+                Block::Space(h) => {
+                    if !page.blocks.is_empty() {
+                        y += h;
+                    }
+                    continue;
+                }
+                _ => {}
+            }
+            let block = &blocks[i];
+            let footnotes = self.with_nested_footnotes(block.footnotes());
+            let mut needed = block.height() + self.footnotes_height(&footnotes);
+            if !footnotes.is_empty() && page.footnotes.is_empty() {
+                needed += separator;
+            }
+            if let Block::Line(ref line) = *block {
+                if line.keep_with_next {
+                    if let Some(next) = blocks[i + 1..].iter().find(|b| b.height() > 0.0) {
+                        needed += next.height();
+                    }
+                }
+            }
+            if y + needed + footnotes_height > text_height && !page.blocks.is_empty() {
+                pages.push(mem::replace(&mut page, Page::new(&chapter, &part)));
+                y = 0.0;
+                footnotes_height = 0.0;
+            }
+            if !footnotes.is_empty() {
+                if page.footnotes.is_empty() {
+                    footnotes_height += separator;
+                }
+                footnotes_height += self.footnotes_height(&footnotes);
+                page.footnotes.extend(footnotes);
+            }
+            page.blocks.push((y, block.clone()));
+            y += block.height();
+        }
+        if !page.blocks.is_empty() {
+            pages.push(page);
+        }
+        pages
+    }
+
+    /// Writes a line to a content stream, given the position of its top
+    fn write_line(&self, out: &mut String, line: &Line, left: f32, top: f32) {
+        let baseline = top - line.ascent;
+        for &(x, ref fragment) in &line.fragments {
+            if fragment.text.is_empty() {
+                continue;
+            }
+            writeln!(out,
+                     "BT /{} {} Tf {} Ts 1 0 0 1 {} {} Tm {} Tj ET",
+                     fragment.font.resource_name(),
+                     pdf_writer::num(fragment.size),
+                     pdf_writer::num(fragment.rise),
+                     pdf_writer::num(left + x),
+                     pdf_writer::num(baseline),
+                     pdf_writer::literal(&fragment.text))
+                .unwrap();
+            if fragment.strike {
+                let y = baseline + fragment.rise + fragment.size * 0.3;
+                writeln!(out,
+                         "{} w {} {} m {} {} l S",
+                         pdf_writer::num(fragment.size * 0.06),
+                         pdf_writer::num(left + x),
+                         pdf_writer::num(y),
+                         pdf_writer::num(left + x + fragment.width()),
+                         pdf_writer::num(y))
+                    .unwrap();
+            }
+        }
+    }
+
+    /// Generates the PDF file
+    fn write_pdf(&self, pages: &[Page], title_pages: usize) -> Result<Vec<u8>> {
+        let chapter_pages = self.book.options.get_bool("pdf.header.chapter_pages").unwrap();
+        let mut pdf = PdfWriter::new();
+        let catalog = pdf.reserve();
+        let pages_id = pdf.reserve();
+        let fonts = pdf.add_fonts();
+        let images: Vec<usize> = self.images.iter().map(|i| pdf.add_image(i)).collect();
+        let mut xobjects = String::from("<< ");
+        for (i, id) in images.iter().enumerate() {
+            write!(xobjects, "/Im{} {} 0 R ", i, id).unwrap();
+        }
+        xobjects.push_str(">>");
+        let resources = pdf.add(format!("<< /Font {} /XObject {} >>", fonts, xobjects));
+
+        let small = self.size * 0.8;
+        let left = self.margin;
+        let top = self.height - self.margin - 1.5 * self.size;
+        let bottom = self.margin + 1.5 * self.size;
+        let mut kids = vec![];
+        for (n, page) in pages.iter().enumerate() {
+            let mut out = String::new();
+            // Running header and footer
+            if n >= title_pages {
+                let number = format!("{}", n + 1);
+                let (header, footer) = if page.opening && !chapter_pages {
+                    (None, Some([String::new(), number, String::new()]))
+                } else {
+                    let data = self.book
+                        .get_metadata(|s| Ok(view_as_text(&Parser::new().parse_inline(s)?)))?
+                        .insert_str("page", number)
+                        .insert_str("chapter", page.chapter.as_str())
+                        .insert_str("part", page.part.as_str())
+                        .build();
+                    // Page numbers start at 1, so even pages are left pages
+                    let (header, footer) = if n % 2 == 0 {
+                        ("pdf.header.odd", "pdf.footer.odd")
+                    } else {
+                        ("pdf.header.even", "pdf.footer.even")
+                    };
+                    (Some(self.book.get_running_layout(header, &data)?),
+                     Some(self.book.get_running_layout(footer, &data)?))
+                };
+                if let Some(header) = header {
+                    let line = self.running_line(&header, Font::Italic, small);
+                    self.write_line(&mut out, &line, left, self.height - self.margin);
+                }
+                if let Some(footer) = footer {
+                    let line = self.running_line(&footer, Font::Regular, small);
+                    self.write_line(&mut out, &line, left, self.margin + small * LEADING);
+                }
+            }
+
+            // Content
+            for &(y, ref block) in &page.blocks {
+                let block_top = top - y;
+                match *block {
+                    Block::Line(ref line) => self.write_line(&mut out, line, left, block_top),
+                    Block::Image(i, x, width, height) => {
+                        writeln!(out,
+                                 "q {} 0 0 {} {} {} cm /Im{} Do Q",
+                                 pdf_writer::num(width),
+                                 pdf_writer::num(height),
+                                 pdf_writer::num(left + x),
+                                 pdf_writer::num(block_top - height),
+                                 i)
+                            .unwrap();
+                    }
+                    Block::Row(ref cells, height, rule) => {
+                        for &(_, ref lines) in cells {
+                            let mut line_top = block_top;
+                            for line in lines {
+                                self.write_line(&mut out, line, left, line_top);
+                                line_top -= line.height;
+                            }
+                        }
+                        if rule {
+                            writeln!(out,
+                                     "0.5 w {} {} m {} {} l S",
+                                     pdf_writer::num(left),
+                                     pdf_writer::num(block_top - height),
+                                     pdf_writer::num(left + self.text_width()),
+                                     pdf_writer::num(block_top - height))
+                                .unwrap();
+                        }
+                    }
+                    _ => {}
+                }
+            }
+
+            // Footnotes, from the bottom of the text area
+            if !page.footnotes.is_empty() {
+                let height = self.footnotes_height(&page.footnotes);
+                let mut y = bottom + height;
+                writeln!(out,
+                         "0.5 w {} {} m {} {} l S",
+                         pdf_writer::num(left),
+                         pdf_writer::num(y + self.size * 0.5),
+                         pdf_writer::num(left + self.text_width() / 3.0),
+                         pdf_writer::num(y + self.size * 0.5))
+                    .unwrap();
+                for n in &page.footnotes {
+                    for line in &self.footnotes[*n] {
+                        self.write_line(&mut out, line, left, y);
+                        y -= line.height;
+                    }
+                }
+            }
+
+            let content = pdf.add_stream("", out.as_bytes());
+            let page_id = pdf.add(format!("<< /Type /Page /Parent {} 0 R /MediaBox [0 0 {} {}] \
+                                           /Resources {} 0 R /Contents {} 0 R >>",
+                                          pages_id,
+                                          pdf_writer::num(self.width),
+                                          pdf_writer::num(self.height),
+                                          resources,
+                                          content));
+            kids.push(format!("{} 0 R", page_id));
+        }
+        pdf.set(pages_id,
+                format!("<< /Type /Pages /Kids [{}] /Count {} >>",
+                        kids.join(" "),
+                        kids.len())
+                    .into_bytes());
+        pdf.set(catalog,
+                format!("<< /Type /Catalog /Pages {} 0 R >>", pages_id).into_bytes());
+
+        let mut info = String::from("<< /Producer (crowbook) ");
+        for &(key, option) in &[("Title", "title"), ("Author", "author"), ("Subject", "subject")] {
+            if let Ok(value) = self.book.options.get_str(option) {
+                let value = view_as_text(&Parser::new().parse_inline(value).unwrap_or_default());
+                write!(info, "/{} {} ", key, pdf_writer::literal(&value)).unwrap();
+            }
+        }
+        info.push_str(">>");
+        let info = pdf.add(info);
+        Ok(pdf.finish(catalog, info))
+    }
+
+    /// Lays out the left, center and right parts of a running header or footer
+    fn running_line(&self, layout: &[String; 3], font: Font, size: f32) -> Line {
+        let mut line = Line::empty(size);
+        for (i, text) in layout.iter().enumerate() {
+            if text.is_empty() {
+                continue;
+            }
+            let width = font.width(text, size);
+            let x = match i {
+                0 => 0.0,
+                1 => (self.text_width() - width) / 2.0,
+                _ => self.text_width() - width,
+            };
+            line.fragments.push((x.max(0.0),
+                                 Fragment {
+                                     text: text.clone(),
+                                     font: font,
+                                     size: size,
+                                     rise: 0.0,
+                                     footnote: None,
+                                     strike: false,
+                                 }));
+        }
+        line
+    }
+}
+
+/// Loads the image set by `rendering.scene_break.image`, if there is one and it
+/// is in a supported format
+fn scene_break_image(book: &Book) -> Result<Option<Image>> {
+    let url = match book.options.get_path("rendering.scene_break.image") {
+        Ok(url) => url,
+        Err(_) => return Ok(None),
+    };
+    let mut data = vec![];
+    File::open(&url)
+        .and_then(|mut f| f.read_to_end(&mut data))
+        .map_err(|_| Error::file_not_found(&book.source, lformat!("image"), url.clone()))?;
+    let image = Image::new(data);
+    if image.is_none() {
+        warn!("{}", lformat!("PDF: scene break image {file} is not in a supported format \
+                              (JPEG, or PNG without transparency nor interlacing), \
+                              displaying rendering.scene_break instead",
+                             file = url));
+    }
+    Ok(image)
+}
+
+/// Returns true if a token must be laid out as a block
+fn is_block(token: &Token) -> bool {
+    match *token {
+        Token::Paragraph(_) |
+        Token::Header(..) |
+        Token::BlockQuote(_) |
+        Token::Admonition(..) |
+        Token::Verse(..) |
+        Token::CodeBlock(..) |
+        Token::Rule |
+        Token::List(_) |
+        Token::OrderedList(..) |
+        Token::DefinitionList(_) |
+        Token::Table(..) |
+        Token::OnlyFor(..) |
+        Token::RawBlock(..) |
+        Token::StandaloneImage(..) => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+fn test_par_style(align: Align) -> ParStyle {
+    ParStyle {
+        align: align,
+        first_indent: 0.0,
+        left: 0.0,
+        right: 0.0,
+        keep_with_next: false,
+    }
+}
+
+#[test]
+fn pdf_break_lines() {
+    let book = Book::new();
+    let mut renderer = PdfRenderer::new(&book).unwrap();
+    // Don't hyphenate words, so lines only break between them
+    renderer.corpus = None;
+    let text = vec!["lorem ipsum"; 40].join(" ");
+    let items = renderer.text_items(&text, Font::Regular, 11.0);
+    let width = renderer.text_width();
+
+    let lines = renderer.break_lines(&items, &test_par_style(Align::Left), 11.0);
+    assert!(lines.len() > 1);
+    let mut words = vec![];
+    for line in &lines {
+        let &(x, ref last) = line.fragments.last().unwrap();
+        assert!(x + last.width() <= width + 0.01);
+        words.extend(line.fragments.iter().map(|&(_, ref f)| f.text.clone()));
+    }
+    assert_eq!(words.join(" "), text);
+
+    // Justified lines end at the right margin, except the last one
+    let lines = renderer.break_lines(&items, &test_par_style(Align::Justify), 11.0);
+    for line in &lines[..lines.len() - 1] {
+        let &(x, ref last) = line.fragments.last().unwrap();
+        assert!((x + last.width() - width).abs() < 0.01);
+    }
+
+    // Forced line breaks
+    let items = vec![Item::Word(renderer.text_items("a", Font::Regular, 11.0).into_iter()
+                                .filter_map(|i| if let Item::Word(w) = i { Some(w) } else { None })
+                                .next()
+                                .unwrap()),
+                     Item::Break,
+                     Item::Break];
+    assert_eq!(renderer.break_lines(&items, &test_par_style(Align::Left), 11.0).len(), 2);
+}
+
+#[test]
+fn pdf_paginate() {
+    let book = Book::new();
+    let mut renderer = PdfRenderer::new(&book).unwrap();
+    let text_height = renderer.text_height();
+    let paragraph = Token::Paragraph(vec![Token::Str(vec!["lorem ipsum"; 30].join(" "))]);
+    let tokens = vec![paragraph; 30];
+    let mut blocks = vec![Block::Chapter(String::from("Chapter"), 1, true)];
+    renderer.render_blocks(&tokens, &mut blocks, 0.0).unwrap();
+    blocks.push(Block::PageBreak);
+    blocks.push(Block::Line(Line::empty(11.0)));
+
+    let pages = renderer.paginate(blocks, true);
+    assert!(pages.len() > 2);
+    for page in &pages {
+        let &(y, ref last) = page.blocks.last().unwrap();
+        assert!(y + last.height() <= text_height + 0.01);
+        assert_eq!(page.chapter, "Chapter");
+    }
+    assert!(pages[0].opening);
+    // The page break puts the last line on its own page
+    assert_eq!(pages.last().unwrap().blocks.len(), 1);
+    assert_eq!(renderer.toc, vec![(1, String::from("Chapter"), 0)]);
+}
+
+#[test]
+fn pdf_unsupported_chars() {
+    let mut book = Book::new();
+    book.add_chapter_from_named_source(Number::Default, "ru.md", "# Глава\n\nПривет".as_bytes())
+        .unwrap();
+    let mut pdf = vec![];
+    let err = PdfRenderer::new(&book).unwrap().render_book(&mut pdf).unwrap_err();
+    assert!(format!("{}", err).contains("ru.md"));
+}
+
+#[test]
+fn pdf_nested_footnotes() {
+    let book = Book::new();
+    let mut renderer = PdfRenderer::new(&book).unwrap();
+    let inner = Token::Footnote(vec![Token::Str(String::from("inner"))]);
+    let outer = Token::Footnote(vec![Token::Str(String::from("outer")), inner]);
+    let tokens = vec![Token::Paragraph(vec![Token::Str(String::from("text")), outer])];
+    let mut blocks = vec![];
+    renderer.render_blocks(&tokens, &mut blocks, 0.0).unwrap();
+
+    let pages = renderer.paginate(blocks, false);
+    assert_eq!(pages.len(), 1);
+    assert_eq!(pages[0].footnotes, vec![0, 1]);
+}
+
+#[test]
+fn pdf_unsupported_lang() {
+    let mut book = Book::new();
+    book.options.set("lang", "ru").unwrap();
+    assert!(PdfRenderer::new(&book).is_err());
+    book.options.set("lang", "fr_FR").unwrap();
+    assert!(PdfRenderer::new(&book).is_ok());
+}
+
+#[test]
+fn pdf_strikethrough() {
+    let book = Book::new();
+    let mut renderer = PdfRenderer::new(&book).unwrap();
+    let tokens = vec![Token::Strikethrough(vec![Token::Str(String::from("old"))]),
+                      Token::Str(String::from(" new"))];
+    let mut style = renderer.base_style();
+    let mut items = vec![];
+    renderer.inline(&tokens, &mut style, &mut items).unwrap();
+    let fragments: Vec<_> = items.iter()
+        .filter_map(|item| if let Item::Word(ref w) = *item { Some(w[0].clone()) } else { None })
+        .collect();
+    assert_eq!(fragments.len(), 2);
+    assert!(fragments[0].strike);
+    assert!(!fragments[1].strike);
+}
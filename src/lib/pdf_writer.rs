@@ -0,0 +1,447 @@
+// Copyright (C) 2017 Élisabeth HENRY.
+//
+// This file is part of Crowbook.
+//
+// Crowbook is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published
+// by the Free Software Foundation, either version 2.1 of the License, or
+// (at your option) any later version.
+//
+// Crowbook is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Low-level PDF generation, used by the native PDF renderer.
+//!
+//! Only the standard Type 1 fonts (Times and Courier) are used, with
+//! WinAnsi encoding, so no font needs to be embedded.
+
+use std::fmt::Write;
+
+/// Widths of Times-Roman glyphs for ASCII characters 32 to 126, in 1/1000 em
+static TIMES_ROMAN: [u16; 95] = [
+    250, 333, 408, 500, 500, 833, 778, 333, 333, 333, 500, 564, 250, 333, 250, 278,
+    500, 500, 500, 500, 500, 500, 500, 500, 500, 500, 278, 278, 564, 564, 564, 444,
+    921, 722, 667, 667, 722, 611, 556, 722, 722, 333, 389, 722, 611, 889, 722, 722,
+    556, 722, 667, 556, 611, 722, 722, 944, 722, 722, 611, 333, 278, 333, 469, 500,
+    333, 444, 500, 444, 500, 444, 333, 500, 500, 278, 278, 500, 278, 778, 500, 500,
+    500, 500, 333, 389, 278, 500, 500, 722, 500, 500, 444, 480, 200, 480, 541];
+
+/// Widths of Times-Bold glyphs for ASCII characters 32 to 126, in 1/1000 em
+static TIMES_BOLD: [u16; 95] = [
+    250, 333, 555, 500, 500, 1000, 833, 333, 333, 333, 500, 570, 250, 333, 250, 278,
+    500, 500, 500, 500, 500, 500, 500, 500, 500, 500, 333, 333, 570, 570, 570, 500,
+    930, 722, 667, 722, 722, 667, 611, 778, 778, 389, 500, 778, 667, 944, 722, 778,
+    611, 778, 722, 556, 667, 722, 722, 1000, 722, 722, 667, 333, 278, 333, 581, 500,
+    333, 500, 556, 444, 556, 444, 333, 500, 556, 278, 333, 556, 278, 833, 556, 500,
+    556, 556, 444, 389, 333, 556, 500, 722, 500, 500, 444, 394, 220, 394, 520];
+
+/// Widths of Times-Italic glyphs for ASCII characters 32 to 126, in 1/1000 em
+static TIMES_ITALIC: [u16; 95] = [
+    250, 333, 420, 500, 500, 833, 778, 333, 333, 333, 500, 675, 250, 333, 250, 278,
+    500, 500, 500, 500, 500, 500, 500, 500, 500, 500, 333, 333, 675, 675, 675, 500,
+    920, 611, 611, 667, 722, 611, 611, 722, 722, 333, 444, 667, 556, 833, 667, 722,
+    611, 722, 611, 500, 556, 722, 611, 833, 611, 556, 556, 389, 278, 389, 422, 500,
+    333, 500, 500, 444, 500, 444, 278, 500, 500, 278, 278, 444, 278, 722, 500, 500,
+    500, 500, 389, 389, 278, 500, 444, 667, 444, 444, 389, 400, 275, 400, 541];
+
+/// Widths of glyphs 0x80 to 0xBF of WinAnsi encoding (from Times-Roman), in 1/1000 em
+static HIGH_WIDTHS: [u16; 64] = [
+    500, 0, 333, 500, 444, 1000, 500, 500, 333, 1000, 556, 333, 889, 0, 611, 0,
+    0, 333, 333, 444, 444, 350, 500, 1000, 333, 980, 389, 333, 722, 0, 444, 722,
+    250, 333, 500, 500, 500, 500, 200, 500, 333, 760, 276, 500, 564, 333, 760, 333,
+    400, 564, 300, 300, 333, 500, 453, 250, 333, 300, 310, 500, 750, 750, 750, 444];
+
+/// Base (unaccented) letters of WinAnsi glyphs 0xC0 to 0xFF, or 0 if the width is
+/// given by `latin1_width`
+static LATIN1_BASES: &[u8; 64] = b"AAAAAA\0CEEEEIIII\0NOOOOO\0OUUUUY\0\0aaaaaa\0ceeeeiiii\0nooooo\0ouuuuy\0y";
+
+/// Widths of glyphs 0xC0 to 0xFF that are not accented letters
+fn latin1_width(b: u8) -> u16 {
+    match b {
+        0xC6 => 889,
+        0xD0 => 722,
+        0xD7 | 0xF7 => 564,
+        0xDE => 556,
+        0xE6 => 667,
+        _ => 500,
+    }
+}
+
+/// The fonts that can be used in a document
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Font {
+    Regular,
+    Bold,
+    Italic,
+    BoldItalic,
+    Mono,
+}
+
+impl Font {
+    /// All available fonts, in the order of their resource names (F1, F2, ...)
+    pub fn all() -> [Font; 5] {
+        [Font::Regular, Font::Bold, Font::Italic, Font::BoldItalic, Font::Mono]
+    }
+
+    /// Name of the font in the page resources
+    pub fn resource_name(self) -> &'static str {
+        match self {
+            Font::Regular => "F1",
+            Font::Bold => "F2",
+            Font::Italic => "F3",
+            Font::BoldItalic => "F4",
+            Font::Mono => "F5",
+        }
+    }
+
+    /// Name of the standard PDF font
+    pub fn base_font(self) -> &'static str {
+        match self {
+            Font::Regular => "Times-Roman",
+            Font::Bold => "Times-Bold",
+            Font::Italic => "Times-Italic",
+            Font::BoldItalic => "Times-BoldItalic",
+            Font::Mono => "Courier",
+        }
+    }
+
+    /// Returns the font with bold and/or italic variant
+    pub fn with_style(self, bold: bool, italic: bool) -> Font {
+        if self == Font::Mono {
+            return self;
+        }
+        match (bold, italic) {
+            (false, false) => Font::Regular,
+            (true, false) => Font::Bold,
+            (false, true) => Font::Italic,
+            (true, true) => Font::BoldItalic,
+        }
+    }
+
+    /// Width of a WinAnsi-encoded glyph, in 1/1000 em
+    pub fn glyph_width(self, b: u8) -> u16 {
+        let table = match self {
+            Font::Mono => return 600,
+            Font::Regular => &TIMES_ROMAN,
+            Font::Bold | Font::BoldItalic => &TIMES_BOLD,
+            Font::Italic => &TIMES_ITALIC,
+        };
+        if b >= 32 && b <= 126 {
+            table[(b - 32) as usize]
+        } else if b >= 0xC0 {
+            let base = LATIN1_BASES[(b - 0xC0) as usize];
+            if base == 0 {
+                latin1_width(b)
+            } else {
+                table[(base - 32) as usize]
+            }
+        } else if b >= 0x80 {
+            HIGH_WIDTHS[(b - 0x80) as usize]
+        } else {
+            0
+        }
+    }
+
+    /// Width of a string at a given size, in points
+    pub fn width(self, s: &str, size: f32) -> f32 {
+        let total: u32 = encode(s).into_iter().map(|b| self.glyph_width(b) as u32).sum();
+        total as f32 * size / 1000.0
+    }
+}
+
+/// Returns the WinAnsi code of a character, if it has one
+pub fn encode_char(c: char) -> Option<u8> {
+    let code = c as u32;
+    if (code >= 32 && code <= 126) || (code >= 0xA0 && code <= 0xFF) {
+        return Some(code as u8);
+    }
+    let b = match c {
+        '\u{20AC}' => 0x80,
+        '\u{201A}' => 0x82,
+        '\u{0192}' => 0x83,
+        '\u{201E}' => 0x84,
+        '\u{2026}' => 0x85,
+        '\u{2020}' => 0x86,
+        '\u{2021}' => 0x87,
+        '\u{02C6}' => 0x88,
+        '\u{2030}' => 0x89,
+        '\u{0160}' => 0x8A,
+        '\u{2039}' => 0x8B,
+        '\u{0152}' => 0x8C,
+        '\u{017D}' => 0x8E,
+        '\u{2018}' => 0x91,
+        '\u{2019}' => 0x92,
+        '\u{201C}' => 0x93,
+        '\u{201D}' => 0x94,
+        '\u{2022}' => 0x95,
+        '\u{2013}' => 0x96,
+        '\u{2014}' => 0x97,
+        '\u{02DC}' => 0x98,
+        '\u{2122}' => 0x99,
+        '\u{0161}' => 0x9A,
+        '\u{203A}' => 0x9B,
+        '\u{0153}' => 0x9C,
+        '\u{017E}' => 0x9E,
+        '\u{0178}' => 0x9F,
+        // Narrow and thin (non-breaking) spaces
+        '\u{202F}' | '\u{2009}' | '\u{2007}' => 0xA0,
+        _ => return None,
+    };
+    Some(b)
+}
+
+/// Encodes a string with WinAnsi encoding, replacing unknown characters with '?'
+pub fn encode(s: &str) -> Vec<u8> {
+    s.chars()
+        .filter(|c| *c != '\u{AD}')
+        .map(|c| encode_char(c).unwrap_or(b'?'))
+        .collect()
+}
+
+/// Returns a PDF literal string, e.g. `(foo)`
+pub fn literal(s: &str) -> String {
+    let mut res = String::from("(");
+    for b in encode(s) {
+        match b {
+            b'(' | b')' | b'\\' => {
+                res.push('\\');
+                res.push(b as char);
+            }
+            b if b >= 32 && b <= 126 => res.push(b as char),
+            _ => write!(res, "\\{:03o}", b).unwrap(),
+        }
+    }
+    res.push(')');
+    res
+}
+
+/// Formats a number with at most two decimals, as PDF operators expect
+pub fn num(x: f32) -> String {
+    let s = format!("{:.2}", x);
+    let s = s.trim_right_matches('0').trim_right_matches('.');
+    if s == "-0" || s.is_empty() {
+        String::from("0")
+    } else {
+        s.to_owned()
+    }
+}
+
+/// An image that can be included in a PDF document
+pub struct Image {
+    /// Width, in pixels
+    pub width: u32,
+    /// Height, in pixels
+    pub height: u32,
+    color_space: &'static str,
+    filter: &'static str,
+    decode_parms: String,
+    data: Vec<u8>,
+}
+
+impl Image {
+    /// Reads a JPEG or PNG image
+    ///
+    /// Returns `None` if the format is not supported (only JPEG, and non-interlaced,
+    /// 8-bit grayscale or RGB PNG images are)
+    pub fn new(data: Vec<u8>) -> Option<Image> {
+        if data.starts_with(&[0xFF, 0xD8]) {
+            Self::from_jpeg(data)
+        } else if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+            Self::from_png(&data)
+        } else {
+            None
+        }
+    }
+
+    fn from_jpeg(data: Vec<u8>) -> Option<Image> {
+        let mut i = 2;
+        while i + 9 < data.len() {
+            if data[i] != 0xFF {
+                return None;
+            }
+            let marker = data[i + 1];
+            let len = ((data[i + 2] as usize) << 8) + data[i + 3] as usize;
+            let is_sof = marker >= 0xC0 && marker <= 0xCF &&
+                marker != 0xC4 && marker != 0xC8 && marker != 0xCC;
+            if is_sof {
+                let height = ((data[i + 5] as u32) << 8) + data[i + 6] as u32;
+                let width = ((data[i + 7] as u32) << 8) + data[i + 8] as u32;
+                let color_space = match data[i + 9] {
+                    1 => "/DeviceGray",
+                    3 => "/DeviceRGB",
+                    4 => "/DeviceCMYK",
+                    _ => return None,
+                };
+                return Some(Image {
+                    width: width,
+                    height: height,
+                    color_space: color_space,
+                    filter: "/DCTDecode",
+                    decode_parms: String::new(),
+                    data: data,
+                });
+            }
+            i += 2 + len;
+        }
+        None
+    }
+
+    fn from_png(data: &[u8]) -> Option<Image> {
+        let mut i = 8;
+        let mut header = None;
+        let mut idat = vec![];
+        while i + 8 <= data.len() {
+            let len = ((data[i] as usize) << 24) + ((data[i + 1] as usize) << 16) +
+                ((data[i + 2] as usize) << 8) + data[i + 3] as usize;
+            let kind = &data[i + 4..i + 8];
+            if i + 12 + len > data.len() {
+                return None;
+            }
+            let chunk = &data[i + 8..i + 8 + len];
+            match kind {
+                b"IHDR" if len >= 13 => {
+                    let width = ((chunk[0] as u32) << 24) + ((chunk[1] as u32) << 16) +
+                        ((chunk[2] as u32) << 8) + chunk[3] as u32;
+                    let height = ((chunk[4] as u32) << 24) + ((chunk[5] as u32) << 16) +
+                        ((chunk[6] as u32) << 8) + chunk[7] as u32;
+                    // bit depth, colour type, interlace method
+                    header = Some((width, height, chunk[8], chunk[9], chunk[12]));
+                }
+                b"IDAT" => idat.extend_from_slice(chunk),
+                b"IEND" => break,
+                _ => {}
+            }
+            i += 12 + len;
+        }
+        let (width, height, depth, colour_type, interlace) = match header {
+            Some(header) => header,
+            None => return None,
+        };
+        let (color_space, colors) = match colour_type {
+            0 => ("/DeviceGray", 1),
+            2 => ("/DeviceRGB", 3),
+            _ => return None,
+        };
+        if depth != 8 || interlace != 0 {
+            return None;
+        }
+        Some(Image {
+            width: width,
+            height: height,
+            color_space: color_space,
+            filter: "/FlateDecode",
+            decode_parms: format!("/DecodeParms << /Predictor 15 /Colors {} /BitsPerComponent 8 \
+                                   /Columns {} >> ",
+                                  colors,
+                                  width),
+            data: idat,
+        })
+    }
+}
+
+/// Builds a PDF file, object by object
+pub struct PdfWriter {
+    objects: Vec<Option<Vec<u8>>>,
+}
+
+impl PdfWriter {
+    /// Creates a new, empty, PDF writer
+    pub fn new() -> PdfWriter {
+        PdfWriter { objects: vec![] }
+    }
+
+    /// Reserves an object number, whose content will be set later
+    pub fn reserve(&mut self) -> usize {
+        self.objects.push(None);
+        self.objects.len()
+    }
+
+    /// Sets the content of a reserved object
+    pub fn set(&mut self, id: usize, content: Vec<u8>) {
+        self.objects[id - 1] = Some(content);
+    }
+
+    /// Adds an object and returns its number
+    pub fn add<S: Into<Vec<u8>>>(&mut self, content: S) -> usize {
+        self.objects.push(Some(content.into()));
+        self.objects.len()
+    }
+
+    /// Adds a stream object, given the entries of its dictionary (without `/Length`)
+    pub fn add_stream(&mut self, dict: &str, data: &[u8]) -> usize {
+        let mut content = format!("<< {}/Length {} >>\nstream\n", dict, data.len()).into_bytes();
+        content.extend_from_slice(data);
+        content.extend_from_slice(b"\nendstream");
+        self.add(content)
+    }
+
+    /// Adds the standard fonts and returns the font resource dictionary
+    pub fn add_fonts(&mut self) -> String {
+        let mut resources = String::from("<< ");
+        for font in &Font::all() {
+            let id = self.add(format!("<< /Type /Font /Subtype /Type1 /BaseFont /{} \
+                                       /Encoding /WinAnsiEncoding >>",
+                                      font.base_font()));
+            write!(resources, "/{} {} 0 R ", font.resource_name(), id).unwrap();
+        }
+        resources.push_str(">>");
+        resources
+    }
+
+    /// Adds an image and returns its object number
+    pub fn add_image(&mut self, image: &Image) -> usize {
+        let dict = format!("/Type /XObject /Subtype /Image /Width {} /Height {} /ColorSpace {} \
+                            /BitsPerComponent 8 /Filter {} {}",
+                           image.width,
+                           image.height,
+                           image.color_space,
+                           image.filter,
+                           image.decode_parms);
+        self.add_stream(&dict, &image.data)
+    }
+
+    /// Writes the whole document, given the number of the catalog and info objects
+    pub fn finish(self, root: usize, info: usize) -> Vec<u8> {
+        let mut out: Vec<u8> = b"%PDF-1.4\n%\xE2\xE3\xCF\xD3\n".to_vec();
+        let mut offsets = vec![];
+        for (i, object) in self.objects.into_iter().enumerate() {
+            offsets.push(out.len());
+            out.extend_from_slice(format!("{} 0 obj\n", i + 1).as_bytes());
+            out.extend_from_slice(&object.unwrap_or_else(|| b"null".to_vec()));
+            out.extend_from_slice(b"\nendobj\n");
+        }
+        let xref = out.len();
+        out.extend_from_slice(format!("xref\n0 {}\n0000000000 65535 f \n", offsets.len() + 1)
+                              .as_bytes());
+        for offset in &offsets {
+            out.extend_from_slice(format!("{:010} 00000 n \n", offset).as_bytes());
+        }
+        out.extend_from_slice(format!("trailer\n<< /Size {} /Root {} 0 R /Info {} 0 R >>\n\
+                                       startxref\n{}\n%%EOF\n",
+                                      offsets.len() + 1,
+                                      root,
+                                      info,
+                                      xref)
+                              .as_bytes());
+        out
+    }
+}
+
+#[test]
+fn pdf_literal() {
+    assert_eq!(literal("foo (bar)"), "(foo \\(bar\\))");
+    assert_eq!(literal("café"), "(caf\\351)");
+    assert_eq!(literal("l’été"), "(l\\222\\351t\\351)");
+}
+
+#[test]
+fn pdf_num() {
+    assert_eq!(num(12.0), "12");
+    assert_eq!(num(0.5), "0.5");
+    assert_eq!(num(-0.001), "0");
+}
@@ -0,0 +1,320 @@
+// Copyright (C) 2017 Élisabeth HENRY.
+//
+// This file is part of Crowbook.
+//
+// Crowbook is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published
+// by the Free Software Foundation, either version 2.1 of the License, or
+// (at your option) any later version.
+//
+// Crowbook is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Print-on-demand presets for LaTeX/PDF output: trim sizes, bleed,
+//! crop marks and gutter margins.
+
+use book::Book;
+use error::{Error, Result};
+use text_view::view_as_text;
+
+/// Trim sizes (width and height, in mm) of the available presets
+const PRESETS: &'static [(&'static str, f32, f32)] = &[("pocket", 107.95, 174.5),
+                                                       ("5x8", 127.0, 203.2),
+                                                       ("5.25x8", 133.35, 203.2),
+                                                       ("5.5x8.5", 139.7, 215.9),
+                                                       ("6x9", 152.4, 228.6),
+                                                       ("a5", 148.0, 210.0),
+                                                       ("royal", 156.0, 234.0),
+                                                       ("a4", 210.0, 297.0)];
+
+/// Standard bleed (0.125in), used by presets with a `-bleed` suffix
+const DEFAULT_BLEED: f32 = 3.175;
+
+/// Space added around the page for crop marks, in mm
+const CROP_MARKS_MARGIN: f32 = 10.0;
+
+/// Values of `tex.print.pdfx` accepted by the `pdfx` package
+const PDFX_STANDARDS: &'static [&'static str] = &["x-1a", "x-1a1", "x-1a3", "x-3", "x-32",
+                                                  "x-4", "x-4p", "a-1b", "a-2b", "a-3b"];
+
+/// Geometry and packages options for a print preset
+#[derive(Debug)]
+pub struct PrintSettings {
+    /// Options for the `geometry` package
+    pub geometry: String,
+    /// Options for the `crop` package, if it is needed
+    pub crop: Option<String>,
+    /// Standard passed to the `pdfx` package, if any
+    pub pdfx: Option<String>,
+}
+
+impl PrintSettings {
+    /// Reads the `tex.print.*` options, returning `None` if no preset is set, and an
+    /// error if the options are not valid or are set without a preset
+    pub fn new(book: &Book) -> Result<Option<PrintSettings>> {
+        let preset = match book.options.get_str("tex.print.preset") {
+            Ok(preset) => preset.to_lowercase(),
+            Err(_) => {
+                let set = ["tex.print.bleed", "tex.print.page_count", "tex.print.pdfx"]
+                    .iter()
+                    .find(|key| book.options.get(key).is_ok());
+                if let Some(key) = set {
+                    return Err(Error::book_option(&book.source,
+                                                  lformat!("{option} can only be used with \
+                                                            tex.print.preset",
+                                                           option = key)));
+                }
+                if book.options.get_bool("tex.print.crop_marks").unwrap() {
+                    return Err(Error::book_option(&book.source,
+                                                  lformat!("tex.print.crop_marks can only be \
+                                                            used with tex.print.preset")));
+                }
+                return Ok(None);
+            }
+        };
+        if book.options.get_bool("tex.stdpage").unwrap() {
+            return Err(Error::book_option(&book.source,
+                                          lformat!("tex.print.preset can't be used with \
+                                                    tex.stdpage")));
+        }
+        let (name, bleed) = if preset.ends_with("-bleed") {
+            (preset.trim_right_matches("-bleed"), DEFAULT_BLEED)
+        } else {
+            (preset.as_str(), 0.0)
+        };
+        let (width, height) = match PRESETS.iter().find(|&&(n, _, _)| n == name) {
+            Some(&(_, width, height)) => (width, height),
+            None => {
+                let names: Vec<_> = PRESETS.iter().map(|&(n, _, _)| n).collect();
+                return Err(Error::book_option(&book.source,
+                                              lformat!("tex.print.preset: unknown preset \
+                                                        '{preset}', expected one of: {presets} \
+                                                        (optionally followed by '-bleed')",
+                                                       preset = preset,
+                                                       presets = names.join(", "))));
+            }
+        };
+        let bleed = match book.options.get_str("tex.print.bleed") {
+            Ok(s) => {
+                parse_dimension(s).ok_or_else(|| {
+                        Error::book_option(&book.source,
+                                           lformat!("tex.print.bleed: invalid dimension \
+                                                     '{value}', expected e.g. '0.125in' or \
+                                                     '3mm'",
+                                                    value = s))
+                    })?
+            }
+            Err(_) => bleed,
+        };
+        let pages = match book.options.get_i32("tex.print.page_count") {
+            Ok(n) if n > 0 => n,
+            Ok(n) => {
+                return Err(Error::book_option(&book.source,
+                                              lformat!("tex.print.page_count: expected a \
+                                                        positive number of pages, found {n}",
+                                                       n = n)));
+            }
+            Err(_) => estimate_page_count(book, width, height),
+        };
+        let pdfx = match book.options.get_str("tex.print.pdfx") {
+            Ok(s) => {
+                if !PDFX_STANDARDS.contains(&s) {
+                    return Err(Error::book_option(&book.source,
+                                                  lformat!("tex.print.pdfx: unsupported \
+                                                            standard '{value}', expected one \
+                                                            of: {standards}",
+                                                           value = s,
+                                                           standards = PDFX_STANDARDS.join(", "))));
+                }
+                Some(s.to_owned())
+            }
+            Err(_) => None,
+        };
+        let crop_marks = book.options.get_bool("tex.print.crop_marks").unwrap();
+
+        let (geometry, crop) = layout(width,
+                                      height,
+                                      bleed,
+                                      crop_marks,
+                                      pages,
+                                      book.options.get_str("tex.margin.top").unwrap(),
+                                      book.options.get_str("tex.margin.bottom").unwrap());
+        Ok(Some(PrintSettings {
+            geometry: geometry,
+            crop: crop,
+            pdfx: pdfx,
+        }))
+    }
+}
+
+/// Returns the options for the `geometry` and `crop` packages, for a trim size in mm
+///
+/// Without crop marks, bleed is laid out as print-on-demand services expect it: it
+/// is only added to the outer edge horizontally (since pages are bound on the inner
+/// one), and to the top and bottom. Crop marks are drawn around the trimmed page with
+/// the `crop` package, so the bleed is then added on all sides.
+fn layout(width: f32,
+          height: f32,
+          bleed: f32,
+          crop_marks: bool,
+          pages: i32,
+          top: &str,
+          bottom: &str)
+          -> (String, Option<String>) {
+    let gutter = gutter(pages);
+    // Outer margin must be larger when there is bleed, so text isn't too close to the cut
+    let outer = if bleed > 0.0 { 12.7 } else { 9.525 };
+    if crop_marks {
+        let geometry = format!("paperwidth={}, paperheight={}, twoside, inner={}, outer={}, \
+                                top={}, bottom={}",
+                               mm(width),
+                               mm(height),
+                               mm(gutter),
+                               mm(outer),
+                               top,
+                               bottom);
+        let extra = 2.0 * bleed + 2.0 * CROP_MARKS_MARGIN;
+        let crop = format!("width={}, height={}, center, cam",
+                           mm(width + extra),
+                           mm(height + extra));
+        (geometry, Some(crop))
+    } else {
+        let geometry = format!("paperwidth={}, paperheight={}, twoside, inner={}, outer={}, \
+                                top={}, bottom={}",
+                               mm(width + bleed),
+                               mm(height + 2.0 * bleed),
+                               mm(gutter),
+                               mm(outer + bleed),
+                               add_mm(top, bleed),
+                               add_mm(bottom, bleed));
+        (geometry, None)
+    }
+}
+
+/// Adds a length in mm to a LaTeX dimension
+fn add_mm(dimension: &str, extra: f32) -> String {
+    if extra == 0.0 {
+        return dimension.to_owned();
+    }
+    match parse_dimension(dimension) {
+        Some(x) => mm(x + extra),
+        None => format!("\\dimexpr {}+{}\\relax", dimension, mm(extra)),
+    }
+}
+
+/// Formats a length in mm for LaTeX
+fn mm(x: f32) -> String {
+    format!("{:.2}mm", x)
+}
+
+/// Parses a LaTeX-like dimension (in mm, cm, in or pt) and returns it in mm
+fn parse_dimension(s: &str) -> Option<f32> {
+    let s = s.trim();
+    let units = [("mm", 1.0), ("cm", 10.0), ("in", 25.4), ("pt", 25.4 / 72.27)];
+    for &(unit, factor) in &units {
+        if s.ends_with(unit) {
+            return s[..s.len() - unit.len()]
+                .trim()
+                .parse::<f32>()
+                .ok()
+                .and_then(|x| if x >= 0.0 { Some(x * factor) } else { None });
+        }
+    }
+    None
+}
+
+/// Returns the inner (gutter) margin, in mm, required for a number of pages
+///
+/// These are the minimal values required by most print-on-demand services.
+fn gutter(pages: i32) -> f32 {
+    let inches = match pages {
+        n if n <= 150 => 0.375,
+        n if n <= 300 => 0.5,
+        n if n <= 500 => 0.625,
+        n if n <= 700 => 0.75,
+        _ => 0.875,
+    };
+    inches * 25.4
+}
+
+/// Estimates the number of pages of a book, from its number of words and trim size
+fn estimate_page_count(book: &Book, width: f32, height: f32) -> i32 {
+    // About 300 words fit on a 6x9 page
+    let words_per_page = 300.0 * (width * height) / (152.4 * 228.6);
+    let words: usize = book.chapters
+        .iter()
+        .map(|c| view_as_text(&c.content).split_whitespace().count())
+        .sum();
+    // Chapters start on a new page
+    (words as f32 / words_per_page) as i32 + book.chapters.len() as i32
+}
+
+#[test]
+fn print_dimensions() {
+    assert_eq!(parse_dimension("3mm"), Some(3.0));
+    assert_eq!(parse_dimension("0.125in"), Some(3.175));
+    assert_eq!(parse_dimension("1 cm"), Some(10.0));
+    assert_eq!(parse_dimension("3"), None);
+    assert_eq!(parse_dimension("-3mm"), None);
+    assert!((gutter(100) - 9.525).abs() < 1e-3);
+    assert!((gutter(400) - 15.875).abs() < 1e-3);
+}
+
+#[cfg(test)]
+/// Returns the value (in mm) of a key in geometry or crop options
+fn option_mm(options: &str, key: &str) -> f32 {
+    let start = options.find(&format!("{}=", key)).unwrap() + key.len() + 1;
+    let value = options[start..].split(',').next().unwrap();
+    parse_dimension(value).unwrap()
+}
+
+#[test]
+fn print_preset_sizes() {
+    let close = |a: f32, b: f32| (a - b).abs() < 0.01;
+    for &(_, width, height) in PRESETS {
+        // No bleed: the page is the trim size
+        let (geometry, crop) = layout(width, height, 0.0, false, 100, "2cm", "1.5cm");
+        assert!(close(option_mm(&geometry, "paperwidth"), width));
+        assert!(close(option_mm(&geometry, "paperheight"), height));
+        assert!(geometry.contains("top=2cm"));
+        assert!(crop.is_none());
+
+        // Bleed on the outer edge, top and bottom only
+        let (geometry, crop) = layout(width, height, DEFAULT_BLEED, false, 100, "2cm", "1.5cm");
+        assert!(close(option_mm(&geometry, "paperwidth"), width + DEFAULT_BLEED));
+        assert!(close(option_mm(&geometry, "paperheight"), height + 2.0 * DEFAULT_BLEED));
+        assert!(close(option_mm(&geometry, "inner"), gutter(100)));
+        assert!(close(option_mm(&geometry, "outer"), 12.7 + DEFAULT_BLEED));
+        assert!(close(option_mm(&geometry, "top"), 20.0 + DEFAULT_BLEED));
+        assert!(close(option_mm(&geometry, "bottom"), 15.0 + DEFAULT_BLEED));
+        assert!(crop.is_none());
+
+        // Crop marks around the trimmed page
+        let (geometry, crop) = layout(width, height, DEFAULT_BLEED, true, 100, "2cm", "1.5cm");
+        let crop = crop.unwrap();
+        assert!(close(option_mm(&geometry, "paperwidth"), width));
+        assert!(close(option_mm(&crop, "width"), width + 2.0 * (DEFAULT_BLEED + CROP_MARKS_MARGIN)));
+        assert!(close(option_mm(&crop, "height"), height + 2.0 * (DEFAULT_BLEED + CROP_MARKS_MARGIN)));
+    }
+    assert_eq!(add_mm("2em", 3.0), "\\dimexpr 2em+3.00mm\\relax");
+}
+
+#[test]
+fn print_options_without_preset() {
+    let book = Book::new();
+    assert!(PrintSettings::new(&book).unwrap().is_none());
+    for &(key, value) in &[("tex.print.bleed", "3mm"),
+                           ("tex.print.page_count", "200"),
+                           ("tex.print.pdfx", "x-1a"),
+                           ("tex.print.crop_marks", "true")] {
+        let mut book = Book::new();
+        book.options.set(key, value).unwrap();
+        let err = PrintSettings::new(&book).unwrap_err();
+        assert!(format!("{}", err).contains(key));
+    }
+}
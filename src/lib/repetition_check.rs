@@ -68,6 +68,7 @@ impl RepetitionDetector {
                 Token::Paragraph(ref mut v) |
                 Token::Header(_, ref mut v) |
                 Token::BlockQuote(ref mut v) |
+                Token::Admonition(_, _, ref mut v) |
                 Token::List(ref mut v) |
                 Token::OrderedList(_, ref mut v) => {
                     let mut ast = parser.tokenize(&view_as_text(v))
@@ -1,7 +1,7 @@
 use token::Token;
 use error::{Error, Result, Source};
 
-use std::collections::HashMap;
+use std::collections::{HashMap, BTreeMap};
 use std::path::{Path, PathBuf};
 use std::borrow::Cow;
 use std::fs;
@@ -20,13 +20,16 @@ pub struct ResourceHandler {
     /// Maps an original url (e.g.) "foo/Readme.md" to a valid link
     /// (e.g.) chapter3.html
     links: HashMap<String, String>,
+    /// Maps an anchor (e.g. "link-3") to the file it is in, if chapters
+    /// are split across multiple files
+    anchors: HashMap<String, String>,
     map_images: bool,
     base64: bool,
 
     /// Maps an original (local) file name to a new file name. Allows to
     /// make sure all image files will be included in e.g. the Epub document.
     #[doc(hidden)]
-    pub images: HashMap<String, String>,
+    pub images: BTreeMap<String, String>,
 }
 
 impl ResourceHandler {
@@ -34,7 +37,8 @@ impl ResourceHandler {
     pub fn new() -> ResourceHandler {
         ResourceHandler {
             links: HashMap::new(),
-            images: HashMap::new(),
+            anchors: HashMap::new(),
+            images: BTreeMap::new(),
             map_images: false,
             base64: false,
         }
@@ -131,7 +135,7 @@ impl ResourceHandler {
 
     /// Returns an iterator the the images files mapping
     #[doc(hidden)]
-    pub fn images_mapping(&self) -> &HashMap<String, String> {
+    pub fn images_mapping(&self) -> &BTreeMap<String, String> {
         &self.images
     }
 
@@ -140,8 +144,38 @@ impl ResourceHandler {
         self.links.insert(from.into(), to.into());
     }
 
+    /// Add a match between an anchor and the (destination) file containing it
+    pub fn add_anchor<S1: Into<String>, S2: Into<String>>(&mut self, anchor: S1, file: S2) {
+        self.anchors.insert(anchor.into(), file.into());
+    }
+
+    /// Returns true if `anchor` has been registered with `add_anchor`
+    pub fn contains_anchor(&self, anchor: &str) -> bool {
+        self.anchors.contains_key(anchor)
+    }
+
     /// Get a destination link from an original link
-    pub fn get_link<'a>(&'a self, from: &'a str) -> &'a str {
+    ///
+    /// If the link contains an anchor (e.g. "foo.md#bar" or "#bar") that has
+    /// been registered with `add_anchor`, it points to the file containing it.
+    pub fn get_link<'a>(&'a self, from: &'a str) -> Cow<'a, str> {
+        if let Some(pos) = from.find('#') {
+            let anchor = &from[pos + 1..];
+            if let Some(file) = self.anchors.get(anchor) {
+                return Cow::Owned(format!("{}#{}", file, anchor));
+            }
+            if pos == 0 {
+                return Cow::Borrowed(from);
+            }
+            if self.contains_link(&from[..pos]) {
+                return Cow::Owned(format!("{}{}", self.get_file_link(&from[..pos]), &from[pos..]));
+            }
+        }
+        Cow::Borrowed(self.get_file_link(from))
+    }
+
+    /// Get a destination file from an original file link (without anchor)
+    fn get_file_link<'a>(&'a self, from: &'a str) -> &'a str {
         if let Some(link) = self.links.get(from) {
             link
         } else {
@@ -242,6 +276,7 @@ pub fn get_files(list: &[String], base: &str) -> Result<Vec<String>> {
                 } else if metadata.is_dir() {
                     let files = WalkDir::new(&abs_path)
                         .follow_links(true)
+                        .sort_by(|a, b| a.file_name().cmp(b.file_name()))
                         .into_iter()
                         .filter_map(|e| e.ok())
                         .filter(|e| e.file_type().is_file())
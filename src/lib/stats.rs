@@ -105,7 +105,8 @@ impl Stats {
 
 
     // Returns the Languuage (defined by Hyphenation crate) according to the str code
-    fn language_from_str(lang: &str) -> Language {
+    #[doc(hidden)]
+    pub fn language_from_str(lang: &str) -> Language {
         // FIXME: handle case where lang is e.g. fr_FR or en_GB
         match lang {
             "cz" => Language::Czech,
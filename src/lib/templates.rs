@@ -66,6 +66,10 @@ pub mod epub3 {
     pub static TITLE: &str = include_str!("../../templates/epub3/titlepage.xhtml");
 }
 
+pub mod cover {
+    pub static SVG: &str = include_str!("../../templates/cover/cover.svg");
+}
+
 pub mod odt {
     pub static CONTENT: &str = include_str!("../../templates/odt/content.xml");
     pub static ODT: &[u8] = include_bytes!("../../templates/odt/template.odt");
@@ -19,3 +19,287 @@ epub.version: 3";
             "A long description");
     assert_eq!(book.options.get_i32("epub.version").unwrap(), 3);
 }
+
+#[test]
+fn header_ids() {
+    use number::Number;
+
+    let mut book = Book::new();
+    book.add_chapter_from_source(Number::Default,
+                                 "# Chapter 1\n\n## A section!\n\n## A section\n\n## Other {#custom}"
+                                     .as_bytes())
+        .unwrap();
+    book.add_chapter_from_source(Number::Default, "# A section\n".as_bytes())
+        .unwrap();
+    let ids: Vec<_> = book.chapters
+        .iter()
+        .flat_map(|c| c.content.iter())
+        .filter(|t| t.is_header())
+        .map(|t| t.attributes().and_then(|a| a.id.clone()).unwrap())
+        .collect();
+    assert_eq!(ids,
+               ["chapter-1-1", "a-section", "a-section-1", "custom", "a-section-2"]);
+}
+
+#[test]
+fn book_variables() {
+    use number::Number;
+    use token::Token;
+
+    let config = "
+title: Some title
+input.variables: true
+vars:
+  product: Crowbook
+  year: 2017
+";
+    let mut book = Book::new();
+    book.read_config(config.as_bytes()).unwrap();
+    test_eq(book.options.get_str("vars.year").unwrap(), "2017");
+    book.add_chapter_from_source(Number::Default,
+                                 "{{product}} ({{ title }})".as_bytes())
+        .unwrap();
+    assert_eq!(book.chapters[0].content[1],
+               Token::Paragraph(vec![Token::Str(String::from("Crowbook (Some title)"))]));
+    assert!(book.add_chapter_from_source(Number::Default, "{{undefined}}".as_bytes()).is_err());
+}
+
+#[test]
+fn book_json() {
+    use number::Number;
+
+    let config = "
+title: Some title
+pdf.margin: 12.5
+tex.class: article
+";
+    let mut book = Book::new();
+    book.read_config(config.as_bytes()).unwrap();
+    book.add_chapter_from_source(Number::Specified(3),
+                                 "# Foo\n\nSome *text*.".as_bytes())
+        .unwrap();
+    let mut json = vec![];
+    book.render_format_to("json", &mut json).unwrap();
+
+    let mut copy = Book::new();
+    copy.read_json(json.as_slice()).unwrap();
+    test_eq(copy.options.get_str("title").unwrap(), "Some title");
+    test_eq(copy.options.get_str("tex.class").unwrap(), "article");
+    assert_eq!(copy.options.get_f32("pdf.margin").unwrap(), 12.5);
+    assert_eq!(copy.chapters.len(), 1);
+    assert_eq!(copy.chapters[0].number, Number::Specified(3));
+    assert_eq!(copy.chapters[0].content, book.chapters[0].content);
+    assert!(copy.read_json("{\"chapters\": 42}".as_bytes()).is_err());
+
+    // Options that can't be set make reading fail
+    let mut value: ::serde_json::Value = ::serde_json::from_slice(&json).unwrap();
+    value["options"]["pdf.margin"] = ::serde_json::Value::String(String::from("wide"));
+    let json = ::serde_json::to_vec(&value).unwrap();
+    let err = Book::new().read_json(json.as_slice()).unwrap_err();
+    assert!(err.is_book_option());
+}
+
+#[cfg(unix)]
+#[test]
+fn book_filters() {
+    use number::Number;
+
+    let config = "
+filters: [cat]
+filters.html: [cat]
+";
+    let mut book = Book::new();
+    book.read_config(config.as_bytes()).unwrap();
+    book.add_chapter_from_source(Number::Default, "# Foo\n\nBar".as_bytes())
+        .unwrap();
+    let mut expected = Book::new();
+    expected.add_chapter_from_source(Number::Default, "# Foo\n\nBar".as_bytes())
+        .unwrap();
+    assert_eq!(book.chapters[0].content, expected.chapters[0].content);
+
+    book.options.set("filters", "[\"false\"]").unwrap();
+    let err = book.add_chapter_from_source(Number::Default, "Bar".as_bytes()).unwrap_err();
+    assert!(format!("{}", err).contains("filter 'false'"));
+}
+
+#[test]
+fn book_identifier() {
+    let config = "
+title: Some title
+author: Some author
+crowbook.reproducible: true
+";
+    let mut book = Book::new();
+    book.read_config(config.as_bytes()).unwrap();
+    let mut same = Book::new();
+    same.read_config(config.as_bytes()).unwrap();
+    assert_eq!(book.identifier(), same.identifier());
+
+    book.options.set("title", "Other title").unwrap();
+    assert!(book.identifier() != same.identifier());
+    assert!(book.identifier().starts_with("urn:uuid:"));
+}
+
+#[test]
+fn book_generated_cover_stdpage() {
+    let config = "
+title: Some title
+cover.generate: true
+tex.stdpage: true
+";
+    let mut book = Book::new();
+    book.read_config(config.as_bytes()).unwrap();
+    let mut tex = vec![];
+    book.render_format_to("tex", &mut tex).unwrap();
+    let tex = String::from_utf8(tex).unwrap();
+    assert!(tex.contains("crowbookcoverbg"));
+    assert!(!tex.contains("\\newgeometry"));
+    assert!(book.generated_cover().is_some());
+}
+
+#[test]
+fn book_running_layout() {
+    use mustache::MapBuilder;
+
+    let config = "
+pdf.header.odd: \"{{chapter}} | {{{page}}}\"
+pdf.header.even: \"{{title}}\"
+pdf.footer.odd: \"a | b | c | d\"
+";
+    let mut book = Book::new();
+    book.read_config(config.as_bytes()).unwrap();
+    let data = MapBuilder::new()
+        .insert_str("chapter", "Tom & Jerry")
+        .insert_str("page", "\\thepage")
+        .insert_str("title", "<Title>")
+        .build();
+    assert_eq!(book.get_running_layout("pdf.header.odd", &data).unwrap(),
+               ["Tom & Jerry".to_owned(), String::new(), "\\thepage".to_owned()]);
+    assert_eq!(book.get_running_layout("pdf.header.even", &data).unwrap(),
+               [String::new(), "<Title>".to_owned(), String::new()]);
+    assert_eq!(book.get_running_layout("pdf.footer.even", &data).unwrap(),
+               [String::new(), String::new(), String::new()]);
+    assert!(book.get_running_layout("pdf.footer.odd", &data).is_err());
+}
+
+#[test]
+fn book_running_header_numbers() {
+    use number::Number;
+
+    let mut book = Book::new();
+    book.options.set("rendering.part.roman_numerals", "true").unwrap();
+    book.add_chapter_from_source(Number::SpecifiedPart(2), "# A part\n".as_bytes())
+        .unwrap();
+    book.add_chapter_from_source(Number::Specified(5), "# A chapter\n".as_bytes())
+        .unwrap();
+    let mut tex = vec![];
+    book.render_format_to("tex", &mut tex).unwrap();
+    let tex = String::from_utf8(tex).unwrap();
+    assert!(tex.contains("\\setcounter{part}{1}"));
+    assert!(tex.contains("\\crowbooksetpart{\\Roman{part}. A part}"));
+    assert!(tex.contains("\\setcounter{chapter}{4}"));
+    assert!(tex.contains("\\crowbooksetchapter{\\arabic{chapter}. A chapter}"));
+}
+
+#[test]
+fn book_verse_memoir() {
+    use number::Number;
+
+    let mut book = Book::new();
+    book.options.set("tex.class", "memoir").unwrap();
+    book.add_chapter_from_source(Number::Default, "```verse 2\nA line\nAnother\n```".as_bytes())
+        .unwrap();
+    let mut tex = vec![];
+    book.render_format_to("tex", &mut tex).unwrap();
+    let tex = String::from_utf8(tex).unwrap();
+    // memoir doesn't load verse.sty, so \poemlines must be defined with its own command
+    assert!(tex.contains("\\providecommand{\\poemlines}[1]{\\linenumberfrequency{#1}}"));
+    assert!(tex.contains("\\poemlines{2}\n\\begin{verse}"));
+}
+
+#[test]
+fn book_tex_non_breaking_spaces() {
+    use number::Number;
+
+    let mut book = Book::new();
+    book.add_chapter_from_source(Number::Default, "A\u{A0}B".as_bytes()).unwrap();
+    let mut tex = vec![];
+    book.render_format_to("tex", &mut tex).unwrap();
+    let tex = String::from_utf8(tex).unwrap();
+    assert!(tex.contains("A\u{A0}B"));
+
+    let mut book = Book::new();
+    book.set_options(&[("input.clean.dialogue", "french")]);
+    book.add_chapter_from_source(Number::Default, "— Hello".as_bytes()).unwrap();
+    let mut tex = vec![];
+    book.render_format_to("tex", &mut tex).unwrap();
+    let tex = String::from_utf8(tex).unwrap();
+    assert!(tex.contains("~Hello"));
+
+    // Dialogues are formatted even if text isn't cleaned
+    let mut book = Book::new();
+    book.set_options(&[("input.clean", "false"), ("input.clean.dialogue", "french")]);
+    book.add_chapter_from_source(Number::Default, "— Hello".as_bytes()).unwrap();
+    let mut tex = vec![];
+    book.render_format_to("tex", &mut tex).unwrap();
+    let tex = String::from_utf8(tex).unwrap();
+    assert!(tex.contains("~Hello"));
+}
+
+#[test]
+fn book_includes() {
+    use number::Number;
+    use std::fs;
+    use std::io::Write;
+    use super::TestDir;
+
+    let dir = TestDir::new("crowbook-test-includes");
+    let root = dir.path.clone();
+    fs::create_dir_all(root.join("sub")).unwrap();
+    let files = [("main.md", "# Main\n\n{{#include sub/part.md}}\n"),
+                 ("sub/part.md", "See [the notes](notes.pdf).\n\n{{#include ../code.rs:2:3}}\n"),
+                 ("code.rs", "fn main() {\n    println!(\"Hello\");\n}\n"),
+                 ("cycle.md", "{{#include sub/cycle.md}}\n"),
+                 ("sub/cycle.md", "{{#include ../cycle.md}}\n")];
+    for &(file, content) in &files {
+        fs::File::create(root.join(file)).unwrap().write_all(content.as_bytes()).unwrap();
+    }
+
+    let mut book = Book::new();
+    book.root = root.clone();
+    book.add_chapter(Number::Default, "main.md").unwrap();
+    let content = format!("{:?}", book.chapters[0].content);
+    assert!(content.contains("\"sub/notes.pdf\""));
+    assert!(content.contains("CodeBlock(\"rust\""));
+    assert!(content.contains("println!"));
+    assert!(!content.contains("fn main"));
+    // Lines of the resolved content point to the file they come from
+    let lines = &book.chapters[0].source.as_ref().unwrap().1;
+    assert_eq!(format!("{}", lines[0]), "main.md:1");
+    assert_eq!(format!("{}", lines[2]), "sub/part.md:1");
+    assert_eq!(format!("{}", lines[4]), "sub/part.md:3");
+    assert!(format!("{}", lines[5]).ends_with("code.rs:2"));
+
+    let err = book.add_chapter(Number::Default, "cycle.md").unwrap_err();
+    assert!(err.is_file_not_found());
+    let err = format!("{}", err);
+    assert!(err.contains("circular include: "));
+    assert!(err.contains("cycle.md -> "));
+}
+
+#[test]
+fn book_tex_external_anchors() {
+    use number::Number;
+
+    let mut book = Book::new();
+    book.add_chapter_from_source(Number::Default,
+                                 "# Introduction\n\n[here](#introduction) and \
+                                  [guide](https://example.com/doc#introduction)"
+                                     .as_bytes())
+        .unwrap();
+    let mut tex = vec![];
+    book.render_format_to("tex", &mut tex).unwrap();
+    let tex = String::from_utf8(tex).unwrap();
+    assert!(tex.contains("\\hyperref[introduction]{here}"));
+    assert!(tex.contains("\\href{https://example.com/doc\\#introduction}{guide}"));
+}
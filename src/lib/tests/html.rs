@@ -68,3 +68,242 @@ Some paragraph
     println!("actual:\n {}", actual);
     test_eq(&actual, &expected);
 }
+
+#[test]
+fn html_split_chapter() {
+    let doc = "
+Foo
+===
+
+Some text
+
+Bar
+---
+
+Some more text
+
+Baz
+---
+
+Even more text
+";
+    let tokens = Parser::new().parse(doc).unwrap();
+    let mut book = Book::new();
+    book.set_options(&[("html.split.level", "2")]);
+    let html = HtmlRenderer::new(&book, "").unwrap();
+    let parts = html.split_chapter(&tokens);
+    assert_eq!(parts.len(), 3);
+    assert_eq!(parts[1][0], Token::Header(2, vec![Token::Str(String::from("Bar"))]));
+
+    book.set_options(&[("html.split.level", "0"),
+                       ("html.split.size", "12")]);
+    let html = HtmlRenderer::new(&book, "").unwrap();
+    let parts = html.split_chapter(&tokens);
+    assert_eq!(parts.len(), 3);
+}
+
+#[test]
+fn html_table_alignment() {
+    let doc = "
+| A | B | C |
+|:--|:-:|--:|
+| a | b | c |
+";
+    let actual = ast_to_html(&Parser::new().parse(doc).unwrap());
+    assert!(actual.contains(r#"<th style = "text-align: left"> A </th>"#));
+    assert!(actual.contains(r#"<th style = "text-align: center"> B </th>"#));
+    assert!(actual.contains(r#"<td style = "text-align: right"> c </td>"#));
+}
+
+#[test]
+fn html_hyphenate() {
+    let doc = "Extraordinarily `extraordinarily` [extraordinarily](http://foo.bar) short";
+    let mut book = Book::new();
+    book.set_options(&[("lang", "en"),
+                       ("html.hyphenate", "true")]);
+    let mut html = HtmlRenderer::new(&book, "").unwrap();
+    let actual = html.render_vec(&Parser::new().parse(doc).unwrap()).unwrap();
+    assert!(actual.starts_with("<p id = \"para-1\">Ex&shy;"));
+    assert!(actual.contains("<code>extraordinarily</code>"));
+    assert!(actual.contains(">extraordinarily</a>"));
+    assert!(actual.contains(" short"));
+}
+
+#[test]
+fn html_gfm_extensions() {
+    let doc = "
+Some ~~deleted~~ text
+
+* [x] done
+
+Term
+: Description
+";
+    let actual = ast_to_html(&Parser::new().parse(doc).unwrap());
+    assert!(actual.contains("<del>deleted</del>"));
+    assert!(actual.contains(r#"<li class = "task"><input type = "checkbox" disabled = "disabled" checked = "checked" /> done</li>"#));
+    assert!(actual.contains("<dl>\n<dt>Term</dt>\n<dd>Description</dd>\n</dl>"));
+}
+
+#[test]
+fn html_attributes() {
+    let doc = "
+Title {#intro}
+-----
+
+Some [text]{.red}
+{.center #par}
+";
+    let actual = ast_to_html(&Parser::new().parse(doc).unwrap());
+    assert!(actual.contains(r#"<h2 id = "intro">Title</h2>"#));
+    assert!(actual.contains(r#"<p id = "par" class = "center">Some <span class = "red">text</span></p>"#));
+}
+
+#[test]
+fn html_attributes_invalid_keys() {
+    let doc = "[text]{lang=fr onclick=x OnLoad=y src=a href=b Style=c srcset=d xlink:href=e \
+               data-x=f}";
+    let actual = ast_to_html(&Parser::new().parse(doc).unwrap());
+    assert!(actual.contains(r#"<span lang = "fr" data-x = "f">text</span>"#));
+    assert!(!actual.contains("srcset"));
+    assert!(!actual.contains("onclick"));
+    assert!(!actual.to_lowercase().contains("onload"));
+    assert!(!actual.contains("src"));
+    assert!(!actual.contains("href"));
+    assert!(!actual.to_lowercase().contains("style"));
+}
+
+#[test]
+fn html_conditional_and_raw_blocks() {
+    let doc = "
+<!-- only: html -->
+
+For *HTML*
+
+<!-- end only -->
+
+<!-- only: tex odt -->
+
+Turn the page.
+
+<!-- end only -->
+
+```{=html}
+<div class = \"widget\"></div>
+```
+
+```{=latex}
+\\newpage
+```
+";
+    let actual = ast_to_html(&Parser::new().parse(doc).unwrap());
+    assert!(actual.contains("<p id = \"para-1\">For <em>HTML</em></p>"));
+    assert!(actual.contains("<div class = \"widget\"></div>"));
+    assert!(!actual.contains("Turn the page"));
+    assert!(!actual.contains("newpage"));
+}
+
+#[test]
+fn html_book_notes() {
+    let mut book = Book::new();
+    book.set_options(&[("rendering.num_depth", "0"),
+                       ("rendering.highlight", "none"),
+                       ("rendering.notes.placement", "book")]);
+    let mut html = HtmlRenderer::new(&book, "").unwrap();
+    html.notes_file = String::from("notes.html");
+    let doc = "
+# Title {#title}
+
+Text[^1]
+
+[^1]: A note
+";
+    let actual = html.render_vec(&Parser::new().parse(doc).unwrap()).unwrap();
+    assert!(actual.contains(r#"<a href = "notes.html#note-dest-1"><sup id = "note-source-1">[1]</sup></a>"#));
+    let mut end_notes = String::new();
+    html.render_end_notes(&mut end_notes);
+    assert_eq!(end_notes, "");
+    let notes = html.render_book_notes();
+    assert!(notes.contains(r##"<h2 class = "notes"><a href = "#title">Title</a></h2>"##));
+    assert!(notes.contains(r#"<a href = "#note-source-1">[1]</a>"#));
+    assert!(notes.contains(r#"<aside id = "note-dest-1"><p id = "para-1">A note</p>"#));
+}
+
+#[test]
+fn html_invalid_notes_placement() {
+    let mut book = Book::new();
+    book.set_options(&[("rendering.notes.placement", "margin")]);
+    let err = format!("{}", HtmlRenderer::new(&book, "").err().unwrap());
+    assert!(err.contains("'auto', 'page', 'chapter', 'book' or 'side'"));
+}
+
+#[test]
+fn html_admonitions() {
+    let doc = "> [!WARNING]\n> Be careful";
+    let actual = ast_to_html(&Parser::new().parse(doc).unwrap());
+    test_eq(&actual,
+            "<aside class = \"admonition warning\">\n\
+             <p class = \"admonition-title\">Warning</p>\n\
+             <p id = \"para-1\">Be careful</p>\n\
+             </aside>\n");
+}
+
+#[test]
+fn html_verse() {
+    let doc = "```verse 2\nA line\n  Another\n```";
+    let actual = ast_to_html(&Parser::new().parse(doc).unwrap());
+    test_eq(&actual,
+            "<div class = \"verse\">\n\
+             <p class = \"stanza\">\n\
+             <span class = \"verse-line\">A line<br /></span>\n\
+             <span class = \"verse-line\" style = \"margin-left: 1em\">\
+             <span class = \"verse-number\">2</span>Another<br /></span>\n\
+             </p>\n\
+             </div>\n");
+}
+
+#[test]
+fn html_scene_break() {
+    let mut book = Book::new();
+    book.set_options(&[("rendering.scene_break", "* * *")]);
+    let mut html = HtmlRenderer::new(&book, "").unwrap();
+    let actual = html.render_vec(&Parser::new().parse("A\n\n***\n\nB").unwrap()).unwrap();
+    assert!(actual.contains("<p class = \"rule\">* * *</p>\n"));
+}
+
+#[test]
+fn html_legacy_chapter_template() {
+    use number::Number;
+
+    let mut book = Book::new();
+    book.set_options(&[("html.chapter.template",
+                        "<h1 id = 'link-{{{link}}}'>{{{title}}}</h1>")]);
+    book.add_chapter_from_source(Number::Unnumbered, "# Introduction\n\n[Back](#introduction)"
+                                     .as_bytes())
+        .unwrap();
+    let mut html = vec![];
+    book.render_format_to("html", &mut html).unwrap();
+    let html = String::from_utf8(html).unwrap();
+    // Both the legacy anchor and the one used by links are present
+    assert!(html.contains("<a id = \"introduction\"></a><h1 id = 'link-1'>Introduction</h1>"));
+    assert!(html.contains("href = \"#introduction\""));
+}
+
+#[test]
+fn html_header_attributes_with_templates() {
+    use number::Number;
+
+    let mut book = Book::new();
+    book.set_options(&[("html.chapter.template",
+                        "<h1 id = 'link-{{{link}}}'>{{{title}}}</h1>")]);
+    book.add_chapter_from_source(Number::DefaultPart, "# First {.big lang=en}".as_bytes())
+        .unwrap();
+    book.add_chapter_from_source(Number::Unnumbered, "# Introduction {.intro}".as_bytes())
+        .unwrap();
+    let mut html = vec![];
+    book.render_format_to("html", &mut html).unwrap();
+    let html = String::from_utf8(html).unwrap();
+    assert!(html.contains("<h2 class = 'part'>"));
+    assert!(html.contains("class = 'part big' lang = \"en\">First</h1>"));
+    assert!(html.contains("<a id = \"introduction\"></a><h1 id = 'link-1' class = \"intro\">Introduction</h1>"));
+}
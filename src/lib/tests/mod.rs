@@ -1,3 +1,9 @@
+use uuid;
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
 /// Equivalent to assert_eq! but with prettier output
 pub fn test_eq(actual: &str, expected: &str) {
     if actual != expected {
@@ -5,6 +11,26 @@ pub fn test_eq(actual: &str, expected: &str) {
     }
 }
 
+/// Temporary directory with a unique name, removed when dropped
+pub struct TestDir {
+    pub path: PathBuf,
+}
+
+impl TestDir {
+    pub fn new(prefix: &str) -> TestDir {
+        let uuid = uuid::Uuid::new_v4();
+        let path = env::temp_dir().join(format!("{}-{}", prefix, uuid.simple()));
+        fs::create_dir_all(&path).unwrap();
+        TestDir { path: path }
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
 mod parser;
 mod html;
 mod book;
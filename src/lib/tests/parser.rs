@@ -179,7 +179,7 @@ fn table_simple() {
 | bla           | bla           |  bla  |
 | bla           | bla           |  bla  |
 ";
-    let expected = "[Table(3, [TableHead([TableCell([Str(\" A             \")]), \
+    let expected = "[Table([Default, Default, Default], [TableHead([TableCell([Str(\" A             \")]), \
                     TableCell([Str(\" Simple        \")]), TableCell([Str(\" Table \")])]), \
                     TableRow([TableCell([Str(\" bla           \")]), TableCell([Str(\" bla           \
                     \")]), TableCell([Str(\"  bla  \")])]), TableRow([TableCell([Str(\" bla           \
@@ -189,6 +189,17 @@ fn table_simple() {
     test_eq(&result, expected);
 }
 
+#[test]
+fn table_alignment() {
+    let doc = "
+| Left | Center | Right | None |
+|:-----|:------:|------:|------|
+| a    | b      | c     | d    |
+";
+    let result = format!("{:?}", parse_from_str(doc));
+    assert!(result.starts_with("[Table([Left, Center, Right, Default], "));
+}
+
 #[test]
 fn superscript() {
     let doc = "Some text^up^";
@@ -250,6 +261,84 @@ fn subscript() {
     test_eq(&result, expected);
 }
 
+#[test]
+fn strikethrough() {
+    let doc = "Some ~~deleted~~ text";
+    let expected = "[Paragraph([Str(\"Some \"), Strikethrough([Str(\"deleted\")]), Str(\" text\")])]";
+    let result = format!("{:?}", parse_from_str(doc));
+    test_eq(&result, expected);
+
+    let doc = "~~Some *deleted* text~~";
+    let expected = "[Paragraph([Strikethrough([Str(\"Some \"), Emphasis([Str(\"deleted\")]), Str(\" text\")])])]";
+    let result = format!("{:?}", parse_from_str(doc));
+    test_eq(&result, expected);
+}
+
+#[test]
+fn task_list() {
+    let doc = "
+* [ ] to do
+* [x] done
+* not a task
+";
+    let expected = "[List([TaskItem(false, [Str(\"to do\")]), TaskItem(true, [Str(\"done\")]), \
+                    Item([Str(\"not a task\")])])]";
+    let result = format!("{:?}", parse_from_str(doc));
+    test_eq(&result, expected);
+}
+
+#[test]
+fn definition_list() {
+    let doc = "
+Term
+: Description
+
+Other term
+: First description
+continued
+: Second description
+";
+    let expected = "[DefinitionList([DefinitionTerm([Str(\"Term\")]), \
+                    DefinitionDescription([Str(\"Description\")]), \
+                    DefinitionTerm([Str(\"Other term\")]), \
+                    DefinitionDescription([Str(\"First description continued\")]), \
+                    DefinitionDescription([Str(\"Second description\")])])]";
+    let result = format!("{:?}", parse_from_str(doc));
+    test_eq(&result, expected);
+}
+
+#[test]
+fn attributes() {
+    let doc = "# Title {#intro .big}";
+    let expected = "[Header(1, [Str(\"Title\"), Attributes(Attributes { id: Some(\"intro\"), \
+                    classes: [\"big\"], pairs: [] })])]";
+    let result = format!("{:?}", parse_from_str(doc));
+    test_eq(&result, expected);
+
+    let doc = "Some text\n{.center lang=fr}";
+    let expected = "[Paragraph([Str(\"Some text\"), Attributes(Attributes { id: None, \
+                    classes: [\"center\"], pairs: [(\"lang\", \"fr\")] })])]";
+    let result = format!("{:?}", parse_from_str(doc));
+    test_eq(&result, expected);
+
+    let doc = "Some [red text]{.red} here";
+    let expected = "[Paragraph([Str(\"Some \"), Span(Attributes { id: None, classes: [\"red\"], \
+                    pairs: [] }, [Str(\"red text\")]), Str(\" here\")])]";
+    let result = format!("{:?}", parse_from_str(doc));
+    test_eq(&result, expected);
+
+    let doc = "![alt](img.png){.wide}";
+    let expected = "[Paragraph([StandaloneImage(\"img.png\", \"\", [Str(\"alt\"), \
+                    Attributes(Attributes { id: None, classes: [\"wide\"], pairs: [] })])])]";
+    let result = format!("{:?}", parse_from_str(doc));
+    test_eq(&result, expected);
+
+    let doc = "Not {attributes}, really {#}";
+    let expected = "[Paragraph([Str(\"Not {attributes}, really {#}\")])]";
+    let result = format!("{:?}", parse_from_str(doc));
+    test_eq(&result, expected);
+}
+
 #[test]
 fn foonote_correct() {
     let doc = "A foonote[^1]...
@@ -271,3 +360,60 @@ fn footnote_incorrect() {
     let result = parser.parse(doc);
     assert!(result.is_err());
 }
+
+#[test]
+fn conditional_and_raw_blocks() {
+    let doc = "<!-- only: html pdf -->\nSome text\n\n<!-- end only -->\n\nAfter";
+    let expected = "[OnlyFor([\"html\", \"tex\"], [Paragraph([Str(\"Some text\")])]), \
+                    Paragraph([Str(\"After\")])]";
+    let result = format!("{:?}", parse_from_str(doc));
+    test_eq(&result, expected);
+
+    let doc = "```{=latex}\n\\newpage\n```";
+    let expected = "[RawBlock(\"tex\", \"\\\\newpage\\n\")]";
+    let result = format!("{:?}", parse_from_str(doc));
+    test_eq(&result, expected);
+}
+
+#[test]
+fn inline_html() {
+    let doc = "Some <em>text</em><br/>\nwith <span class=\"smallcaps\">Caps</span> and <foo>";
+    let expected = "[Paragraph([Str(\"Some \"), Emphasis([Str(\"text\")]), HardBreak, SoftBreak, \
+                    Str(\"with \"), Span(Attributes { id: None, classes: [\"smallcaps\"], \
+                    pairs: [] }, [Str(\"Caps\")]), Str(\" and <foo>\")])]";
+    let result = format!("{:?}", parse_from_str(doc));
+    test_eq(&result, expected);
+
+    let doc = "Unclosed <b>bold";
+    let expected = "[Paragraph([Str(\"Unclosed <b>bold\")])]";
+    let result = format!("{:?}", parse_from_str(doc));
+    test_eq(&result, expected);
+
+    let doc = "<p class=\"center\">A <i>block</i> &amp; more</p>\n\n<hr>";
+    let expected = "[Paragraph([Str(\"A \"), Emphasis([Str(\"block\")]), Str(\" & more\"), \
+                    Attributes(Attributes { id: None, classes: [\"center\"], pairs: [] })]), \
+                    Rule]";
+    let result = format!("{:?}", parse_from_str(doc));
+    test_eq(&result, expected);
+}
+
+#[test]
+fn admonitions() {
+    let doc = "> [!NOTE] A *title*\n> Content\n\n> [!tip]\n>\n> Para\n\n> [!FOO] x";
+    let expected = "[Admonition(\"note\", \"A title\", [Paragraph([Str(\"Content\")])]), \
+                    Admonition(\"tip\", \"\", [Paragraph([Str(\"Para\")])]), \
+                    BlockQuote([Paragraph([Str(\"[!FOO] x\")])])]";
+    let result = format!("{:?}", parse_from_str(doc));
+    test_eq(&result, expected);
+}
+
+#[test]
+fn verse() {
+    let doc = "```verse 2\nThe *first* line\n  an indented one\n\n\n# not a header\n1. nor a list\n```";
+    let expected = "[Verse(2, [Stanza([VerseLine(0, [Str(\"The \"), Emphasis([Str(\"first\")]), \
+                    Str(\" line\")]), VerseLine(2, [Str(\"an indented one\")])]), \
+                    Stanza([VerseLine(0, [Str(\"# not a header\")]), \
+                    VerseLine(0, [Str(\"1. nor a list\")])])])]";
+    let result = format!("{:?}", parse_from_str(doc));
+    test_eq(&result, expected);
+}
@@ -30,6 +30,9 @@ pub fn traverse_token<F1, F2, R>(token: &Token, f: &F1, add: &F2) -> R
         Token::Str(ref s) => f(s),
 
         Token::SoftBreak => f(" "),
+
+        Token::Attributes(_) |
+        Token::RawBlock(..) => f(""),
         
         Token::Rule |
         Token::HardBreak => f("\n"),
@@ -15,7 +15,7 @@
 // You should have received ba copy of the GNU Lesser General Public License
 // along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 /// The inner type for an annotation.
 ///
 /// This Enum might grow additional variants, so library users should
@@ -26,13 +26,41 @@ pub enum Data {
     __NonExhaustive,
 }
 
+/// Horizontal alignment of a table column.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Alignment {
+    /// No alignment was specified, use the renderer's default
+    Default,
+    Left,
+    Center,
+    Right,
+}
+
+/// Attributes of an element, set with the `{#id .class key=value}` syntax.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct Attributes {
+    /// Identifier, set with `#id`
+    pub id: Option<String>,
+    /// Classes, set with `.class`
+    pub classes: Vec<String>,
+    /// Other attributes, set with `key=value`
+    pub pairs: Vec<(String, String)>,
+}
+
+impl Attributes {
+    /// Returns true if `class` is one of the classes
+    pub fn has_class(&self, class: &str) -> bool {
+        self.classes.iter().any(|c| c == class)
+    }
+}
+
 /// A single token representing a Markdown element.
 ///
 /// A Markdown document is, thus, a Vec of `Token`s.
 ///
 /// This Enum might grow additional variants, so library users should
 /// **not** rely on exhaustive matching.
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub enum Token {
     /// The most simple element, containing a String
     Str(String),
@@ -48,13 +76,26 @@ pub enum Token {
     Code(Vec<Token>),
     /// A quote
     BlockQuote(Vec<Token>),
+    /// A callout block (e.g. a note or a warning), with its kind (`note`, `tip`,
+    /// `important`, `warning` or `caution`), a title (empty to use the default
+    /// one for this kind) and its content
+    Admonition(String, String, Vec<Token>),
     /// Code block with language and content
     CodeBlock(String, Vec<Token>),
+    /// A poem or any verse, with the interval of line numbering (0 if lines are not
+    /// numbered) and a list of `Stanza`s
+    Verse(usize, Vec<Token>),
+    /// A stanza of a verse block, containing a list of `VerseLine`s
+    Stanza(Vec<Token>),
+    /// A line of verse, with its indentation (in spaces) and content
+    VerseLine(usize, Vec<Token>),
 
     /// Superscript, indicated with ^...^
     Superscript(Vec<Token>),
     /// Subscript, indicated with ~...~
     Subscript(Vec<Token>),
+    /// ~~Deleted~~ text, a.k.a. strikethrough
+    Strikethrough(Vec<Token>),
 
     /// Unordered list, with a vector of `Item`s
     List(Vec<Token>),
@@ -62,9 +103,19 @@ pub enum Token {
     OrderedList(usize, Vec<Token>),
     /// Item of a list
     Item(Vec<Token>),
+    /// Item of a task list, with a checkbox that is checked or not
+    TaskItem(bool, Vec<Token>),
+
+    /// Definition list, containing `DefinitionTerm`s each followed by one or more
+    /// `DefinitionDescription`s
+    DefinitionList(Vec<Token>),
+    /// Term of a definition list
+    DefinitionTerm(Vec<Token>),
+    /// Description of a term in a definition list
+    DefinitionDescription(Vec<Token>),
 
-    /// Table with number of rows, and a list of `TableHead` and `TableRows`
-    Table(i32, Vec<Token>),
+    /// Table with the alignment of each column, and a list of `TableHead` and `TableRows`
+    Table(Vec<Alignment>, Vec<Token>),
     /// Table header, contains `TableCell`s
     TableHead(Vec<Token>),
     /// Row of a table, contains `TableCell`s
@@ -92,6 +143,21 @@ pub enum Token {
     /// An annotation inserted by crowbook for e.g. grammar checking
     Annotation(Data, Vec<Token>),
 
+    /// Attributes of the element containing this token (a paragraph, a header,
+    /// or an image, in which case it is the last of the alt tokens).
+    ///
+    /// Use `Token::attributes` to get the attributes of an element.
+    Attributes(Attributes),
+    /// Inline text with attributes, e.g. `[some text]{.class}`
+    Span(Attributes, Vec<Token>),
+
+    /// Content that must only be rendered for some output formats (`html`, `epub`,
+    /// `tex`, `odt` or `proofread`)
+    OnlyFor(Vec<String>, Vec<Token>),
+    /// Raw content with its output format, that must be rendered verbatim by
+    /// the matching renderer and ignored by the other ones
+    RawBlock(String, String),
+
     /// Hint that destructuring should not be exhaustive
     #[doc(hidden)]
     __NonExhaustive,
@@ -103,20 +169,31 @@ impl Token {
     /// Returns the inner list of tokens contained in this token (if any)
     pub fn inner(&self) -> Option<&[Token]> {
         match *self {
-            Rule | SoftBreak | HardBreak | Str(_) => None,
+            Rule | SoftBreak | HardBreak | Str(_) | Token::Attributes(_) | RawBlock(..) => None,
 
             Paragraph(ref v) |
+            Span(_, ref v) |
+            OnlyFor(_, ref v) |
             Header(_, ref v) |
             Emphasis(ref v) |
             Strong(ref v) |
             Code(ref v) |
             BlockQuote(ref v) |
+            Admonition(_, _, ref v) |
             CodeBlock(_, ref v) |
+            Verse(_, ref v) |
+            Stanza(ref v) |
+            VerseLine(_, ref v) |
             Subscript(ref v) |
             Superscript(ref v) |
+            Strikethrough(ref v) |
             List(ref v) |
             OrderedList(_, ref v) |
             Item(ref v) |
+            TaskItem(_, ref v) |
+            DefinitionList(ref v) |
+            DefinitionTerm(ref v) |
+            DefinitionDescription(ref v) |
             Table(_, ref v) |
             TableHead(ref v) |
             TableRow(ref v) |
@@ -134,21 +211,32 @@ impl Token {
     /// Returns the inner list of tokens contained in this token (if any) (mutable version)
     pub fn inner_mut(&mut self) -> Option<&mut Vec<Token>> {
         match *self {
-            Rule | SoftBreak | HardBreak | Str(_) => None,
+            Rule | SoftBreak | HardBreak | Str(_) | Token::Attributes(_) | RawBlock(..) => None,
 
             Paragraph(ref mut v) |
+            Span(_, ref mut v) |
+            OnlyFor(_, ref mut v) |
             Annotation(_, ref mut v) |
             Header(_, ref mut v) |
             Emphasis(ref mut v) |
             Strong(ref mut v) |
             Code(ref mut v) |
             BlockQuote(ref mut v) |
+            Admonition(_, _, ref mut v) |
             CodeBlock(_, ref mut v) |
+            Verse(_, ref mut v) |
+            Stanza(ref mut v) |
+            VerseLine(_, ref mut v) |
             Subscript(ref mut v) |
             Superscript(ref mut v) |
+            Strikethrough(ref mut v) |
             List(ref mut v) |
             OrderedList(_, ref mut v) |
             Item(ref mut v) |
+            TaskItem(_, ref mut v) |
+            DefinitionList(ref mut v) |
+            DefinitionTerm(ref mut v) |
+            DefinitionDescription(ref mut v) |
             Table(_, ref mut v) |
             TableHead(ref mut v) |
             TableRow(ref mut v) |
@@ -162,6 +250,28 @@ impl Token {
         }
     }
 
+    /// Returns the attributes of this token, set with the `{#id .class key=value}`
+    /// syntax, if any
+    pub fn attributes(&self) -> Option<&Attributes> {
+        if let Token::Span(ref attributes, _) = *self {
+            return Some(attributes);
+        }
+        match self.inner().and_then(|v| v.last()) {
+            Some(&Token::Attributes(ref attributes)) => Some(attributes),
+            _ => None,
+        }
+    }
+
+    /// Returns false if this token is a conditional or raw block that must not be
+    /// rendered by a renderer producing one of `formats`
+    pub fn is_rendered_in(&self, formats: &[&str]) -> bool {
+        match *self {
+            Token::OnlyFor(ref only, _) => only.iter().any(|f| formats.contains(&f.as_str())),
+            Token::RawBlock(ref format, _) => formats.contains(&format.as_str()),
+            _ => true,
+        }
+    }
+
     /// Checks whether token is an str
     pub fn is_str(&self) -> bool {
         if let Token::Str(_) = *self {
@@ -210,7 +320,11 @@ impl Token {
                 | Token::List(..) | Token::OrderedList(..) | Token::Table(..)
                 | Token::TableHead(..) | Token::TableRow(..) | Token::Footnote(..)
                 | Token::TableCell(..) | Token::Annotation(..) | Token::Item(..)
-                | Token::BlockQuote(..) => true,
+                | Token::BlockQuote(..) | Token::Strikethrough(..) | Token::TaskItem(..)
+                | Token::DefinitionList(..) | Token::DefinitionTerm(..)
+                | Token::DefinitionDescription(..) | Token::Span(..)
+                | Token::OnlyFor(..) | Token::Admonition(..) | Token::Verse(..)
+                | Token::Stanza(..) | Token::VerseLine(..) => true,
             _ => false,
         }
     }
@@ -0,0 +1,199 @@
+// Copyright (C) 2017 Élisabeth HENRY.
+//
+// This file is part of Crowbook.
+//
+// Crowbook is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published
+// by the Free Software Foundation, either version 2.1 of the License, or
+// (at your option) any later version.
+//
+// Crowbook is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with Crowbook.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Substitution of book variables (`{{name}}`) in Markdown sources.
+
+use error::{Error, Result, Source};
+use bookoptions::BookOptions;
+use include::CodeBlocks;
+
+/// Replaces the variables of `content`, the content of `file`.
+///
+/// `{{name}}` is replaced by the value of the option `vars.name` if it is set, else by
+/// the value of the metadata `name` (e.g. `{{title}}`, `{{version}}` or `{{metadata.foo}}`).
+/// Variables are not replaced in code blocks (fenced or indented) and code spans (even
+/// if they span several lines), and `\{{` is replaced by a literal `{{`. Nothing is
+/// replaced unless `input.variables` is set to true.
+///
+/// Returns an error (with the line where it is used) if a variable is not defined.
+pub fn substitute(options: &BookOptions, file: &str, content: String) -> Result<String> {
+    if !options.get_bool("input.variables").unwrap() || !content.contains("{{") {
+        return Ok(content);
+    }
+    let lines: Vec<_> = content.lines().collect();
+    let mut result = String::with_capacity(content.len());
+    let mut code = CodeBlocks::new();
+    // Backticks opening a code span that isn't closed yet
+    let mut span = None;
+    for (i, line) in lines.iter().enumerate() {
+        if code.is_code(line) || line.trim().is_empty() {
+            // Code spans can't contain blank lines nor code blocks
+            span = None;
+            result.push_str(line);
+        } else {
+            let mut source = Source::new(file);
+            source.set_line(i as u32 + 1);
+            result.push_str(&substitute_line(options, line, &lines[i + 1..], &mut span, &source)?);
+        }
+        result.push('\n');
+    }
+    Ok(result)
+}
+
+/// Returns true if a run of backticks appears in the rest of the paragraph
+fn closed_later(following: &[&str], run: &str) -> bool {
+    following.iter()
+        .take_while(|line| !line.trim().is_empty())
+        .any(|line| line.contains(run))
+}
+
+/// Replaces the variables in a line (outside of code blocks)
+///
+/// `span` contains the backticks of a code span opened on a previous line, and is
+/// updated if a code span is left open at the end of this one.
+fn substitute_line(options: &BookOptions,
+                   line: &str,
+                   following: &[&str],
+                   span: &mut Option<String>,
+                   source: &Source)
+                   -> Result<String> {
+    let mut result = String::with_capacity(line.len());
+    let mut rest = line;
+    if let Some(run) = span.take() {
+        match rest.find(run.as_str()) {
+            Some(end) => {
+                let end = end + run.len();
+                result.push_str(&rest[..end]);
+                rest = &rest[end..];
+            }
+            None => {
+                *span = Some(run);
+                return Ok(line.to_owned());
+            }
+        }
+    }
+    while !rest.is_empty() {
+        if rest.starts_with("\\{{") {
+            result.push_str("{{");
+            rest = &rest[3..];
+        } else if rest.starts_with('`') {
+            // Leave code spans alone: find the closing run of backticks of the same length
+            let n = rest.len() - rest.trim_left_matches('`').len();
+            let run = &rest[..n];
+            let end = match rest[n..].find(run) {
+                Some(end) => n + end + n,
+                None if closed_later(following, run) => {
+                    // The code span continues on the next lines
+                    *span = Some(run.to_owned());
+                    rest.len()
+                }
+                None => n,
+            };
+            result.push_str(&rest[..end]);
+            rest = &rest[end..];
+        } else if rest.starts_with("{{") {
+            match variable_name(&rest[2..]) {
+                Some((name, len)) => {
+                    result.push_str(&value(options, name, source)?);
+                    rest = &rest[2 + len..];
+                }
+                None => {
+                    result.push_str("{{");
+                    rest = &rest[2..];
+                }
+            }
+        } else {
+            let c = rest.chars().next().unwrap();
+            result.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    Ok(result)
+}
+
+/// If `s` starts with a variable name followed by `}}`, returns the name and
+/// the length of the text to skip
+fn variable_name(s: &str) -> Option<(&str, usize)> {
+    let end = match s.find("}}") {
+        Some(end) => end,
+        None => return None,
+    };
+    let name = s[..end].trim();
+    let valid = name.chars().next().map_or(false, |c| c.is_alphabetic()) &&
+                name.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '-' || c == '.');
+    if valid {
+        Some((name, end + 2))
+    } else {
+        None
+    }
+}
+
+/// Returns the value of a variable
+fn value(options: &BookOptions, name: &str, source: &Source) -> Result<String> {
+    if let Ok(value) = options.get_str(&format!("vars.{}", name)) {
+        return Ok(value.to_owned());
+    }
+    if options.get_metadata().iter().any(|key| key == name) {
+        if let Ok(value) = options.get_str(name) {
+            return Ok(value.to_owned());
+        }
+    }
+    Err(Error::parser(source,
+                      lformat!("variable '{name}' is not defined; set it with the option \
+                                'vars.{name}' or use a metadata",
+                               name = name)))
+}
+
+#[test]
+fn variables_substitute() {
+    let mut options = BookOptions::new();
+    options.set("input.variables", "true").unwrap();
+    options.set("title", "Foo").unwrap();
+    options.set("vars.product", "Bar").unwrap();
+    let content = String::from("{{title}} and {{ product }}, \\{{title}} `{{title}}`\n\
+                                ```\n{{product}}\n```\n{{#include x.md}}\n");
+    assert_eq!(substitute(&options, "a.md", content).unwrap(),
+               "Foo and Bar, {{title}} `{{title}}`\n```\n{{product}}\n```\n{{#include x.md}}\n");
+    let err = substitute(&options, "a.md", String::from("\n{{unknown}}")).unwrap_err();
+    assert!(err.is_parser());
+    assert!(format!("{}", err).starts_with("a.md:2: "));
+}
+
+#[test]
+fn variables_indented_code() {
+    let mut options = BookOptions::new();
+    options.set("input.variables", "true").unwrap();
+    options.set("title", "Foo").unwrap();
+    let content = String::from("{{title}}\n\n    {{title}}\n\n    {{unknown}}\n\n\
+                                - {{title}}\n\n    {{title}}\n");
+    assert_eq!(substitute(&options, "a.md", content).unwrap(),
+               "Foo\n\n    {{title}}\n\n    {{unknown}}\n\n- Foo\n\n    Foo\n");
+}
+
+#[test]
+fn variables_multiline_code_spans() {
+    let mut options = BookOptions::new();
+    options.set("input.variables", "true").unwrap();
+    options.set("title", "Foo").unwrap();
+    let content = String::from("`a\n{{unknown}}` {{title}}\n\n`` b\n\n{{title}} ``\n");
+    assert_eq!(substitute(&options, "a.md", content).unwrap(),
+               "`a\n{{unknown}}` Foo\n\n`` b\n\nFoo ``\n");
+
+    options.set("input.variables", "false").unwrap();
+    let content = String::from("{{unknown}}\n");
+    assert_eq!(substitute(&options, "a.md", content).unwrap(), "{{unknown}}\n");
+}
@@ -19,16 +19,53 @@ use error::{Error, Result};
 
 use std::path::{Path, PathBuf};
 use std::io;
-use std::io::Write;
-use std::process::Command;
+use std::io::{Read, Write};
+use std::process::{Command, Output};
 use std::fs::{self, File, DirBuilder};
 use uuid;
 use std::ops::Drop;
 
+use walkdir::WalkDir;
+use chrono::NaiveDateTime;
+
+/// Commands used to generate a PDF file from a LaTeX file
+pub struct TexPipeline<'a> {
+    /// The TeX engine, e.g. `xelatex`
+    pub command: &'a str,
+    /// Whether to let `latexmk` run the engine and auxiliary tools
+    pub latexmk: bool,
+    /// Bibliography tool (`biber`, `bibtex`), or `auto` to detect it
+    pub bibliography: &'a str,
+    /// Index tool, e.g. `makeindex` or `texindy`
+    pub index: &'a str,
+    /// Maximum number of times the engine is run
+    pub max_passes: i32,
+}
+
+/// Returns true if a LaTeX log asks to run LaTeX again
+fn needs_rerun(log: &str) -> bool {
+    log.contains("Rerun to get") || log.contains("Label(s) may have changed") ||
+        log.contains("Please rerun LaTeX") || log.contains("Rerun LaTeX")
+}
+
+/// Returns true if `command` is a known TeX engine, so it accepts TeX's own arguments
+/// (e.g. `-interaction`), unlike a wrapper script
+fn is_tex_engine(command: &str) -> bool {
+    let name = Path::new(command)
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("");
+    ["tex", "latex", "pdftex", "pdflatex", "xetex", "xelatex", "luatex", "lualatex", "platex",
+     "uplatex"]
+        .contains(&name)
+}
+
 /// Struct used to create zip (using filesystem and zip command)
 pub struct Zipper {
     args: Vec<String>,
     path: PathBuf,
+    timestamp: Option<i64>,
+    keep: bool,
 }
 
 impl Zipper {
@@ -52,9 +89,86 @@ impl Zipper {
         Ok(Zipper {
             args: vec![],
             path: zipper_path,
+            timestamp: None,
+            keep: false,
         })
     }
 
+    /// Sets a fixed timestamp for generated files, so the results are reproducible.
+    ///
+    /// If set, all files are given this modification time before zipping them,
+    /// zip archives don't store extra attributes, and LaTeX commands are
+    /// run with `SOURCE_DATE_EPOCH` set to it.
+    pub fn set_timestamp(&mut self, timestamp: Option<i64>) {
+        self.timestamp = timestamp;
+    }
+
+    /// Sets whether the temporary directory must be kept when the zipper is dropped,
+    /// e.g. so the user can inspect the files generated by LaTeX.
+    pub fn set_keep(&mut self, keep: bool) {
+        self.keep = keep;
+    }
+
+    /// Returns the path of the temporary directory
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Reads a file of the temporary directory (e.g. a log), if it exists
+    pub fn read(&self, file: &str) -> Option<String> {
+        let mut content = vec![];
+        File::open(self.path.join(file))
+            .and_then(|mut f| f.read_to_end(&mut content))
+            .ok()
+            .map(|_| String::from_utf8_lossy(&content).into_owned())
+    }
+
+    /// Returns the list of files in the temporary directory, sorted by name
+    fn sorted_files(&self) -> Vec<String> {
+        let mut files: Vec<String> = WalkDir::new(&self.path)
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_file())
+            .filter_map(|e| e.path()
+                        .strip_prefix(&self.path)
+                        .ok()
+                        .map(|p| format!("{}", p.display())))
+            .collect();
+        files.sort();
+        files
+    }
+
+    /// Sets the modification time of all files to the fixed timestamp, if there is one
+    fn touch_files(&self) -> Result<()> {
+        if let Some(timestamp) = self.timestamp {
+            let date = match NaiveDateTime::from_timestamp_opt(timestamp, 0) {
+                Some(date) => date.format("%Y%m%d%H%M.%S").to_string(),
+                None => {
+                    warn!("{}", lformat!("timestamp {timestamp} is out of range, not setting \
+                                          the modification time of files",
+                                         timestamp = timestamp));
+                    return Ok(());
+                }
+            };
+            let output = Command::new("touch")
+                .current_dir(&self.path)
+                .env("TZ", "UTC")
+                .arg("-t")
+                .arg(&date)
+                .args(&self.sorted_files())
+                .output()
+                .map_err(|e| {
+                    Error::zipper(lformat!("failed to set modification time of temporary files: {error}",
+                                           error = e))
+                })?;
+            if !output.status.success() {
+                return Err(Error::zipper(lformat!("failed to set modification time of temporary files: {error}",
+                                                  error = String::from_utf8_lossy(&output.stderr))));
+            }
+        }
+        Ok(())
+    }
+
     /// writes a content to a temporary file
     pub fn write<P:AsRef<Path>>(&mut self, path: P, content: &[u8], add_args: bool) -> Result<()> {
         let path = path.as_ref();
@@ -160,34 +274,235 @@ This is forbidden because we are supposed \
     /// zip all files in zipper's tmp dir to a given file name and write to odt file
     pub fn generate_odt(&mut self, command_name: &str, odt_file: &mut Write) -> Result<String> {
         let mut command = Command::new(command_name);
-        command.arg("-r");
-        command.arg("result.odt");
-        command.arg(".");
+        // zip stores modification times in local time
+        command.env("TZ", "UTC");
+        if self.timestamp.is_some() {
+            // List files explicitly so their order doesn't depend on the file system
+            self.touch_files()?;
+            command.arg("-X");
+            command.arg("result.odt");
+            command.args(&self.sorted_files());
+        } else {
+            command.arg("-r");
+            command.arg("result.odt");
+            command.arg(".");
+        }
         self.run_command(command, command_name, "result.odt", odt_file)
     }
 
+    /// zip all files written with `add_args` to an EPUB file
+    ///
+    /// `mimetype` is added first and uncompressed, as required by the EPUB specification.
+    pub fn generate_epub(&mut self, command_name: &str, epub_file: &mut Write) -> Result<String> {
+        self.write("mimetype", b"application/epub+zip", false)?;
+        self.touch_files()?;
+        // zip stores modification times in local time
+        let output = Command::new(command_name)
+            .current_dir(&self.path)
+            .env("TZ", "UTC")
+            .arg("-X0")
+            .arg("result.epub")
+            .arg("mimetype")
+            .output()
+            .map_err(|e| {
+                Error::zipper(lformat!("failed to execute {name}: {error}",
+                                       name = command_name,
+                                       error = e))
+            })?;
+        if !output.status.success() {
+            return Err(Error::zipper(lformat!("{command} didn't return succesfully",
+                                              command = command_name)));
+        }
+
+        let mut command = Command::new(command_name);
+        command.env("TZ", "UTC");
+        if self.timestamp.is_some() {
+            command.arg("-X");
+        }
+        command.arg("-9");
+        command.arg("result.epub");
+        self.run_command(command, command_name, "result.epub", epub_file)
+    }
+
 
     /// generate a pdf file into given file name
+    ///
+    /// Unless `latexmk` is used, the TeX engine is run until cross-references
+    /// are stable (or `max_passes` is reached), running bibliography and index tools
+    /// after the first pass if the document needs them.
     pub fn generate_pdf(&mut self,
-                        command_name: &str,
+                        pipeline: &TexPipeline,
                         tex_file: &str,
                         pdf_file: &mut Write)
                         -> Result<String> {
-        // first pass
-        let _ = Command::new(command_name)
-            .current_dir(&self.path)
-            .arg(tex_file)
-            .output();
+        let stem = tex_file.trim_right_matches(".tex").to_owned();
+        let pdf = format!("{}.pdf", stem);
+        if pipeline.latexmk {
+            let mut command = Command::new("latexmk");
+            self.set_tex_env(&mut command);
+            command.arg("-interaction=nonstopmode");
+            match pipeline.command {
+                "xelatex" => {
+                    command.arg("-pdfxe");
+                }
+                "lualatex" => {
+                    command.arg("-pdflua");
+                }
+                "pdflatex" => {
+                    command.arg("-pdf");
+                }
+                engine => {
+                    command.arg("-pdf").arg(format!("-pdflatex={} %O %S", engine));
+                }
+            }
+            command.arg(tex_file);
+            let output = self.run_tool(command, "latexmk", None)?;
+            return self.copy_result(&output, "latexmk", &pdf, pdf_file);
+        }
 
-        // second pass
-        let mut command = Command::new(command_name);
-        command.arg(tex_file);
-        self.run_command(command, command_name, "result.pdf", pdf_file)
+        let aux = format!("{}.aux", stem);
+        let log = format!("{}.log", stem);
+        let mut previous_aux = None;
+        let mut pass = 0;
+        loop {
+            pass += 1;
+            debug!("{}", lformat!("Running {command}, pass {n}",
+                                  command = pipeline.command,
+                                  n = pass));
+            let mut command = Command::new(pipeline.command);
+            self.set_tex_env(&mut command);
+            if is_tex_engine(pipeline.command) {
+                command.arg("-interaction=nonstopmode");
+            }
+            command.arg(tex_file);
+            let output = self.run_tool(command, pipeline.command, Some("tex.command"))?;
+            // Don't run auxiliary tools or other passes on a document that doesn't compile
+            self.check_success(&output, pipeline.command)?;
+            let current_aux = self.read(&aux);
+            let mut rerun = needs_rerun(&self.read(&log).unwrap_or_default()) ||
+                current_aux != previous_aux;
+            if pass == 1 && self.run_auxiliary_tools(pipeline, &stem)? {
+                rerun = true;
+            }
+            previous_aux = current_aux;
+            if !rerun {
+                return self.copy_result(&output, pipeline.command, &pdf, pdf_file);
+            }
+            if pass >= pipeline.max_passes {
+                warn!("{}", lformat!("LaTeX: cross-references were still changing after {n} \
+                                      passes, they might be wrong",
+                                     n = pass));
+                return self.copy_result(&output, pipeline.command, &pdf, pdf_file);
+            }
+        }
+    }
+
+    /// Runs bibliography and index tools if the document needs them, and returns
+    /// true if one was run
+    fn run_auxiliary_tools(&mut self, pipeline: &TexPipeline, stem: &str) -> Result<bool> {
+        let mut ran = false;
+        let has_bcf = self.path.join(format!("{}.bcf", stem)).exists();
+        let has_bibdata = self.read(&format!("{}.aux", stem))
+            .map_or(false, |aux| aux.contains("\\bibdata"));
+        let bibliography = match pipeline.bibliography {
+            "auto" if has_bcf => Some("biber"),
+            "auto" if has_bibdata => Some("bibtex"),
+            "auto" => None,
+            command if has_bcf || has_bibdata => Some(command),
+            _ => None,
+        };
+        if let Some(bibliography) = bibliography {
+            let mut command = Command::new(bibliography);
+            command.arg(stem);
+            let output = self.run_tool(command, bibliography, Some("tex.bibliography.command"))?;
+            self.check_success(&output, bibliography)?;
+            ran = true;
+        }
+        let idx = format!("{}.idx", stem);
+        if self.read(&idx).map_or(false, |content| !content.is_empty()) {
+            let mut command = Command::new(pipeline.index);
+            command.arg(&idx);
+            let output = self.run_tool(command, pipeline.index, Some("tex.index.command"))?;
+            self.check_success(&output, pipeline.index)?;
+            ran = true;
+        }
+        Ok(ran)
+    }
+
+    /// Runs a command in the temporary directory, with a clear error if it can't be found
+    ///
+    /// `option` is the option setting this command, if any.
+    fn run_tool(&self, mut command: Command, command_name: &str, option: Option<&str>) -> Result<Output> {
+        command.current_dir(&self.path)
+            .output()
+            .map_err(|e| if e.kind() == io::ErrorKind::NotFound {
+                match option {
+                    Some(option) => {
+                        Error::zipper(lformat!("could not find command '{name}' (set by option \
+                                                '{option}'), is it installed and in your PATH?",
+                                               name = command_name,
+                                               option = option))
+                    }
+                    None => {
+                        Error::zipper(lformat!("could not find command '{name}', is it installed \
+                                                and in your PATH?",
+                                               name = command_name))
+                    }
+                }
+            } else {
+                Error::zipper(lformat!("failed to run command '{name}': {error}",
+                                       name = command_name,
+                                       error = e))
+            })
+    }
+
+    /// Returns an error if a command didn't return successfully
+    fn check_success(&self, output: &Output, command_name: &str) -> Result<()> {
+        if output.status.success() {
+            Ok(())
+        } else {
+            debug!("{}",
+                   lformat!("{command} didn't return succesfully: {output}",
+                            command = command_name,
+                            output = String::from_utf8_lossy(&output.stdout)));
+            Err(Error::zipper(lformat!("{command} didn't return succesfully",
+                                       command = command_name)))
+        }
+    }
+
+    /// Checks that a command was successful and copies the file it generated
+    fn copy_result(&self,
+                   output: &Output,
+                   command_name: &str,
+                   in_file: &str,
+                   out: &mut Write)
+                   -> Result<String> {
+        self.check_success(output, command_name)?;
+        let mut file = File::open(self.path.join(in_file))
+            .map_err(|_| {
+                Error::zipper(lformat!("could not open result of command '{command}'",
+                                       command = command_name))
+            })?;
+        io::copy(&mut file, out)
+            .map_err(|_| Error::zipper(lformat!("error copying file '{file}'",
+                                                file = in_file)))?;
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+
+    /// Makes LaTeX use the fixed timestamp instead of current date, if there is one
+    fn set_tex_env(&self, command: &mut Command) {
+        if let Some(timestamp) = self.timestamp {
+            command.env("SOURCE_DATE_EPOCH", format!("{}", timestamp))
+                .env("FORCE_SOURCE_DATE", "1");
+        }
     }
 }
 
 impl Drop for Zipper {
     fn drop(&mut self) {
+        if self.keep {
+            return;
+        }
         if let Err(err) = fs::remove_dir_all(&self.path) {
             println!("Error in zipper: could not delete temporary directory {}, error: {}",
                      self.path.to_string_lossy(),
@@ -195,3 +510,20 @@ impl Drop for Zipper {
         }
     }
 }
+
+#[test]
+fn zipper_needs_rerun() {
+    assert!(needs_rerun("LaTeX Warning: Label(s) may have changed. Rerun to get cross-references right."));
+    assert!(needs_rerun("Package rerunfilecheck Warning: File `result.out' has changed.\n\
+                         (rerunfilecheck)                Rerun to get outlines right"));
+    assert!(needs_rerun("Package biblatex Warning: Please rerun LaTeX."));
+    assert!(!needs_rerun("Output written on result.pdf (12 pages)."));
+}
+
+#[test]
+fn zipper_is_tex_engine() {
+    assert!(is_tex_engine("xelatex"));
+    assert!(is_tex_engine("/usr/bin/pdflatex"));
+    assert!(!is_tex_engine("tectonic"));
+    assert!(!is_tex_engine("./build.sh"));
+}